@@ -11,7 +11,9 @@ use interceptor_core::plugin::config::PluginSystemConfig;
 use interceptor_core::plugin::manager::PluginManager;
 use interceptor_core::rules::RuleEngine;
 use interceptor_core::storage::CaptureStorage;
-use interceptor_core::{Intruder, ProjectManager, Scanner, ScopeManager, WsCapture};
+use interceptor_core::{
+    Intruder, NetworkConditions, ProjectManager, Repeater, Scanner, ScopeManager, WsCapture,
+};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::sync::RwLock;
 use tracing::info;
@@ -33,7 +35,8 @@ async fn main() -> anyhow::Result<()> {
     let db_path =
         std::env::var("INTERCEPTOR_DB_PATH").unwrap_or_else(|_| "data/interceptor.sqlite".into());
     let storage = Arc::new(CaptureStorage::new(db_path)?);
-    let capture = Arc::new(RequestCapture::with_storage(10_000, Some(storage.clone())));
+    storage.clone().spawn_retention();
+    let capture = Arc::new(RequestCapture::with_storage(10_000, Some(storage.clone()))?);
 
     let cert_manager = Arc::new(CertManager::new()?);
     let rules = Arc::new(RuleEngine::new());
@@ -42,7 +45,21 @@ async fn main() -> anyhow::Result<()> {
     let scope = Arc::new(ScopeManager::new());
     info!("Initialized ScopeManager");
 
-    let intruder = Arc::new(Intruder::new());
+    let mut license_manager = interceptor_core::license::LicenseManager::new();
+    if let Err(e) = license_manager.load_license() {
+        tracing::warn!("Failed to load license: {}", e);
+    }
+    let license_manager = Arc::new(license_manager);
+
+    let intruder = Arc::new(
+        Intruder::new()
+            .with_capture(capture.clone())
+            .with_wordlist_dir(
+                std::env::var("INTERCEPTOR_WORDLIST_DIR")
+                    .unwrap_or_else(|_| "data/wordlists".into()),
+            )
+            .with_max_rps(license_manager.tier().max_rps()),
+    );
     info!("Initialized Intruder");
 
     let ws_capture = Arc::new(WsCapture::new(10_000));
@@ -176,12 +193,6 @@ async fn main() -> anyhow::Result<()> {
         tracing::warn!("Failed to load plugins: {}", e);
     }
 
-    let mut license_manager = interceptor_core::license::LicenseManager::new();
-    if let Err(e) = license_manager.load_license() {
-        tracing::warn!("Failed to load license: {}", e);
-    }
-    let license_manager = Arc::new(license_manager);
-
     let project_manager = Arc::new(ProjectManager::new(Some(storage.clone())));
 
     let state = AppState {
@@ -190,10 +201,12 @@ async fn main() -> anyhow::Result<()> {
         pool: ConnectionPool::new(),
         rules,
         scope,
+        network: Arc::new(NetworkConditions::new()),
         intruder,
         scanner: Arc::new(Scanner::new()),
         ws_capture,
         project_manager,
+        repeater: Arc::new(Repeater::new()),
         api_token,
         max_body_bytes,
         max_concurrency,
@@ -17,6 +17,9 @@ pub struct RepeatRequest {
     pub url: Option<String>,
     pub headers: Option<Vec<HeaderPatch>>,
     pub modified_body: Option<String>,
+    /// Run match-and-replace rules on the request and response
+    #[serde(default)]
+    pub apply_rules: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -25,6 +28,9 @@ pub struct ManualRequest {
     pub url: String,
     pub headers: Option<HashMap<String, String>>,
     pub body: Option<String>,
+    /// Run match-and-replace rules on the request and response
+    #[serde(default)]
+    pub apply_rules: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
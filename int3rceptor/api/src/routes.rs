@@ -1,38 +1,57 @@
 use crate::{
+    error::ApiError,
     models::{
-        ActivityQuery, AppSettings, DashboardActivity, HeaderPatch, ManualRequest, ManualResponse,
-        PluginInfo, PluginToggle, RepeatRequest,
+        ActivityQuery, AppSettings, DashboardActivity, ManualRequest, ManualResponse, PluginInfo,
+        PluginToggle, RepeatRequest,
     },
     state::AppState,
 };
-use axum::extract::{Extension, Multipart, Path, Query};
-use axum::http::{header, HeaderMap, HeaderName, StatusCode};
-use axum::response::{IntoResponse, Json};
-use axum::routing::{delete, get, post};
+use axum::extract::{DefaultBodyLimit, Extension, Multipart, Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{delete, get, post, put};
 use axum::Router;
 use base64::engine::general_purpose::STANDARD as BASE64;
 use base64::Engine;
 use bytes::Bytes;
-use http_body_util::BodyExt;
-use hyper::{Method, Request, Uri};
-use interceptor_core::capture::{CaptureEntry, CaptureQuery, CapturedRequest, CapturedResponse};
-use interceptor_core::comparer::{CompareRequest, Comparer};
-use interceptor_core::connection_pool::ProxyBody;
+use interceptor_core::capture::{
+    Annotation, CaptureEntry, CaptureFilter, CaptureQuery, CaptureSort, CaptureSource, Highlight,
+    SortOrder,
+};
+use interceptor_core::codegen::{self, CodeFormat};
+use interceptor_core::comparer::{CompareMode, CompareRequest, CompareResponse, Comparer};
+use interceptor_core::database::{EncryptionKeyProvider, KeySource, KEY_ENV, PASSPHRASE_ENV};
 use interceptor_core::encoding::{Encoder, TransformRequest};
+use interceptor_core::error::ProxyError;
+use interceptor_core::har;
+use interceptor_core::hosts::HostEntry;
+use interceptor_core::import::{self, ImportFormat};
+use interceptor_core::intruder::{AttackProgress, IntruderResult, ResultQuery};
 use interceptor_core::metrics;
+use interceptor_core::network::NetworkProfile;
 use interceptor_core::plugin::config::PluginConfig;
+use interceptor_core::race::{RaceClient, RaceMode, RaceReport, MAX_RACE_REQUESTS};
+use interceptor_core::repeater::{
+    DiffTarget, Exchange, HistoryItem, RepeaterClient, RepeaterRequest, RepeaterTab,
+};
+use interceptor_core::retention::{PruneReport, RetentionPolicy, StorageStats};
 use interceptor_core::rules::Rule;
-use reqwest::Client;
+use interceptor_core::scope::ScopeConfig;
+use interceptor_core::search::SearchExpr;
+use interceptor_core::storage::keys::NewKey;
+use interceptor_core::storage::CaptureStorage;
 use serde::Deserialize;
 use serde_json::json;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
-use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use time::OffsetDateTime;
 
 // Maximum allowed body size to prevent DoS attacks (10MB)
 const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+// Imported HAR, Burp, mitmproxy and pcap files carry whole sessions (100MB)
+const MAX_IMPORT_SIZE: usize = 100 * 1024 * 1024;
 
 // ... existing handlers ...
 
@@ -107,25 +126,91 @@ pub fn router() -> Router {
         .route("/api/requests", get(list_requests).delete(clear_requests))
         .route("/api/requests/:id", get(get_request))
         .route("/api/requests/:id/repeat", post(repeat_request))
+        .route("/api/requests/:id/dry-run", post(dry_run_request))
+        .route("/api/requests/:id/export", get(export_request_code))
+        .route(
+            "/api/requests/:id/annotation",
+            put(set_annotation).delete(clear_annotation),
+        )
         .route("/api/repeater/send", post(send_manual_request))
+        .route("/api/repeater/parse-curl", post(parse_curl_command))
+        .route(
+            "/api/repeater/tabs",
+            get(list_repeater_tabs).post(create_repeater_tab),
+        )
+        .route(
+            "/api/repeater/tabs/:id",
+            get(get_repeater_tab)
+                .put(update_repeater_tab)
+                .delete(close_repeater_tab),
+        )
+        .route("/api/repeater/tabs/:id/send", post(send_repeater_tab))
+        .route("/api/repeater/tabs/:id/diff", get(diff_repeater_tab))
+        .route("/api/repeater/race", post(race_requests))
         .route("/api/settings", get(get_settings).put(update_settings))
         .route("/api/plugins", get(list_plugins))
         .route("/api/plugins/upload", post(upload_plugin))
         .route("/api/plugins/:name/toggle", post(toggle_plugin))
         .route("/api/requests/export", get(export_requests))
+        .route(
+            "/api/requests/import",
+            post(import_requests).layer(DefaultBodyLimit::max(MAX_IMPORT_SIZE)),
+        )
         .route("/api/ca-cert", get(download_ca_cert))
         .route(
             "/api/rules",
             get(list_rules).post(add_rule).delete(clear_rules),
         )
+        .route("/api/rules/hits", get(rule_hits).delete(reset_rule_hits))
         .route("/api/scope", get(get_scope).put(set_scope))
+        .route("/api/scope/import", post(import_scope))
+        .route(
+            "/api/hosts",
+            get(list_hosts).put(replace_hosts).post(set_host),
+        )
+        .route("/api/hosts/:host", delete(remove_host))
+        .route(
+            "/api/network/profiles",
+            get(list_network_profiles)
+                .put(replace_network_profiles)
+                .post(upsert_network_profile),
+        )
+        .route("/api/network/profiles/:id", delete(remove_network_profile))
+        .route(
+            "/api/capture/filter",
+            get(get_capture_filter).put(set_capture_filter),
+        )
+        .route("/api/storage/stats", get(storage_stats))
+        .route(
+            "/api/storage/retention",
+            get(get_retention).put(set_retention),
+        )
+        .route("/api/storage/prune", post(prune_storage))
+        .route("/api/storage/rotate-key", post(rotate_storage_key))
         .route("/api/intruder/generate", post(intruder_generate))
         .route(
             "/api/intruder/results",
             get(intruder_results).delete(intruder_clear),
         )
+        .route("/api/intruder/results/:id", get(intruder_result))
         .route("/api/intruder/start", post(intruder_start))
         .route("/api/intruder/stop", post(intruder_stop))
+        .route(
+            "/api/intruder/attacks",
+            get(list_attacks).post(intruder_start),
+        )
+        .route(
+            "/api/intruder/attacks/:id",
+            get(get_attack).delete(delete_attack),
+        )
+        .route("/api/intruder/attacks/:id/pause", post(pause_attack))
+        .route("/api/intruder/attacks/:id/resume", post(resume_attack))
+        .route("/api/intruder/attacks/:id/stop", post(stop_attack))
+        .route("/api/intruder/attacks/:id/results", get(attack_results))
+        .route(
+            "/api/intruder/attacks/:id/results/:request_id",
+            get(attack_result),
+        )
         // Scanner routes
         .route(
             "/api/scanner/config",
@@ -201,20 +286,19 @@ async fn get_license(Extension(state): Extension<Arc<AppState>>) -> impl IntoRes
 async fn list_requests(
     Query(params): Query<ListParams>,
     Extension(state): Extension<Arc<AppState>>,
-) -> impl IntoResponse {
-    let query: CaptureQuery = params.into();
-    let items = state.capture.query(&query);
-    Json(items)
+) -> Result<Json<Vec<CaptureEntry>>, ApiError> {
+    let query = CaptureQuery::try_from(params)?;
+    Ok(Json(state.capture.query(&query)?))
 }
 
 async fn get_request(
     Path(id): Path<u64>,
     Extension(state): Extension<Arc<AppState>>,
-) -> impl IntoResponse {
-    match state.capture.get(id) {
+) -> Result<Response, ApiError> {
+    Ok(match state.capture.get(id)? {
         Some(item) => Json(item).into_response(),
         None => StatusCode::NOT_FOUND.into_response(),
-    }
+    })
 }
 
 async fn repeat_request(
@@ -222,111 +306,58 @@ async fn repeat_request(
     Extension(state): Extension<Arc<AppState>>,
     Json(payload): Json<RepeatRequest>,
 ) -> impl IntoResponse {
-    let Some(entry) = state.capture.get(id) else {
-        return StatusCode::NOT_FOUND.into_response();
+    let entry = match state.capture.get(id) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
     };
 
-    let target_url = payload.url.as_deref().unwrap_or(&entry.request.url);
-    let uri: Uri = match target_url.parse() {
-        Ok(u) => u,
-        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
-    };
-
-    let body_bytes = if let Some(body) = payload.modified_body {
-        let bytes = body.into_bytes();
-        if bytes.len() > MAX_BODY_SIZE {
-            return (StatusCode::PAYLOAD_TOO_LARGE, "Request body exceeds maximum size of 10MB").into_response();
+    let body = match payload.modified_body {
+        Some(body) if body.len() > MAX_BODY_SIZE => {
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "Request body exceeds maximum size of 10MB",
+            )
+                .into_response();
         }
-        bytes
-    } else {
-        entry.request.body.clone()
+        Some(body) => body,
+        None => String::from_utf8_lossy(&entry.request.body).into_owned(),
     };
-
-    let method_str = payload.method.as_deref().unwrap_or(&entry.request.method);
-    let method = match method_str.parse::<Method>() {
-        Ok(m) => m,
-        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    let headers = match payload.headers {
+        Some(patches) => patches
+            .into_iter()
+            .filter(|h| !h.name.is_empty())
+            .map(|h| (h.name, h.value))
+            .collect(),
+        None => entry.request.headers.clone(),
     };
-
-    let mut builder = Request::builder().method(method.clone()).uri(uri.clone());
-
-    let header_source: Vec<HeaderPatch> = payload
-        .headers
-        .clone()
-        .unwrap_or_else(|| to_header_patches(&entry.request.headers));
-
-    for pair in &header_source {
-        if pair.name.is_empty() {
-            continue;
-        }
-        if let Ok(header_name) = HeaderName::from_bytes(pair.name.as_bytes()) {
-            builder = builder.header(header_name, &pair.value);
-        }
-    }
-
-    let request_body = ProxyBody::from(Bytes::from(body_bytes.clone()));
-    let request = match builder.body(request_body) {
-        Ok(req) => req,
-        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+    let request = RepeaterRequest {
+        method: payload.method.unwrap_or(entry.request.method),
+        url: payload.url.unwrap_or(entry.request.url),
+        headers,
+        body,
     };
 
-    let client = state.pool.client();
-    let start = std::time::Instant::now();
-    let response = match client.request(request).await {
-        Ok(resp) => resp,
-        Err(err) => {
-            tracing::warn!(%err, "replay request failed");
-            return StatusCode::BAD_GATEWAY.into_response();
-        }
+    let exchange = match dispatch_repeater_request(&state, &request, payload.apply_rules).await {
+        Ok(exchange) => exchange,
+        Err(e) => return e.into_response(),
     };
-    let duration = start.elapsed().as_millis();
-
-    let (parts, body) = response.into_parts();
-    let body_bytes_resp = match body.collect().await {
-        Ok(collected) => collected.to_bytes(),
+    let response = match exchange.outcome {
+        Ok(response) => response,
         Err(err) => {
-            tracing::warn!(%err, "failed to read replay response");
+            tracing::warn!(%err, "replay request failed");
             return StatusCode::BAD_GATEWAY.into_response();
         }
     };
-    let preview_len = body_bytes_resp.len().min(4096);
-    let body_preview = BASE64.encode(&body_bytes_resp[..preview_len]);
-
-    let mut captured_request = CapturedRequest::new(
-        method.to_string(),
-        uri.to_string(),
-        uri.scheme_str() == Some("https"),
-    );
-    captured_request.headers = header_source
-        .iter()
-        .map(|h| (h.name.clone(), h.value.clone()))
-        .collect();
-    captured_request.body = body_bytes.clone();
-    captured_request.timestamp_ms = OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000;
-
-    let response_headers: Vec<(String, String)> = parts
-        .headers
-        .iter()
-        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
-        .collect();
-
-    let captured_response = CapturedResponse {
-        request_id: 0,
-        status_code: parts.status.as_u16(),
-        headers: response_headers.clone(),
-        body: body_bytes_resp.clone().to_vec(),
-        duration_ms: duration,
-    };
-    state
-        .capture
-        .push(captured_request, Some(captured_response));
+    let preview_len = response.body.len().min(4096);
+    let body_preview = BASE64.encode(&response.body.as_bytes()[..preview_len]);
 
     Json(json!({
         "id": id,
-        "status": parts.status.as_u16(),
-        "duration_ms": duration,
+        "status": response.status,
+        "duration_ms": response.time_ms,
         "timestamp_ms": (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000),
-        "headers": response_headers,
+        "headers": response.headers,
         "body_preview": body_preview,
     }))
     .into_response()
@@ -335,20 +366,86 @@ async fn repeat_request(
 async fn export_requests(
     Query(params): Query<ExportParams>,
     Extension(state): Extension<Arc<AppState>>,
-) -> impl IntoResponse {
-    let query: CaptureQuery = params.filters.into();
-    let entries = state.capture.query(&query);
-    build_export_response(entries, params.format)
+) -> Result<Response, ApiError> {
+    let mut query = CaptureQuery::try_from(params.filters)?;
+    query.metadata_only = false;
+    let entries = state.capture.query(&query)?;
+    Ok(build_export_response(entries, params.format).into_response())
 }
 
-fn to_header_patches(headers: &[(String, String)]) -> Vec<HeaderPatch> {
-    headers
-        .iter()
-        .map(|(name, value)| HeaderPatch {
-            name: name.clone(),
-            value: value.clone(),
-        })
-        .collect()
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    /// Detected from the file contents when omitted
+    format: Option<ImportFormat>,
+}
+
+/// Import traffic recorded by another tool, keeping the original timestamps
+async fn import_requests(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<ImportParams>,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let capture = state.capture.clone();
+    let ids =
+        tokio::task::spawn_blocking(move || capture.import(import::parse(params.format, &body)?))
+            .await
+            .map_err(|e| ApiError::internal(e.to_string()))??;
+    Ok(Json(json!({ "imported": ids.len(), "ids": ids })))
+}
+
+#[derive(Debug, Deserialize)]
+struct CodeExportParams {
+    #[serde(rename = "as")]
+    format: CodeFormat,
+}
+
+/// Render a captured request as curl, raw HTTP or client code
+async fn export_request_code(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<CodeExportParams>,
+) -> Result<Response, ApiError> {
+    let entry = state
+        .capture
+        .get(id)?
+        .ok_or_else(|| ApiError::not_found(format!("Request {id}")))?;
+    let code = codegen::render(&entry.request, params.format);
+    Ok(([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], code).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+struct ParseCurlPayload {
+    command: String,
+}
+
+/// Turn a pasted curl command into a repeater request
+async fn parse_curl_command(
+    Json(payload): Json<ParseCurlPayload>,
+) -> Result<Json<ManualRequest>, ApiError> {
+    let parsed = codegen::parse_curl(&payload.command)?;
+    let mut headers: HashMap<String, String> = HashMap::new();
+    for (name, value) in parsed.headers {
+        match headers.get_mut(&name) {
+            Some(existing) if name.eq_ignore_ascii_case("cookie") => {
+                existing.push_str("; ");
+                existing.push_str(&value);
+            }
+            Some(existing) => {
+                existing.push_str(", ");
+                existing.push_str(&value);
+            }
+            None => {
+                headers.insert(name, value);
+            }
+        }
+    }
+    Ok(Json(ManualRequest {
+        method: parsed.method,
+        url: parsed.url,
+        headers: (!headers.is_empty()).then_some(headers),
+        body: parsed.body,
+        apply_rules: false,
+    }))
 }
 
 #[derive(Debug, Deserialize, Default)]
@@ -358,19 +455,55 @@ struct ListParams {
     status: Option<u16>,
     tls: Option<bool>,
     search: Option<String>,
+    /// Query-language expression, e.g. `host:api.* status:>=400`
+    q: Option<String>,
+    since: Option<i64>,
+    until: Option<i64>,
+    #[serde(default)]
+    sort: CaptureSort,
+    #[serde(default)]
+    order: SortOrder,
+    highlight: Option<Highlight>,
+    tag: Option<String>,
+    source: Option<CaptureSource>,
+    annotated: Option<bool>,
+    /// Id of the last entry already seen
+    cursor: Option<u64>,
     limit: Option<usize>,
+    /// Return bodies inline instead of just their sizes
+    #[serde(default)]
+    include_bodies: bool,
 }
 
-impl From<ListParams> for CaptureQuery {
-    fn from(value: ListParams) -> Self {
-        CaptureQuery {
+impl TryFrom<ListParams> for CaptureQuery {
+    type Error = ProxyError;
+
+    fn try_from(value: ListParams) -> Result<Self, Self::Error> {
+        let expr = value
+            .q
+            .as_deref()
+            .filter(|q| !q.trim().is_empty())
+            .map(SearchExpr::parse)
+            .transpose()?;
+        Ok(CaptureQuery {
             method: value.method,
             host: value.host,
             status: value.status,
             tls: value.tls,
             search: value.search,
+            expr,
+            since: value.since,
+            until: value.until,
+            sort: value.sort,
+            order: value.order,
+            highlight: value.highlight,
+            tag: value.tag,
+            source: value.source,
+            annotated: value.annotated,
+            cursor: value.cursor,
             limit: value.limit,
-        }
+            metadata_only: !value.include_bodies,
+        })
     }
 }
 
@@ -426,45 +559,13 @@ fn build_export_response(entries: Vec<CaptureEntry>, format: ExportFormat) -> im
             (headers, w).into_response()
         }
         ExportFormat::Har => {
-            let log_entries: Vec<_> = entries
-                .iter()
-                .map(|entry| {
-                    let started = OffsetDateTime::from_unix_timestamp_nanos(entry.request.timestamp_ms * 1_000_000)
-                        .unwrap_or_else(|_| OffsetDateTime::now_utc());
-                    let started_str = started
-                        .format(&Rfc3339)
-                        .unwrap_or_else(|_| started.to_string());
-                    json!({
-                        "startedDateTime": started_str,
-                        "time": entry.response.as_ref().map(|r| r.duration_ms as u64).unwrap_or(0),
-                        "request": {
-                            "method": entry.request.method,
-                            "url": entry.request.url,
-                            "headers": entry.request.headers,
-                            "bodySize": entry.request.body.len(),
-                        },
-                        "response": {
-                            "status": entry.response.as_ref().map(|r| r.status_code).unwrap_or(0),
-                            "headers": entry.response.as_ref().map(|r| r.headers.clone()).unwrap_or_default(),
-                            "bodySize": entry.response.as_ref().map(|r| r.body.len()).unwrap_or(0),
-                        }
-                    })
-                })
-                .collect();
-            let payload = json!({
-                "log": {
-                    "version": "1.2",
-                    "creator": {"name": "Interceptor", "version": "0.1"},
-                    "entries": log_entries,
-                }
-            });
             let mut headers = HeaderMap::new();
             headers.insert(header::CONTENT_TYPE, "application/json".parse().unwrap());
             headers.insert(
                 header::CONTENT_DISPOSITION,
                 "attachment; filename=interceptor.har".parse().unwrap(),
             );
-            (headers, Json(payload)).into_response()
+            (headers, Json(har::export(&entries))).into_response()
         }
     }
 }
@@ -510,6 +611,54 @@ async fn clear_rules(Extension(state): Extension<Arc<AppState>>) -> impl IntoRes
     StatusCode::NO_CONTENT
 }
 
+async fn rule_hits(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.rules.rule_hits())
+}
+
+async fn reset_rule_hits(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    state.rules.reset_hits();
+    StatusCode::NO_CONTENT
+}
+
+async fn dry_run_request(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> impl IntoResponse {
+    let entry = match state.capture.get(id) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return StatusCode::NOT_FOUND.into_response(),
+        Err(err) => return ApiError::from(err).into_response(),
+    };
+
+    match state.rules.dry_run(&entry) {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "error": e.to_string() })),
+        )
+            .into_response(),
+    }
+}
+
+async fn set_annotation(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+    Json(annotation): Json<Annotation>,
+) -> Result<StatusCode, ApiError> {
+    if state.capture.annotate(id, annotation)? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found(format!("Request {id}")))
+    }
+}
+
+async fn clear_annotation(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<StatusCode, ApiError> {
+    set_annotation(Path(id), Extension(state), Json(Annotation::default())).await
+}
+
 // Scope handlers
 async fn get_scope(
     Extension(state): Extension<Arc<AppState>>,
@@ -519,52 +668,273 @@ async fn get_scope(
 
 async fn set_scope(
     Extension(state): Extension<Arc<AppState>>,
-    Json(config): Json<interceptor_core::scope::ScopeConfig>,
-) -> StatusCode {
+    Json(value): Json<serde_json::Value>,
+) -> Result<StatusCode, ApiError> {
+    let config: ScopeConfig =
+        serde_json::from_value(value).map_err(|e| ProxyError::ScopeInvalid(e.to_string()))?;
     state.scope.set_config(config);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replace the scope from a JSON document or a newline-separated target list
+async fn import_scope(
+    Extension(state): Extension<Arc<AppState>>,
+    body: String,
+) -> Result<Json<ScopeConfig>, ApiError> {
+    let mut config = ScopeConfig::import(&body)?;
+    // Target lists carry no out-of-scope action, so keep the current one
+    if !body.trim_start().starts_with('{') {
+        config.out_of_scope = state.scope.out_of_scope_action();
+    }
+    state.scope.set_config(config.clone());
+    Ok(Json(config))
+}
+
+async fn get_capture_filter(Extension(state): Extension<Arc<AppState>>) -> Json<CaptureFilter> {
+    Json(state.capture.filter())
+}
+
+async fn set_capture_filter(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(filter): Json<CaptureFilter>,
+) -> StatusCode {
+    state.capture.set_filter(filter);
     StatusCode::NO_CONTENT
 }
 
+// Storage handlers
+fn capture_storage(state: &AppState) -> Result<Arc<CaptureStorage>, ApiError> {
+    state
+        .capture
+        .storage()
+        .cloned()
+        .ok_or_else(|| ApiError::not_found("Persistent storage"))
+}
+
+async fn storage_stats(
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<StorageStats>, ApiError> {
+    Ok(Json(capture_storage(&state)?.stats()?))
+}
+
+async fn get_retention(
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<RetentionPolicy>, ApiError> {
+    Ok(Json(capture_storage(&state)?.retention_policy()?))
+}
+
+async fn set_retention(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(policy): Json<RetentionPolicy>,
+) -> Result<StatusCode, ApiError> {
+    capture_storage(&state)?.set_retention_policy(&policy)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Prune now with the saved policy limits, even if the background task is disabled
+async fn prune_storage(
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<PruneReport>, ApiError> {
+    let storage = capture_storage(&state)?;
+    let policy = storage.retention_policy()?;
+    let report = tokio::task::spawn_blocking(move || storage.prune(&policy))
+        .await
+        .map_err(|e| ApiError::internal(e.to_string()))??;
+    Ok(Json(report))
+}
+
+#[derive(Deserialize)]
+struct RotateKeyRequest {
+    passphrase: Option<String>,
+    /// 64 hex characters
+    key: Option<String>,
+    /// Confirms the caller will update the environment variable the key is read from
+    #[serde(default)]
+    update_env: bool,
+}
+
+/// Start re-encrypting stored captures under a new key in the background
+///
+/// A key file is rewritten once the rotation finishes. A key read from the
+/// environment cannot be, so the caller has to confirm they will set it.
+async fn rotate_storage_key(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(req): Json<RotateKeyRequest>,
+) -> Result<(StatusCode, Json<serde_json::Value>), ApiError> {
+    let storage = capture_storage(&state)?;
+    let new_key = match (req.passphrase, req.key) {
+        (Some(passphrase), None) if !passphrase.is_empty() => NewKey::Passphrase(passphrase),
+        (None, Some(key)) => NewKey::Key(
+            EncryptionKeyProvider::from_hex(&key)
+                .map_err(|e| ApiError::bad_request(e.to_string()))?,
+        ),
+        _ => return Err(ApiError::bad_request("Provide either passphrase or key")),
+    };
+    if storage.is_rotating_key() {
+        return Err(ProxyError::AlreadyExists("key rotation in progress".to_string()).into());
+    }
+    let destination = match storage.key_source() {
+        KeySource::File(path) => json!({ "key_file": path }),
+        KeySource::Env(_) => {
+            let var = match new_key {
+                NewKey::Passphrase(_) => PASSPHRASE_ENV,
+                NewKey::Key(_) => KEY_ENV,
+            };
+            if !req.update_env {
+                return Err(ApiError::bad_request(format!(
+                    "The new key must be set in {} before restarting; confirm with update_env",
+                    var
+                )));
+            }
+            json!({ "update_env": var })
+        }
+        KeySource::Explicit => json!({}),
+    };
+    tokio::task::spawn_blocking(move || match storage.rotate_key(new_key) {
+        Ok(report) => tracing::info!(captures = report.captures, "Storage key rotation finished"),
+        Err(e) => tracing::error!("Storage key rotation failed: {}", e),
+    });
+    Ok((StatusCode::ACCEPTED, Json(destination)))
+}
+
+// Hosts override handlers
+async fn list_hosts(Extension(state): Extension<Arc<AppState>>) -> Json<Vec<HostEntry>> {
+    Json(state.pool.hosts().entries())
+}
+
+async fn replace_hosts(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(entries): Json<Vec<HostEntry>>,
+) -> Result<StatusCode, ApiError> {
+    state.pool.hosts().replace(entries)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_host(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(entry): Json<HostEntry>,
+) -> Result<StatusCode, ApiError> {
+    if state.pool.hosts().set(&entry.host, entry.ip)? {
+        Ok(StatusCode::CREATED)
+    } else {
+        Ok(StatusCode::OK)
+    }
+}
+
+async fn remove_host(
+    Path(host): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> StatusCode {
+    if state.pool.hosts().remove(&host) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
+// Network simulation handlers
+async fn list_network_profiles(
+    Extension(state): Extension<Arc<AppState>>,
+) -> Json<Vec<NetworkProfile>> {
+    Json(state.network.get_profiles())
+}
+
+async fn replace_network_profiles(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(profiles): Json<Vec<NetworkProfile>>,
+) -> Result<StatusCode, ApiError> {
+    state.network.set_profiles(profiles)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn upsert_network_profile(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(profile): Json<NetworkProfile>,
+) -> Result<StatusCode, ApiError> {
+    state.network.upsert_profile(profile)?;
+    Ok(StatusCode::CREATED)
+}
+
+async fn remove_network_profile(
+    Path(id): Path<String>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> StatusCode {
+    if state.network.remove_profile(&id) {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::NOT_FOUND
+    }
+}
+
 // Intruder handlers
+
+/// Generated requests returned by the preview endpoint
+const INTRUDER_PREVIEW_LIMIT: usize = 1000;
+
 async fn intruder_generate(
     Extension(state): Extension<Arc<AppState>>,
     Json(req): Json<IntruderGenerateRequest>,
-) -> impl IntoResponse {
-    match state.intruder.generate_requests(&req.template, &req.config) {
-        Ok(requests) => {
-            // Only return request strings for preview, not payloads
-            let request_strings: Vec<String> = requests.into_iter().map(|(r, _)| r).collect();
-            Json(json!({ "requests": request_strings })).into_response()
-        }
-        Err(err) => (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({ "error": err.to_string() })),
-        )
-            .into_response(),
+) -> Result<Json<serde_json::Value>, ApiError> {
+    // Compiling a wordlist set reads the file, so keep it off the async workers
+    let intruder = state.intruder.clone();
+    let request_strings = tokio::task::spawn_blocking(move || {
+        intruder
+            .generate_requests(&req.template, &req.config)
+            .map(|requests| {
+                // Only return request strings for preview, not payloads
+                requests
+                    .take(INTRUDER_PREVIEW_LIMIT)
+                    .map(|(r, _)| r)
+                    .collect::<Vec<String>>()
+            })
+    })
+    .await
+    .map_err(|e| ApiError::internal(e.to_string()))?
+    .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    Ok(Json(json!({ "requests": request_strings })))
+}
+
+/// Results of the most recent attack
+async fn intruder_results(
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<ResultQuery>,
+) -> Result<Json<Vec<IntruderResult>>, ApiError> {
+    match state.intruder.latest() {
+        Some(id) => attack_results(Path(id), Extension(state), Query(query)).await,
+        None => Ok(Json(Vec::new())),
     }
 }
 
-async fn intruder_results(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
-    Json(state.intruder.get_results())
+/// A result of the most recent attack
+async fn intruder_result(
+    Path(request_id): Path<usize>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<IntruderResult>, ApiError> {
+    let id = state
+        .intruder
+        .latest()
+        .ok_or_else(|| ApiError::not_found(format!("Intruder result {request_id}")))?;
+    attack_result(Path((id, request_id)), Extension(state)).await
 }
 
 async fn intruder_clear(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
-    state.intruder.clear_results();
+    state.intruder.clear_finished();
     StatusCode::NO_CONTENT
 }
 
+/// Queue an attack; progress is streamed over the WebSocket
 async fn intruder_start(
     Extension(state): Extension<Arc<AppState>>,
     Json(req): Json<IntruderGenerateRequest>,
-) -> impl IntoResponse {
-    match state
-        .intruder
-        .start_attack(req.template, req.config, state.pool.clone())
-        .await
-    {
-        Ok(_) => StatusCode::OK.into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response(),
-    }
+) -> Result<Json<AttackProgress>, ApiError> {
+    let intruder = state.intruder.clone();
+    let pool = state.pool.clone();
+    let id =
+        tokio::task::spawn_blocking(move || intruder.start_attack(req.template, req.config, pool))
+            .await
+            .map_err(|e| ApiError::internal(e.to_string()))?
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    Ok(Json(state.intruder.progress(id)?))
 }
 
 async fn intruder_stop(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
@@ -572,6 +942,66 @@ async fn intruder_stop(Extension(state): Extension<Arc<AppState>>) -> impl IntoR
     StatusCode::OK
 }
 
+async fn list_attacks(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.intruder.list())
+}
+
+async fn get_attack(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<AttackProgress>, ApiError> {
+    Ok(Json(state.intruder.progress(id)?))
+}
+
+async fn delete_attack(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<StatusCode, ApiError> {
+    state.intruder.remove(id)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn pause_attack(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<AttackProgress>, ApiError> {
+    Ok(Json(state.intruder.pause(id)?))
+}
+
+async fn resume_attack(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<AttackProgress>, ApiError> {
+    Ok(Json(state.intruder.resume(id)?))
+}
+
+async fn stop_attack(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<AttackProgress>, ApiError> {
+    Ok(Json(state.intruder.stop(id)?))
+}
+
+/// Filtered and sorted results; stored responses are left out of the list
+async fn attack_results(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+    Query(query): Query<ResultQuery>,
+) -> Result<Json<Vec<IntruderResult>>, ApiError> {
+    let mut results = state.intruder.query(id, &query)?;
+    for result in &mut results {
+        result.response = None;
+    }
+    Ok(Json(results))
+}
+
+async fn attack_result(
+    Path((id, request_id)): Path<(u64, usize)>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<IntruderResult>, ApiError> {
+    Ok(Json(state.intruder.get_result(id, request_id)?))
+}
+
 #[derive(Deserialize)]
 struct IntruderGenerateRequest {
     template: String,
@@ -675,70 +1105,224 @@ fn validate_url(url: &str) -> Result<reqwest::Url, String> {
     Ok(parsed)
 }
 
-async fn send_manual_request(Json(payload): Json<ManualRequest>) -> impl IntoResponse {
-    // Validate URL to prevent SSRF
-    let parsed_url = match validate_url(&payload.url) {
-        Ok(url) => url,
-        Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+async fn send_manual_request(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<ManualRequest>,
+) -> impl IntoResponse {
+    let request = RepeaterRequest {
+        method: payload.method,
+        url: payload.url,
+        headers: payload.headers.unwrap_or_default().into_iter().collect(),
+        body: payload.body.unwrap_or_default(),
+    };
+    let exchange = match dispatch_repeater_request(&state, &request, payload.apply_rules).await {
+        Ok(exchange) => exchange,
+        Err(e) => return e.into_response(),
     };
+    match exchange.outcome {
+        Ok(res) => Json(ManualResponse {
+            status: res.status,
+            status_text: res.status_text,
+            headers: res.headers.into_iter().collect(),
+            body: res.body,
+            time_ms: res.time_ms,
+            size_bytes: res.size_bytes,
+        })
+        .into_response(),
+        Err(e) => (StatusCode::BAD_GATEWAY, e).into_response(),
+    }
+}
+
+/// Send a repeater request through the proxy's upstream path, capturing it
+async fn dispatch_repeater_request(
+    state: &AppState,
+    request: &RepeaterRequest,
+    apply_rules: bool,
+) -> Result<Exchange, ApiError> {
+    // Validate URL to prevent SSRF
+    validate_url(&request.url).map_err(ApiError::bad_request)?;
 
-    // Only allow invalid certs in development mode
-    let allow_invalid_certs = std::env::var("INTERCEPTOR_DEV_MODE")
-        .map(|v| v == "true" || v == "1")
-        .unwrap_or(false);
+    let client = RepeaterClient::new(
+        state.pool.clone(),
+        state.capture.clone(),
+        state.rules.clone(),
+        state.scope.clone(),
+    )
+    .with_plugins(state.plugin_manager.clone())
+    .with_network(state.network.clone());
+    Ok(client.send(request, apply_rules).await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateTabPayload {
+    name: Option<String>,
+    request: Option<RepeaterRequest>,
+    /// Captured request to copy into the new tab
+    from_request: Option<u64>,
+}
 
-    let client = Client::builder()
-        .danger_accept_invalid_certs(allow_invalid_certs)
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .unwrap_or_default();
+async fn list_repeater_tabs(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
+    Json(state.repeater.list())
+}
 
-    let method = match payload.method.parse::<reqwest::Method>() {
-        Ok(m) => m,
-        Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+async fn create_repeater_tab(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<CreateTabPayload>,
+) -> Result<Json<RepeaterTab>, ApiError> {
+    let request = match payload.from_request {
+        Some(id) => {
+            let entry = state
+                .capture
+                .get(id)?
+                .ok_or_else(|| ApiError::not_found(format!("Request {id}")))?;
+            RepeaterRequest {
+                method: entry.request.method,
+                url: entry.request.url,
+                headers: entry.request.headers,
+                body: String::from_utf8_lossy(&entry.request.body).into_owned(),
+            }
+        }
+        None => payload.request.unwrap_or_else(|| RepeaterRequest {
+            method: "GET".to_string(),
+            ..Default::default()
+        }),
     };
+    Ok(Json(state.repeater.create_tab(payload.name, request)))
+}
 
-    let start = Instant::now();
+async fn get_repeater_tab(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<Json<RepeaterTab>, ApiError> {
+    state
+        .repeater
+        .get(id)
+        .map(Json)
+        .ok_or_else(|| ApiError::not_found(format!("Repeater tab {id}")))
+}
 
-    let mut req_builder = client.request(method, parsed_url);
+#[derive(Debug, Deserialize)]
+struct UpdateTabPayload {
+    name: Option<String>,
+    request: Option<RepeaterRequest>,
+}
 
-    if let Some(headers) = payload.headers {
-        for (k, v) in headers {
-            req_builder = req_builder.header(k, v);
-        }
-    }
+async fn update_repeater_tab(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<UpdateTabPayload>,
+) -> Result<Json<RepeaterTab>, ApiError> {
+    Ok(Json(state.repeater.update_tab(
+        id,
+        payload.name,
+        payload.request,
+    )?))
+}
 
-    if let Some(body) = payload.body {
-        req_builder = req_builder.body(body);
+async fn close_repeater_tab(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+) -> Result<StatusCode, ApiError> {
+    if state.repeater.close_tab(id) {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found(format!("Repeater tab {id}")))
     }
+}
 
-    match req_builder.send().await {
-        Ok(res) => {
-            let status = res.status().as_u16();
-            let status_text = res.status().canonical_reason().unwrap_or("").to_string();
+#[derive(Debug, Deserialize, Default)]
+struct SendTabPayload {
+    /// Defaults to the request currently in the tab
+    request: Option<RepeaterRequest>,
+    /// Run match-and-replace rules on the request and response
+    #[serde(default)]
+    apply_rules: bool,
+}
 
-            let mut headers = std::collections::HashMap::new();
-            for (k, v) in res.headers() {
-                headers.insert(k.to_string(), v.to_str().unwrap_or("").to_string());
-            }
+/// Send from a tab and append the exchange to its history
+async fn send_repeater_tab(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+    payload: Option<Json<SendTabPayload>>,
+) -> Result<Json<HistoryItem>, ApiError> {
+    let Json(payload) = payload.unwrap_or_default();
+    let request = match payload.request {
+        Some(request) => request,
+        None => {
+            state
+                .repeater
+                .get(id)
+                .ok_or_else(|| ApiError::not_found(format!("Repeater tab {id}")))?
+                .request
+        }
+    };
+    let exchange = dispatch_repeater_request(&state, &request, payload.apply_rules).await?;
+    Ok(Json(state.repeater.record(id, request, exchange)?))
+}
 
-            let body_bytes = res.bytes().await.unwrap_or_default();
-            let size_bytes = body_bytes.len();
-            let body = String::from_utf8_lossy(&body_bytes).to_string();
-            let time_ms = start.elapsed().as_millis() as u64;
-
-            Json(ManualResponse {
-                status,
-                status_text,
-                headers,
-                body,
-                time_ms,
-                size_bytes,
-            })
-            .into_response()
+#[derive(Debug, Deserialize)]
+struct TabDiffParams {
+    left: u64,
+    right: u64,
+    #[serde(default)]
+    target: DiffTarget,
+    mode: Option<CompareMode>,
+}
+
+/// Diff two history items of a tab
+async fn diff_repeater_tab(
+    Path(id): Path<u64>,
+    Extension(state): Extension<Arc<AppState>>,
+    Query(params): Query<TabDiffParams>,
+) -> Result<Json<CompareResponse>, ApiError> {
+    let mode = params.mode.unwrap_or(CompareMode::Lines);
+    Ok(Json(state.repeater.diff(
+        id,
+        params.left,
+        params.right,
+        params.target,
+        mode,
+    )?))
+}
+
+#[derive(Debug, Deserialize)]
+struct RacePayload {
+    /// Request to send `count` identical copies of
+    request: Option<RepeaterRequest>,
+    count: Option<usize>,
+    /// Distinct requests to race, used instead of `request` when present
+    variants: Option<Vec<RepeaterRequest>>,
+    #[serde(default)]
+    mode: RaceMode,
+}
+
+/// Release a set of requests at the same moment to probe for race conditions
+async fn race_requests(
+    Extension(state): Extension<Arc<AppState>>,
+    Json(payload): Json<RacePayload>,
+) -> Result<Json<RaceReport>, ApiError> {
+    let requests = match (payload.variants, payload.request) {
+        (Some(variants), _) => variants,
+        (None, Some(request)) => {
+            let count = payload.count.unwrap_or(2).min(MAX_RACE_REQUESTS + 1);
+            vec![request; count]
         }
-        Err(e) => (StatusCode::BAD_GATEWAY, format!("Request failed: {}", e)).into_response(),
+        (None, None) => {
+            return Err(ApiError::bad_request(
+                "Provide a request or variants to race",
+            ));
+        }
+    };
+    for request in &requests {
+        validate_url(&request.url).map_err(ApiError::bad_request)?;
     }
+
+    let client = RaceClient::new(
+        state.pool.clone(),
+        state.capture.clone(),
+        state.scope.clone(),
+    );
+    Ok(Json(client.race(payload.mode, requests).await?))
 }
 
 async fn get_settings(Extension(state): Extension<Arc<AppState>>) -> impl IntoResponse {
@@ -862,7 +1446,6 @@ async fn comparer_diff(Json(req): Json<CompareRequest>) -> impl IntoResponse {
 #[derive(Deserialize)]
 struct ProjectSaveRequest {
     path: String,
-    scope: Vec<String>,
 }
 
 #[derive(Deserialize)]
@@ -886,15 +1469,23 @@ async fn project_save(
     Json(req): Json<ProjectSaveRequest>,
 ) -> impl IntoResponse {
     let settings = serde_json::to_value(&*state.settings.read().await).unwrap_or_default();
-    match state.project_manager.export(req.scope, settings) {
-        Ok(data) => match data.save_to_file(&req.path) {
-            Ok(_) => StatusCode::OK.into_response(),
-            Err(e) => (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(json!({ "error": e.to_string() })),
-            )
-                .into_response(),
-        },
+    match state
+        .project_manager
+        .export(state.scope.get_config(), settings)
+    {
+        Ok(mut data) => {
+            data.hosts = state.pool.hosts().entries();
+            data.repeater = state.repeater.snapshot();
+            data.intruder = state.intruder.snapshot();
+            match data.save_to_file(&req.path) {
+                Ok(_) => StatusCode::OK.into_response(),
+                Err(e) => (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({ "error": e.to_string() })),
+                )
+                    .into_response(),
+            }
+        }
         Err(e) => (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(json!({ "error": e.to_string() })),
@@ -909,13 +1500,17 @@ async fn project_load(
 ) -> impl IntoResponse {
     match interceptor_core::project::ProjectData::load_from_file(&req.path) {
         Ok(data) => {
+            // Restore hosts overrides first: it is the only section that can be
+            // rejected, and it is replaced all at once, so a bad one changes nothing
+            if let Err(e) = state.pool.hosts().replace(data.hosts.clone()) {
+                return ApiError::from(e).into_response();
+            }
+
             // Restore scope
-            state
-                .scope
-                .set_config(interceptor_core::scope::ScopeConfig {
-                    includes: data.scope.clone(),
-                    excludes: vec![], // TODO: Save excludes in project data
-                });
+            state.scope.set_config(data.scope.clone());
+
+            state.repeater.restore(data.repeater.clone());
+            state.intruder.restore(data.intruder.clone());
 
             // Restore settings
             if let Ok(settings) = serde_json::from_value(data.settings.clone()) {
@@ -960,7 +1555,9 @@ async fn project_new(
     state
         .scope
         .set_config(interceptor_core::scope::ScopeConfig::default());
-    state.intruder.clear_results();
+    state.pool.hosts().clear();
+    state.repeater.clear();
+    state.intruder.clear();
     state.ws_capture.clear();
     state.scanner.clear_findings();
 
@@ -1,7 +1,7 @@
 use interceptor_core::{
     capture::RequestCapture, cert_manager::CertManager, connection_pool::ConnectionPool,
-    plugin::manager::PluginManager, rules::RuleEngine, Intruder, ProjectManager, Scanner,
-    ScopeManager, WsCapture,
+    plugin::manager::PluginManager, rules::RuleEngine, Intruder, NetworkConditions, ProjectManager,
+    Repeater, Scanner, ScopeManager, WsCapture,
 };
 use std::sync::Arc;
 
@@ -18,10 +18,12 @@ pub struct AppState {
     pub pool: ConnectionPool,
     pub rules: Arc<RuleEngine>,
     pub scope: Arc<ScopeManager>,
+    pub network: Arc<NetworkConditions>,
     pub intruder: Arc<Intruder>,
     pub scanner: Arc<Scanner>,
     pub ws_capture: Arc<WsCapture>,
     pub project_manager: Arc<ProjectManager>,
+    pub repeater: Arc<Repeater>,
     pub api_token: Option<Arc<String>>,
     pub max_body_bytes: usize,
     pub max_concurrency: usize,
@@ -7,6 +7,7 @@ use tokio::sync::broadcast::error::RecvError;
 
 use crate::state::AppState;
 use interceptor_core::capture::CaptureEntry;
+use interceptor_core::intruder::AttackProgress;
 use std::sync::Arc;
 
 pub async fn ws_route(
@@ -14,10 +15,15 @@ pub async fn ws_route(
     Extension(state): Extension<Arc<AppState>>,
 ) -> impl IntoResponse {
     let receiver = state.capture.subscribe();
-    ws.on_upgrade(move |socket| handle_socket(socket, receiver))
+    let attacks = state.intruder.subscribe();
+    ws.on_upgrade(move |socket| handle_socket(socket, receiver, attacks))
 }
 
-async fn handle_socket(mut socket: WebSocket, mut receiver: broadcast::Receiver<CaptureEntry>) {
+async fn handle_socket(
+    mut socket: WebSocket,
+    mut receiver: broadcast::Receiver<CaptureEntry>,
+    mut attacks: broadcast::Receiver<AttackProgress>,
+) {
     let welcome = json!({
         "type": "hello",
         "data": "connected",
@@ -31,12 +37,18 @@ async fn handle_socket(mut socket: WebSocket, mut receiver: broadcast::Receiver<
     }
 
     loop {
-        match receiver.recv().await {
-            Ok(entry) => {
-                let payload = json!({
-                    "type": "request",
-                    "data": entry,
-                });
+        let payload = tokio::select! {
+            entry = receiver.recv() => entry.map(|entry| json!({
+                "type": "request",
+                "data": entry,
+            })),
+            progress = attacks.recv() => progress.map(|progress| json!({
+                "type": "intruder_progress",
+                "data": progress,
+            })),
+        };
+        match payload {
+            Ok(payload) => {
                 if socket
                     .send(Message::Text(payload.to_string()))
                     .await
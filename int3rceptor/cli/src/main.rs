@@ -11,19 +11,20 @@
 // ║  License: https://github.com/S1b-Team/int3rceptor/blob/main/LICENSE      ║
 // ╚═══════════════════════════════════════════════════════════════════════════╝
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use interceptor_api::audit::AuditLogger;
 use interceptor_api::csrf::CsrfProtection;
 use interceptor_api::ip_filter::{IpFilter, IpFilterConfig};
 use interceptor_api::models::{AppSettings, ProxyConfig, UiConfig};
 use interceptor_core::connection_pool::ConnectionPool;
+use interceptor_core::import::{self, ImportFormat};
 use interceptor_core::plugin::config::PluginSystemConfig;
 use interceptor_core::plugin::manager::PluginManager;
 use interceptor_core::proxy::ProxyServer;
 use interceptor_core::tls::TlsInterceptor;
 use interceptor_core::{
     capture::RequestCapture, cert_manager::CertManager, rules::RuleEngine, storage::CaptureStorage,
-    Intruder, ProjectManager, Scanner, ScopeManager, WsCapture,
+    Intruder, NetworkConditions, ProjectManager, Scanner, ScopeManager, WsCapture,
 };
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -50,6 +51,19 @@ struct Cli {
     export_ca: Option<PathBuf>,
     #[arg(short, long, default_value = "info")]
     verbosity: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Import traffic from HAR, Burp XML, mitmproxy or pcap files into the capture database
+    Import {
+        file: PathBuf,
+        /// har, burp, mitmproxy or pcap; detected from the contents when omitted
+        #[arg(short, long)]
+        format: Option<ImportFormat>,
+    },
 }
 
 #[tokio::main]
@@ -103,7 +117,14 @@ async fn main() -> anyhow::Result<()> {
     let db_path =
         std::env::var("INTERCEPTOR_DB_PATH").unwrap_or_else(|_| "data/interceptor.sqlite".into());
     let storage = Arc::new(CaptureStorage::new(db_path)?);
-    let capture = Arc::new(RequestCapture::with_storage(10_000, Some(storage.clone())));
+    storage.clone().spawn_retention();
+    let capture = Arc::new(RequestCapture::with_storage(10_000, Some(storage.clone()))?);
+    if let Some(Command::Import { file, format }) = &cli.command {
+        let data = std::fs::read(file)?;
+        let ids = capture.import(import::parse(*format, &data)?)?;
+        println!("Imported {} requests from {}", ids.len(), file.display());
+        return Ok(());
+    }
     let cert_manager = Arc::new(CertManager::new()?);
     let rules = Arc::new(RuleEngine::new());
     info!("Initialized RuleEngine");
@@ -111,7 +132,17 @@ async fn main() -> anyhow::Result<()> {
     let scope = Arc::new(ScopeManager::new());
     info!("Initialized ScopeManager");
 
-    let intruder = Arc::new(Intruder::new());
+    let network = Arc::new(NetworkConditions::new());
+
+    let intruder = Arc::new(
+        Intruder::new()
+            .with_capture(capture.clone())
+            .with_wordlist_dir(
+                std::env::var("INTERCEPTOR_WORDLIST_DIR")
+                    .unwrap_or_else(|_| "data/wordlists".into()),
+            )
+            .with_max_rps(license_tier.max_rps()),
+    );
     info!("Initialized Intruder");
 
     let ws_capture = Arc::new(WsCapture::new(10_000));
@@ -195,16 +226,21 @@ async fn main() -> anyhow::Result<()> {
     }
     let license_manager = Arc::new(license_manager);
 
+    // One pool for proxy and API so hosts overrides apply to both
+    let pool = ConnectionPool::new();
+
     let api_state = interceptor_api::state::AppState {
         capture: capture.clone(),
         cert_manager: cert_manager.clone(),
-        pool: ConnectionPool::new(),
+        pool: pool.clone(),
         rules: rules.clone(),
         scope: scope.clone(),
+        network: network.clone(),
         intruder: intruder.clone(),
         scanner: scanner.clone(),
         ws_capture: ws_capture.clone(),
         project_manager,
+        repeater: Arc::new(interceptor_core::Repeater::new()),
         api_token,
         max_body_bytes,
         max_concurrency,
@@ -226,7 +262,9 @@ async fn main() -> anyhow::Result<()> {
         Some(tls),
         Some(plugin_manager),
         Some(scanner),
-    );
+    )
+    .with_pool(pool)
+    .with_network(network);
     let proxy_task = tokio::spawn(async move { proxy.run().await });
 
     let (api_res, proxy_res) = tokio::try_join!(api_task, proxy_task)?;
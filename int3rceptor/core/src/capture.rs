@@ -1,3 +1,5 @@
+use crate::error::{ProxyError, Result};
+use crate::search::SearchExpr;
 use crate::storage::CaptureStorage;
 use parking_lot::RwLock;
 use serde::{Deserialize, Serialize};
@@ -8,6 +10,8 @@ use time::OffsetDateTime;
 use tokio::sync::broadcast;
 
 const DEFAULT_CAPACITY: usize = 10_000;
+const MAX_TAG_LEN: usize = 64;
+const MAX_NOTES_LEN: usize = 64 * 1024;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CapturedRequest {
@@ -18,6 +22,8 @@ pub struct CapturedRequest {
     pub headers: Vec<(String, String)>,
     pub body: Vec<u8>,
     pub tls: bool,
+    #[serde(default)]
+    pub source: CaptureSource,
 }
 
 impl CapturedRequest {
@@ -30,10 +36,40 @@ impl CapturedRequest {
             headers: Vec::new(),
             body: Vec::new(),
             tls,
+            source: CaptureSource::Proxy,
         }
     }
 }
 
+/// Where a captured exchange came from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureSource {
+    #[default]
+    Proxy,
+    Repeater,
+    Import,
+}
+
+impl CaptureSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CaptureSource::Proxy => "proxy",
+            CaptureSource::Repeater => "repeater",
+            CaptureSource::Import => "import",
+        }
+    }
+}
+
+impl std::str::FromStr for CaptureSource {
+    type Err = ProxyError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_ascii_lowercase()))
+            .map_err(|_| ProxyError::invalid_config("source", format!("unknown source: {s}")))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CapturedResponse {
     pub request_id: u64,
@@ -47,6 +83,127 @@ pub struct CapturedResponse {
 pub struct CaptureEntry {
     pub request: CapturedRequest,
     pub response: Option<CapturedResponse>,
+    #[serde(default)]
+    pub annotation: Annotation,
+    /// Set when bodies were left out of a metadata-only query
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub omitted_bodies: Option<BodySizes>,
+}
+
+/// Sizes of bodies that were not loaded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BodySizes {
+    pub request: u64,
+    pub response: u64,
+}
+
+/// Highlight colors for marking entries in the history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Highlight {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Cyan,
+    Blue,
+    Purple,
+    Pink,
+    Gray,
+}
+
+impl Highlight {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Highlight::Red => "red",
+            Highlight::Orange => "orange",
+            Highlight::Yellow => "yellow",
+            Highlight::Green => "green",
+            Highlight::Cyan => "cyan",
+            Highlight::Blue => "blue",
+            Highlight::Purple => "purple",
+            Highlight::Pink => "pink",
+            Highlight::Gray => "gray",
+        }
+    }
+}
+
+impl std::str::FromStr for Highlight {
+    type Err = ProxyError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        serde_json::from_value(serde_json::Value::String(s.to_ascii_lowercase()))
+            .map_err(|_| ProxyError::invalid_config("highlight", format!("unknown color: {s}")))
+    }
+}
+
+/// Tester markup on a captured entry
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    #[serde(default)]
+    pub highlight: Option<Highlight>,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        self.highlight.is_none() && self.notes.is_empty() && self.tags.is_empty()
+    }
+
+    /// Validate, lowercasing and de-duplicating tags
+    pub fn normalize(mut self) -> Result<Self> {
+        if self.notes.len() > MAX_NOTES_LEN {
+            return Err(ProxyError::invalid_config("notes", "notes are too long"));
+        }
+        let mut tags = Vec::with_capacity(self.tags.len());
+        for tag in &self.tags {
+            let tag = tag.trim().to_lowercase();
+            if tag.is_empty() || tag.len() > MAX_TAG_LEN || tag.chars().any(char::is_control) {
+                return Err(ProxyError::invalid_config(
+                    "tags",
+                    format!("invalid tag: {tag:?}"),
+                ));
+            }
+            if !tags.contains(&tag) {
+                tags.push(tag);
+            }
+        }
+        tags.sort();
+        self.tags = tags;
+        Ok(self)
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag.trim()))
+    }
+}
+
+impl CaptureEntry {
+    /// Request plus response body size
+    pub fn size_bytes(&self) -> usize {
+        match self.omitted_bodies {
+            Some(sizes) => (sizes.request + sizes.response) as usize,
+            None => self.request.body.len() + self.response.as_ref().map_or(0, |r| r.body.len()),
+        }
+    }
+
+    /// Drop the bodies, keeping their sizes
+    pub fn without_bodies(mut self) -> Self {
+        if self.omitted_bodies.is_none() {
+            self.omitted_bodies = Some(BodySizes {
+                request: self.request.body.len() as u64,
+                response: self.response.as_ref().map_or(0, |r| r.body.len() as u64),
+            });
+            self.request.body = Vec::new();
+            if let Some(response) = self.response.as_mut() {
+                response.body = Vec::new();
+            }
+        }
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,6 +222,95 @@ pub struct ActivityQuery {
     pub level: Option<String>,
 }
 
+/// Decides which completed exchanges are worth keeping (skips static assets)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureFilter {
+    pub enabled: bool,
+    /// File extensions (without the dot) that are never captured
+    #[serde(default)]
+    pub skip_extensions: Vec<String>,
+    /// Response MIME type prefixes that are never captured, e.g. `image/`
+    #[serde(default)]
+    pub skip_mime_types: Vec<String>,
+}
+
+impl Default for CaptureFilter {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            skip_extensions: [
+                "png", "jpg", "jpeg", "gif", "bmp", "ico", "svg", "webp", "avif", "woff", "woff2",
+                "ttf", "otf", "eot", "mp3", "mp4", "webm", "ogg", "wav", "css", "map",
+            ]
+            .iter()
+            .map(|s| s.to_string())
+            .collect(),
+            skip_mime_types: ["image/", "font/", "audio/", "video/", "text/css"]
+                .iter()
+                .map(|s| s.to_string())
+                .collect(),
+        }
+    }
+}
+
+impl CaptureFilter {
+    pub fn should_capture(
+        &self,
+        request: &CapturedRequest,
+        response: Option<&CapturedResponse>,
+    ) -> bool {
+        if !self.enabled {
+            return true;
+        }
+
+        if let Some(ext) = url_extension(&request.url) {
+            if self
+                .skip_extensions
+                .iter()
+                .any(|e| e.eq_ignore_ascii_case(&ext))
+            {
+                return false;
+            }
+        }
+
+        let content_type = response.and_then(|r| {
+            r.headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case("content-type"))
+                .map(|(_, v)| {
+                    v.split(';')
+                        .next()
+                        .unwrap_or_default()
+                        .trim()
+                        .to_ascii_lowercase()
+                })
+        });
+        if let Some(content_type) = content_type {
+            if self
+                .skip_mime_types
+                .iter()
+                .any(|m| content_type.starts_with(&m.to_ascii_lowercase()))
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Lowercased extension of the last path segment, ignoring query and fragment
+fn url_extension(url: &str) -> Option<String> {
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let path = path.split_once("://").map(|(_, rest)| rest).unwrap_or(path);
+    let segment = path.split_once('/').map(|(_, p)| p)?.rsplit('/').next()?;
+    let (_, ext) = segment.rsplit_once('.')?;
+    if ext.is_empty() {
+        return None;
+    }
+    Some(ext.to_ascii_lowercase())
+}
+
 #[derive(Debug)]
 pub struct RequestCapture {
     capacity: usize,
@@ -74,36 +320,47 @@ pub struct RequestCapture {
     storage: Option<Arc<CaptureStorage>>,
     activities: RwLock<VecDeque<DashboardActivity>>,
     activity_counter: AtomicU64,
+    filter: RwLock<CaptureFilter>,
 }
 
 impl RequestCapture {
     pub fn new(capacity: usize) -> Self {
-        Self::with_storage(capacity, None)
+        Self::from_parts(capacity, None, VecDeque::new(), 0)
     }
 
-    pub fn with_storage(capacity: usize, storage: Option<Arc<CaptureStorage>>) -> Self {
-        let (tx, _) = broadcast::channel(1_024);
-
+    /// In-memory history backed by `storage`, preloaded with its newest captures
+    ///
+    /// Fails if the highest stored id cannot be read, since new captures
+    /// would otherwise reuse ids and overwrite stored ones.
+    pub fn with_storage(capacity: usize, storage: Option<Arc<CaptureStorage>>) -> Result<Self> {
         let mut entries = VecDeque::new();
         let mut max_id = 0;
 
         if let Some(store) = &storage {
+            // Imports keep old timestamps, so the newest rows need not hold the highest id
+            max_id = store.max_id()?;
             // Load latest requests
             let query = CaptureQuery {
                 limit: Some(capacity),
                 ..Default::default()
             };
-            if let Ok(loaded) = store.query(&query) {
+            match store.query(&query) {
                 // Query returns DESC order (newest first), so we need to reverse for VecDeque
-                for entry in loaded.into_iter().rev() {
-                    if entry.request.id > max_id {
-                        max_id = entry.request.id;
-                    }
-                    entries.push_back(entry);
-                }
+                Ok(loaded) => entries.extend(loaded.into_iter().rev()),
+                Err(err) => tracing::error!("load_history_error" = %err),
             }
         }
 
+        Ok(Self::from_parts(capacity, storage, entries, max_id))
+    }
+
+    fn from_parts(
+        capacity: usize,
+        storage: Option<Arc<CaptureStorage>>,
+        entries: VecDeque<CaptureEntry>,
+        max_id: u64,
+    ) -> Self {
+        let (tx, _) = broadcast::channel(1_024);
         Self {
             capacity: if capacity == 0 {
                 DEFAULT_CAPACITY
@@ -116,9 +373,32 @@ impl RequestCapture {
             storage,
             activities: RwLock::new(VecDeque::new()),
             activity_counter: AtomicU64::new(1),
+            filter: RwLock::new(CaptureFilter::default()),
         }
     }
 
+    /// Persistent store behind the in-memory history, if any
+    pub fn storage(&self) -> Option<&Arc<CaptureStorage>> {
+        self.storage.as_ref()
+    }
+
+    pub fn filter(&self) -> CaptureFilter {
+        self.filter.read().clone()
+    }
+
+    pub fn set_filter(&self, filter: CaptureFilter) {
+        *self.filter.write() = filter;
+    }
+
+    /// Whether an exchange passes the capture filter
+    pub fn should_capture(
+        &self,
+        request: &CapturedRequest,
+        response: Option<&CapturedResponse>,
+    ) -> bool {
+        self.filter.read().should_capture(request, response)
+    }
+
     pub fn push(
         &self,
         mut request: CapturedRequest,
@@ -129,7 +409,52 @@ impl RequestCapture {
         if let Some(resp) = response.as_mut() {
             resp.request_id = id;
         }
-        let entry = CaptureEntry { request, response };
+        let entry = CaptureEntry {
+            request,
+            response,
+            annotation: Annotation::default(),
+            omitted_bodies: None,
+        };
+        self.remember(entry.clone());
+        if let Some(storage) = &self.storage {
+            storage.enqueue(entry);
+        }
+        id
+    }
+
+    /// Add entries recorded elsewhere, keeping their timestamps and annotations
+    ///
+    /// Entries get fresh ids so they never overwrite existing captures. They
+    /// are written to storage before returning rather than queued, since a
+    /// large import would overflow the writer queue.
+    pub fn import(&self, entries: Vec<CaptureEntry>) -> Result<Vec<u64>> {
+        let mut entries = entries
+            .into_iter()
+            .map(|mut entry| {
+                entry.annotation = std::mem::take(&mut entry.annotation).normalize()?;
+                entry.omitted_bodies = None;
+                Ok(entry)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for entry in &mut entries {
+            let id = self.counter.fetch_add(1, Ordering::SeqCst);
+            entry.request.id = id;
+            if let Some(resp) = entry.response.as_mut() {
+                resp.request_id = id;
+            }
+        }
+        if let Some(storage) = &self.storage {
+            storage.insert_many(&entries)?;
+        }
+        let ids = entries.iter().map(|entry| entry.request.id).collect();
+        for entry in entries {
+            self.remember(entry);
+        }
+        Ok(ids)
+    }
+
+    /// Add to the in-memory history and notify subscribers
+    fn remember(&self, entry: CaptureEntry) {
         let notify = entry.clone();
         let mut guard = self.entries.write();
         guard.push_front(entry);
@@ -137,18 +462,44 @@ impl RequestCapture {
             guard.pop_back();
         }
         drop(guard);
-        let _ = self.notifier.send(notify.clone());
-        if let Some(storage) = &self.storage {
-            if let Err(err) = storage.insert(&notify) {
-                tracing::warn!("persist_capture_error" = %err);
-            }
-        }
-        id
+        let _ = self.notifier.send(notify);
     }
 
-    pub fn get(&self, id: u64) -> Option<CaptureEntry> {
+    /// Look up an entry, falling back to storage for ones evicted from memory
+    ///
+    /// Storage errors such as a key mismatch are returned rather than treated
+    /// as a missing entry.
+    pub fn get(&self, id: u64) -> Result<Option<CaptureEntry>> {
         let guard = self.entries.read();
-        guard.iter().find(|item| item.request.id == id).cloned()
+        if let Some(entry) = guard.iter().find(|item| item.request.id == id) {
+            return Ok(Some(entry.clone()));
+        }
+        drop(guard);
+        // Older entries may only be on disk
+        match &self.storage {
+            Some(storage) => storage.get(id),
+            None => Ok(None),
+        }
+    }
+
+    /// Replace the annotation on an entry; returns false if the id is unknown
+    pub fn annotate(&self, id: u64, annotation: Annotation) -> Result<bool> {
+        let annotation = annotation.normalize()?;
+        let mut found = false;
+        if let Some(entry) = self
+            .entries
+            .write()
+            .iter_mut()
+            .find(|entry| entry.request.id == id)
+        {
+            entry.annotation = annotation.clone();
+            found = true;
+        }
+        // Entries evicted from memory may still be in storage
+        if let Some(storage) = &self.storage {
+            found |= storage.set_annotation(id, &annotation)?;
+        }
+        Ok(found)
     }
 
     pub fn get_all(&self) -> Vec<CaptureEntry> {
@@ -176,17 +527,47 @@ impl RequestCapture {
         self.notifier.subscribe()
     }
 
-    pub fn query(&self, filter: &CaptureQuery) -> Vec<CaptureEntry> {
+    pub fn query(&self, filter: &CaptureQuery) -> Result<Vec<CaptureEntry>> {
         if let Some(storage) = &self.storage {
-            return storage.query(filter).unwrap_or_default();
+            return storage.query(filter);
         }
         let guard = self.entries.read();
-        guard
+        let cursor = match filter.cursor {
+            Some(id) => match guard.iter().find(|entry| entry.request.id == id) {
+                Some(entry) => Some((filter.sort.key(entry), id)),
+                // Unknown cursor: nothing comes after it
+                None => return Ok(Vec::new()),
+            },
+            None => None,
+        };
+        let mut matched: Vec<&CaptureEntry> = guard
             .iter()
             .filter(|entry| filter.matches(entry))
+            .filter(|entry| {
+                cursor.is_none_or(|cursor| {
+                    let key = (filter.sort.key(entry), entry.request.id);
+                    match filter.order {
+                        SortOrder::Asc => key > cursor,
+                        SortOrder::Desc => key < cursor,
+                    }
+                })
+            })
+            .collect();
+        matched.sort_by_key(|entry| (filter.sort.key(entry), entry.request.id));
+        if filter.order == SortOrder::Desc {
+            matched.reverse();
+        }
+        Ok(matched
+            .into_iter()
             .take(filter.limit.unwrap_or(guard.len()))
-            .cloned()
-            .collect()
+            .map(|entry| {
+                if filter.metadata_only {
+                    entry.clone().without_bodies()
+                } else {
+                    entry.clone()
+                }
+            })
+            .collect())
     }
 
     // Activity log methods
@@ -235,6 +616,40 @@ impl RequestCapture {
     }
 }
 
+/// Field captures are ordered by; ties are broken by id
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureSort {
+    #[default]
+    Time,
+    Duration,
+    Size,
+    Status,
+}
+
+impl CaptureSort {
+    /// Sort key for an entry; a missing response sorts before any value
+    pub fn key(&self, entry: &CaptureEntry) -> i64 {
+        match self {
+            CaptureSort::Time => entry.request.timestamp_ms as i64,
+            CaptureSort::Duration => entry
+                .response
+                .as_ref()
+                .map_or(-1, |r| r.duration_ms.min(i64::MAX as u128) as i64),
+            CaptureSort::Size => entry.size_bytes() as i64,
+            CaptureSort::Status => entry.response.as_ref().map_or(-1, |r| r.status_code as i64),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct CaptureQuery {
     pub method: Option<String>,
@@ -242,11 +657,28 @@ pub struct CaptureQuery {
     pub status: Option<u16>,
     pub tls: Option<bool>,
     pub search: Option<String>,
+    /// Parsed query-language expression, see [`crate::search`]
+    pub expr: Option<SearchExpr>,
+    /// Only entries with `timestamp_ms >= since`
+    pub since: Option<i64>,
+    /// Only entries with `timestamp_ms < until`
+    pub until: Option<i64>,
+    pub sort: CaptureSort,
+    pub order: SortOrder,
+    pub highlight: Option<Highlight>,
+    pub tag: Option<String>,
+    pub source: Option<CaptureSource>,
+    /// Only entries with (or without) any annotation
+    pub annotated: Option<bool>,
+    /// Id of the last entry of the previous page; results continue after it
+    pub cursor: Option<u64>,
     pub limit: Option<usize>,
+    /// Leave bodies out of the results, reporting only their sizes
+    pub metadata_only: bool,
 }
 
 impl CaptureQuery {
-    fn matches(&self, entry: &CaptureEntry) -> bool {
+    pub(crate) fn matches(&self, entry: &CaptureEntry) -> bool {
         if let Some(method) = &self.method {
             if &entry.request.method != method {
                 return false;
@@ -278,6 +710,37 @@ impl CaptureQuery {
                 return false;
             }
         }
+        if let Some(expr) = &self.expr {
+            if !expr.matches(entry) {
+                return false;
+            }
+        }
+        if self.highlight.is_some() && entry.annotation.highlight != self.highlight {
+            return false;
+        }
+        if let Some(tag) = &self.tag {
+            if !entry.annotation.has_tag(tag) {
+                return false;
+            }
+        }
+        if self
+            .source
+            .is_some_and(|source| entry.request.source != source)
+        {
+            return false;
+        }
+        if let Some(annotated) = self.annotated {
+            if entry.annotation.is_empty() == annotated {
+                return false;
+            }
+        }
+        let timestamp = entry.request.timestamp_ms;
+        if self.since.is_some_and(|since| timestamp < since as i128) {
+            return false;
+        }
+        if self.until.is_some_and(|until| timestamp >= until as i128) {
+            return false;
+        }
         true
     }
 }
@@ -320,7 +783,7 @@ mod tests {
         let id = capture.push(req, resp);
         assert!(id > 0);
 
-        let entry = capture.get(id).unwrap();
+        let entry = capture.get(id).unwrap().unwrap();
         assert_eq!(entry.request.method, "POST");
         assert_eq!(entry.response.unwrap().status_code, 201);
     }
@@ -372,7 +835,7 @@ mod tests {
             ..Default::default()
         };
 
-        let results = capture.query(&query);
+        let results = capture.query(&query).unwrap();
         assert_eq!(results.len(), 2);
         assert!(results.iter().all(|e| e.request.method == "GET"));
     }
@@ -399,7 +862,7 @@ mod tests {
             ..Default::default()
         };
 
-        let results = capture.query(&query);
+        let results = capture.query(&query).unwrap();
         assert_eq!(results.len(), 2);
     }
 
@@ -425,7 +888,7 @@ mod tests {
             ..Default::default()
         };
 
-        let results = capture.query(&query);
+        let results = capture.query(&query).unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].response.as_ref().unwrap().status_code, 200);
     }
@@ -449,7 +912,7 @@ mod tests {
             ..Default::default()
         };
 
-        let results = capture.query(&query);
+        let results = capture.query(&query).unwrap();
         assert_eq!(results.len(), 2);
         assert!(results.iter().all(|e| e.request.tls));
     }
@@ -467,7 +930,7 @@ mod tests {
             ..Default::default()
         };
 
-        let results = capture.query(&query);
+        let results = capture.query(&query).unwrap();
         assert_eq!(results.len(), 2);
     }
 
@@ -487,7 +950,7 @@ mod tests {
             ..Default::default()
         };
 
-        let results = capture.query(&query);
+        let results = capture.query(&query).unwrap();
         assert_eq!(results.len(), 3);
     }
 
@@ -515,7 +978,7 @@ mod tests {
             ..Default::default()
         };
 
-        let results = capture.query(&query);
+        let results = capture.query(&query).unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].request.url, "https://api.test.com/users");
     }
@@ -542,4 +1005,243 @@ mod tests {
         assert!(result.is_ok());
         assert_eq!(result.unwrap().request.url, "/notify");
     }
+
+    #[test]
+    fn test_capture_filter_disabled_by_default() {
+        let capture = RequestCapture::new(10);
+        let req = create_test_request("GET", "https://example.com/logo.png", true);
+        assert!(capture.should_capture(&req, None));
+    }
+
+    #[test]
+    fn test_capture_filter_extensions_and_mime() {
+        let filter = CaptureFilter {
+            enabled: true,
+            ..Default::default()
+        };
+
+        let image = create_test_request("GET", "https://example.com/img/Logo.PNG?v=2", true);
+        assert!(!filter.should_capture(&image, None));
+
+        let api = create_test_request("GET", "https://example.com/api/users", true);
+        assert!(filter.should_capture(&api, Some(&create_test_response(200, 1))));
+
+        let mut font = create_test_response(200, 1);
+        font.headers = vec![(
+            "Content-Type".to_string(),
+            "font/woff2; charset=binary".to_string(),
+        )];
+        assert!(!filter.should_capture(&api, Some(&font)));
+
+        // Dots in the host are not extensions
+        let host_only = create_test_request("GET", "https://cdn.example.css", true);
+        assert!(filter.should_capture(&host_only, None));
+    }
+
+    #[test]
+    fn test_capture_query_expression() {
+        let capture = RequestCapture::new(100);
+
+        let mut login = create_test_request("POST", "https://api.example.com/login", true);
+        login.body = b"user=admin&password=hunter2".to_vec();
+        capture.push(login, None);
+        capture.push(
+            create_test_request("GET", "https://www.example.com/", true),
+            None,
+        );
+
+        let query = CaptureQuery {
+            expr: Some(SearchExpr::parse("host:api.* body:password").unwrap()),
+            ..Default::default()
+        };
+        let results = capture.query(&query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].request.method, "POST");
+    }
+
+    #[test]
+    fn test_capture_query_pagination() {
+        let capture = RequestCapture::new(100);
+        for status in [500, 200, 404, 200] {
+            capture.push(
+                create_test_request("GET", "/items", false),
+                Some(create_test_response(status, 1)),
+            );
+        }
+
+        let mut query = CaptureQuery {
+            sort: CaptureSort::Status,
+            order: SortOrder::Asc,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let ids = |entries: Vec<CaptureEntry>| -> Vec<u64> {
+            entries.iter().map(|e| e.request.id).collect()
+        };
+        assert_eq!(ids(capture.query(&query).unwrap()), vec![2, 4]);
+        query.cursor = Some(4);
+        assert_eq!(ids(capture.query(&query).unwrap()), vec![3, 1]);
+        query.cursor = Some(99);
+        assert!(capture.query(&query).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_annotate_and_filter() {
+        let capture = RequestCapture::new(100);
+        let id = capture.push(create_test_request("GET", "/a", false), None);
+        capture.push(create_test_request("GET", "/b", false), None);
+
+        let annotation = Annotation {
+            highlight: Some(Highlight::Yellow),
+            notes: "check later".to_string(),
+            tags: vec![" XSS ".to_string(), "xss".to_string(), "csrf".to_string()],
+        };
+        assert!(capture.annotate(id, annotation).unwrap());
+        assert!(!capture.annotate(999, Annotation::default()).unwrap());
+
+        let entry = capture.get(id).unwrap().unwrap();
+        assert_eq!(entry.annotation.tags, vec!["csrf", "xss"]);
+
+        let query = CaptureQuery {
+            tag: Some("xss".to_string()),
+            highlight: Some(Highlight::Yellow),
+            ..Default::default()
+        };
+        assert_eq!(capture.query(&query).unwrap().len(), 1);
+
+        let query = CaptureQuery {
+            annotated: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(capture.query(&query).unwrap()[0].request.url, "/b");
+    }
+
+    #[test]
+    fn test_metadata_only_query() {
+        let capture = RequestCapture::new(10);
+        let mut request = create_test_request("POST", "/upload", false);
+        request.body = vec![b'x'; 100];
+        let id = capture.push(request, Some(create_test_response(200, 5)));
+
+        let query = CaptureQuery {
+            metadata_only: true,
+            ..Default::default()
+        };
+        let entry = &capture.query(&query).unwrap()[0];
+        assert!(entry.request.body.is_empty());
+        assert_eq!(entry.size_bytes(), 102);
+        let json = serde_json::to_value(entry).unwrap();
+        assert_eq!(json["omitted_bodies"]["request"], 100);
+
+        let full = capture.get(id).unwrap().unwrap();
+        assert_eq!(full.request.body.len(), 100);
+        assert!(serde_json::to_value(&full)
+            .unwrap()
+            .get("omitted_bodies")
+            .is_none());
+    }
+
+    #[test]
+    fn test_import_assigns_fresh_ids() {
+        let capture = RequestCapture::new(10);
+        let existing = capture.push(create_test_request("GET", "/live", false), None);
+
+        let mut request = create_test_request("GET", "/old", false);
+        request.id = existing;
+        request.timestamp_ms = 1_600_000_000_000;
+        let imported = CaptureEntry {
+            request,
+            response: Some(create_test_response(404, 3)),
+            annotation: Annotation {
+                tags: vec![" Imported ".to_string()],
+                ..Default::default()
+            },
+            omitted_bodies: None,
+        };
+        let ids = capture.import(vec![imported]).unwrap();
+        assert_eq!(ids, vec![existing + 1]);
+
+        let entry = capture.get(ids[0]).unwrap().unwrap();
+        assert_eq!(entry.request.timestamp_ms, 1_600_000_000_000);
+        assert_eq!(entry.response.unwrap().request_id, ids[0]);
+        assert_eq!(entry.annotation.tags, vec!["imported"]);
+        assert_eq!(capture.get(existing).unwrap().unwrap().request.url, "/live");
+    }
+
+    #[test]
+    fn test_ids_continue_after_imports_past_capacity() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage =
+            Arc::new(CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap());
+        let capture = RequestCapture::with_storage(2, Some(storage.clone())).unwrap();
+        capture.push(create_test_request("GET", "/live1", false), None);
+        capture.push(create_test_request("GET", "/live2", false), None);
+        let old = (0..3)
+            .map(|_| {
+                let mut request = create_test_request("GET", "/old", false);
+                request.timestamp_ms = 1_600_000_000_000;
+                CaptureEntry {
+                    request,
+                    response: None,
+                    annotation: Annotation::default(),
+                    omitted_bodies: None,
+                }
+            })
+            .collect();
+        let imported = capture.import(old).unwrap();
+        drop(capture);
+
+        // Only the two live captures are reloaded, but the imports hold the highest ids
+        let capture = RequestCapture::with_storage(2, Some(storage.clone())).unwrap();
+        let id = capture.push(create_test_request("GET", "/new", false), None);
+        assert!(id > *imported.iter().max().unwrap());
+        assert_eq!(storage.query(&CaptureQuery::default()).unwrap().len(), 6);
+        assert_eq!(capture.get(imported[2]).unwrap().unwrap().request.url, "/old");
+    }
+
+    #[test]
+    fn test_storage_errors_are_not_hidden() {
+        use crate::database::EncryptionKeyProvider;
+
+        let dir = tempfile::tempdir().unwrap();
+        let storage = Arc::new(
+            CaptureStorage::with_key_provider(
+                dir.path().join("test.db"),
+                EncryptionKeyProvider::from_key(EncryptionKeyProvider::generate_new()),
+            )
+            .unwrap(),
+        );
+        let entry = CaptureEntry {
+            request: create_test_request("GET", "/a", false),
+            response: Some(create_test_response(200, 1)),
+            annotation: Annotation::default(),
+            omitted_bodies: None,
+        };
+        storage.insert(&entry).unwrap();
+        storage
+            .connect()
+            .unwrap()
+            .execute("UPDATE bodies SET data = zeroblob(40)", [])
+            .unwrap();
+
+        let capture = RequestCapture::with_storage(10, Some(storage)).unwrap();
+        assert!(capture.get(entry.request.id).is_err());
+        assert!(capture.query(&CaptureQuery::default()).is_err());
+    }
+
+    #[test]
+    fn test_annotation_validation() {
+        let bad_tag = Annotation {
+            tags: vec!["  ".to_string()],
+            ..Default::default()
+        };
+        assert!(bad_tag.normalize().is_err());
+        assert_eq!("Purple".parse::<Highlight>().unwrap(), Highlight::Purple);
+        assert!("mauve".parse::<Highlight>().is_err());
+
+        // Entries saved before annotations existed still load
+        let json = r#"{"request":{"id":1,"timestamp_ms":0,"method":"GET","url":"/","headers":[],"body":[],"tls":false},"response":null}"#;
+        let entry: CaptureEntry = serde_json::from_str(json).unwrap();
+        assert!(entry.annotation.is_empty());
+    }
 }
@@ -0,0 +1,529 @@
+//! curl commands, in both directions
+//!
+//! Rendering targets POSIX shells. Parsing accepts what browsers' "Copy as
+//! cURL (bash)" and hand-written commands use; options that do not change the
+//! request, such as `-k` or `-o`, are skipped.
+
+use super::portable_headers;
+use crate::capture::CapturedRequest;
+use crate::error::{ProxyError, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+/// Short options that take a value but do not affect the request
+const SKIPPED_SHORT: &str = "oxmwEKrTyYzcCDQUPt";
+
+/// Long options that take a value but do not affect the request
+const SKIPPED_LONG: &[&str] = &[
+    "--output",
+    "--proxy",
+    "--proxy-user",
+    "--max-time",
+    "--connect-timeout",
+    "--retry",
+    "--retry-delay",
+    "--retry-max-time",
+    "--write-out",
+    "--cert",
+    "--key",
+    "--cacert",
+    "--capath",
+    "--ciphers",
+    "--resolve",
+    "--connect-to",
+    "--limit-rate",
+    "--max-redirs",
+    "--interface",
+    "--dns-servers",
+    "--unix-socket",
+    "--cookie-jar",
+    "--dump-header",
+    "--config",
+    "--range",
+    "--time-cond",
+    "--expect100-timeout",
+];
+
+/// Request described by a curl command
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CurlRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Option<String>,
+}
+
+pub(super) fn render(request: &CapturedRequest) -> String {
+    let mut args = vec!["curl".to_string()];
+    match request.method.as_str() {
+        "GET" if request.body.is_empty() => {}
+        "POST" if !request.body.is_empty() => {}
+        "HEAD" => args.push("--head".to_string()),
+        method => args.push(format!("-X {}", shell_quote(method))),
+    }
+    args.push(shell_quote(&request.url));
+    for (name, value) in portable_headers(request) {
+        args.push(format!("-H {}", shell_quote(&format!("{name}: {value}"))));
+    }
+    let mut prefix = String::new();
+    if !request.body.is_empty() {
+        // Shell words cannot hold NUL bytes, so binary bodies go through stdin
+        let text = std::str::from_utf8(&request.body).ok();
+        match text.filter(|text| !text.contains('\0')) {
+            Some(text) => args.push(format!("--data-raw {}", shell_quote(text))),
+            None => {
+                prefix = format!(
+                    "printf '%s' {} | base64 -d | ",
+                    shell_quote(&BASE64.encode(&request.body))
+                );
+                args.push("--data-binary @-".to_string());
+            }
+        }
+    }
+    format!("{prefix}{}", args.join(" \\\n  "))
+}
+
+/// Single-quote `value` unless it is made of characters the shell leaves alone
+fn shell_quote(value: &str) -> String {
+    let safe = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_@%+=:,./-".contains(c));
+    if safe {
+        value.to_string()
+    } else {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+}
+
+/// Parse a pasted curl command
+pub fn parse_curl(command: &str) -> Result<CurlRequest> {
+    let words = shell_words(command)?;
+    let mut words = words.into_iter().peekable();
+    if words.peek().is_some_and(|w| w == "curl") {
+        words.next();
+    }
+
+    let mut method = None;
+    let mut url = None;
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut data: Vec<String> = Vec::new();
+    let mut json = false;
+    let mut head = false;
+    let mut get = false;
+
+    while let Some(word) = words.next() {
+        let (option, attached) = match split_option(&word) {
+            Some(parts) => parts,
+            None => {
+                url.get_or_insert(word);
+                continue;
+            }
+        };
+        let mut value = || -> Result<String> {
+            match &attached {
+                Some(value) => Ok(value.clone()),
+                None => words
+                    .next()
+                    .ok_or_else(|| invalid(&format!("{option} needs a value"))),
+            }
+        };
+        match option.as_str() {
+            "-X" | "--request" => method = Some(value()?),
+            "-H" | "--header" => {
+                let header = value()?;
+                // `Name;` sends an empty header, `Name:` removes a default one
+                if let Some(name) = header.strip_suffix(';') {
+                    headers.push((name.trim().to_string(), String::new()));
+                } else if let Some((name, val)) = header.split_once(':') {
+                    if !val.trim().is_empty() {
+                        headers.push((name.trim().to_string(), val.trim().to_string()));
+                    }
+                }
+            }
+            "-d" | "--data" | "--data-ascii" | "--data-binary" => {
+                let value = value()?;
+                if value.starts_with('@') {
+                    return Err(invalid("file references in request data are not supported"));
+                }
+                data.push(value);
+            }
+            "--data-raw" => data.push(value()?),
+            "--data-urlencode" => data.push(url_encode_data(&value()?)?),
+            "--json" => {
+                data.push(value()?);
+                json = true;
+            }
+            "-F" | "--form" => return Err(invalid("multipart forms (-F) are not supported")),
+            "-b" | "--cookie" => {
+                let cookie = value()?;
+                // Without `=` the value names a cookie file
+                if cookie.contains('=') {
+                    headers.push(("Cookie".to_string(), cookie));
+                }
+            }
+            "-A" | "--user-agent" => headers.push(("User-Agent".to_string(), value()?)),
+            "-e" | "--referer" => headers.push(("Referer".to_string(), value()?)),
+            "-u" | "--user" => {
+                let mut credentials = value()?;
+                if !credentials.contains(':') {
+                    credentials.push(':');
+                }
+                headers.push((
+                    "Authorization".to_string(),
+                    format!("Basic {}", BASE64.encode(credentials)),
+                ));
+            }
+            "--url" => {
+                let value = value()?;
+                url.get_or_insert(value);
+            }
+            "-I" | "--head" => head = true,
+            "-G" | "--get" => get = true,
+            option if takes_skipped_value(option) => {
+                value()?;
+            }
+            _ => {}
+        }
+    }
+
+    let mut url = url.ok_or_else(|| invalid("no URL given"))?;
+    if !url.contains("://") {
+        url = format!("http://{url}");
+    }
+    let has_header = |headers: &[(String, String)], name: &str| {
+        headers.iter().any(|(n, _)| n.eq_ignore_ascii_case(name))
+    };
+    let mut body = None;
+    if !data.is_empty() {
+        let joined = data.join("&");
+        if get {
+            url.push(if url.contains('?') { '&' } else { '?' });
+            url.push_str(&joined);
+        } else {
+            if json {
+                if !has_header(&headers, "content-type") {
+                    headers.push(("Content-Type".to_string(), "application/json".to_string()));
+                }
+                if !has_header(&headers, "accept") {
+                    headers.push(("Accept".to_string(), "application/json".to_string()));
+                }
+            } else if !has_header(&headers, "content-type") {
+                headers.push((
+                    "Content-Type".to_string(),
+                    "application/x-www-form-urlencoded".to_string(),
+                ));
+            }
+            body = Some(joined);
+        }
+    }
+    let method = method.unwrap_or_else(|| {
+        if head {
+            "HEAD"
+        } else if body.is_some() {
+            "POST"
+        } else {
+            "GET"
+        }
+        .to_string()
+    });
+    Ok(CurlRequest {
+        method,
+        url,
+        headers,
+        body,
+    })
+}
+
+/// Split `-XPOST` into (`-X`, `POST`); plain flags come back without a value
+fn split_option(word: &str) -> Option<(String, Option<String>)> {
+    if word.starts_with("--") {
+        return Some((word.to_string(), None));
+    }
+    let rest = word.strip_prefix('-')?;
+    let mut chars = rest.chars();
+    let first = chars.next()?;
+    let attached = chars.as_str();
+    if attached.is_empty() {
+        return Some((word.to_string(), None));
+    }
+    if "XHdbAeuF".contains(first) || SKIPPED_SHORT.contains(first) {
+        return Some((format!("-{first}"), Some(attached.to_string())));
+    }
+    // Combined flags such as `-sSLk`; only -I and -G matter
+    let flag = ['I', 'G'].into_iter().find(|f| rest.contains(*f));
+    Some((
+        flag.map_or_else(|| word.to_string(), |f| format!("-{f}")),
+        None,
+    ))
+}
+
+fn takes_skipped_value(option: &str) -> bool {
+    match option.strip_prefix('-') {
+        Some(short) if short.len() == 1 => SKIPPED_SHORT.contains(short),
+        _ => SKIPPED_LONG.contains(&option),
+    }
+}
+
+/// `--data-urlencode` forms: `content`, `=content` and `name=content`
+fn url_encode_data(value: &str) -> Result<String> {
+    // Whichever of `=` and `@` comes first decides the form; `@` reads a file
+    let file = match (value.find('@'), value.find('=')) {
+        (Some(at), Some(eq)) => at < eq,
+        (at, _) => at.is_some(),
+    };
+    if file {
+        return Err(invalid("file references in request data are not supported"));
+    }
+    Ok(match value.split_once('=') {
+        Some(("", content)) => urlencoding::encode(content).into_owned(),
+        Some((name, content)) => format!("{name}={}", urlencoding::encode(content)),
+        None => urlencoding::encode(value).into_owned(),
+    })
+}
+
+/// Split a command line into words the way a POSIX shell would
+fn shell_words(input: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Vec<u8> = Vec::new();
+    let mut in_word = false;
+    let mut chars = input.chars().peekable();
+    let push = |buf: &mut Vec<u8>, c: char| {
+        let mut tmp = [0u8; 4];
+        buf.extend_from_slice(c.encode_utf8(&mut tmp).as_bytes());
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if in_word {
+                    words.push(String::from_utf8_lossy(&word).into_owned());
+                    word.clear();
+                    in_word = false;
+                }
+            }
+            '\\' => match chars.next() {
+                // Line continuation
+                Some('\n') => {}
+                Some('\r') if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                }
+                Some(escaped) => {
+                    push(&mut word, escaped);
+                    in_word = true;
+                }
+                None => {}
+            },
+            '\'' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => push(&mut word, c),
+                        None => return Err(invalid("unterminated single quote")),
+                    }
+                }
+            }
+            '"' => {
+                in_word = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some('\n') => {}
+                            Some(c @ ('"' | '\\' | '$' | '`')) => push(&mut word, c),
+                            Some(c) => {
+                                push(&mut word, '\\');
+                                push(&mut word, c);
+                            }
+                            None => return Err(invalid("unterminated double quote")),
+                        },
+                        Some(c) => push(&mut word, c),
+                        None => return Err(invalid("unterminated double quote")),
+                    }
+                }
+            }
+            '$' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                in_word = true;
+                ansi_c_string(&mut chars, &mut word)?;
+            }
+            c => {
+                push(&mut word, c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(String::from_utf8_lossy(&word).into_owned());
+    }
+    Ok(words)
+}
+
+/// Body of a bash `$'...'` string, after the opening quote
+fn ansi_c_string(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    word: &mut Vec<u8>,
+) -> Result<()> {
+    let mut tmp = [0u8; 4];
+    loop {
+        let c = chars
+            .next()
+            .ok_or_else(|| invalid("unterminated $'...' string"))?;
+        match c {
+            '\'' => return Ok(()),
+            '\\' => {
+                let escaped = chars
+                    .next()
+                    .ok_or_else(|| invalid("unterminated $'...' string"))?;
+                match escaped {
+                    'n' => word.push(b'\n'),
+                    'r' => word.push(b'\r'),
+                    't' => word.push(b'\t'),
+                    'a' => word.push(0x07),
+                    'b' => word.push(0x08),
+                    'e' | 'E' => word.push(0x1b),
+                    'f' => word.push(0x0c),
+                    'v' => word.push(0x0b),
+                    'x' => {
+                        let digits: String =
+                            std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_hexdigit()))
+                                .take(2)
+                                .collect();
+                        let byte = u8::from_str_radix(&digits, 16)
+                            .map_err(|_| invalid("invalid \\x escape"))?;
+                        word.push(byte);
+                    }
+                    'u' | 'U' => {
+                        let max = if escaped == 'u' { 4 } else { 8 };
+                        let digits: String =
+                            std::iter::from_fn(|| chars.next_if(|c| c.is_ascii_hexdigit()))
+                                .take(max)
+                                .collect();
+                        let c = u32::from_str_radix(&digits, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| invalid("invalid unicode escape"))?;
+                        word.extend_from_slice(c.encode_utf8(&mut tmp).as_bytes());
+                    }
+                    '0'..='7' => {
+                        let mut value = escaped.to_digit(8).unwrap_or_default();
+                        for _ in 0..2 {
+                            match chars.next_if(|c| c.is_digit(8)) {
+                                Some(d) => value = value * 8 + d.to_digit(8).unwrap_or_default(),
+                                None => break,
+                            }
+                        }
+                        word.push(value as u8);
+                    }
+                    other => word.extend_from_slice(other.encode_utf8(&mut tmp).as_bytes()),
+                }
+            }
+            c => word.extend_from_slice(c.encode_utf8(&mut tmp).as_bytes()),
+        }
+    }
+}
+
+fn invalid(msg: &str) -> ProxyError {
+    ProxyError::InvalidRequest(format!("curl: {msg}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(method: &str, body: &[u8]) -> CapturedRequest {
+        let mut request = CapturedRequest::new(method, "https://api.test/items?a=1&b=2", true);
+        request.headers = vec![
+            ("Host".to_string(), "api.test".to_string()),
+            ("Cookie".to_string(), "sid=it's".to_string()),
+        ];
+        request.body = body.to_vec();
+        request
+    }
+
+    #[test]
+    fn test_render_quotes_for_the_shell() {
+        let command = render(&request("POST", b"{\"q\":\"o'k\"}"));
+        assert_eq!(
+            command,
+            "curl \\\n  'https://api.test/items?a=1&b=2' \\\n  -H 'Cookie: sid=it'\\''s' \\\n  --data-raw '{\"q\":\"o'\\''k\"}'"
+        );
+        assert!(render(&request("GET", b"")).starts_with("curl \\\n  'https://"));
+        assert!(render(&request("DELETE", b"")).starts_with("curl \\\n  -X DELETE \\\n"));
+        let binary = render(&request("PUT", &[0, 1, 2]));
+        assert!(binary.starts_with("printf '%s' AAEC | base64 -d | curl \\\n  -X PUT"));
+        assert!(binary.ends_with("--data-binary @-"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original = request("PATCH", b"name=a b&note=\"x\"\n");
+        let parsed = parse_curl(&render(&original)).unwrap();
+        assert_eq!(parsed.method, "PATCH");
+        assert_eq!(parsed.url, original.url);
+        assert_eq!(
+            parsed.headers[0],
+            ("Cookie".to_string(), "sid=it's".to_string())
+        );
+        assert_eq!(parsed.body.as_deref(), Some("name=a b&note=\"x\"\n"));
+    }
+
+    #[test]
+    fn test_parse_browser_command() {
+        let command = r#"curl 'https://app.test/api' \
+  -H 'accept: */*' \
+  -H "x-token: a\"b" \
+  -b 'session=abc' \
+  --data-raw $'{"msg":"line\nnext \u00e9"}' \
+  --compressed -sSk -o /dev/null"#;
+        let parsed = parse_curl(command).unwrap();
+        assert_eq!(parsed.method, "POST");
+        assert_eq!(
+            parsed.headers,
+            vec![
+                ("accept".to_string(), "*/*".to_string()),
+                ("x-token".to_string(), "a\"b".to_string()),
+                ("Cookie".to_string(), "session=abc".to_string()),
+                (
+                    "Content-Type".to_string(),
+                    "application/x-www-form-urlencoded".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            parsed.body.as_deref(),
+            Some("{\"msg\":\"line\nnext \u{e9}\"}")
+        );
+    }
+
+    #[test]
+    fn test_parse_options() {
+        let parsed = parse_curl("curl -XPUT example.test/x --json '{}' -u admin:pw").unwrap();
+        assert_eq!(parsed.method, "PUT");
+        assert_eq!(parsed.url, "http://example.test/x");
+        assert!(parsed.headers.contains(&(
+            "Authorization".to_string(),
+            "Basic YWRtaW46cHc=".to_string()
+        )));
+        assert!(parsed
+            .headers
+            .contains(&("Content-Type".to_string(), "application/json".to_string())));
+
+        let parsed =
+            parse_curl("curl -G https://s.test/find -d q=1 --data-urlencode 'name=a b'").unwrap();
+        assert_eq!(parsed.method, "GET");
+        assert_eq!(parsed.url, "https://s.test/find?q=1&name=a%20b");
+        assert!(parsed.body.is_none());
+
+        assert_eq!(parse_curl("curl -I https://h.test").unwrap().method, "HEAD");
+        assert!(parse_curl("curl -d @body.json https://h.test").is_err());
+        assert!(parse_curl("curl --data-urlencode q@f.txt https://h.test").is_err());
+        assert!(parse_curl("curl -H 'x: 'y")
+            .unwrap_err()
+            .to_string()
+            .contains("no URL"));
+        assert!(parse_curl("curl 'https://h.test").is_err());
+    }
+}
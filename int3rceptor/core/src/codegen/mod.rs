@@ -0,0 +1,254 @@
+//! Render captured requests as code for reproduction steps
+//!
+//! Headers that clients compute themselves (`Content-Length`, HTTP/2
+//! pseudo-headers) are left out of every format except raw HTTP, and `Host`
+//! is only kept where the target URL does not already imply it.
+
+mod curl;
+
+pub use curl::{parse_curl, CurlRequest};
+
+use crate::capture::CapturedRequest;
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeFormat {
+    Curl,
+    /// HTTP/1.1 request text
+    Raw,
+    /// Python `requests`
+    Python,
+    /// JavaScript `fetch`
+    Fetch,
+    /// Rust `reqwest`
+    Rust,
+}
+
+/// Render `request` in `format`
+pub fn render(request: &CapturedRequest, format: CodeFormat) -> String {
+    match format {
+        CodeFormat::Curl => curl::render(request),
+        CodeFormat::Raw => raw_http(request),
+        CodeFormat::Python => python(request),
+        CodeFormat::Fetch => fetch(request),
+        CodeFormat::Rust => reqwest(request),
+    }
+}
+
+/// Headers worth reproducing outside of raw HTTP
+fn portable_headers(request: &CapturedRequest) -> impl Iterator<Item = &(String, String)> {
+    let authority = request
+        .url
+        .parse::<Uri>()
+        .ok()
+        .and_then(|uri| uri.authority().map(|a| a.as_str().to_ascii_lowercase()));
+    request.headers.iter().filter(move |(name, value)| {
+        if name.eq_ignore_ascii_case("host") {
+            // Keep a Host that differs from the URL, e.g. for virtual host testing
+            return authority.as_deref() != Some(value.to_ascii_lowercase().as_str());
+        }
+        !name.starts_with(':') && !name.eq_ignore_ascii_case("content-length")
+    })
+}
+
+fn raw_http(request: &CapturedRequest) -> String {
+    let uri = request.url.parse::<Uri>().ok();
+    let target = uri
+        .as_ref()
+        .and_then(|uri| uri.path_and_query())
+        .map_or("/", |pq| pq.as_str());
+    let mut out = format!("{} {} HTTP/1.1\r\n", request.method, target);
+    let has_host = request
+        .headers
+        .iter()
+        .any(|(name, _)| name.eq_ignore_ascii_case("host"));
+    if !has_host {
+        if let Some(authority) = uri.as_ref().and_then(|uri| uri.authority()) {
+            out.push_str(&format!("Host: {authority}\r\n"));
+        }
+    }
+    // HTTP/2 pseudo-headers have no HTTP/1.1 form; the URL already gave Host
+    for (name, value) in request
+        .headers
+        .iter()
+        .filter(|(name, _)| !name.starts_with(':'))
+    {
+        out.push_str(&format!("{name}: {value}\r\n"));
+    }
+    out.push_str("\r\n");
+    out.push_str(&String::from_utf8_lossy(&request.body));
+    out
+}
+
+fn python(request: &CapturedRequest) -> String {
+    let mut out = String::from("import requests\n\n");
+    let headers: Vec<_> = portable_headers(request).collect();
+    if !headers.is_empty() {
+        out.push_str("headers = {\n");
+        for (name, value) in &headers {
+            out.push_str(&format!("    {}: {},\n", quoted(name), quoted(value)));
+        }
+        out.push_str("}\n");
+    }
+    if !request.body.is_empty() {
+        out.push_str(&format!("data = {}\n", body_literal(&request.body)));
+    }
+    out.push_str(&format!(
+        "\nresponse = requests.request({}, {}",
+        quoted(&request.method),
+        quoted(&request.url)
+    ));
+    if !headers.is_empty() {
+        out.push_str(", headers=headers");
+    }
+    if !request.body.is_empty() {
+        out.push_str(", data=data");
+    }
+    out.push_str(")\nprint(response.status_code)\nprint(response.text)\n");
+    out
+}
+
+fn fetch(request: &CapturedRequest) -> String {
+    let mut out = format!(
+        "const response = await fetch({}, {{\n  method: {},\n",
+        quoted(&request.url),
+        quoted(&request.method)
+    );
+    let headers: Vec<_> = portable_headers(request).collect();
+    if !headers.is_empty() {
+        out.push_str("  headers: {\n");
+        for (name, value) in headers {
+            out.push_str(&format!("    {}: {},\n", quoted(name), quoted(value)));
+        }
+        out.push_str("  },\n");
+    }
+    if !request.body.is_empty() {
+        let body = match std::str::from_utf8(&request.body) {
+            Ok(text) => quoted(text),
+            Err(_) => format!("new Uint8Array({:?})", request.body),
+        };
+        out.push_str(&format!("  body: {body},\n"));
+    }
+    out.push_str("});\nconsole.log(response.status, await response.text());\n");
+    out
+}
+
+fn reqwest(request: &CapturedRequest) -> String {
+    let method = match request.method.as_str() {
+        m @ ("GET" | "POST" | "PUT" | "DELETE" | "HEAD" | "OPTIONS" | "CONNECT" | "PATCH"
+        | "TRACE") => format!("reqwest::Method::{m}"),
+        m => format!("reqwest::Method::from_bytes(b{m:?})?"),
+    };
+    let mut out = format!(
+        "let client = reqwest::Client::new();\nlet response = client\n    .request({method}, {:?})\n",
+        request.url
+    );
+    for (name, value) in portable_headers(request) {
+        out.push_str(&format!("    .header({name:?}, {value:?})\n"));
+    }
+    if !request.body.is_empty() {
+        let body = match std::str::from_utf8(&request.body) {
+            Ok(text) => format!("{text:?}"),
+            Err(_) => format!("&{}[..]", byte_string(&request.body)),
+        };
+        out.push_str(&format!("    .body({body})\n"));
+    }
+    out.push_str(
+        "    .send()\n    .await?;\nprintln!(\"{}\", response.status());\nprintln!(\"{}\", response.text().await?);\n",
+    );
+    out
+}
+
+/// Double-quoted literal valid in Python and JavaScript
+fn quoted(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| format!("{value:?}"))
+}
+
+/// Python string or bytes literal for a body
+fn body_literal(body: &[u8]) -> String {
+    match std::str::from_utf8(body) {
+        Ok(text) => quoted(text),
+        Err(_) => byte_string(body),
+    }
+}
+
+/// `b"..."` literal with escapes, valid in both Python and Rust
+fn byte_string(bytes: &[u8]) -> String {
+    let escaped: String = bytes
+        .iter()
+        .flat_map(|&b| std::ascii::escape_default(b))
+        .map(char::from)
+        .collect();
+    format!("b\"{escaped}\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> CapturedRequest {
+        let mut request = CapturedRequest::new("POST", "https://api.test/v1/items?q=a%20b", true);
+        request.headers = vec![
+            ("Host".to_string(), "api.test".to_string()),
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Content-Length".to_string(), "14".to_string()),
+            ("X-Note".to_string(), "it's \"quoted\"".to_string()),
+        ];
+        request.body = br#"{"name":"o'k"}"#.to_vec();
+        request
+    }
+
+    #[test]
+    fn test_raw_http() {
+        let mut request = request();
+        request.url = "https://api.test/v1/items?q=1".to_string();
+        let raw = render(&request, CodeFormat::Raw);
+        assert!(raw.starts_with("POST /v1/items?q=1 HTTP/1.1\r\nHost: api.test\r\n"));
+        assert!(raw.contains("Content-Length: 14\r\n"));
+        assert!(raw.ends_with("\r\n\r\n{\"name\":\"o'k\"}"));
+
+        request.headers = vec![(":authority".to_string(), "h2.test".to_string())];
+        let raw = render(&request, CodeFormat::Raw);
+        assert!(raw.contains("\r\nHost: api.test\r\n"));
+        assert!(!raw.contains("h2.test"));
+    }
+
+    #[test]
+    fn test_python_and_fetch() {
+        let python = render(&request(), CodeFormat::Python);
+        assert!(python.contains(r#"    "X-Note": "it's \"quoted\"","#));
+        assert!(python.contains(r#"data = "{\"name\":\"o'k\"}""#));
+        assert!(python.contains("headers=headers, data=data)"));
+        assert!(!python.contains("Content-Length"));
+        assert!(!python.contains("\"Host\""));
+
+        let mut vhost = request();
+        vhost.url = "https://10.0.0.5/".to_string();
+        let fetch = render(&vhost, CodeFormat::Fetch);
+        assert!(fetch.contains("  method: \"POST\",\n"));
+        assert!(fetch.contains("    \"Host\": \"api.test\",\n"));
+
+        let mut binary = request();
+        binary.body = vec![0, 0xff, b'"'];
+        assert!(render(&binary, CodeFormat::Python).contains(r#"data = b"\x00\xff\"""#));
+        assert!(render(&binary, CodeFormat::Fetch).contains("body: new Uint8Array([0, 255, 34])"));
+    }
+
+    #[test]
+    fn test_reqwest() {
+        let code = render(&request(), CodeFormat::Rust);
+        assert!(
+            code.contains(".request(reqwest::Method::POST, \"https://api.test/v1/items?q=a%20b\")")
+        );
+        assert!(code.contains(r#".header("X-Note", "it's \"quoted\"")"#));
+
+        let mut custom = request();
+        custom.method = "PURGE".to_string();
+        custom.body = vec![1, 0xff];
+        let code = render(&custom, CodeFormat::Rust);
+        assert!(code.contains("reqwest::Method::from_bytes(b\"PURGE\")?"));
+        assert!(code.contains(r#".body(&b"\x01\xff"[..])"#));
+    }
+}
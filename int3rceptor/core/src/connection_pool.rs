@@ -1,16 +1,22 @@
+use crate::hosts::{HostMap, OverrideResolver};
 use http_body_util::Full;
-use hyper::body::Bytes;
+use hyper::body::{Bytes, Incoming};
 use hyper_rustls::HttpsConnectorBuilder;
 use hyper_util::client::legacy::{connect::HttpConnector, Client};
 use hyper_util::rt::TokioExecutor;
 use std::sync::Arc;
 
 pub type ProxyBody = Full<Bytes>;
-pub type HttpClient = Client<hyper_rustls::HttpsConnector<HttpConnector>, ProxyBody>;
+type Connector = hyper_rustls::HttpsConnector<HttpConnector<OverrideResolver>>;
+pub type HttpClient = Client<Connector, ProxyBody>;
+/// Client that forwards a request body as it arrives
+pub type StreamingClient = Client<Connector, Incoming>;
 
 #[derive(Clone)]
 pub struct ConnectionPool {
     client: Arc<HttpClient>,
+    streaming: Arc<StreamingClient>,
+    hosts: HostMap,
 }
 
 impl Default for ConnectionPool {
@@ -21,7 +27,12 @@ impl Default for ConnectionPool {
 
 impl ConnectionPool {
     pub fn new() -> Self {
-        let mut connector = HttpConnector::new();
+        Self::with_hosts(HostMap::new())
+    }
+
+    /// Pool whose upstream connections resolve through the given hosts overrides
+    pub fn with_hosts(hosts: HostMap) -> Self {
+        let mut connector = HttpConnector::new_with_resolver(OverrideResolver::new(hosts.clone()));
         connector.enforce_http(false);
         let https = HttpsConnectorBuilder::new()
             .with_native_roots()
@@ -30,13 +41,24 @@ impl ConnectionPool {
             .enable_http1()
             .enable_http2()
             .wrap_connector(connector);
-        let client = Client::builder(TokioExecutor::new()).build(https);
+        let client = Client::builder(TokioExecutor::new()).build(https.clone());
+        let streaming = Client::builder(TokioExecutor::new()).build(https);
         Self {
             client: Arc::new(client),
+            streaming: Arc::new(streaming),
+            hosts,
         }
     }
 
     pub fn client(&self) -> Arc<HttpClient> {
         self.client.clone()
     }
+
+    pub fn streaming_client(&self) -> Arc<StreamingClient> {
+        self.streaming.clone()
+    }
+
+    pub fn hosts(&self) -> &HostMap {
+        &self.hosts
+    }
 }
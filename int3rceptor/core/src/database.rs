@@ -10,24 +10,63 @@ use anyhow::{anyhow, Result};
 use rand::Rng;
 use std::path::{Path, PathBuf};
 
+/// Environment variable holding the hex master key
+pub const KEY_ENV: &str = "INTERCEPTOR_ENCRYPTION_KEY";
+/// Environment variable holding a passphrase the master key is derived from
+pub const PASSPHRASE_ENV: &str = "INTERCEPTOR_ENCRYPTION_PASSPHRASE";
+
+/// Where the master key was loaded from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    /// Given directly by the caller
+    Explicit,
+    /// Hex key file, rewritten when the key is rotated
+    File(PathBuf),
+    /// Environment variable, which only the operator can update
+    Env(&'static str),
+}
+
+impl KeySource {
+    /// Save a replacement master key where it will be loaded from next time
+    ///
+    /// Returns false for sources that cannot be written.
+    pub fn store(&self, key: &[u8; 32]) -> Result<bool> {
+        let KeySource::File(path) = self else {
+            return Ok(false);
+        };
+        // Write beside the old file and rename, so a crash never leaves it half-written
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, hex::encode(key))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tmp, std::fs::Permissions::from_mode(0o600))?;
+        }
+        std::fs::rename(&tmp, path)?;
+        Ok(true)
+    }
+}
+
 /// Master key provider - handles encryption key generation and management
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EncryptionKeyProvider {
     /// Master encryption key (256-bit)
     master_key: [u8; 32],
     /// Whether encryption is enabled
     enabled: bool,
+    source: KeySource,
 }
 
 impl EncryptionKeyProvider {
     /// Create new key provider from environment variable or generate new key
     pub fn new() -> Result<Self> {
         // Try to load from environment variable
-        if let Ok(key_hex) = std::env::var("INTERCEPTOR_ENCRYPTION_KEY") {
+        if let Ok(key_hex) = std::env::var(KEY_ENV) {
             if let Ok(key) = Self::from_hex(&key_hex) {
                 return Ok(Self {
                     master_key: key,
                     enabled: true,
+                    source: KeySource::Env(KEY_ENV),
                 });
             }
         }
@@ -37,12 +76,7 @@ impl EncryptionKeyProvider {
             .unwrap_or_else(|_| ".interceptor_key".to_string());
 
         if Path::new(&key_path).exists() {
-            let key_hex = std::fs::read_to_string(&key_path)?;
-            let key = Self::from_hex(key_hex.trim())?;
-            return Ok(Self {
-                master_key: key,
-                enabled: true,
-            });
+            return Self::from_file(key_path);
         }
 
         // If INTERCEPTOR_UNENCRYPTED is set, allow unencrypted mode (dev/testing only)
@@ -51,15 +85,62 @@ impl EncryptionKeyProvider {
                 // codeql[rust/hard-coded-cryptographic-value]: Intentionally disabled encryption for dev mode
                 master_key: [0u8; 32],
                 enabled: false,
+                source: KeySource::Explicit,
             });
         }
 
         Err(anyhow!(
             "No encryption key found. Set INTERCEPTOR_ENCRYPTION_KEY, \
-             INTERCEPTOR_ENCRYPTION_KEY_FILE, or INTERCEPTOR_UNENCRYPTED."
+             INTERCEPTOR_ENCRYPTION_KEY_FILE, INTERCEPTOR_ENCRYPTION_PASSPHRASE \
+             or INTERCEPTOR_UNENCRYPTED."
         ))
     }
 
+    /// Provider for an explicit master key, with encryption enabled
+    pub fn from_key(master_key: [u8; 32]) -> Self {
+        Self {
+            master_key,
+            enabled: true,
+            source: KeySource::Explicit,
+        }
+    }
+
+    /// Provider that leaves data unencrypted
+    pub fn disabled() -> Self {
+        Self {
+            // codeql[rust/hard-coded-cryptographic-value]: Encryption disabled, the key is never used
+            master_key: [0u8; 32],
+            enabled: false,
+            source: KeySource::Explicit,
+        }
+    }
+
+    /// Provider for the hex key stored in `path`
+    pub fn from_file(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let key_hex = std::fs::read_to_string(&path)?;
+        let key = Self::from_hex(key_hex.trim())?;
+        Ok(Self::from_key(key).with_source(KeySource::File(path)))
+    }
+
+    /// Record where the key came from, so a rotated key can be saved there
+    pub fn with_source(mut self, source: KeySource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Provider whose master key is derived from a passphrase with Argon2
+    pub fn from_passphrase(passphrase: &str, salt: &[u8; 16]) -> Result<Self> {
+        Ok(Self::from_key(crypto::derive_key_from_password(
+            passphrase, salt,
+        )?))
+    }
+
+    /// Passphrase from `INTERCEPTOR_ENCRYPTION_PASSPHRASE`, if set and non-empty
+    pub fn passphrase_from_env() -> Option<String> {
+        std::env::var(PASSPHRASE_ENV).ok().filter(|p| !p.is_empty())
+    }
+
     /// Parse hex string to 32-byte key
     pub fn from_hex(hex: &str) -> Result<[u8; 32]> {
         if hex.len() != 64 {
@@ -97,15 +178,20 @@ impl EncryptionKeyProvider {
     pub fn is_enabled(&self) -> bool {
         self.enabled
     }
+
+    pub fn source(&self) -> &KeySource {
+        &self.source
+    }
+
+    /// Derive an independent subkey for a named purpose, so the master key is never reused directly
+    pub fn derive_subkey(&self, purpose: &str) -> [u8; 32] {
+        crypto::hmac_sha256(&self.master_key, purpose.as_bytes())
+    }
 }
 
 impl Default for EncryptionKeyProvider {
     fn default() -> Self {
-        Self::new().unwrap_or(Self {
-            // codeql[rust/hard-coded-cryptographic-value]: Fallback when key loading fails, encryption disabled
-            master_key: [0u8; 32],
-            enabled: false,
-        })
+        Self::new().unwrap_or_else(|_| Self::disabled())
     }
 }
 
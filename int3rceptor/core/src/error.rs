@@ -279,6 +279,12 @@ pub enum ProxyError {
     #[error("Database query failed: {query} - {reason}")]
     DatabaseQuery { query: String, reason: String },
 
+    #[error("Database migration to v{version} failed: {reason}")]
+    DatabaseMigration { version: u32, reason: String },
+
+    #[error("Storage encryption key mismatch: {0}")]
+    StorageKeyMismatch(String),
+
     #[error("Storage is full: {used} / {capacity} bytes")]
     StorageFull { used: u64, capacity: u64 },
 
@@ -385,8 +391,11 @@ impl ProxyError {
             Self::ProxyAlreadyRunning { .. } => ErrorCode::ProxyAlreadyRunning,
             Self::UpstreamFailed(_) => ErrorCode::ProxyUpstreamFailed,
             Self::ConnectFailed { .. } => ErrorCode::ProxyConnectFailed,
-            Self::Database(_) | Self::DatabaseConnection(_) => ErrorCode::DatabaseConnection,
+            Self::Database(_) | Self::DatabaseConnection(_) | Self::StorageKeyMismatch(_) => {
+                ErrorCode::DatabaseConnection
+            }
             Self::DatabaseQuery { .. } => ErrorCode::DatabaseQuery,
+            Self::DatabaseMigration { .. } => ErrorCode::DatabaseMigration,
             Self::StorageFull { .. } => ErrorCode::StorageFull,
             Self::Serde(_)
             | Self::SerializationFailed { .. }
@@ -479,6 +488,14 @@ impl ProxyError {
         }
     }
 
+    /// Create a database migration error
+    pub fn db_migration(version: u32, reason: impl Into<String>) -> Self {
+        Self::DatabaseMigration {
+            version,
+            reason: reason.into(),
+        }
+    }
+
     /// Create an invalid config error
     pub fn invalid_config(field: impl Into<String>, reason: impl Into<String>) -> Self {
         Self::InvalidConfig {
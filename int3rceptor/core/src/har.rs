@@ -0,0 +1,479 @@
+//! HAR 1.2 export and import
+//!
+//! Fields HAR has no slot for are carried in `_`-prefixed custom fields, which
+//! the spec allows and other tools ignore: `_tls` on entries and `_encoding`
+//! on request bodies that are not valid UTF-8.
+
+use crate::capture::{Annotation, CaptureEntry, CaptureSource, CapturedRequest, CapturedResponse};
+use crate::error::{ProxyError, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+const HAR_VERSION: &str = "1.2";
+const HTTP_VERSION: &str = "HTTP/1.1";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Har {
+    pub log: HarLog,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HarLog {
+    pub version: String,
+    pub creator: HarCreator,
+    pub entries: Vec<HarEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HarCreator {
+    pub name: String,
+    pub version: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarEntry {
+    pub started_date_time: String,
+    /// Total time in milliseconds
+    pub time: f64,
+    pub request: HarRequest,
+    pub response: HarResponse,
+    #[serde(default)]
+    pub cache: serde_json::Value,
+    #[serde(default)]
+    pub timings: HarTimings,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    #[serde(rename = "_tls", default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<bool>,
+}
+
+/// Headers, query parameters and cookies
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HarNameValue {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<HarNameValue>,
+    #[serde(default)]
+    pub headers: Vec<HarNameValue>,
+    #[serde(default)]
+    pub query_string: Vec<HarNameValue>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub post_data: Option<HarPostData>,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarPostData {
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(rename = "_encoding", default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarResponse {
+    /// 0 when no response was received
+    pub status: u16,
+    #[serde(default)]
+    pub status_text: String,
+    #[serde(default)]
+    pub http_version: String,
+    #[serde(default)]
+    pub cookies: Vec<HarNameValue>,
+    #[serde(default)]
+    pub headers: Vec<HarNameValue>,
+    #[serde(default)]
+    pub content: HarContent,
+    #[serde(rename = "redirectURL", default)]
+    pub redirect_url: String,
+    #[serde(default = "unknown_size")]
+    pub headers_size: i64,
+    #[serde(default = "unknown_size")]
+    pub body_size: i64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HarContent {
+    pub size: i64,
+    #[serde(default)]
+    pub mime_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<String>,
+}
+
+/// Phase durations in milliseconds, -1 where unknown
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HarTimings {
+    pub blocked: f64,
+    pub dns: f64,
+    pub connect: f64,
+    pub ssl: f64,
+    pub send: f64,
+    pub wait: f64,
+    pub receive: f64,
+}
+
+impl Default for HarTimings {
+    fn default() -> Self {
+        Self {
+            blocked: -1.0,
+            dns: -1.0,
+            connect: -1.0,
+            ssl: -1.0,
+            send: 0.0,
+            wait: 0.0,
+            receive: 0.0,
+        }
+    }
+}
+
+fn unknown_size() -> i64 {
+    -1
+}
+
+/// Build a HAR log from captured entries
+pub fn export(entries: &[CaptureEntry]) -> Har {
+    Har {
+        log: HarLog {
+            version: HAR_VERSION.to_string(),
+            creator: HarCreator {
+                name: "Interceptor".to_string(),
+                version: env!("CARGO_PKG_VERSION").to_string(),
+            },
+            entries: entries.iter().map(export_entry).collect(),
+        },
+    }
+}
+
+/// Parse a HAR document into entries with their original timestamps
+///
+/// Ids are left at 0; they are assigned when the entries are imported.
+pub fn parse(data: &[u8]) -> Result<Vec<CaptureEntry>> {
+    let har: Har = serde_json::from_slice(data)?;
+    har.log
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(index, entry)| {
+            import_entry(entry)
+                .map_err(|err| ProxyError::InvalidRequest(format!("HAR entry {index}: {err}")))
+        })
+        .collect()
+}
+
+fn export_entry(entry: &CaptureEntry) -> HarEntry {
+    let request = &entry.request;
+    let started = OffsetDateTime::from_unix_timestamp_nanos(request.timestamp_ms * 1_000_000)
+        .unwrap_or(OffsetDateTime::UNIX_EPOCH);
+    let duration = entry
+        .response
+        .as_ref()
+        .map(|r| r.duration_ms as f64)
+        .unwrap_or(0.0);
+
+    let post_data = (!request.body.is_empty()).then(|| {
+        let (text, encoding) = encode_body(&request.body);
+        HarPostData {
+            mime_type: header_value(&request.headers, "content-type").unwrap_or_default(),
+            text,
+            encoding,
+        }
+    });
+
+    HarEntry {
+        started_date_time: started
+            .format(&Rfc3339)
+            .unwrap_or_else(|_| started.to_string()),
+        time: duration,
+        request: HarRequest {
+            method: request.method.clone(),
+            url: request.url.clone(),
+            http_version: HTTP_VERSION.to_string(),
+            cookies: Vec::new(),
+            headers: to_name_values(&request.headers),
+            query_string: query_string(&request.url),
+            post_data,
+            headers_size: -1,
+            body_size: request.body.len() as i64,
+        },
+        response: export_response(entry.response.as_ref()),
+        cache: serde_json::json!({}),
+        timings: HarTimings {
+            wait: duration,
+            ..Default::default()
+        },
+        comment: (!entry.annotation.notes.is_empty()).then(|| entry.annotation.notes.clone()),
+        tls: Some(request.tls),
+    }
+}
+
+fn export_response(response: Option<&CapturedResponse>) -> HarResponse {
+    let Some(response) = response else {
+        return HarResponse {
+            status: 0,
+            status_text: String::new(),
+            http_version: String::new(),
+            cookies: Vec::new(),
+            headers: Vec::new(),
+            content: HarContent::default(),
+            redirect_url: String::new(),
+            headers_size: -1,
+            body_size: -1,
+        };
+    };
+    let (text, encoding) = encode_body(&response.body);
+    HarResponse {
+        status: response.status_code,
+        status_text: StatusCode::from_u16(response.status_code)
+            .ok()
+            .and_then(|s| s.canonical_reason())
+            .unwrap_or_default()
+            .to_string(),
+        http_version: HTTP_VERSION.to_string(),
+        cookies: Vec::new(),
+        headers: to_name_values(&response.headers),
+        content: HarContent {
+            size: response.body.len() as i64,
+            mime_type: header_value(&response.headers, "content-type").unwrap_or_default(),
+            text: (!response.body.is_empty()).then_some(text),
+            encoding,
+        },
+        redirect_url: header_value(&response.headers, "location").unwrap_or_default(),
+        headers_size: -1,
+        body_size: response.body.len() as i64,
+    }
+}
+
+fn import_entry(entry: &HarEntry) -> Result<CaptureEntry> {
+    let started = OffsetDateTime::parse(&entry.started_date_time, &Rfc3339).map_err(|err| {
+        ProxyError::InvalidRequest(format!(
+            "invalid startedDateTime {:?}: {err}",
+            entry.started_date_time
+        ))
+    })?;
+    let har_request = &entry.request;
+    let body = match &har_request.post_data {
+        Some(post) => decode_body(&post.text, post.encoding.as_deref())?,
+        None => Vec::new(),
+    };
+    let request = CapturedRequest {
+        id: 0,
+        timestamp_ms: started.unix_timestamp_nanos() / 1_000_000,
+        method: har_request.method.clone(),
+        url: har_request.url.clone(),
+        headers: from_name_values(&har_request.headers),
+        body,
+        tls: entry
+            .tls
+            .unwrap_or_else(|| har_request.url.starts_with("https://")),
+        source: CaptureSource::Import,
+    };
+
+    // Browsers record aborted requests with status 0
+    let response = match &entry.response {
+        HarResponse { status: 0, .. } => None,
+        har_response => Some(CapturedResponse {
+            request_id: 0,
+            status_code: har_response.status,
+            headers: from_name_values(&har_response.headers),
+            body: match &har_response.content.text {
+                Some(text) => decode_body(text, har_response.content.encoding.as_deref())?,
+                None => Vec::new(),
+            },
+            duration_ms: entry.time.max(0.0).round() as u128,
+        }),
+    };
+
+    Ok(CaptureEntry {
+        request,
+        response,
+        annotation: Annotation {
+            notes: entry.comment.clone().unwrap_or_default(),
+            ..Default::default()
+        },
+        omitted_bodies: None,
+    })
+}
+
+/// UTF-8 bodies are stored as text, anything else as base64
+fn encode_body(body: &[u8]) -> (String, Option<String>) {
+    match std::str::from_utf8(body) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => (BASE64.encode(body), Some("base64".to_string())),
+    }
+}
+
+fn decode_body(text: &str, encoding: Option<&str>) -> Result<Vec<u8>> {
+    match encoding {
+        Some(encoding) if encoding.eq_ignore_ascii_case("base64") => BASE64
+            .decode(text.trim())
+            .map_err(|err| ProxyError::InvalidRequest(format!("invalid base64 body: {err}"))),
+        _ => Ok(text.as_bytes().to_vec()),
+    }
+}
+
+fn to_name_values(headers: &[(String, String)]) -> Vec<HarNameValue> {
+    headers
+        .iter()
+        .map(|(name, value)| HarNameValue {
+            name: name.clone(),
+            value: value.clone(),
+        })
+        .collect()
+}
+
+fn from_name_values(values: &[HarNameValue]) -> Vec<(String, String)> {
+    values
+        .iter()
+        .map(|v| (v.name.clone(), v.value.clone()))
+        .collect()
+}
+
+fn header_value(headers: &[(String, String)], name: &str) -> Option<String> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.clone())
+}
+
+fn query_string(url: &str) -> Vec<HarNameValue> {
+    let Some((_, query)) = url.split_once('?') else {
+        return Vec::new();
+    };
+    let query = query.split('#').next().unwrap_or_default();
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            HarNameValue {
+                name: name.to_string(),
+                value: value.to_string(),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry() -> CaptureEntry {
+        let mut request = CapturedRequest::new("POST", "https://api.test/login?next=%2Fhome", true);
+        request.id = 4;
+        request.timestamp_ms = 1_700_000_000_123;
+        request.headers = vec![("Content-Type".to_string(), "application/json".to_string())];
+        request.body = br#"{"user":"admin"}"#.to_vec();
+        CaptureEntry {
+            request,
+            response: Some(CapturedResponse {
+                request_id: 4,
+                status_code: 302,
+                headers: vec![("Location".to_string(), "/home".to_string())],
+                body: vec![0x89, b'P', b'N', b'G', 0xff],
+                duration_ms: 42,
+            }),
+            annotation: Annotation {
+                notes: "login flow".to_string(),
+                ..Default::default()
+            },
+            omitted_bodies: None,
+        }
+    }
+
+    #[test]
+    fn test_export_maps_entry() {
+        let har = export(&[entry()]);
+        assert_eq!(har.log.version, "1.2");
+        let exported = &har.log.entries[0];
+        assert_eq!(exported.started_date_time, "2023-11-14T22:13:20.123Z");
+        assert_eq!(exported.time, 42.0);
+        assert_eq!(exported.tls, Some(true));
+        assert_eq!(exported.request.query_string[0].name, "next");
+        let post = exported.request.post_data.as_ref().unwrap();
+        assert_eq!(post.mime_type, "application/json");
+        assert!(post.encoding.is_none());
+        assert_eq!(exported.response.status_text, "Found");
+        assert_eq!(exported.response.redirect_url, "/home");
+        assert_eq!(
+            exported.response.content.encoding.as_deref(),
+            Some("base64")
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let original = entry();
+        let json = serde_json::to_vec(&export(std::slice::from_ref(&original))).unwrap();
+        let imported = parse(&json).unwrap().remove(0);
+        assert_eq!(imported.request.timestamp_ms, original.request.timestamp_ms);
+        assert_eq!(imported.request.body, original.request.body);
+        assert_eq!(imported.request.headers, original.request.headers);
+        assert!(imported.request.tls);
+        let response = imported.response.unwrap();
+        assert_eq!(response.body, original.response.as_ref().unwrap().body);
+        assert_eq!(response.duration_ms, 42);
+        assert_eq!(imported.annotation.notes, "login flow");
+    }
+
+    #[test]
+    fn test_parse_browser_har() {
+        let har = br#"{"log":{"version":"1.2","creator":{"name":"WebInspector","version":"537.36"},
+            "pages":[],"entries":[
+            {"startedDateTime":"2024-03-01T10:00:00.500+01:00","time":12.6,
+             "request":{"method":"GET","url":"http://site.test/","httpVersion":"HTTP/2.0",
+                "headers":[{"name":"accept","value":"*/*"}],"queryString":[],"cookies":[],
+                "headersSize":-1,"bodySize":0},
+             "response":{"status":200,"statusText":"","httpVersion":"HTTP/2.0","headers":[],
+                "cookies":[],"content":{"size":2,"mimeType":"text/plain","text":"aGk=",
+                "encoding":"base64"},"redirectURL":"","headersSize":-1,"bodySize":-1},
+             "cache":{},"timings":{"send":0.1,"wait":12,"receive":0.5}},
+            {"startedDateTime":"2024-03-01T10:00:01Z","time":0,
+             "request":{"method":"GET","url":"https://site.test/aborted","headers":[]},
+             "response":{"status":0,"content":{"size":0}}}
+        ]}}"#;
+        let entries = parse(har).unwrap();
+        assert_eq!(entries[0].request.timestamp_ms, 1_709_283_600_500);
+        assert!(!entries[0].request.tls);
+        let response = entries[0].response.as_ref().unwrap();
+        assert_eq!(response.body, b"hi");
+        assert_eq!(response.duration_ms, 13);
+        assert!(entries[1].response.is_none());
+        assert!(entries[1].request.tls);
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_timestamp() {
+        let har = br#"{"log":{"version":"1.2","creator":{"name":"x","version":"1"},"entries":[
+            {"startedDateTime":"yesterday","time":0,
+             "request":{"method":"GET","url":"http://a.test/"},"response":{"status":0}}]}}"#;
+        let err = parse(har).unwrap_err();
+        assert!(err.to_string().contains("HAR entry 0"));
+    }
+}
@@ -0,0 +1,218 @@
+//! Hosts-override table for upstream connections
+//!
+//! Lets pre-production hostnames resolve to fixed addresses without touching DNS.
+//! Only the TCP destination changes: SNI and the `Host` header keep the original name.
+
+use crate::error::{ProxyError, Result};
+use hyper::http::uri::Authority;
+use hyper_util::client::legacy::connect::dns::{GaiResolver, Name};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tower_service::Service;
+
+/// A single hostname to IP override
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostEntry {
+    pub host: String,
+    pub ip: IpAddr,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct HostMap {
+    entries: Arc<RwLock<HashMap<String, IpAddr>>>,
+}
+
+impl HostMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Address override for a hostname, if any
+    pub fn lookup(&self, host: &str) -> Option<IpAddr> {
+        self.entries.read().get(&normalize_host(host)).copied()
+    }
+
+    /// Add or update an override; `true` if the hostname was new
+    pub fn set(&self, host: &str, ip: IpAddr) -> Result<bool> {
+        let host = normalize_host(host);
+        if host.is_empty()
+            || !host
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
+        {
+            return Err(ProxyError::invalid_config(
+                "host",
+                format!("invalid hostname: {host:?}"),
+            ));
+        }
+        Ok(self.entries.write().insert(host, ip).is_none())
+    }
+
+    pub fn remove(&self, host: &str) -> bool {
+        self.entries.write().remove(&normalize_host(host)).is_some()
+    }
+
+    pub fn clear(&self) {
+        self.entries.write().clear();
+    }
+
+    /// All overrides, sorted by hostname
+    pub fn entries(&self) -> Vec<HostEntry> {
+        let mut entries: Vec<HostEntry> = self
+            .entries
+            .read()
+            .iter()
+            .map(|(host, ip)| HostEntry {
+                host: host.clone(),
+                ip: *ip,
+            })
+            .collect();
+        entries.sort_by(|a, b| a.host.cmp(&b.host));
+        entries
+    }
+
+    /// Replace the whole table, validating every entry first
+    pub fn replace(&self, entries: Vec<HostEntry>) -> Result<()> {
+        let staging = HostMap::new();
+        for entry in &entries {
+            staging.set(&entry.host, entry.ip)?;
+        }
+        *self.entries.write() = staging.entries.read().clone();
+        Ok(())
+    }
+
+    /// Resolve `host:port` for a raw TCP connection, honouring overrides
+    pub fn resolve_authority(&self, authority: &str) -> Option<SocketAddr> {
+        let authority: Authority = authority.parse().ok()?;
+        let port = authority.port_u16()?;
+        self.lookup(authority.host())
+            .map(|ip| SocketAddr::new(ip, port))
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// DNS resolver for the upstream connector that consults the [`HostMap`] first
+#[derive(Clone)]
+pub struct OverrideResolver {
+    hosts: HostMap,
+    inner: GaiResolver,
+}
+
+impl OverrideResolver {
+    pub fn new(hosts: HostMap) -> Self {
+        Self {
+            hosts,
+            inner: GaiResolver::new(),
+        }
+    }
+}
+
+type ResolveFuture =
+    Pin<Box<dyn Future<Output = std::io::Result<std::vec::IntoIter<SocketAddr>>> + Send>>;
+
+impl Service<Name> for OverrideResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = std::io::Error;
+    type Future = ResolveFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        // The connector fills in the port after resolution
+        if let Some(ip) = self.hosts.lookup(name.as_str()) {
+            return Box::pin(async move { Ok(vec![SocketAddr::new(ip, 0)].into_iter()) });
+        }
+        let lookup = self.inner.call(name);
+        Box::pin(async move { Ok(lookup.await?.collect::<Vec<_>>().into_iter()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_set_lookup_is_case_insensitive() {
+        let hosts = HostMap::new();
+        assert!(hosts
+            .set("Staging.Example.com.", "10.0.0.5".parse().unwrap())
+            .unwrap());
+        assert!(!hosts
+            .set("staging.example.com", "10.0.0.5".parse().unwrap())
+            .unwrap());
+
+        assert_eq!(
+            hosts.lookup("staging.example.com"),
+            Some("10.0.0.5".parse().unwrap())
+        );
+        assert_eq!(hosts.lookup("example.com"), None);
+        assert!(hosts.remove("STAGING.example.com"));
+        assert!(hosts.entries().is_empty());
+    }
+
+    #[test]
+    fn test_replace_rejects_invalid_entries() {
+        let hosts = HostMap::new();
+        hosts
+            .set("keep.example.com", "127.0.0.1".parse().unwrap())
+            .unwrap();
+
+        let result = hosts.replace(vec![
+            HostEntry {
+                host: "ok.example.com".to_string(),
+                ip: "10.0.0.1".parse().unwrap(),
+            },
+            HostEntry {
+                host: "bad host".to_string(),
+                ip: "10.0.0.2".parse().unwrap(),
+            },
+        ]);
+
+        assert!(result.is_err());
+        assert_eq!(hosts.entries().len(), 1);
+        assert_eq!(hosts.entries()[0].host, "keep.example.com");
+    }
+
+    #[test]
+    fn test_resolve_authority() {
+        let hosts = HostMap::new();
+        hosts.set("api.internal", "::1".parse().unwrap()).unwrap();
+
+        assert_eq!(
+            hosts.resolve_authority("api.internal:8443"),
+            Some(SocketAddr::from_str("[::1]:8443").unwrap())
+        );
+        assert_eq!(hosts.resolve_authority("other.internal:443"), None);
+        assert_eq!(hosts.resolve_authority("api.internal"), None);
+        assert_eq!(hosts.resolve_authority("[::1]:443"), None);
+        assert_eq!(hosts.resolve_authority("[::1]"), None);
+    }
+
+    #[tokio::test]
+    async fn test_resolver_uses_overrides() {
+        let hosts = HostMap::new();
+        hosts
+            .set("preprod.example.test", "192.0.2.10".parse().unwrap())
+            .unwrap();
+        let mut resolver = OverrideResolver::new(hosts);
+
+        let addrs: Vec<SocketAddr> = resolver
+            .call(Name::from_str("preprod.example.test").unwrap())
+            .await
+            .unwrap()
+            .collect();
+        assert_eq!(addrs, vec!["192.0.2.10:0".parse().unwrap()]);
+    }
+}
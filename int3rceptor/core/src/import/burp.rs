@@ -0,0 +1,258 @@
+//! Burp Suite XML history export ("Save items")
+//!
+//! The format is a flat list of `<item>` elements, so it is scanned directly
+//! rather than with a full XML parser. Raw messages are usually base64.
+
+use super::http1;
+use crate::capture::{Annotation, CaptureEntry, CaptureSource, CapturedRequest, CapturedResponse};
+use crate::error::{ProxyError, Result};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use time::{Date, Month, OffsetDateTime, PrimitiveDateTime, Time};
+
+const CDATA_START: &str = "<![CDATA[";
+const CDATA_END: &str = "]]>";
+
+pub fn parse(data: &[u8]) -> Result<Vec<CaptureEntry>> {
+    let xml = String::from_utf8_lossy(data);
+    if find(&xml, "<items").is_none() {
+        return Err(invalid("not a Burp items export"));
+    }
+    let mut entries = Vec::new();
+    let mut rest: &str = &xml;
+    while let Some(start) = find(rest, "<item>") {
+        let body = &rest[start + "<item>".len()..];
+        let end = find(body, "</item>").ok_or_else(|| invalid("unterminated <item>"))?;
+        let entry = parse_item(&body[..end]).map_err(|err| {
+            ProxyError::InvalidRequest(format!("Burp item {}: {err}", entries.len()))
+        })?;
+        entries.push(entry);
+        rest = &body[end + "</item>".len()..];
+    }
+    Ok(entries)
+}
+
+fn parse_item(item: &str) -> Result<CaptureEntry> {
+    let url = element(item, "url")
+        .map(|e| e.text)
+        .ok_or_else(|| invalid("missing <url>"))?;
+    let tls = element(item, "protocol").map_or(url.starts_with("https://"), |e| e.text == "https");
+    let timestamp_ms = element(item, "time")
+        .and_then(|e| parse_time(&e.text))
+        .unwrap_or_else(|| OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000);
+
+    let raw_request = element(item, "request")
+        .map(|e| e.bytes())
+        .transpose()?
+        .unwrap_or_default();
+    let (request, _) =
+        http1::parse_request(&raw_request)?.ok_or_else(|| invalid("truncated request"))?;
+
+    let raw_response = element(item, "response")
+        .map(|e| e.bytes())
+        .transpose()?
+        .unwrap_or_default();
+    let response = if raw_response.is_empty() {
+        None
+    } else {
+        let (response, _) = http1::parse_response(&raw_response, &request.method)?
+            .ok_or_else(|| invalid("truncated response"))?;
+        Some(CapturedResponse {
+            request_id: 0,
+            status_code: response.status,
+            headers: response.headers,
+            body: response.body,
+            // Burp does not export timings
+            duration_ms: 0,
+        })
+    };
+
+    Ok(CaptureEntry {
+        request: CapturedRequest {
+            id: 0,
+            timestamp_ms,
+            method: request.method,
+            url,
+            headers: request.headers,
+            body: request.body,
+            tls,
+            source: CaptureSource::Import,
+        },
+        response,
+        annotation: Annotation {
+            notes: element(item, "comment").map(|e| e.text).unwrap_or_default(),
+            ..Default::default()
+        },
+        omitted_bodies: None,
+    })
+}
+
+struct Element {
+    attrs: String,
+    text: String,
+}
+
+impl Element {
+    fn bytes(&self) -> Result<Vec<u8>> {
+        if self.attrs.contains("base64=\"true\"") {
+            BASE64
+                .decode(self.text.trim())
+                .map_err(|err| invalid(&format!("invalid base64 message: {err}")))
+        } else {
+            Ok(self.text.as_bytes().to_vec())
+        }
+    }
+}
+
+/// First child element called `name`, with CDATA unwrapped and entities decoded
+fn element(xml: &str, name: &str) -> Option<Element> {
+    let open = format!("<{name}");
+    let mut rest = xml;
+    let start = loop {
+        let at = find(rest, &open)?;
+        let after = &rest[at + open.len()..];
+        if after.starts_with(['>', ' ', '/']) {
+            break after;
+        }
+        rest = after;
+    };
+    let tag_end = start.find('>')?;
+    let attrs = &start[..tag_end];
+    if attrs.ends_with('/') {
+        return Some(Element {
+            attrs: attrs.trim_end_matches('/').to_string(),
+            text: String::new(),
+        });
+    }
+    let content = &start[tag_end + 1..];
+    let end = find(content, &format!("</{name}>"))?;
+    Some(Element {
+        attrs: attrs.to_string(),
+        text: unwrap_text(&content[..end]),
+    })
+}
+
+fn unwrap_text(mut content: &str) -> String {
+    let mut text = String::new();
+    while let Some(start) = content.find(CDATA_START) {
+        text.push_str(&html_escape::decode_html_entities(&content[..start]));
+        let inner = &content[start + CDATA_START.len()..];
+        let end = inner.find(CDATA_END).unwrap_or(inner.len());
+        text.push_str(&inner[..end]);
+        content = inner.get(end + CDATA_END.len()..).unwrap_or_default();
+    }
+    text.push_str(&html_escape::decode_html_entities(content));
+    text
+}
+
+/// Position of `needle` outside CDATA sections
+fn find(haystack: &str, needle: &str) -> Option<usize> {
+    let mut offset = 0;
+    loop {
+        let rest = &haystack[offset..];
+        let hit = rest.find(needle)?;
+        match rest.find(CDATA_START) {
+            Some(cdata) if cdata < hit => {
+                let end = rest[cdata..].find(CDATA_END)? + cdata + CDATA_END.len();
+                offset += end;
+            }
+            _ => return Some(offset + hit),
+        }
+    }
+}
+
+/// Java `Date.toString()`, e.g. `Tue Mar 05 14:03:19 CET 2024`
+///
+/// Zone abbreviations are ambiguous, so the time is read as UTC.
+fn parse_time(value: &str) -> Option<i128> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    let [_, month, day, clock, _, year] = parts.as_slice() else {
+        return None;
+    };
+    let month = match *month {
+        "Jan" => Month::January,
+        "Feb" => Month::February,
+        "Mar" => Month::March,
+        "Apr" => Month::April,
+        "May" => Month::May,
+        "Jun" => Month::June,
+        "Jul" => Month::July,
+        "Aug" => Month::August,
+        "Sep" => Month::September,
+        "Oct" => Month::October,
+        "Nov" => Month::November,
+        "Dec" => Month::December,
+        _ => return None,
+    };
+    let date = Date::from_calendar_date(year.parse().ok()?, month, day.parse().ok()?).ok()?;
+    let mut clock = clock.split(':').map(|p| p.parse::<u8>().ok());
+    let time = Time::from_hms(clock.next()??, clock.next()??, clock.next()??).ok()?;
+    Some(
+        PrimitiveDateTime::new(date, time)
+            .assume_utc()
+            .unix_timestamp_nanos()
+            / 1_000_000,
+    )
+}
+
+fn invalid(msg: &str) -> ProxyError {
+    ProxyError::InvalidRequest(msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_items() {
+        let request = BASE64
+            .encode("POST /login HTTP/1.1\r\nHost: app.test\r\nContent-Length: 7\r\n\r\nuser=me");
+        let xml = format!(
+            r#"<?xml version="1.0"?>
+<!DOCTYPE items [<!ELEMENT items (item*)>]>
+<items burpVersion="2024.1" exportTime="Tue Mar 05 14:05:00 CET 2024">
+  <item>
+    <time>Tue Mar 05 14:03:19 CET 2024</time>
+    <url><![CDATA[https://app.test/login]]></url>
+    <host ip="10.0.0.1">app.test</host>
+    <port>443</port>
+    <protocol>https</protocol>
+    <method><![CDATA[POST]]></method>
+    <request base64="true"><![CDATA[{request}]]></request>
+    <status>200</status>
+    <response base64="false"><![CDATA[HTTP/1.1 200 OK
+Content-Length: 2
+
+ok]]></response>
+    <comment>weak &amp; guessable</comment>
+  </item>
+  <item>
+    <time>Tue Mar 05 14:04:00 CET 2024</time>
+    <url><![CDATA[http://app.test/</item>]]></url>
+    <protocol>http</protocol>
+    <request base64="false"><![CDATA[GET /</item> HTTP/1.1
+Host: app.test
+
+]]></request>
+    <response/>
+    <comment></comment>
+  </item>
+</items>"#
+        );
+        let entries = parse(xml.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 2);
+        let login = &entries[0];
+        assert_eq!(login.request.timestamp_ms, 1_709_647_399_000);
+        assert!(login.request.tls);
+        assert_eq!(login.request.body, b"user=me");
+        assert_eq!(login.response.as_ref().unwrap().body, b"ok");
+        assert_eq!(login.annotation.notes, "weak & guessable");
+        assert_eq!(entries[1].request.url, "http://app.test/</item>");
+        assert!(entries[1].response.is_none());
+    }
+
+    #[test]
+    fn test_rejects_other_xml() {
+        assert!(parse(b"<html></html>").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! HTTP/1.x message parsing for recorded byte streams
+//!
+//! Callers always hold everything that was recorded, so a body cut short by
+//! the end of the data is returned as far as it goes instead of waiting.
+
+use crate::error::{ProxyError, Result};
+
+const MAX_HEADERS: usize = 256;
+
+#[derive(Debug, Clone)]
+pub(crate) struct ParsedRequest {
+    pub method: String,
+    pub target: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct ParsedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Parse one request; `None` if the data ends inside the head
+pub(crate) fn parse_request(data: &[u8]) -> Result<Option<(ParsedRequest, usize)>> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut req = httparse::Request::new(&mut headers);
+    let head_len = match req.parse(data) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(err) => return Err(ProxyError::InvalidRequest(format!("HTTP request: {err}"))),
+    };
+    let headers = collect_headers(req.headers);
+    let (body, body_len) = read_body(&headers, &data[head_len..], false);
+    Ok(Some((
+        ParsedRequest {
+            method: req.method.unwrap_or_default().to_string(),
+            target: req.path.unwrap_or_default().to_string(),
+            headers,
+            body,
+        },
+        head_len + body_len,
+    )))
+}
+
+/// Parse one response to a `method` request; `None` if the data ends inside the head
+pub(crate) fn parse_response(data: &[u8], method: &str) -> Result<Option<(ParsedResponse, usize)>> {
+    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+    let mut resp = httparse::Response::new(&mut headers);
+    let head_len = match resp.parse(data) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(err) => return Err(ProxyError::InvalidResponse(format!("HTTP response: {err}"))),
+    };
+    let status = resp.code.unwrap_or_default();
+    let headers = collect_headers(resp.headers);
+    let (body, body_len) = if method.eq_ignore_ascii_case("HEAD")
+        || (100..200).contains(&status)
+        || status == 204
+        || status == 304
+    {
+        (Vec::new(), 0)
+    } else {
+        read_body(&headers, &data[head_len..], true)
+    };
+    Ok(Some((
+        ParsedResponse {
+            status,
+            headers,
+            body,
+        },
+        head_len + body_len,
+    )))
+}
+
+pub(crate) fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+fn collect_headers(headers: &[httparse::Header<'_>]) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|h| {
+            (
+                h.name.to_string(),
+                String::from_utf8_lossy(h.value).into_owned(),
+            )
+        })
+        .collect()
+}
+
+/// Body bytes and how much of `rest` they used
+fn read_body(headers: &[(String, String)], rest: &[u8], until_close: bool) -> (Vec<u8>, usize) {
+    let chunked = header(headers, "transfer-encoding")
+        .and_then(|te| te.rsplit(',').next())
+        .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"));
+    if chunked {
+        return dechunk(rest);
+    }
+    if let Some(len) = header(headers, "content-length").and_then(|v| v.trim().parse().ok()) {
+        let len = rest.len().min(len);
+        return (rest[..len].to_vec(), len);
+    }
+    if until_close {
+        (rest.to_vec(), rest.len())
+    } else {
+        (Vec::new(), 0)
+    }
+}
+
+fn dechunk(data: &[u8]) -> (Vec<u8>, usize) {
+    let mut body = Vec::new();
+    let mut pos = 0;
+    loop {
+        let Some(line_end) = find_crlf(&data[pos..]) else {
+            return (body, data.len());
+        };
+        let line = String::from_utf8_lossy(&data[pos..pos + line_end]);
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            return (body, data.len());
+        };
+        pos += line_end + 2;
+        if size == 0 {
+            // Skip trailers up to the blank line
+            while let Some(end) = find_crlf(&data[pos..]) {
+                pos += end + 2;
+                if end == 0 {
+                    break;
+                }
+            }
+            return (body, pos.min(data.len()));
+        }
+        let Some(chunk) = data.get(pos..pos.saturating_add(size)) else {
+            body.extend_from_slice(&data[pos..]);
+            return (body, data.len());
+        };
+        body.extend_from_slice(chunk);
+        pos = (pos + size + 2).min(data.len());
+    }
+}
+
+fn find_crlf(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|w| w == b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipelined_requests() {
+        let data = b"POST /a HTTP/1.1\r\nHost: x\r\nContent-Length: 3\r\n\r\nabcGET /b HTTP/1.1\r\nHost: x\r\n\r\n";
+        let (first, used) = parse_request(data).unwrap().unwrap();
+        assert_eq!(first.body, b"abc");
+        let (second, rest) = parse_request(&data[used..]).unwrap().unwrap();
+        assert_eq!(second.target, "/b");
+        assert_eq!(used + rest, data.len());
+        assert!(parse_request(b"GET / HTTP/1.1\r\nHost:").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_response_framing() {
+        let data = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2;x=y\r\nde\r\n0\r\n\r\nHTTP/1.1 304 Not Modified\r\n\r\n";
+        let (resp, used) = parse_response(data, "GET").unwrap().unwrap();
+        assert_eq!(resp.body, b"abcde");
+        let (resp, _) = parse_response(&data[used..], "GET").unwrap().unwrap();
+        assert_eq!(resp.status, 304);
+
+        let (resp, _) = parse_response(b"HTTP/1.0 200 OK\r\n\r\nuntil close", "GET")
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.body, b"until close");
+        let (resp, _) = parse_response(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nshort", "GET")
+            .unwrap()
+            .unwrap();
+        assert_eq!(resp.body, b"short");
+    }
+}
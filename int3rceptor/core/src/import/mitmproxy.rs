@@ -0,0 +1,293 @@
+//! mitmproxy flow dumps (`mitmdump -w`)
+//!
+//! A dump is a sequence of tnetstrings, one per flow. Only HTTP flows are
+//! imported; TCP, UDP and DNS flows are skipped.
+
+use super::authority;
+use crate::capture::{Annotation, CaptureEntry, CaptureSource, CapturedRequest, CapturedResponse};
+use crate::error::{ProxyError, Result};
+
+/// Decoded tnetstring value
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Bytes(Vec<u8>),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Null,
+    List(Vec<Value>),
+    Dict(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Dict(items) => items.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    fn string(&self) -> Option<String> {
+        self.bytes()
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned())
+    }
+
+    fn number(&self) -> Option<f64> {
+        match self {
+            Value::Int(value) => Some(*value as f64),
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<CaptureEntry>> {
+    let mut entries = Vec::new();
+    let mut rest = data;
+    let mut index = 0;
+    while !rest.iter().all(u8::is_ascii_whitespace) {
+        let (flow, used) = read_value(rest)
+            .map_err(|err| ProxyError::InvalidRequest(format!("mitmproxy flow {index}: {err}")))?;
+        rest = &rest[used..];
+        let is_http = flow.get("type").and_then(Value::string).as_deref() == Some("http");
+        if is_http {
+            entries.push(flow_entry(&flow).map_err(|err| {
+                ProxyError::InvalidRequest(format!("mitmproxy flow {index}: {err}"))
+            })?);
+        }
+        index += 1;
+    }
+    Ok(entries)
+}
+
+fn flow_entry(flow: &Value) -> std::result::Result<CaptureEntry, String> {
+    let request = flow.get("request").ok_or("missing request")?;
+    let field = |name: &str| {
+        request
+            .get(name)
+            .and_then(Value::string)
+            .ok_or_else(|| format!("missing request.{name}"))
+    };
+    let scheme = field("scheme")?;
+    let tls = scheme == "https";
+    let host = match request.get("authority").and_then(Value::string) {
+        Some(authority) if !authority.is_empty() => authority,
+        _ => {
+            let port = request.get("port").and_then(Value::number).unwrap_or(0.0) as u16;
+            authority(&field("host")?, port, tls)
+        }
+    };
+    let started = request
+        .get("timestamp_start")
+        .and_then(Value::number)
+        .unwrap_or(0.0);
+
+    let response = match flow.get("response") {
+        Some(response @ Value::Dict(_)) => {
+            let ended = response
+                .get("timestamp_end")
+                .or_else(|| response.get("timestamp_start"))
+                .and_then(Value::number)
+                .unwrap_or(started);
+            Some(CapturedResponse {
+                request_id: 0,
+                status_code: response
+                    .get("status_code")
+                    .and_then(Value::number)
+                    .ok_or("missing response.status_code")? as u16,
+                headers: headers(response),
+                body: content(response),
+                duration_ms: ((ended - started).max(0.0) * 1000.0).round() as u128,
+            })
+        }
+        _ => None,
+    };
+
+    Ok(CaptureEntry {
+        request: CapturedRequest {
+            id: 0,
+            timestamp_ms: (started * 1000.0) as i128,
+            method: field("method")?,
+            url: format!("{scheme}://{host}{}", field("path")?),
+            headers: headers(request),
+            body: content(request),
+            tls,
+            source: CaptureSource::Import,
+        },
+        response,
+        annotation: Annotation {
+            notes: flow
+                .get("comment")
+                .and_then(Value::string)
+                .unwrap_or_default(),
+            ..Default::default()
+        },
+        omitted_bodies: None,
+    })
+}
+
+fn headers(message: &Value) -> Vec<(String, String)> {
+    let Some(Value::List(fields)) = message.get("headers") else {
+        return Vec::new();
+    };
+    fields
+        .iter()
+        .filter_map(|field| match field {
+            Value::List(pair) if pair.len() == 2 => Some((pair[0].string()?, pair[1].string()?)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn content(message: &Value) -> Vec<u8> {
+    message
+        .get("content")
+        .and_then(Value::bytes)
+        .map(<[u8]>::to_vec)
+        .unwrap_or_default()
+}
+
+/// Flows nest a few levels; anything deeper is not a flow dump
+const MAX_DEPTH: usize = 32;
+
+/// Read one tnetstring: `<length>:<payload><type>`
+fn read_value(data: &[u8]) -> std::result::Result<(Value, usize), String> {
+    read_nested(data, 0)
+}
+
+fn read_nested(data: &[u8], depth: usize) -> std::result::Result<(Value, usize), String> {
+    if depth > MAX_DEPTH {
+        return Err("values nested too deeply".to_string());
+    }
+    let start = data
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .ok_or("unexpected end of data")?;
+    let data = &data[start..];
+    let colon = data
+        .iter()
+        .take(12)
+        .position(|&b| b == b':')
+        .ok_or("missing length prefix")?;
+    let len: usize = std::str::from_utf8(&data[..colon])
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or("invalid length prefix")?;
+    let payload_start = colon + 1;
+    let payload = data
+        .get(payload_start..payload_start.saturating_add(len))
+        .ok_or("truncated value")?;
+    let kind = *data.get(payload_start + len).ok_or("missing type marker")?;
+    let text = || std::str::from_utf8(payload).map_err(|_| "invalid number".to_string());
+    let value = match kind {
+        b',' | b';' => Value::Bytes(payload.to_vec()),
+        b'#' => Value::Int(text()?.parse().map_err(|_| "invalid integer")?),
+        b'^' => Value::Float(text()?.parse().map_err(|_| "invalid float")?),
+        b'!' => Value::Bool(payload == b"true"),
+        b'~' => Value::Null,
+        b']' => {
+            let mut items = Vec::new();
+            let mut rest = payload;
+            while !rest.is_empty() {
+                let (item, used) = read_nested(rest, depth + 1)?;
+                items.push(item);
+                rest = &rest[used..];
+            }
+            Value::List(items)
+        }
+        b'}' => {
+            let mut items = Vec::new();
+            let mut rest = payload;
+            while !rest.is_empty() {
+                let (key, used) = read_nested(rest, depth + 1)?;
+                rest = &rest[used..];
+                let (value, used) = read_nested(rest, depth + 1)?;
+                rest = &rest[used..];
+                items.push((key.string().ok_or("dictionary key is not a string")?, value));
+            }
+            Value::Dict(items)
+        }
+        other => return Err(format!("unknown type marker {:?}", other as char)),
+    };
+    Ok((value, start + payload_start + len + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tnet(value: &str, kind: char) -> String {
+        format!("{}:{}{}", value.len(), value, kind)
+    }
+
+    fn dict(items: &[(&str, String)]) -> String {
+        let body: String = items
+            .iter()
+            .map(|(k, v)| format!("{}{}", tnet(k, ';'), v))
+            .collect();
+        tnet(&body, '}')
+    }
+
+    fn header_list(pairs: &[(&str, &str)]) -> String {
+        let body: String = pairs
+            .iter()
+            .map(|(k, v)| tnet(&format!("{}{}", tnet(k, ','), tnet(v, ',')), ']'))
+            .collect();
+        tnet(&body, ']')
+    }
+
+    #[test]
+    fn test_parse_http_flow() {
+        let request = dict(&[
+            ("host", tnet("api.test", ';')),
+            ("port", tnet("8443", '#')),
+            ("method", tnet("PUT", ',')),
+            ("scheme", tnet("https", ',')),
+            ("authority", tnet("", ',')),
+            ("path", tnet("/v1/items?x=1", ',')),
+            ("headers", header_list(&[("content-type", "text/plain")])),
+            ("content", tnet("hello", ',')),
+            ("timestamp_start", tnet("1700000000.25", '^')),
+        ]);
+        let response = dict(&[
+            ("status_code", tnet("201", '#')),
+            ("headers", header_list(&[])),
+            ("content", tnet("", '~')),
+            ("timestamp_end", tnet("1700000000.5", '^')),
+        ]);
+        let http = dict(&[
+            ("type", tnet("http", ';')),
+            ("request", request),
+            ("response", response),
+            ("comment", tnet("from mitm", ';')),
+        ]);
+        let tcp = dict(&[("type", tnet("tcp", ';'))]);
+        let dump = format!("{http}{tcp}\n");
+
+        let entries = parse(dump.as_bytes()).unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.request.url, "https://api.test:8443/v1/items?x=1");
+        assert_eq!(entry.request.timestamp_ms, 1_700_000_000_250);
+        assert_eq!(entry.request.body, b"hello");
+        assert_eq!(entry.request.headers[0].1, "text/plain");
+        let response = entry.response.as_ref().unwrap();
+        assert_eq!(response.status_code, 201);
+        assert_eq!(response.duration_ms, 250);
+        assert_eq!(entry.annotation.notes, "from mitm");
+    }
+
+    #[test]
+    fn test_rejects_truncated_dump() {
+        assert!(parse(b"100:4:type;").is_err());
+        assert!(read_value(b"3:1,2]").is_err());
+        assert_eq!(read_value(b"0:~").unwrap(), (Value::Null, 3));
+    }
+}
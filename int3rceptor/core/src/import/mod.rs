@@ -0,0 +1,155 @@
+//! Importers for traffic recorded by other tools
+//!
+//! Each importer turns a file into [`CaptureEntry`] records with their
+//! original timestamps and id 0; ids are assigned by
+//! [`RequestCapture::import`](crate::capture::RequestCapture::import).
+
+pub mod burp;
+pub(crate) mod http1;
+pub mod mitmproxy;
+pub mod pcap;
+
+use crate::capture::CaptureEntry;
+use crate::error::{ProxyError, Result};
+use crate::har;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportFormat {
+    Har,
+    /// Burp Suite "Save items" XML
+    Burp,
+    /// mitmproxy flow dump (`mitmdump -w`)
+    Mitmproxy,
+    /// pcap or pcapng with plain HTTP/1.x traffic
+    Pcap,
+}
+
+impl ImportFormat {
+    /// Guess the format from the first bytes of a file
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        if pcap::is_capture(data) {
+            return Some(Self::Pcap);
+        }
+        let start = data
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(data.len());
+        let data = &data[start..];
+        match data.first()? {
+            b'{' => Some(Self::Har),
+            b'<' => Some(Self::Burp),
+            b'0'..=b'9' => Some(Self::Mitmproxy),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for ImportFormat {
+    type Err = ProxyError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "har" => Ok(Self::Har),
+            "burp" | "xml" => Ok(Self::Burp),
+            "mitmproxy" | "mitm" | "flows" => Ok(Self::Mitmproxy),
+            "pcap" | "pcapng" => Ok(Self::Pcap),
+            other => Err(ProxyError::invalid_config(
+                "format",
+                format!("unknown import format: {other}"),
+            )),
+        }
+    }
+}
+
+/// Parse `data` as `format`, or detect the format when `None`
+pub fn parse(format: Option<ImportFormat>, data: &[u8]) -> Result<Vec<CaptureEntry>> {
+    let format = match format.or_else(|| ImportFormat::detect(data)) {
+        Some(format) => format,
+        None => {
+            return Err(ProxyError::invalid_config(
+                "format",
+                "could not detect the import format",
+            ))
+        }
+    };
+    let mut entries = match format {
+        ImportFormat::Har => har::parse(data)?,
+        ImportFormat::Burp => burp::parse(data)?,
+        ImportFormat::Mitmproxy => mitmproxy::parse(data)?,
+        ImportFormat::Pcap => pcap::parse(data)?,
+    };
+    entries.sort_by_key(|entry| entry.request.timestamp_ms);
+    Ok(entries)
+}
+
+/// Absolute URL for a request target, which may already be absolute (proxy form)
+pub(crate) fn absolute_url(target: &str, scheme: &str, authority: &str) -> String {
+    if target.contains("://") {
+        return target.to_string();
+    }
+    let path = if target.starts_with('/') { target } else { "/" };
+    format!("{scheme}://{authority}{path}")
+}
+
+/// `host` or `host:port`, leaving out the scheme's default port
+pub(crate) fn authority(host: &str, port: u16, tls: bool) -> String {
+    let default = if tls { 443 } else { 80 };
+    let host = if host.contains(':') && !host.starts_with('[') {
+        format!("[{host}]")
+    } else {
+        host.to_string()
+    };
+    if port == default || port == 0 {
+        host
+    } else {
+        format!("{host}:{port}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_formats() {
+        assert_eq!(
+            ImportFormat::detect(b"  {\"log\":{}}"),
+            Some(ImportFormat::Har)
+        );
+        assert_eq!(
+            ImportFormat::detect(b"<?xml version=\"1.0\"?><items>"),
+            Some(ImportFormat::Burp)
+        );
+        assert_eq!(
+            ImportFormat::detect(b"12:4:type;..."),
+            Some(ImportFormat::Mitmproxy)
+        );
+        assert_eq!(
+            ImportFormat::detect(&[0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0]),
+            Some(ImportFormat::Pcap)
+        );
+        assert_eq!(ImportFormat::detect(b"GET / HTTP/1.1"), None);
+        assert_eq!(
+            "pcapng".parse::<ImportFormat>().unwrap(),
+            ImportFormat::Pcap
+        );
+        assert!("zip".parse::<ImportFormat>().is_err());
+    }
+
+    #[test]
+    fn test_urls() {
+        assert_eq!(
+            absolute_url("/a?b", "http", "x.test:81"),
+            "http://x.test:81/a?b"
+        );
+        assert_eq!(
+            absolute_url("https://y.test/", "http", "x.test"),
+            "https://y.test/"
+        );
+        assert_eq!(authority("x.test", 443, true), "x.test");
+        assert_eq!(authority("::1", 8080, false), "[::1]:8080");
+    }
+}
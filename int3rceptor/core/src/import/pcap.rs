@@ -0,0 +1,627 @@
+//! Plain-HTTP traffic from pcap and pcapng captures
+//!
+//! Packets are decoded down to TCP, both directions of each connection are
+//! reassembled by sequence number, and the client stream is split into
+//! HTTP/1.x requests that are paired in order with the server's responses.
+//! TLS and HTTP/2 connections do not parse as HTTP/1 and are skipped.
+
+use super::{absolute_url, authority, http1};
+use crate::capture::{Annotation, CaptureEntry, CaptureSource, CapturedRequest, CapturedResponse};
+use crate::error::{ProxyError, Result};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const PCAPNG_SECTION: u32 = 0x0A0D_0D0A;
+const PCAPNG_INTERFACE: u32 = 1;
+const PCAPNG_SIMPLE_PACKET: u32 = 3;
+const PCAPNG_ENHANCED_PACKET: u32 = 6;
+
+const LINK_NULL: u32 = 0;
+const LINK_ETHERNET: u32 = 1;
+const LINK_RAW: u32 = 101;
+const LINK_LOOP: u32 = 108;
+const LINK_LINUX_SLL: u32 = 113;
+const LINK_LINUX_SLL2: u32 = 276;
+
+const TCP_SYN: u8 = 0x02;
+const TCP_ACK: u8 = 0x10;
+
+/// Whether `data` starts with a pcap or pcapng header
+pub(crate) fn is_capture(data: &[u8]) -> bool {
+    matches!(
+        data.get(..4),
+        Some(
+            [0xa1, 0xb2, 0xc3, 0xd4]
+                | [0xd4, 0xc3, 0xb2, 0xa1]
+                | [0xa1, 0xb2, 0x3c, 0x4d]
+                | [0x4d, 0x3c, 0xb2, 0xa1]
+                | [0x0a, 0x0d, 0x0d, 0x0a]
+        )
+    )
+}
+
+pub fn parse(data: &[u8]) -> Result<Vec<CaptureEntry>> {
+    let packets = if data.starts_with(&[0x0a, 0x0d, 0x0d, 0x0a]) {
+        read_pcapng(data)?
+    } else {
+        read_pcap(data)?
+    };
+
+    let mut open: HashMap<(SocketAddr, SocketAddr), Connection> = HashMap::new();
+    let mut closed = Vec::new();
+    for packet in &packets {
+        let Some(segment) = ip_payload(packet.link, packet.data).and_then(tcp_segment) else {
+            continue;
+        };
+        let key = if segment.src < segment.dst {
+            (segment.src, segment.dst)
+        } else {
+            (segment.dst, segment.src)
+        };
+        let opening = segment.flags & TCP_SYN != 0 && segment.flags & TCP_ACK == 0;
+        // A new handshake on a used address pair starts a new connection
+        if opening && open.get(&key).is_some_and(Connection::has_data) {
+            closed.extend(open.remove(&key));
+        }
+        let conn = open.entry(key).or_insert_with(|| Connection::new(key));
+        conn.add(&segment, packet.ts_ms, opening);
+    }
+    closed.extend(open.into_values());
+
+    Ok(closed.iter().flat_map(Connection::exchanges).collect())
+}
+
+struct Packet<'a> {
+    ts_ms: i128,
+    link: u32,
+    data: &'a [u8],
+}
+
+/// Fixed-endianness reads that return `None` past the end
+#[derive(Clone, Copy)]
+struct Bytes<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl Bytes<'_> {
+    fn u16(&self, at: usize) -> Option<u16> {
+        let raw: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(raw)
+        } else {
+            u16::from_le_bytes(raw)
+        })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let raw: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(raw)
+        } else {
+            u32::from_le_bytes(raw)
+        })
+    }
+}
+
+fn be16(data: &[u8], at: usize) -> Option<u16> {
+    Bytes {
+        data,
+        big_endian: true,
+    }
+    .u16(at)
+}
+
+fn be32(data: &[u8], at: usize) -> Option<u32> {
+    Bytes {
+        data,
+        big_endian: true,
+    }
+    .u32(at)
+}
+
+fn read_pcap(data: &[u8]) -> Result<Vec<Packet<'_>>> {
+    let (big_endian, nanos) = match data.get(..4) {
+        Some([0xa1, 0xb2, 0xc3, 0xd4]) => (true, false),
+        Some([0xd4, 0xc3, 0xb2, 0xa1]) => (false, false),
+        Some([0xa1, 0xb2, 0x3c, 0x4d]) => (true, true),
+        Some([0x4d, 0x3c, 0xb2, 0xa1]) => (false, true),
+        _ => return Err(invalid("not a pcap file")),
+    };
+    let reader = Bytes { data, big_endian };
+    let link = reader
+        .u32(20)
+        .ok_or_else(|| invalid("truncated pcap header"))?;
+    let frac_per_ms = if nanos { 1_000_000 } else { 1_000 };
+
+    let mut packets = Vec::new();
+    let mut pos = 24;
+    // A capture cut off mid-record keeps the packets before it
+    while let (Some(secs), Some(frac), Some(len)) =
+        (reader.u32(pos), reader.u32(pos + 4), reader.u32(pos + 8))
+    {
+        let start = pos + 16;
+        let Some(packet) = data.get(start..start + len as usize) else {
+            break;
+        };
+        packets.push(Packet {
+            ts_ms: secs as i128 * 1000 + (frac / frac_per_ms) as i128,
+            link,
+            data: packet,
+        });
+        pos = start + len as usize;
+    }
+    Ok(packets)
+}
+
+fn read_pcapng(data: &[u8]) -> Result<Vec<Packet<'_>>> {
+    let mut reader = Bytes {
+        data,
+        big_endian: false,
+    };
+    // (link type, timestamp units per second) per interface in the section
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut packets = Vec::new();
+    let mut pos = 0;
+    while pos + 12 <= data.len() {
+        if reader.u32(pos) == Some(PCAPNG_SECTION) {
+            reader.big_endian = match data.get(pos + 8..pos + 12) {
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => true,
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => false,
+                _ => return Err(invalid("invalid pcapng byte-order magic")),
+            };
+            interfaces.clear();
+        }
+        let kind = reader.u32(pos).unwrap_or_default();
+        let len = reader.u32(pos + 4).unwrap_or_default() as usize;
+        if len < 12 || pos + len > data.len() {
+            break;
+        }
+        let body = Bytes {
+            data: &data[pos + 8..pos + len - 4],
+            big_endian: reader.big_endian,
+        };
+        match kind {
+            PCAPNG_INTERFACE => {
+                let link = body.u16(0).unwrap_or_default() as u32;
+                interfaces.push((link, interface_resolution(body)));
+            }
+            PCAPNG_ENHANCED_PACKET => {
+                let (Some(interface), Some(high), Some(low), Some(captured)) =
+                    (body.u32(0), body.u32(4), body.u32(8), body.u32(12))
+                else {
+                    break;
+                };
+                let Some(&(link, units)) = interfaces.get(interface as usize) else {
+                    break;
+                };
+                let ticks = ((high as u64) << 32) | low as u64;
+                if let Some(packet) = body.data.get(20..20 + captured as usize) {
+                    packets.push(Packet {
+                        ts_ms: ticks as i128 * 1000 / units as i128,
+                        link,
+                        data: packet,
+                    });
+                }
+            }
+            PCAPNG_SIMPLE_PACKET => {
+                // No timestamp; the original length may exceed what was captured
+                if let (Some(&(link, _)), Some(original)) = (interfaces.first(), body.u32(0)) {
+                    let end = body.data.len().min(4 + original as usize);
+                    packets.push(Packet {
+                        ts_ms: 0,
+                        link,
+                        data: &body.data[4..end],
+                    });
+                }
+            }
+            _ => {}
+        }
+        pos += len;
+    }
+    Ok(packets)
+}
+
+/// `if_tsresol` from interface description options, microseconds by default
+fn interface_resolution(body: Bytes<'_>) -> u64 {
+    let mut pos = 8;
+    while let (Some(code), Some(len)) = (body.u16(pos), body.u16(pos + 2)) {
+        if code == 0 {
+            break;
+        }
+        if code == 9 {
+            if let Some(&value) = body.data.get(pos + 4) {
+                let exp = (value & 0x7f) as u32;
+                let units = if value & 0x80 != 0 {
+                    2u64.checked_pow(exp)
+                } else {
+                    10u64.checked_pow(exp)
+                };
+                return units.filter(|&u| u > 0).unwrap_or(1_000_000);
+            }
+        }
+        pos += 4 + (len as usize).div_ceil(4) * 4;
+    }
+    1_000_000
+}
+
+fn ip_payload(link: u32, frame: &[u8]) -> Option<&[u8]> {
+    match link {
+        LINK_ETHERNET => {
+            let mut ethertype = be16(frame, 12)?;
+            let mut offset = 14;
+            // 802.1Q and 802.1ad tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                ethertype = be16(frame, offset + 2)?;
+                offset += 4;
+            }
+            matches!(ethertype, 0x0800 | 0x86dd).then(|| frame.get(offset..))?
+        }
+        LINK_NULL | LINK_LOOP => frame.get(4..),
+        LINK_RAW | 12 | 14 => Some(frame),
+        LINK_LINUX_SLL => frame.get(16..),
+        LINK_LINUX_SLL2 => frame.get(20..),
+        _ => None,
+    }
+}
+
+struct Segment<'a> {
+    src: SocketAddr,
+    dst: SocketAddr,
+    seq: u32,
+    flags: u8,
+    payload: &'a [u8],
+}
+
+fn tcp_segment(ip: &[u8]) -> Option<Segment<'_>> {
+    let (src, dst, tcp) = match ip.first()? >> 4 {
+        4 => {
+            let header_len = ((ip[0] & 0x0f) as usize) * 4;
+            let total = (be16(ip, 2)? as usize).min(ip.len());
+            // Fragments are rare for TCP and not reassembled
+            if ip.get(9)? != &6 || be16(ip, 6)? & 0x3fff != 0 {
+                return None;
+            }
+            let src: [u8; 4] = ip.get(12..16)?.try_into().ok()?;
+            let dst: [u8; 4] = ip.get(16..20)?.try_into().ok()?;
+            (
+                IpAddr::V4(Ipv4Addr::from(src)),
+                IpAddr::V4(Ipv4Addr::from(dst)),
+                ip.get(header_len..total)?,
+            )
+        }
+        6 => {
+            let end = (40 + be16(ip, 4)? as usize).min(ip.len());
+            let mut next = *ip.get(6)?;
+            let mut offset = 40;
+            // Hop-by-hop, routing and destination options
+            while matches!(next, 0 | 43 | 60) {
+                next = *ip.get(offset)?;
+                offset += (*ip.get(offset + 1)? as usize + 1) * 8;
+            }
+            if next != 6 {
+                return None;
+            }
+            let src: [u8; 16] = ip.get(8..24)?.try_into().ok()?;
+            let dst: [u8; 16] = ip.get(24..40)?.try_into().ok()?;
+            (
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                ip.get(offset..end)?,
+            )
+        }
+        _ => return None,
+    };
+    let data_offset = ((tcp.get(12)? >> 4) as usize) * 4;
+    Some(Segment {
+        src: SocketAddr::new(src, be16(tcp, 0)?),
+        dst: SocketAddr::new(dst, be16(tcp, 2)?),
+        seq: be32(tcp, 4)?,
+        flags: *tcp.get(13)?,
+        payload: tcp.get(data_offset..)?,
+    })
+}
+
+/// One direction of a connection
+#[derive(Default)]
+struct Half {
+    /// Sequence number of the first data byte, known once the SYN is seen
+    start: Option<u32>,
+    segments: Vec<(u32, i128, Vec<u8>)>,
+}
+
+struct Connection {
+    ends: [SocketAddr; 2],
+    halves: [Half; 2],
+    client: Option<usize>,
+}
+
+/// Reassembled bytes of one direction with the capture time of each piece
+struct Stream {
+    data: Vec<u8>,
+    marks: Vec<(usize, i128)>,
+}
+
+impl Connection {
+    fn new(key: (SocketAddr, SocketAddr)) -> Self {
+        Self {
+            ends: [key.0, key.1],
+            halves: Default::default(),
+            client: None,
+        }
+    }
+
+    fn has_data(&self) -> bool {
+        self.halves.iter().any(|half| !half.segments.is_empty())
+    }
+
+    fn add(&mut self, segment: &Segment<'_>, ts_ms: i128, opening: bool) {
+        let side = usize::from(segment.src != self.ends[0]);
+        let half = &mut self.halves[side];
+        if segment.flags & TCP_SYN != 0 {
+            half.start = Some(segment.seq.wrapping_add(1));
+            if opening {
+                self.client = Some(side);
+            }
+        }
+        if !segment.payload.is_empty() {
+            half.segments
+                .push((segment.seq, ts_ms, segment.payload.to_vec()));
+        }
+    }
+
+    fn exchanges(&self) -> Vec<CaptureEntry> {
+        let streams = [self.halves[0].assemble(), self.halves[1].assemble()];
+        let looks_like_client =
+            |side: usize| matches!(http1::parse_request(&streams[side].data), Ok(Some(_)));
+        let Some(client) = self
+            .client
+            .filter(|&side| looks_like_client(side))
+            .or_else(|| (0..2).find(|&side| looks_like_client(side)))
+        else {
+            return Vec::new();
+        };
+        let (requests, responses) = (&streams[client], &streams[1 - client]);
+        let server = self.ends[1 - client];
+
+        let mut entries = Vec::new();
+        let (mut req_pos, mut resp_pos) = (0, 0);
+        while let Ok(Some((request, used))) = http1::parse_request(&requests.data[req_pos..]) {
+            let started = requests.time_at(req_pos);
+            req_pos += used;
+
+            let mut response = None;
+            while let Ok(Some((parsed, used))) =
+                http1::parse_response(&responses.data[resp_pos..], &request.method)
+            {
+                resp_pos += used;
+                // Interim responses precede the real one
+                if (100..200).contains(&parsed.status) && parsed.status != 101 {
+                    continue;
+                }
+                let finished = responses.time_at(resp_pos.saturating_sub(1));
+                response = Some(CapturedResponse {
+                    request_id: 0,
+                    status_code: parsed.status,
+                    headers: parsed.headers,
+                    body: parsed.body,
+                    duration_ms: (finished - started).max(0) as u128,
+                });
+                break;
+            }
+
+            let host = http1::header(&request.headers, "host")
+                .map(str::to_string)
+                .unwrap_or_else(|| authority(&server.ip().to_string(), server.port(), false));
+            let upgraded = response.as_ref().is_some_and(|r| r.status_code == 101);
+            entries.push(CaptureEntry {
+                request: CapturedRequest {
+                    id: 0,
+                    timestamp_ms: started,
+                    method: request.method,
+                    url: absolute_url(&request.target, "http", &host),
+                    headers: request.headers,
+                    body: request.body,
+                    tls: false,
+                    source: CaptureSource::Import,
+                },
+                response,
+                annotation: Annotation::default(),
+                omitted_bodies: None,
+            });
+            // The rest of the connection is no longer HTTP
+            if upgraded {
+                break;
+            }
+        }
+        entries
+    }
+}
+
+impl Half {
+    fn assemble(&self) -> Stream {
+        let mut stream = Stream {
+            data: Vec::new(),
+            marks: Vec::new(),
+        };
+        let Some(first) = self.start.or_else(|| self.segments.first().map(|s| s.0)) else {
+            return stream;
+        };
+        // Offsets relative to the first byte; without a SYN, earlier
+        // retransmissions can sit before the first segment seen
+        let mut ordered: Vec<(i64, i128, &[u8])> = self
+            .segments
+            .iter()
+            .map(|(seq, ts, data)| (seq.wrapping_sub(first) as i32 as i64, *ts, data.as_slice()))
+            .collect();
+        let lowest = ordered.iter().map(|s| s.0).min().unwrap_or(0);
+        if self.start.is_none() && lowest < 0 {
+            ordered.iter_mut().for_each(|s| s.0 -= lowest);
+        }
+        ordered.sort_by_key(|s| s.0);
+
+        for (offset, ts, data) in ordered {
+            let end = offset + data.len() as i64;
+            let have = stream.data.len() as i64;
+            if offset < 0 || end <= have {
+                continue;
+            }
+            // Stop at the first hole; later bytes cannot be placed
+            if offset > have {
+                break;
+            }
+            stream.marks.push((stream.data.len(), ts));
+            stream
+                .data
+                .extend_from_slice(&data[(have - offset) as usize..]);
+        }
+        stream
+    }
+}
+
+impl Stream {
+    fn time_at(&self, offset: usize) -> i128 {
+        let index = self.marks.partition_point(|(at, _)| *at <= offset);
+        self.marks
+            .get(index.saturating_sub(1))
+            .map_or(0, |(_, ts)| *ts)
+    }
+}
+
+fn invalid(msg: &str) -> ProxyError {
+    ProxyError::InvalidRequest(msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT: [u8; 4] = [10, 0, 0, 2];
+    const SERVER: [u8; 4] = [10, 0, 0, 1];
+
+    /// Ethernet + IPv4 + TCP frame
+    fn frame(from_client: bool, seq: u32, flags: u8, payload: &[u8]) -> Vec<u8> {
+        let (src, dst, sport, dport) = if from_client {
+            (CLIENT, SERVER, 50000u16, 8080u16)
+        } else {
+            (SERVER, CLIENT, 8080, 50000)
+        };
+        let mut tcp = Vec::new();
+        tcp.extend_from_slice(&sport.to_be_bytes());
+        tcp.extend_from_slice(&dport.to_be_bytes());
+        tcp.extend_from_slice(&seq.to_be_bytes());
+        tcp.extend_from_slice(&0u32.to_be_bytes());
+        tcp.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+
+        let mut ip = vec![0x45, 0];
+        ip.extend_from_slice(&((20 + tcp.len()) as u16).to_be_bytes());
+        ip.extend_from_slice(&[0, 0, 0x40, 0, 64, 6, 0, 0]);
+        ip.extend_from_slice(&src);
+        ip.extend_from_slice(&dst);
+        ip.extend_from_slice(&tcp);
+
+        let mut eth = vec![0u8; 12];
+        eth.extend_from_slice(&[0x08, 0x00]);
+        eth.extend_from_slice(&ip);
+        eth
+    }
+
+    fn pcap(packets: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let mut out = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&65535u32.to_le_bytes());
+        out.extend_from_slice(&LINK_ETHERNET.to_le_bytes());
+        for (ms, data) in packets {
+            out.extend_from_slice(&(1_700_000_000 + ms / 1000).to_le_bytes());
+            out.extend_from_slice(&((ms % 1000) * 1000).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    #[test]
+    fn test_reassembles_http_exchanges() {
+        let req1 = b"POST /login HTTP/1.1\r\nHost: app.test:8080\r\nContent-Length: 5\r\n\r\nhello";
+        let req2 = b"GET /next HTTP/1.1\r\n\r\n";
+        let resp1 = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let resp2 = b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+        let c = 1000u32;
+        let s = 5000u32;
+        let split = 20;
+        let packets = vec![
+            (0, frame(true, c - 1, TCP_SYN, b"")),
+            (1, frame(false, s - 1, TCP_SYN | TCP_ACK, b"")),
+            // Second half of the first request arrives first, then is retransmitted
+            (12, frame(true, c + split as u32, TCP_ACK, &req1[split..])),
+            (10, frame(true, c, TCP_ACK, &req1[..split])),
+            (13, frame(true, c + split as u32, TCP_ACK, &req1[split..])),
+            (40, frame(false, s, TCP_ACK, resp1)),
+            (50, frame(true, c + req1.len() as u32, TCP_ACK, req2)),
+            (75, frame(false, s + resp1.len() as u32, TCP_ACK, resp2)),
+        ];
+        let entries = parse(&pcap(&packets)).unwrap();
+        assert_eq!(entries.len(), 2);
+
+        let login = &entries[0];
+        assert_eq!(login.request.url, "http://app.test:8080/login");
+        assert_eq!(login.request.body, b"hello");
+        assert_eq!(login.request.timestamp_ms, 1_700_000_000_010);
+        let response = login.response.as_ref().unwrap();
+        assert_eq!(response.status_code, 200);
+        assert_eq!(response.body, b"ok");
+        assert_eq!(response.duration_ms, 30);
+
+        let next = &entries[1];
+        assert_eq!(next.request.url, "http://10.0.0.1:8080/next");
+        assert_eq!(next.response.as_ref().unwrap().status_code, 404);
+    }
+
+    #[test]
+    fn test_reads_pcapng() {
+        let request = b"GET / HTTP/1.1\r\nHost: ng.test\r\n\r\n";
+        let ip = frame(true, 1, TCP_ACK, request)[14..].to_vec();
+
+        let block = |kind: u32, body: Vec<u8>| {
+            let len = (12 + body.len()) as u32;
+            let mut out = kind.to_le_bytes().to_vec();
+            out.extend_from_slice(&len.to_le_bytes());
+            out.extend_from_slice(&body);
+            out.extend_from_slice(&len.to_le_bytes());
+            out
+        };
+        let mut section = 0x1a2b3c4du32.to_le_bytes().to_vec();
+        section.extend_from_slice(&[1, 0, 0, 0]);
+        section.extend_from_slice(&u64::MAX.to_le_bytes());
+        // Raw IP, nanosecond resolution
+        let mut interface = (LINK_RAW as u16).to_le_bytes().to_vec();
+        interface.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        interface.extend_from_slice(&[9, 0, 1, 0, 9, 0, 0, 0, 0, 0, 0, 0]);
+        let ticks: u64 = 1_700_000_000_123_456_789;
+        let mut packet = 0u32.to_le_bytes().to_vec();
+        packet.extend_from_slice(&((ticks >> 32) as u32).to_le_bytes());
+        packet.extend_from_slice(&(ticks as u32).to_le_bytes());
+        packet.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&(ip.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&ip);
+        packet.resize(packet.len().div_ceil(4) * 4, 0);
+
+        let mut file = block(PCAPNG_SECTION, section);
+        file.extend(block(PCAPNG_INTERFACE, interface));
+        file.extend(block(PCAPNG_ENHANCED_PACKET, packet));
+        assert!(is_capture(&file));
+
+        let entries = parse(&file).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].request.url, "http://ng.test/");
+        assert_eq!(entries[0].request.timestamp_ms, 1_700_000_000_123);
+        assert!(entries[0].response.is_none());
+    }
+
+    #[test]
+    fn test_rejects_garbage() {
+        assert!(parse(b"not a capture").is_err());
+    }
+}
@@ -0,0 +1,374 @@
+//! Grep rules, anomaly flags and result queries for intruder attacks
+
+use super::{IntruderOptions, IntruderResult};
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+/// Length differences below this many bytes are never flagged
+const LENGTH_SLACK: usize = 10;
+/// Responses this many times slower than the median are flagged...
+const TIME_FACTOR: u64 = 3;
+/// ...as long as they are also at least this much slower
+const TIME_SLACK_MS: u64 = 250;
+
+/// Way in which a result stands out from the rest of the attack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anomaly {
+    /// Status differs from the most common one
+    Status,
+    /// Length is away from the median
+    Length,
+    /// Much slower than the median
+    Time,
+}
+
+/// Compiled grep-match and grep-extract patterns
+pub(crate) struct Grep {
+    matchers: Vec<Regex>,
+    extractors: Vec<Regex>,
+}
+
+impl Grep {
+    pub(crate) fn compile(options: &IntruderOptions) -> Result<Self> {
+        let compile = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|p| {
+                    Regex::new(p).map_err(|e| anyhow::anyhow!("Invalid grep pattern {p}: {e}"))
+                })
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            matchers: compile(&options.grep_match)?,
+            extractors: compile(&options.grep_extract)?,
+        })
+    }
+
+    /// Match flags and extracted values, one per pattern
+    ///
+    /// Extraction takes the first capture group when the pattern has one.
+    pub(crate) fn apply(&self, text: &str) -> (Vec<bool>, Vec<Option<String>>) {
+        let matches = self.matchers.iter().map(|re| re.is_match(text)).collect();
+        let extracted = self
+            .extractors
+            .iter()
+            .map(|re| {
+                let captures = re.captures(text)?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|m| m.as_str().to_string())
+            })
+            .collect();
+        (matches, extracted)
+    }
+}
+
+/// Text the grep patterns run against: headers, blank line, body
+pub(crate) fn grep_text(headers: &[(String, String)], body: &[u8]) -> String {
+    let mut text = String::new();
+    for (name, value) in headers {
+        text.push_str(name);
+        text.push_str(": ");
+        text.push_str(value);
+        text.push_str("\r\n");
+    }
+    text.push_str("\r\n");
+    text.push_str(&String::from_utf8_lossy(body));
+    text
+}
+
+/// Typical response of an attack that anomalies are measured against
+///
+/// The most common status and the median length and time of the results
+/// that got a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Baseline {
+    status: u16,
+    length: usize,
+    duration_ms: u64,
+}
+
+impl Baseline {
+    /// `None` until at least two results got a response
+    pub(crate) fn of(results: &[IntruderResult]) -> Option<Self> {
+        let answered: Vec<&IntruderResult> = results.iter().filter(|r| r.error.is_none()).collect();
+        if answered.len() < 2 {
+            return None;
+        }
+
+        let mut statuses: HashMap<u16, usize> = HashMap::new();
+        for result in &answered {
+            *statuses.entry(result.status_code).or_default() += 1;
+        }
+        let status = statuses
+            .into_iter()
+            .max_by_key(|(status, count)| (*count, Reverse(*status)))
+            .map(|(status, _)| status)
+            .unwrap_or_default();
+        Some(Self {
+            status,
+            length: median(answered.iter().map(|r| r.response_length).collect()),
+            duration_ms: median(answered.iter().map(|r| r.duration_ms).collect()),
+        })
+    }
+
+    /// Ways in which `result` deviates from the baseline
+    pub(crate) fn anomalies(&self, result: &IntruderResult) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+        if result.error.is_some() {
+            return anomalies;
+        }
+        if result.status_code != self.status {
+            anomalies.push(Anomaly::Status);
+        }
+        if result.response_length.abs_diff(self.length) > (self.length / 20).max(LENGTH_SLACK) {
+            anomalies.push(Anomaly::Length);
+        }
+        if result.duration_ms > self.duration_ms.saturating_mul(TIME_FACTOR)
+            && result.duration_ms - self.duration_ms >= TIME_SLACK_MS
+        {
+            anomalies.push(Anomaly::Time);
+        }
+        anomalies
+    }
+}
+
+/// Copy of `result` with its anomaly flags filled in
+pub(crate) fn flagged(result: &IntruderResult, baseline: Option<&Baseline>) -> IntruderResult {
+    IntruderResult {
+        anomalies: baseline.map(|b| b.anomalies(result)).unwrap_or_default(),
+        ..result.clone()
+    }
+}
+
+fn median<T: Ord + Copy + Default>(mut values: Vec<T>) -> T {
+    values.sort_unstable();
+    values.get(values.len() / 2).copied().unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResultSort {
+    #[default]
+    RequestId,
+    Status,
+    Length,
+    Duration,
+    Payload,
+}
+
+/// Filters, ordering and paging for attack results
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ResultQuery {
+    pub status: Option<u16>,
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    /// Substring of any payload
+    pub payload: Option<String>,
+    /// At least one grep-match pattern matched
+    pub matched: Option<bool>,
+    /// Has at least one anomaly flag
+    pub interesting: Option<bool>,
+    /// Failed without a response
+    pub errors: Option<bool>,
+    pub sort: ResultSort,
+    pub descending: bool,
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+impl ResultQuery {
+    fn matches(&self, result: &IntruderResult, anomalies: &[Anomaly]) -> bool {
+        if self.status.is_some_and(|s| s != result.status_code) {
+            return false;
+        }
+        if self.min_length.is_some_and(|l| result.response_length < l)
+            || self.max_length.is_some_and(|l| result.response_length > l)
+        {
+            return false;
+        }
+        if let Some(needle) = &self.payload {
+            if !result.payloads.iter().any(|p| p.contains(needle.as_str())) {
+                return false;
+            }
+        }
+        if self
+            .matched
+            .is_some_and(|m| result.grep_matches.contains(&true) != m)
+        {
+            return false;
+        }
+        if self.interesting.is_some_and(|i| anomalies.is_empty() == i) {
+            return false;
+        }
+        self.errors.is_none_or(|e| result.error.is_some() == e)
+    }
+
+    /// Matching results with their anomaly flags; only the returned page is cloned
+    pub(crate) fn apply(
+        &self,
+        results: &[IntruderResult],
+        baseline: Option<&Baseline>,
+    ) -> Vec<IntruderResult> {
+        let mut matched: Vec<(&IntruderResult, Vec<Anomaly>)> = results
+            .iter()
+            .map(|r| (r, baseline.map(|b| b.anomalies(r)).unwrap_or_default()))
+            .filter(|(r, anomalies)| self.matches(r, anomalies))
+            .collect();
+        match self.sort {
+            ResultSort::RequestId => matched.sort_by_key(|(r, _)| r.request_id),
+            ResultSort::Status => matched.sort_by_key(|(r, _)| (r.status_code, r.request_id)),
+            ResultSort::Length => matched.sort_by_key(|(r, _)| (r.response_length, r.request_id)),
+            ResultSort::Duration => matched.sort_by_key(|(r, _)| (r.duration_ms, r.request_id)),
+            ResultSort::Payload => matched.sort_by(|(a, _), (b, _)| {
+                a.payloads
+                    .cmp(&b.payloads)
+                    .then(a.request_id.cmp(&b.request_id))
+            }),
+        }
+        if self.descending {
+            matched.reverse();
+        }
+        matched
+            .into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .map(|(r, anomalies)| IntruderResult {
+                anomalies,
+                ..r.clone()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(id: usize, status: u16, length: usize, duration_ms: u64) -> IntruderResult {
+        IntruderResult {
+            request_id: id,
+            payloads: vec![format!("p{id}")],
+            status_code: status,
+            response_length: length,
+            duration_ms,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_grep() {
+        let grep = Grep::compile(&IntruderOptions {
+            grep_match: vec!["(?i)welcome".to_string(), "error".to_string()],
+            grep_extract: vec![r"csrf=(\w+)".to_string(), "Server: .*".to_string()],
+            ..Default::default()
+        })
+        .unwrap();
+        let text = grep_text(
+            &[("Set-Cookie".to_string(), "csrf=abc123".to_string())],
+            b"Welcome back",
+        );
+        let (matches, extracted) = grep.apply(&text);
+        assert_eq!(matches, vec![true, false]);
+        assert_eq!(extracted, vec![Some("abc123".to_string()), None]);
+
+        let invalid = IntruderOptions {
+            grep_match: vec!["(".to_string()],
+            ..Default::default()
+        };
+        assert!(Grep::compile(&invalid).is_err());
+    }
+
+    #[test]
+    fn test_anomalies_against_baseline() {
+        let results = vec![
+            result(0, 200, 1000, 40),
+            result(1, 200, 1004, 50),
+            result(2, 302, 1000, 45),
+            result(3, 200, 1500, 60),
+            result(4, 200, 998, 900),
+            IntruderResult {
+                error: Some("connection refused".to_string()),
+                ..result(5, 0, 0, 0)
+            },
+        ];
+        let baseline = Baseline::of(&results).unwrap();
+        let flags: Vec<_> = results.iter().map(|r| baseline.anomalies(r)).collect();
+        assert_eq!(
+            flags,
+            vec![
+                vec![],
+                vec![],
+                vec![Anomaly::Status],
+                vec![Anomaly::Length],
+                vec![Anomaly::Time],
+                vec![],
+            ]
+        );
+        assert_eq!(Baseline::of(&results[..1]), None);
+        assert_eq!(
+            flagged(&results[2], Some(&baseline)).anomalies,
+            vec![Anomaly::Status]
+        );
+        assert!(flagged(&results[2], None).anomalies.is_empty());
+    }
+
+    #[test]
+    fn test_query_filters_and_sorts() {
+        let results = vec![
+            result(0, 200, 205, 10),
+            result(1, 404, 195, 10),
+            result(2, 200, 200, 10),
+            IntruderResult {
+                error: Some("timed out".to_string()),
+                ..result(3, 0, 0, 0)
+            },
+        ];
+
+        let query = ResultQuery {
+            errors: Some(false),
+            sort: ResultSort::Length,
+            descending: true,
+            ..Default::default()
+        };
+        let baseline = Baseline::of(&results);
+        let ids: Vec<_> = query
+            .apply(&results, baseline.as_ref())
+            .iter()
+            .map(|r| r.request_id)
+            .collect();
+        assert_eq!(ids, vec![0, 2, 1]);
+
+        let query = ResultQuery {
+            interesting: Some(true),
+            ..Default::default()
+        };
+        let interesting = query.apply(&results, baseline.as_ref());
+        assert_eq!(interesting.len(), 1);
+        assert_eq!(interesting[0].request_id, 1);
+
+        let query = ResultQuery {
+            payload: Some("p2".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query.apply(&results, baseline.as_ref())[0].request_id, 2);
+
+        let query = ResultQuery {
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let ids: Vec<_> = query
+            .apply(&results, baseline.as_ref())
+            .iter()
+            .map(|r| r.request_id)
+            .collect();
+        assert_eq!(ids, vec![1, 2]);
+    }
+}
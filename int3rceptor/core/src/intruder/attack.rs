@@ -0,0 +1,345 @@
+//! A single intruder attack and the task that runs it
+
+use super::analysis::{flagged, grep_text, Baseline, Grep};
+use super::throttle::{retry_after, Throttle};
+use super::{
+    send_request, GeneratedRequests, IntruderConfig, IntruderResult, ResultQuery, StoredResponse,
+    MAX_CONCURRENCY,
+};
+use crate::connection_pool::ConnectionPool;
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+use tokio::sync::{broadcast, watch, Semaphore};
+
+/// Minimum gap between progress updates while requests complete
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttackStatus {
+    /// Waiting for a free attack slot
+    Queued,
+    Running,
+    Paused,
+    Done,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttackProgress {
+    pub id: u64,
+    pub status: AttackStatus,
+    pub total: u64,
+    /// Requests that completed, with or without a response
+    pub sent: u64,
+    pub errored: u64,
+    /// Index of the next request to dispatch
+    pub next_index: u64,
+    /// Ended by a stop rather than running out of requests
+    #[serde(default)]
+    pub stopped: bool,
+    /// Gap currently added between sends because many requests fail
+    #[serde(default)]
+    pub throttle_ms: u64,
+}
+
+/// A finished attack as kept in a project
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttackRecord {
+    pub id: u64,
+    pub template: String,
+    pub config: IntruderConfig,
+    pub created_at_ms: i128,
+    pub progress: AttackProgress,
+    pub results: Vec<IntruderResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Control {
+    Run,
+    Pause,
+    Stop,
+}
+
+pub(crate) struct Attack {
+    pub(crate) id: u64,
+    template: String,
+    config: IntruderConfig,
+    created_at_ms: i128,
+    progress: RwLock<AttackProgress>,
+    results: RwLock<Vec<IntruderResult>>,
+    /// Anomaly baseline and the number of results it was computed from
+    baseline: Mutex<(usize, Option<Baseline>)>,
+    control: watch::Sender<Control>,
+    throttle: Throttle,
+    last_emit: Mutex<Instant>,
+    notifier: broadcast::Sender<AttackProgress>,
+}
+
+impl Attack {
+    pub(crate) fn new(
+        id: u64,
+        template: String,
+        config: IntruderConfig,
+        total: u64,
+        throttle: Throttle,
+        notifier: broadcast::Sender<AttackProgress>,
+    ) -> Self {
+        Self {
+            id,
+            template,
+            config,
+            created_at_ms: OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000,
+            progress: RwLock::new(AttackProgress {
+                id,
+                status: AttackStatus::Queued,
+                total,
+                sent: 0,
+                errored: 0,
+                next_index: 0,
+                stopped: false,
+                throttle_ms: 0,
+            }),
+            results: RwLock::new(Vec::new()),
+            baseline: Mutex::new((0, None)),
+            control: watch::Sender::new(Control::Run),
+            throttle,
+            last_emit: Mutex::new(Instant::now()),
+            notifier,
+        }
+    }
+
+    pub(crate) fn restore(
+        record: AttackRecord,
+        notifier: broadcast::Sender<AttackProgress>,
+    ) -> Self {
+        let mut progress = record.progress;
+        progress.id = record.id;
+        progress.status = AttackStatus::Done;
+        Self {
+            id: record.id,
+            template: record.template,
+            config: record.config,
+            created_at_ms: record.created_at_ms,
+            progress: RwLock::new(progress),
+            results: RwLock::new(record.results),
+            baseline: Mutex::new((0, None)),
+            control: watch::Sender::new(Control::Stop),
+            throttle: Throttle::new(0, false),
+            last_emit: Mutex::new(Instant::now()),
+            notifier,
+        }
+    }
+
+    pub(crate) fn progress(&self) -> AttackProgress {
+        self.progress.read().clone()
+    }
+
+    pub(crate) fn record(&self) -> AttackRecord {
+        AttackRecord {
+            id: self.id,
+            template: self.template.clone(),
+            config: self.config.clone(),
+            created_at_ms: self.created_at_ms,
+            progress: self.progress(),
+            results: self.results.read().clone(),
+        }
+    }
+
+    pub(crate) fn query(&self, query: &ResultQuery) -> Vec<IntruderResult> {
+        let results = self.results.read();
+        query.apply(&results, self.baseline(&results).as_ref())
+    }
+
+    /// A single result with its anomaly flags
+    pub(crate) fn result(&self, request_id: usize) -> Option<IntruderResult> {
+        let results = self.results.read();
+        let baseline = self.baseline(&results);
+        results
+            .iter()
+            .find(|r| r.request_id == request_id)
+            .map(|r| flagged(r, baseline.as_ref()))
+    }
+
+    /// Results are only ever appended, so the baseline is recomputed only
+    /// when their number changes
+    fn baseline(&self, results: &[IntruderResult]) -> Option<Baseline> {
+        let mut cached = self.baseline.lock();
+        if cached.0 != results.len() {
+            *cached = (results.len(), Baseline::of(results));
+        }
+        cached.1
+    }
+
+    pub(crate) fn control(&self, control: Control) {
+        if self.progress.read().status != AttackStatus::Done {
+            self.control.send_replace(control);
+        }
+    }
+
+    fn set_status(&self, status: AttackStatus) {
+        {
+            let mut progress = self.progress.write();
+            progress.status = status;
+            if status == AttackStatus::Done {
+                progress.stopped = *self.control.borrow() == Control::Stop;
+            }
+        }
+        self.emit(true);
+    }
+
+    fn push(&self, result: IntruderResult) {
+        {
+            let mut progress = self.progress.write();
+            progress.sent += 1;
+            if result.error.is_some() {
+                progress.errored += 1;
+            }
+            progress.throttle_ms = self.throttle.penalty().as_millis() as u64;
+        }
+        self.results.write().push(result);
+        self.emit(false);
+    }
+
+    fn emit(&self, force: bool) {
+        {
+            let mut last = self.last_emit.lock();
+            if !force && last.elapsed() < PROGRESS_INTERVAL {
+                return;
+            }
+            *last = Instant::now();
+        }
+        let _ = self.notifier.send(self.progress());
+    }
+
+    /// Dispatch requests until they run out or the attack is stopped
+    ///
+    /// A running attack holds one of the shared `slots`; pausing gives it
+    /// back and resuming queues for it again, picking up at the next index.
+    pub(crate) async fn run(
+        self: Arc<Self>,
+        mut requests: GeneratedRequests,
+        grep: Grep,
+        pool: ConnectionPool,
+        slots: Arc<Semaphore>,
+    ) {
+        let grep = Arc::new(grep);
+        let mut control = self.control.subscribe();
+        // Validated when the attack is created
+        let concurrency = self.config.options.concurrency.clamp(1, MAX_CONCURRENCY);
+        let delay = self.config.options.delay_ms;
+        let store_responses = self.config.options.store_responses;
+        let target: Option<Arc<str>> = self.config.target.as_deref().map(Arc::from);
+        let inflight = Arc::new(Semaphore::new(concurrency));
+
+        'attack: loop {
+            let slot = tokio::select! {
+                slot = slots.clone().acquire_owned() => slot.ok(),
+                _ = control.wait_for(|c| *c != Control::Run) => None,
+            };
+            if let Some(_slot) = slot {
+                self.set_status(AttackStatus::Running);
+                loop {
+                    match *control.borrow_and_update() {
+                        Control::Run => {}
+                        Control::Pause => break,
+                        Control::Stop => break 'attack,
+                    }
+                    let Some((raw, payloads)) = requests.next() else {
+                        break 'attack;
+                    };
+                    let Ok(permit) = inflight.clone().acquire_owned().await else {
+                        break 'attack;
+                    };
+                    if delay > 0 {
+                        tokio::time::sleep(Duration::from_millis(delay)).await;
+                    }
+                    self.throttle.wait().await;
+
+                    let request_id = {
+                        let mut progress = self.progress.write();
+                        progress.next_index += 1;
+                        progress.next_index as usize - 1
+                    };
+                    let attack = self.clone();
+                    let pool = pool.clone();
+                    let grep = grep.clone();
+                    let target = target.clone();
+                    tokio::spawn(async move {
+                        let _permit = permit;
+                        let retry = &attack.config.options.retry;
+                        let mut control = attack.control.subscribe();
+                        let mut result = IntruderResult {
+                            request_id,
+                            payloads,
+                            ..Default::default()
+                        };
+
+                        let (outcome, duration) = loop {
+                            let start = Instant::now();
+                            let outcome = send_request(&pool, &raw, target.as_deref()).await;
+                            let duration = start.elapsed();
+                            let failed = match &outcome {
+                                Ok((status, headers, _)) if retry.statuses.contains(status) => {
+                                    Some(retry_after(headers))
+                                }
+                                Ok(_) => None,
+                                Err(_) => Some(None),
+                            };
+                            attack.throttle.record(failed.is_some());
+                            let Some(wait) =
+                                failed.and_then(|after| retry.delay(result.retries, after))
+                            else {
+                                break (outcome, duration);
+                            };
+                            tokio::select! {
+                                _ = tokio::time::sleep(wait) => {}
+                                _ = control.wait_for(|c| *c == Control::Stop) => {
+                                    break (outcome, duration);
+                                }
+                            }
+                            attack.throttle.wait().await;
+                            result.retries += 1;
+                        };
+
+                        match outcome {
+                            Ok((status, headers, body)) => {
+                                result.status_code = status;
+                                result.response_length = body.len();
+                                (result.grep_matches, result.extracted) =
+                                    grep.apply(&grep_text(&headers, &body));
+                                if store_responses {
+                                    result.response = Some(StoredResponse {
+                                        headers,
+                                        body: String::from_utf8_lossy(&body).into_owned(),
+                                    });
+                                }
+                            }
+                            Err(e) => result.error = Some(e.to_string()),
+                        }
+                        result.duration_ms = duration.as_millis() as u64;
+                        attack.push(result);
+                    });
+                }
+            }
+
+            // Paused, possibly while still queued
+            if *control.borrow() == Control::Stop {
+                break;
+            }
+            self.set_status(AttackStatus::Paused);
+            match control.wait_for(|c| *c != Control::Pause).await {
+                Ok(c) if *c == Control::Run => {}
+                _ => break,
+            }
+            self.set_status(AttackStatus::Queued);
+        }
+
+        if let Ok(permits) = u32::try_from(concurrency) {
+            let _ = inflight.acquire_many(permits).await;
+        }
+        self.set_status(AttackStatus::Done);
+    }
+}
@@ -0,0 +1,951 @@
+pub mod analysis;
+pub mod attack;
+pub mod payloads;
+mod template;
+pub mod throttle;
+
+use crate::capture::RequestCapture;
+use crate::connection_pool::ConnectionPool;
+use crate::error::{self, ProxyError};
+use anyhow::Result;
+use http_body_util::BodyExt;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, Semaphore};
+
+use analysis::Grep;
+pub use analysis::{Anomaly, ResultQuery, ResultSort};
+use attack::{Attack, Control};
+pub use attack::{AttackProgress, AttackRecord, AttackStatus};
+use payloads::{CompiledSet, PayloadIter, SourceContext};
+pub use payloads::{HashAlgorithm, NumberFormat, PayloadProcessor, PayloadSet, PayloadSource};
+use template::{parse_request, parse_target, Template};
+pub use throttle::RetryPolicy;
+use throttle::Throttle;
+
+/// Attacks allowed to send at the same time; later ones wait as queued
+pub const MAX_RUNNING_ATTACKS: usize = 4;
+
+/// Upper bound on requests in flight for one attack
+pub const MAX_CONCURRENCY: usize = 1024;
+
+/// Lazily generated `(request, payload per position)` pairs
+pub struct GeneratedRequests {
+    total: u64,
+    requests: Box<dyn Iterator<Item = (String, Vec<String>)> + Send>,
+}
+
+impl GeneratedRequests {
+    /// Number of requests the attack will send in full
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+}
+
+impl Iterator for GeneratedRequests {
+    type Item = (String, Vec<String>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.requests.next()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntruderConfig {
+    /// Where requests go, as `scheme://host[:port]`, instead of the request
+    /// line or `Host` header
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<String>,
+    pub positions: Vec<IntruderPosition>,
+    /// Shared payload set, used by positions that have no set of their own
+    #[serde(default)]
+    pub payloads: PayloadSet,
+    pub attack_type: AttackType,
+    #[serde(default)]
+    pub options: IntruderOptions,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntruderOptions {
+    pub concurrency: usize,
+    pub delay_ms: u64,
+    /// Keep response headers and bodies on each result
+    #[serde(default)]
+    pub store_responses: bool,
+    /// Patterns flagged per result when they match the response
+    #[serde(default)]
+    pub grep_match: Vec<String>,
+    /// Patterns whose first group (or whole match) is pulled from each response
+    #[serde(default)]
+    pub grep_extract: Vec<String>,
+    /// Cap on requests per second, retries included; lowered to the
+    /// license tier's cap
+    #[serde(default)]
+    pub max_rps: Option<usize>,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// Slow down while many requests fail or get a retryable status
+    #[serde(default = "default_true")]
+    pub adaptive_throttle: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for IntruderOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 1,
+            delay_ms: 0,
+            store_responses: false,
+            grep_match: Vec::new(),
+            grep_extract: Vec::new(),
+            max_rps: None,
+            retry: RetryPolicy::default(),
+            adaptive_throttle: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IntruderPosition {
+    /// UTF-16 offsets into the template, used when it has no `§name§` marker
+    pub start: usize,
+    pub end: usize,
+    pub name: String,
+    /// Payload set for this position (Pitchfork and ClusterBomb)
+    #[serde(default)]
+    pub payloads: PayloadSet,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AttackType {
+    Sniper,      // One payload set, iterate through each position
+    Battering,   // One payload set, same payload in all positions
+    Pitchfork,   // Multiple payload sets, iterate in parallel
+    ClusterBomb, // Multiple payload sets, all combinations
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IntruderResult {
+    pub request_id: usize,
+    /// Payload placed in each position, in position order
+    pub payloads: Vec<String>,
+    pub status_code: u16,
+    pub response_length: usize,
+    pub duration_ms: u64,
+    /// Attempts repeated after a network error or retryable status
+    #[serde(default)]
+    pub retries: u32,
+    /// Why no response was received
+    #[serde(default)]
+    pub error: Option<String>,
+    /// One flag per grep-match pattern
+    #[serde(default)]
+    pub grep_matches: Vec<bool>,
+    /// One value per grep-extract pattern
+    #[serde(default)]
+    pub extracted: Vec<Option<String>>,
+    /// Filled in when results are read, relative to the whole attack
+    #[serde(default)]
+    pub anomalies: Vec<Anomaly>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response: Option<StoredResponse>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredResponse {
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+pub struct Intruder {
+    attacks: RwLock<Vec<Arc<Attack>>>,
+    next_id: AtomicU64,
+    /// Shared by all attacks, bounding how many run at once
+    slots: Arc<Semaphore>,
+    notifier: broadcast::Sender<AttackProgress>,
+    /// History that extract payload sources read responses from
+    capture: Option<Arc<RequestCapture>>,
+    /// Directory wordlist payload sources are confined to
+    wordlist_dir: Option<PathBuf>,
+    /// Requests per second allowed by the license, per attack
+    max_rps: usize,
+}
+
+impl Intruder {
+    pub fn new() -> Self {
+        let (notifier, _) = broadcast::channel(256);
+        Self {
+            attacks: RwLock::new(Vec::new()),
+            next_id: AtomicU64::new(1),
+            slots: Arc::new(Semaphore::new(MAX_RUNNING_ATTACKS)),
+            notifier,
+            capture: None,
+            wordlist_dir: None,
+            max_rps: usize::MAX,
+        }
+    }
+
+    pub fn with_capture(mut self, capture: Arc<RequestCapture>) -> Self {
+        self.capture = Some(capture);
+        self
+    }
+
+    /// Allow wordlist payload sources, read only from files under `dir`
+    pub fn with_wordlist_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.wordlist_dir = Some(dir.into());
+        self
+    }
+
+    /// Cap every attack at the license tier's `max_rps`
+    pub fn with_max_rps(mut self, max_rps: usize) -> Self {
+        self.max_rps = max_rps;
+        self
+    }
+
+    /// Progress updates for every attack
+    pub fn subscribe(&self) -> broadcast::Receiver<AttackProgress> {
+        self.notifier.subscribe()
+    }
+
+    /// Queue a new attack and return its id
+    pub fn start_attack(
+        &self,
+        template: String,
+        config: IntruderConfig,
+        pool: ConnectionPool,
+    ) -> Result<u64> {
+        let concurrency = config.options.concurrency;
+        if !(1..=MAX_CONCURRENCY).contains(&concurrency) {
+            anyhow::bail!("Concurrency must be between 1 and {MAX_CONCURRENCY}, got {concurrency}");
+        }
+        if let Some(target) = &config.target {
+            parse_target(target)?;
+        }
+        let grep = Grep::compile(&config.options)?;
+        let requests = self.generate_requests(&template, &config)?;
+        let max_rps = match config.options.max_rps {
+            Some(rps) if rps > 0 => rps.min(self.max_rps),
+            _ => self.max_rps,
+        };
+        let throttle = Throttle::new(max_rps, config.options.adaptive_throttle);
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let attack = Arc::new(Attack::new(
+            id,
+            template,
+            config,
+            requests.total(),
+            throttle,
+            self.notifier.clone(),
+        ));
+        self.attacks.write().push(attack.clone());
+        tokio::spawn(attack.run(requests, grep, pool, self.slots.clone()));
+        Ok(id)
+    }
+
+    pub fn pause(&self, id: u64) -> error::Result<AttackProgress> {
+        self.control(id, Control::Pause)
+    }
+
+    /// Continue a paused attack from the request after the last one sent
+    pub fn resume(&self, id: u64) -> error::Result<AttackProgress> {
+        self.control(id, Control::Run)
+    }
+
+    pub fn stop(&self, id: u64) -> error::Result<AttackProgress> {
+        self.control(id, Control::Stop)
+    }
+
+    fn control(&self, id: u64, control: Control) -> error::Result<AttackProgress> {
+        let attack = self.attack(id)?;
+        attack.control(control);
+        Ok(attack.progress())
+    }
+
+    fn attack(&self, id: u64) -> error::Result<Arc<Attack>> {
+        self.attacks
+            .read()
+            .iter()
+            .find(|attack| attack.id == id)
+            .cloned()
+            .ok_or_else(|| ProxyError::IntruderJobNotFound(id.to_string()))
+    }
+
+    /// Stop every attack that has not finished
+    pub fn stop_attack(&self) {
+        for attack in self.attacks.read().iter() {
+            attack.control(Control::Stop);
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.attacks
+            .read()
+            .iter()
+            .any(|attack| attack.progress().status != AttackStatus::Done)
+    }
+
+    pub fn list(&self) -> Vec<AttackProgress> {
+        self.attacks.read().iter().map(|a| a.progress()).collect()
+    }
+
+    pub fn progress(&self, id: u64) -> error::Result<AttackProgress> {
+        Ok(self.attack(id)?.progress())
+    }
+
+    /// Id of the most recently started attack
+    pub fn latest(&self) -> Option<u64> {
+        self.attacks.read().last().map(|attack| attack.id)
+    }
+
+    /// Results of an attack with anomaly flags, filtered and sorted
+    pub fn query(&self, id: u64, query: &ResultQuery) -> error::Result<Vec<IntruderResult>> {
+        Ok(self.attack(id)?.query(query))
+    }
+
+    pub fn get_result(&self, id: u64, request_id: usize) -> error::Result<IntruderResult> {
+        self.attack(id)?
+            .result(request_id)
+            .ok_or_else(|| ProxyError::not_found(format!("Intruder result {request_id}")))
+    }
+
+    /// Stop an attack and drop it with its results
+    pub fn remove(&self, id: u64) -> error::Result<()> {
+        let attack = self.attack(id)?;
+        attack.control(Control::Stop);
+        self.attacks.write().retain(|a| a.id != id);
+        Ok(())
+    }
+
+    /// Drop every finished attack
+    pub fn clear_finished(&self) {
+        self.attacks
+            .write()
+            .retain(|attack| attack.progress().status != AttackStatus::Done);
+    }
+
+    /// Finished attacks, for saving in a project
+    pub fn snapshot(&self) -> Vec<AttackRecord> {
+        self.attacks
+            .read()
+            .iter()
+            .filter(|attack| attack.progress().status == AttackStatus::Done)
+            .map(|attack| attack.record())
+            .collect()
+    }
+
+    /// Replace all attacks with ones loaded from a project
+    pub fn restore(&self, records: Vec<AttackRecord>) {
+        self.stop_attack();
+        let next_id = records.iter().map(|r| r.id).max().unwrap_or(0) + 1;
+        let restored = records
+            .into_iter()
+            .map(|record| Arc::new(Attack::restore(record, self.notifier.clone())))
+            .collect();
+        *self.attacks.write() = restored;
+        self.next_id.fetch_max(next_id, Ordering::SeqCst);
+    }
+
+    /// Stop and drop every attack
+    pub fn clear(&self) {
+        self.stop_attack();
+        self.attacks.write().clear();
+    }
+
+    /// Generate requests with their associated payloads
+    ///
+    /// Payload sets are checked up front; the requests themselves are only
+    /// built as the returned iterator is consumed.
+    pub fn generate_requests(
+        &self,
+        template: &str,
+        config: &IntruderConfig,
+    ) -> Result<GeneratedRequests> {
+        let context = SourceContext {
+            capture: self.capture.as_deref(),
+            wordlist_dir: self.wordlist_dir.as_deref(),
+        };
+        let template = Template::compile(template, &config.positions)?;
+        let count = config.positions.len();
+        let (total, payloads): (u64, Box<dyn Iterator<Item = Vec<String>> + Send>) =
+            match config.attack_type {
+                AttackType::Sniper => {
+                    let shared = CompiledSet::compile(&config.payloads, context)?;
+                    (
+                        shared.len().saturating_mul(count as u64),
+                        sniper(shared, count),
+                    )
+                }
+                AttackType::Battering => {
+                    let shared = CompiledSet::compile(&config.payloads, context)?;
+                    let total = shared.len();
+                    let payloads = shared.iter().map(move |payload| vec![payload; count]);
+                    (total, Box::new(payloads))
+                }
+                AttackType::Pitchfork => {
+                    let sets = position_sets(config, context)?;
+                    let total = sets.iter().map(CompiledSet::len).min().unwrap_or(0);
+                    (total, pitchfork(sets))
+                }
+                AttackType::ClusterBomb => {
+                    let sets = position_sets(config, context)?;
+                    let total = sets
+                        .iter()
+                        .map(CompiledSet::len)
+                        .fold(1, u64::saturating_mul);
+                    (total, Box::new(ClusterBomb::new(sets)))
+                }
+            };
+        if count == 0 {
+            return Ok(GeneratedRequests {
+                total: 0,
+                requests: Box::new(std::iter::empty()),
+            });
+        }
+        Ok(GeneratedRequests {
+            total,
+            requests: Box::new(payloads.map(move |used| (template.fill(&used), used))),
+        })
+    }
+}
+
+/// One position at a time gets each shared payload, the others are emptied
+fn sniper(shared: CompiledSet, count: usize) -> Box<dyn Iterator<Item = Vec<String>> + Send> {
+    Box::new(shared.iter().flat_map(move |payload| {
+        (0..count).map(move |target| {
+            (0..count)
+                .map(|i| {
+                    if i == target {
+                        payload.clone()
+                    } else {
+                        String::new()
+                    }
+                })
+                .collect()
+        })
+    }))
+}
+
+/// Walks the position sets in step, stopping at the shortest
+fn pitchfork(sets: Vec<CompiledSet>) -> Box<dyn Iterator<Item = Vec<String>> + Send> {
+    let mut iters: Vec<PayloadIter> = sets.iter().map(CompiledSet::iter).collect();
+    Box::new(std::iter::from_fn(move || {
+        iters.iter_mut().map(Iterator::next).collect()
+    }))
+}
+
+/// Every combination of the position sets, first position varying fastest
+struct ClusterBomb {
+    sets: Vec<CompiledSet>,
+    iters: Vec<PayloadIter>,
+    current: Option<Vec<String>>,
+    done: bool,
+}
+
+impl ClusterBomb {
+    fn new(sets: Vec<CompiledSet>) -> Self {
+        Self {
+            iters: sets.iter().map(CompiledSet::iter).collect(),
+            sets,
+            current: None,
+            done: false,
+        }
+    }
+}
+
+impl Iterator for ClusterBomb {
+    type Item = Vec<String>;
+
+    fn next(&mut self) -> Option<Vec<String>> {
+        if self.done {
+            return None;
+        }
+        let Some(current) = self.current.as_mut() else {
+            let first: Option<Vec<String>> = self.iters.iter_mut().map(Iterator::next).collect();
+            self.done = first.is_none();
+            self.current = first.clone();
+            return first;
+        };
+        for (i, iter) in self.iters.iter_mut().enumerate() {
+            if let Some(payload) = iter.next() {
+                current[i] = payload;
+                return Some(current.clone());
+            }
+            // This position wrapped; restart it and carry into the next one
+            *iter = self.sets[i].iter();
+            match iter.next() {
+                Some(payload) => current[i] = payload,
+                None => break,
+            }
+        }
+        self.done = true;
+        None
+    }
+}
+
+/// Payload set of each position, falling back to the shared set
+fn position_sets(config: &IntruderConfig, context: SourceContext<'_>) -> Result<Vec<CompiledSet>> {
+    let shared = CompiledSet::compile(&config.payloads, context)?;
+    config
+        .positions
+        .iter()
+        .map(|position| {
+            if position.payloads.is_empty() {
+                Ok(shared.clone())
+            } else {
+                CompiledSet::compile(&position.payloads, context)
+            }
+        })
+        .collect()
+}
+
+/// Send one generated request, returning status, headers and body
+async fn send_request(
+    pool: &ConnectionPool,
+    raw: &str,
+    target: Option<&str>,
+) -> Result<(u16, Vec<(String, String)>, bytes::Bytes)> {
+    let response = pool.client().request(parse_request(raw, target)?).await?;
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.to_string(),
+                String::from_utf8_lossy(v.as_bytes()).into_owned(),
+            )
+        })
+        .collect();
+    let body = response.collect().await?.to_bytes();
+    Ok((status, headers, body))
+}
+
+impl Default for Intruder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_config(
+        positions: Vec<&str>,
+        payloads: Vec<&str>,
+        attack_type: AttackType,
+    ) -> IntruderConfig {
+        IntruderConfig {
+            target: None,
+            positions: positions
+                .into_iter()
+                .enumerate()
+                .map(|(i, name)| IntruderPosition {
+                    start: i * 10,
+                    end: i * 10 + 5,
+                    name: name.to_string(),
+                    payloads: PayloadSet::default(),
+                })
+                .collect(),
+            payloads: payloads
+                .into_iter()
+                .map(String::from)
+                .collect::<Vec<_>>()
+                .into(),
+            attack_type,
+            options: IntruderOptions::default(),
+        }
+    }
+
+    #[test]
+    fn test_sniper_attack() {
+        let intruder = Intruder::new();
+        let config = create_config(vec!["pos1", "pos2"], vec!["A", "B"], AttackType::Sniper);
+        let template = "param1=§pos1§&param2=§pos2§";
+
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        let requests: Vec<String> = results.into_iter().map(|(r, _)| r).collect();
+
+        // Sniper: each payload in each position = 2 payloads * 2 positions = 4
+        assert_eq!(requests.len(), 4);
+
+        // Check that each request has one position filled and others empty
+        assert!(requests.contains(&"param1=A&param2=".to_string()));
+        assert!(requests.contains(&"param1=&param2=A".to_string()));
+        assert!(requests.contains(&"param1=B&param2=".to_string()));
+        assert!(requests.contains(&"param1=&param2=B".to_string()));
+    }
+
+    #[test]
+    fn test_battering_attack() {
+        let intruder = Intruder::new();
+        let config = create_config(vec!["pos1", "pos2"], vec!["X", "Y"], AttackType::Battering);
+        let template = "a=§pos1§&b=§pos2§";
+
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        let requests: Vec<String> = results.into_iter().map(|(r, _)| r).collect();
+
+        // Battering: same payload in all positions = 2 payloads
+        assert_eq!(requests.len(), 2);
+        assert!(requests.contains(&"a=X&b=X".to_string()));
+        assert!(requests.contains(&"a=Y&b=Y".to_string()));
+    }
+
+    #[test]
+    fn test_pitchfork_attack() {
+        let intruder = Intruder::new();
+        let config = create_config(
+            vec!["user", "pass"],
+            vec!["admin", "secret"],
+            AttackType::Pitchfork,
+        );
+        let template = "username=§user§&password=§pass§";
+
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        let requests: Vec<String> = results.into_iter().map(|(r, _)| r).collect();
+
+        // Pitchfork: parallel iteration = min(payloads, positions) iterations
+        assert_eq!(requests.len(), 2);
+        // Both positions get same index payload
+        assert!(requests.contains(&"username=admin&password=admin".to_string()));
+        assert!(requests.contains(&"username=secret&password=secret".to_string()));
+    }
+
+    #[test]
+    fn test_cluster_bomb_attack() {
+        let intruder = Intruder::new();
+        let config = create_config(vec!["p1", "p2"], vec!["1", "2"], AttackType::ClusterBomb);
+        let template = "x=§p1§&y=§p2§";
+
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        let requests: Vec<String> = results.into_iter().map(|(r, _)| r).collect();
+
+        // Cluster bomb: all combinations = 2^2 = 4
+        assert_eq!(requests.len(), 4);
+        assert!(requests.contains(&"x=1&y=1".to_string()));
+        assert!(requests.contains(&"x=1&y=2".to_string()));
+        assert!(requests.contains(&"x=2&y=1".to_string()));
+        assert!(requests.contains(&"x=2&y=2".to_string()));
+    }
+
+    #[test]
+    fn test_per_position_payload_sets() {
+        let intruder = Intruder::new();
+        let mut config = create_config(vec!["user", "pass"], vec![], AttackType::Pitchfork);
+        config.positions[0].payloads =
+            vec!["admin".to_string(), "root".into(), "guest".into()].into();
+        config.positions[1].payloads = vec!["hunter2".to_string(), "toor".into()].into();
+        let template = "u=§user§&p=§pass§";
+
+        // Pitchfork stops at the shortest set
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "u=admin&p=hunter2".to_string(),
+                    vec!["admin".to_string(), "hunter2".to_string()]
+                ),
+                (
+                    "u=root&p=toor".to_string(),
+                    vec!["root".to_string(), "toor".to_string()]
+                ),
+            ]
+        );
+
+        // ClusterBomb takes the product of the sets; positions without a set use the shared one
+        config.attack_type = AttackType::ClusterBomb;
+        config.positions[1].payloads = PayloadSet::default();
+        config.payloads = vec!["x".to_string(), "y".into()].into();
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[0].1, vec!["admin", "x"]);
+        assert_eq!(results[5].1, vec!["guest", "y"]);
+        assert!(results.iter().any(|(r, _)| r == "u=root&p=y"));
+
+        // Sniper records an empty payload for the positions left blank
+        config.attack_type = AttackType::Sniper;
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results[1],
+            ("u=&p=x".to_string(), vec![String::new(), "x".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_generation_is_lazy() {
+        let intruder = Intruder::new();
+        let mut config = create_config(vec!["id", "sig"], vec![], AttackType::ClusterBomb);
+        config.payloads = PayloadSet {
+            sources: vec![PayloadSource::Numbers {
+                from: 0,
+                to: 999_999,
+                step: 1,
+                format: NumberFormat::default(),
+            }],
+            processors: vec![PayloadProcessor::Prefix {
+                value: "u".to_string(),
+            }],
+        };
+
+        let mut requests = intruder.generate_requests("/§id§/§sig§", &config).unwrap();
+        assert_eq!(requests.next().unwrap().0, "/u0/u0");
+        assert_eq!(requests.nth(999_999).unwrap().0, "/u0/u1");
+    }
+
+    #[test]
+    fn test_cluster_bomb_empty_positions() {
+        let intruder = Intruder::new();
+        let config = IntruderConfig {
+            target: None,
+            positions: vec![],
+            payloads: vec!["a".to_string()].into(),
+            attack_type: AttackType::ClusterBomb,
+            options: IntruderOptions::default(),
+        };
+
+        let requests = intruder
+            .generate_requests("template", &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert!(requests.is_empty());
+    }
+
+    #[test]
+    fn test_single_position_sniper() {
+        let intruder = Intruder::new();
+        let config = create_config(vec!["id"], vec!["1", "2", "3"], AttackType::Sniper);
+        let template = "/user/§id§";
+
+        let results = intruder
+            .generate_requests(template, &config)
+            .unwrap()
+            .collect::<Vec<_>>();
+        let requests: Vec<String> = results.into_iter().map(|(r, _)| r).collect();
+
+        assert_eq!(requests.len(), 3);
+        assert!(requests.contains(&"/user/1".to_string()));
+        assert!(requests.contains(&"/user/2".to_string()));
+        assert!(requests.contains(&"/user/3".to_string()));
+    }
+
+    async fn wait_for(intruder: &Intruder, id: u64, status: AttackStatus) -> AttackProgress {
+        for _ in 0..500 {
+            let progress = intruder.progress(id).unwrap();
+            if progress.status == status {
+                return progress;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        panic!("attack {id} never reached {status:?}");
+    }
+
+    #[tokio::test]
+    async fn test_attack_pause_resume_and_restore() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = stream.read(&mut buf).await {
+                        if n == 0 {
+                            break;
+                        }
+                        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+                        if stream.write_all(response).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let intruder = Intruder::new();
+        let mut progress = intruder.subscribe();
+        let mut config = create_config(vec!["n"], vec![], AttackType::Sniper);
+        config.payloads = PayloadSet {
+            sources: vec![PayloadSource::Null { count: 6 }],
+            processors: vec![],
+        };
+        config.options.delay_ms = 60;
+        let template = format!("GET http://{addr}/§n§ HTTP/1.1\nHost: {addr}\n\n");
+        let id = intruder
+            .start_attack(template, config, ConnectionPool::new())
+            .unwrap();
+        assert_eq!(intruder.progress(id).unwrap().total, 6);
+
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+        intruder.pause(id).unwrap();
+        let paused = wait_for(&intruder, id, AttackStatus::Paused).await;
+        assert!(paused.next_index < 6);
+        tokio::time::sleep(std::time::Duration::from_millis(150)).await;
+        assert_eq!(intruder.progress(id).unwrap().next_index, paused.next_index);
+
+        intruder.resume(id).unwrap();
+        let done = wait_for(&intruder, id, AttackStatus::Done).await;
+        assert_eq!((done.sent, done.errored, done.next_index), (6, 0, 6));
+        assert!(!done.stopped);
+        let ids: Vec<_> = intruder
+            .query(id, &ResultQuery::default())
+            .unwrap()
+            .iter()
+            .map(|r| r.request_id)
+            .collect();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5]);
+
+        let mut statuses = Vec::new();
+        while let Ok(update) = progress.try_recv() {
+            statuses.push(update.status);
+        }
+        assert!(statuses.contains(&AttackStatus::Paused));
+        assert_eq!(statuses.last(), Some(&AttackStatus::Done));
+
+        let restored = Intruder::new();
+        restored.restore(intruder.snapshot());
+        assert_eq!(restored.list().len(), 1);
+        assert_eq!(restored.get_result(id, 3).unwrap().status_code, 200);
+        assert!(restored.resume(id).is_ok());
+        assert_eq!(restored.progress(id).unwrap().status, AttackStatus::Done);
+        assert!(restored.pause(id + 1).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_retries_under_license_rate_cap() {
+        use std::sync::atomic::AtomicUsize;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let served = Arc::new(AtomicUsize::new(0));
+        let counter = served.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let counter = counter.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok(n) = stream.read(&mut buf).await {
+                        if n == 0 {
+                            break;
+                        }
+                        let response: &[u8] = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                            b"HTTP/1.1 503 Busy\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n"
+                        } else {
+                            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                        };
+                        if stream.write_all(response).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        let intruder = Intruder::new().with_max_rps(20);
+        let mut config = create_config(vec!["p"], vec!["a", "b", "c"], AttackType::Sniper);
+        config.target = Some(format!("http://{addr}"));
+        config.options.max_rps = Some(1000);
+        config.options.adaptive_throttle = false;
+        config.options.retry = RetryPolicy {
+            max_retries: 2,
+            backoff_ms: 10,
+            ..Default::default()
+        };
+        let start = std::time::Instant::now();
+        let id = intruder
+            .start_attack(
+                "GET /§p§ HTTP/1.1\nHost: example.com\n\n".to_string(),
+                config,
+                ConnectionPool::new(),
+            )
+            .unwrap();
+        let done = wait_for(&intruder, id, AttackStatus::Done).await;
+        assert_eq!((done.sent, done.errored), (3, 0));
+        assert_eq!(served.load(Ordering::SeqCst), 4);
+        // Four sends at 20 per second
+        assert!(start.elapsed() >= std::time::Duration::from_millis(150));
+
+        let results = intruder.query(id, &ResultQuery::default()).unwrap();
+        assert!(results.iter().all(|r| r.status_code == 200));
+        assert_eq!(results[0].retries, 1);
+        assert_eq!(results[1].retries + results[2].retries, 0);
+    }
+
+    #[tokio::test]
+    async fn test_failed_requests_are_recorded() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let intruder = Intruder::new();
+        let config = create_config(vec!["id"], vec!["1", "2"], AttackType::Sniper);
+        let template = format!("GET http://{addr}/§id§ HTTP/1.1\n\n");
+        let id = intruder
+            .start_attack(template, config, ConnectionPool::new())
+            .unwrap();
+        let done = wait_for(&intruder, id, AttackStatus::Done).await;
+        assert_eq!((done.sent, done.errored), (2, 2));
+
+        let failed = ResultQuery {
+            errors: Some(true),
+            ..Default::default()
+        };
+        let results = intruder.query(id, &failed).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.error.is_some()));
+        assert!(intruder.get_result(id, 1).unwrap().error.is_some());
+        assert!(intruder.get_result(id, 2).is_err());
+
+        intruder.remove(id).unwrap();
+        assert!(intruder.list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_out_of_range_concurrency() {
+        let intruder = Intruder::new();
+        for concurrency in [0, MAX_CONCURRENCY + 1, usize::MAX] {
+            let mut config = create_config(vec!["id"], vec!["1"], AttackType::Sniper);
+            config.options.concurrency = concurrency;
+            let template = "GET http://127.0.0.1:1/§id§ HTTP/1.1\n\n".to_string();
+            assert!(intruder
+                .start_attack(template, config, ConnectionPool::new())
+                .is_err());
+        }
+        assert!(intruder.list().is_empty());
+    }
+}
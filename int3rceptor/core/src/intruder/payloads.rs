@@ -0,0 +1,773 @@
+//! Payload sets for intruder positions
+//!
+//! A set is one or more sources followed by a chain of processors applied to
+//! every value. Sources expand lazily, so large ranges, brute-force spaces and
+//! wordlists are never held in memory.
+
+use crate::capture::RequestCapture;
+use crate::encoding::{Encoder, EncodingType, TransformOperation, TransformRequest};
+use anyhow::{bail, Result};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+pub type PayloadIter = Box<dyn Iterator<Item = String> + Send>;
+
+/// Case permutations double with every letter, so cap the word length
+const MAX_PERMUTED_LETTERS: u32 = 24;
+
+/// Sources and processors making up one payload set
+///
+/// A plain JSON list of strings is accepted as a set with a single list source.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "PayloadSetRepr")]
+pub struct PayloadSet {
+    pub sources: Vec<PayloadSource>,
+    pub processors: Vec<PayloadProcessor>,
+}
+
+impl PayloadSet {
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+}
+
+impl From<Vec<String>> for PayloadSet {
+    fn from(values: Vec<String>) -> Self {
+        Self {
+            sources: vec![PayloadSource::List { values }],
+            processors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PayloadSetRepr {
+    Literal(Vec<String>),
+    Full {
+        #[serde(default)]
+        sources: Vec<PayloadSource>,
+        #[serde(default)]
+        processors: Vec<PayloadProcessor>,
+    },
+}
+
+impl From<PayloadSetRepr> for PayloadSet {
+    fn from(repr: PayloadSetRepr) -> Self {
+        match repr {
+            PayloadSetRepr::Literal(values) => values.into(),
+            PayloadSetRepr::Full {
+                sources,
+                processors,
+            } => Self {
+                sources,
+                processors,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PayloadSource {
+    List {
+        values: Vec<String>,
+    },
+    /// `from` to `to` inclusive
+    Numbers {
+        from: i64,
+        to: i64,
+        #[serde(default = "default_step")]
+        step: i64,
+        #[serde(default)]
+        format: NumberFormat,
+    },
+    /// Every string over `charset` with a length in the given range
+    BruteForce {
+        charset: String,
+        min_length: usize,
+        max_length: usize,
+    },
+    /// One payload per non-empty line of a file in the wordlist directory
+    Wordlist {
+        path: PathBuf,
+    },
+    /// Every upper/lower case combination of the word's letters
+    CasePermutations {
+        word: String,
+    },
+    /// Empty payloads, for repeating the base request
+    Null {
+        count: usize,
+    },
+    /// Regex matches in earlier responses from the capture history
+    Extract {
+        pattern: String,
+        /// Capture group to take, 0 for the whole match
+        #[serde(default)]
+        group: usize,
+        /// Captured requests to read, all of them when empty
+        #[serde(default)]
+        requests: Vec<u64>,
+    },
+}
+
+fn default_step() -> i64 {
+    1
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NumberFormat {
+    pub hex: bool,
+    /// Zero-pad to at least this many digits
+    pub min_digits: usize,
+}
+
+impl NumberFormat {
+    fn apply(&self, n: i64) -> String {
+        let width = self.min_digits;
+        if self.hex {
+            format!("{n:0width$x}")
+        } else {
+            format!("{n:0width$}")
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PayloadProcessor {
+    Prefix {
+        value: String,
+    },
+    Suffix {
+        value: String,
+    },
+    Encode {
+        encoding: EncodingType,
+    },
+    Hash {
+        algorithm: HashAlgorithm,
+    },
+    Replace {
+        pattern: String,
+        replacement: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// A validated payload set that can be iterated any number of times
+#[derive(Clone)]
+pub(crate) struct CompiledSet {
+    sources: Arc<[Source]>,
+    steps: Arc<[Step]>,
+}
+
+enum Source {
+    List(Arc<[String]>),
+    Numbers {
+        from: i64,
+        to: i64,
+        step: i64,
+        format: NumberFormat,
+    },
+    BruteForce {
+        charset: Arc<[char]>,
+        min_length: usize,
+        max_length: usize,
+    },
+    /// Path and the number of payloads it held when compiled
+    Wordlist(PathBuf, u64),
+    CasePermutations(Arc<[char]>),
+    Null(usize),
+}
+
+enum Step {
+    Prefix(String),
+    Suffix(String),
+    Encode(EncodingType),
+    Hash(HashAlgorithm),
+    Replace(Regex, String),
+}
+
+/// Outside state that sources are resolved against
+#[derive(Clone, Copy, Default)]
+pub(crate) struct SourceContext<'a> {
+    /// History that extract sources read responses from
+    pub capture: Option<&'a RequestCapture>,
+    /// Only directory wordlists are read from
+    pub wordlist_dir: Option<&'a Path>,
+}
+
+impl CompiledSet {
+    /// Check a set and resolve anything that needs outside state
+    pub(crate) fn compile(set: &PayloadSet, context: SourceContext<'_>) -> Result<Self> {
+        let sources = set
+            .sources
+            .iter()
+            .map(|source| compile_source(source, context))
+            .collect::<Result<Vec<_>>>()?;
+        let steps = set
+            .processors
+            .iter()
+            .map(|processor| {
+                Ok(match processor {
+                    PayloadProcessor::Prefix { value } => Step::Prefix(value.clone()),
+                    PayloadProcessor::Suffix { value } => Step::Suffix(value.clone()),
+                    PayloadProcessor::Encode { encoding } => Step::Encode(*encoding),
+                    PayloadProcessor::Hash { algorithm } => Step::Hash(*algorithm),
+                    PayloadProcessor::Replace {
+                        pattern,
+                        replacement,
+                    } => Step::Replace(Regex::new(pattern)?, replacement.clone()),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            sources: sources.into(),
+            steps: steps.into(),
+        })
+    }
+
+    /// Number of payloads one pass yields
+    pub(crate) fn len(&self) -> u64 {
+        self.sources
+            .iter()
+            .map(source_len)
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Fresh pass over every payload in the set, processors applied
+    pub(crate) fn iter(&self) -> PayloadIter {
+        let mut payloads: PayloadIter = Box::new(std::iter::empty());
+        for source in self.sources.iter() {
+            payloads = Box::new(payloads.chain(source_iter(source)));
+        }
+        if self.steps.is_empty() {
+            return payloads;
+        }
+        let steps = self.steps.clone();
+        Box::new(payloads.map(move |payload| {
+            steps
+                .iter()
+                .fold(payload, |payload, step| apply(step, payload))
+        }))
+    }
+}
+
+fn compile_source(source: &PayloadSource, context: SourceContext<'_>) -> Result<Source> {
+    Ok(match source {
+        PayloadSource::List { values } => Source::List(values.clone().into()),
+        PayloadSource::Numbers {
+            from,
+            to,
+            step,
+            format,
+        } => {
+            if *step == 0 {
+                bail!("Number range step cannot be 0");
+            }
+            Source::Numbers {
+                from: *from,
+                to: *to,
+                step: *step,
+                format: format.clone(),
+            }
+        }
+        PayloadSource::BruteForce {
+            charset,
+            min_length,
+            max_length,
+        } => {
+            if charset.is_empty() {
+                bail!("Brute force character set is empty");
+            }
+            if min_length > max_length {
+                bail!("Brute force minimum length exceeds the maximum");
+            }
+            Source::BruteForce {
+                charset: charset.chars().collect(),
+                min_length: *min_length,
+                max_length: *max_length,
+            }
+        }
+        PayloadSource::Wordlist { path } => {
+            let dir = context
+                .wordlist_dir
+                .ok_or_else(|| anyhow::anyhow!("No wordlist directory is configured"))?;
+            let path = resolve_wordlist(dir, path)?;
+            let file = File::open(&path)
+                .map_err(|e| anyhow::anyhow!("Cannot open wordlist {}: {e}", path.display()))?;
+            let count = wordlist(file).count() as u64;
+            Source::Wordlist(path, count)
+        }
+        PayloadSource::CasePermutations { word } => {
+            let letters = word.chars().filter(|c| has_case(*c)).count() as u32;
+            if letters > MAX_PERMUTED_LETTERS {
+                bail!("Case permutations support at most {MAX_PERMUTED_LETTERS} letters");
+            }
+            Source::CasePermutations(word.chars().collect())
+        }
+        PayloadSource::Null { count } => Source::Null(*count),
+        PayloadSource::Extract {
+            pattern,
+            group,
+            requests,
+        } => {
+            let capture = context
+                .capture
+                .ok_or_else(|| anyhow::anyhow!("No capture history to extract from"))?;
+            Source::List(extract(capture, &Regex::new(pattern)?, *group, requests)?.into())
+        }
+    })
+}
+
+/// Path of a wordlist inside `dir`, with `..` and symlinks resolved so it
+/// cannot point anywhere else on the server
+fn resolve_wordlist(dir: &Path, path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        bail!("Wordlist path must be relative to the wordlist directory");
+    }
+    let dir = dir
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Cannot open wordlist directory {}: {e}", dir.display()))?;
+    let resolved = dir
+        .join(path)
+        .canonicalize()
+        .map_err(|e| anyhow::anyhow!("Cannot open wordlist {}: {e}", path.display()))?;
+    if !resolved.starts_with(&dir) {
+        bail!(
+            "Wordlist {} is outside the wordlist directory",
+            path.display()
+        );
+    }
+    Ok(resolved)
+}
+
+fn source_iter(source: &Source) -> PayloadIter {
+    match source {
+        Source::List(values) => {
+            let values = values.clone();
+            Box::new((0..values.len()).map(move |i| values[i].clone()))
+        }
+        Source::Numbers {
+            from,
+            to,
+            step,
+            format,
+        } => {
+            let (to, step, format) = (*to, *step, format.clone());
+            Box::new(
+                std::iter::successors(Some(*from), move |n| n.checked_add(step))
+                    .take_while(move |n| if step > 0 { *n <= to } else { *n >= to })
+                    .map(move |n| format.apply(n)),
+            )
+        }
+        Source::BruteForce {
+            charset,
+            min_length,
+            max_length,
+        } => Box::new(BruteForce {
+            charset: charset.clone(),
+            max_length: *max_length,
+            indices: Some(vec![0; *min_length]),
+        }),
+        Source::Wordlist(path, _) => match File::open(path) {
+            Ok(file) => Box::new(wordlist(file)),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), "Cannot reopen wordlist: {e}");
+                Box::new(std::iter::empty())
+            }
+        },
+        Source::CasePermutations(word) => {
+            let word = word.clone();
+            let letters = word.iter().filter(|c| has_case(**c)).count() as u32;
+            Box::new((0..1u64 << letters).map(move |mask| {
+                let mut bit = 0;
+                word.iter()
+                    .map(|c| {
+                        if !has_case(*c) {
+                            return c.to_string();
+                        }
+                        let upper = mask & (1 << bit) != 0;
+                        bit += 1;
+                        if upper {
+                            c.to_uppercase().to_string()
+                        } else {
+                            c.to_lowercase().to_string()
+                        }
+                    })
+                    .collect()
+            }))
+        }
+        Source::Null(count) => Box::new(std::iter::repeat_n(String::new(), *count)),
+    }
+}
+
+fn wordlist(file: File) -> impl Iterator<Item = String> + Send {
+    BufReader::new(file)
+        .lines()
+        .map_while(|line| line.ok())
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .filter(|line| !line.is_empty())
+}
+
+/// Number of payloads a source yields, saturating
+fn source_len(source: &Source) -> u64 {
+    match source {
+        Source::List(values) => values.len() as u64,
+        Source::Numbers { from, to, step, .. } => {
+            let span = i128::from(*to) - i128::from(*from);
+            if span != 0 && (span < 0) != (*step < 0) {
+                0
+            } else {
+                u64::try_from(span / i128::from(*step) + 1).unwrap_or(u64::MAX)
+            }
+        }
+        Source::BruteForce {
+            charset,
+            min_length,
+            max_length,
+        } => (*min_length..=*max_length)
+            .map(|length| {
+                u32::try_from(length)
+                    .ok()
+                    .and_then(|length| (charset.len() as u64).checked_pow(length))
+                    .unwrap_or(u64::MAX)
+            })
+            .fold(0, u64::saturating_add),
+        Source::Wordlist(_, count) => *count,
+        Source::CasePermutations(word) => 1 << word.iter().filter(|c| has_case(**c)).count(),
+        Source::Null(count) => *count as u64,
+    }
+}
+
+fn has_case(c: char) -> bool {
+    c.is_lowercase() || c.is_uppercase()
+}
+
+fn apply(step: &Step, payload: String) -> String {
+    match step {
+        Step::Prefix(value) => format!("{value}{payload}"),
+        Step::Suffix(value) => payload + value,
+        Step::Encode(encoding) => {
+            Encoder::transform(TransformRequest {
+                text: payload,
+                encoding: *encoding,
+                operation: TransformOperation::Encode,
+            })
+            .text
+        }
+        Step::Hash(HashAlgorithm::Sha1) => hex::encode(Sha1::digest(payload.as_bytes())),
+        Step::Hash(HashAlgorithm::Sha256) => hex::encode(Sha256::digest(payload.as_bytes())),
+        Step::Hash(HashAlgorithm::Sha512) => hex::encode(Sha512::digest(payload.as_bytes())),
+        Step::Replace(regex, replacement) => regex
+            .replace_all(&payload, replacement.as_str())
+            .into_owned(),
+    }
+}
+
+/// Distinct matches in captured response bodies, in history order
+fn extract(
+    capture: &RequestCapture,
+    regex: &Regex,
+    group: usize,
+    ids: &[u64],
+) -> Result<Vec<String>> {
+    let entries = if ids.is_empty() {
+        capture.get_all()
+    } else {
+        let mut entries = Vec::with_capacity(ids.len());
+        for id in ids {
+            entries.extend(capture.get(*id)?);
+        }
+        entries
+    };
+    let mut seen = HashSet::new();
+    let mut values = Vec::new();
+    for entry in entries {
+        let Some(response) = entry.response else {
+            continue;
+        };
+        let body = String::from_utf8_lossy(&response.body);
+        for captures in regex.captures_iter(&body) {
+            if let Some(value) = captures.get(group) {
+                if seen.insert(value.as_str().to_string()) {
+                    values.push(value.as_str().to_string());
+                }
+            }
+        }
+    }
+    Ok(values)
+}
+
+/// Counts through the charset like an odometer, growing the length on wrap
+struct BruteForce {
+    charset: Arc<[char]>,
+    max_length: usize,
+    indices: Option<Vec<usize>>,
+}
+
+impl Iterator for BruteForce {
+    type Item = String;
+
+    fn next(&mut self) -> Option<String> {
+        let indices = self.indices.as_mut()?;
+        if indices.len() > self.max_length {
+            self.indices = None;
+            return None;
+        }
+        let value = indices.iter().map(|i| self.charset[*i]).collect();
+
+        let mut position = indices.len();
+        loop {
+            if position == 0 {
+                let length = indices.len() + 1;
+                *indices = vec![0; length];
+                break;
+            }
+            position -= 1;
+            indices[position] += 1;
+            if indices[position] < self.charset.len() {
+                break;
+            }
+            indices[position] = 0;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{CapturedRequest, CapturedResponse};
+
+    fn payloads(source: PayloadSource, processors: Vec<PayloadProcessor>) -> Vec<String> {
+        let set = PayloadSet {
+            sources: vec![source],
+            processors,
+        };
+        CompiledSet::compile(&set, SourceContext::default())
+            .unwrap()
+            .iter()
+            .collect()
+    }
+
+    #[test]
+    fn test_generators() {
+        let numbers = PayloadSource::Numbers {
+            from: 8,
+            to: 12,
+            step: 2,
+            format: NumberFormat {
+                hex: true,
+                min_digits: 2,
+            },
+        };
+        assert_eq!(payloads(numbers, vec![]), vec!["08", "0a", "0c"]);
+
+        let down = PayloadSource::Numbers {
+            from: 3,
+            to: 1,
+            step: -1,
+            format: NumberFormat::default(),
+        };
+        assert_eq!(payloads(down, vec![]), vec!["3", "2", "1"]);
+
+        let brute = PayloadSource::BruteForce {
+            charset: "ab".to_string(),
+            min_length: 1,
+            max_length: 2,
+        };
+        assert_eq!(
+            payloads(brute, vec![]),
+            vec!["a", "b", "aa", "ab", "ba", "bb"]
+        );
+
+        let cases = PayloadSource::CasePermutations {
+            word: "a1b".to_string(),
+        };
+        assert_eq!(payloads(cases, vec![]), vec!["a1b", "A1b", "a1B", "A1B"]);
+
+        assert_eq!(
+            payloads(PayloadSource::Null { count: 2 }, vec![]),
+            vec!["", ""]
+        );
+    }
+
+    #[test]
+    fn test_set_len() {
+        let set = PayloadSet {
+            sources: vec![
+                PayloadSource::Numbers {
+                    from: 10,
+                    to: 0,
+                    step: -3,
+                    format: NumberFormat::default(),
+                },
+                PayloadSource::BruteForce {
+                    charset: "abc".to_string(),
+                    min_length: 0,
+                    max_length: 2,
+                },
+                PayloadSource::CasePermutations {
+                    word: "ab1".to_string(),
+                },
+            ],
+            processors: vec![],
+        };
+        let compiled = CompiledSet::compile(&set, SourceContext::default()).unwrap();
+        // 10, 7, 4, 1 + (1 + 3 + 9) + 4
+        assert_eq!(compiled.len(), 21);
+        assert_eq!(compiled.iter().count(), 21);
+    }
+
+    fn wordlist_set(path: &str) -> PayloadSet {
+        PayloadSet {
+            sources: vec![PayloadSource::Wordlist {
+                path: PathBuf::from(path),
+            }],
+            processors: vec![],
+        }
+    }
+
+    #[test]
+    fn test_wordlist_is_reread_per_pass() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("words.txt"), "admin\r\n\nroot\n").unwrap();
+        let context = SourceContext {
+            wordlist_dir: Some(dir.path()),
+            ..Default::default()
+        };
+        let compiled = CompiledSet::compile(&wordlist_set("words.txt"), context).unwrap();
+        assert_eq!(compiled.iter().collect::<Vec<_>>(), vec!["admin", "root"]);
+        assert_eq!(compiled.iter().count(), 2);
+        assert_eq!(compiled.len(), 2);
+
+        assert!(CompiledSet::compile(&wordlist_set("missing.txt"), context).is_err());
+        let unconfigured = SourceContext::default();
+        assert!(CompiledSet::compile(&wordlist_set("words.txt"), unconfigured).is_err());
+    }
+
+    #[test]
+    fn test_wordlist_stays_in_directory() {
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("wordlists");
+        std::fs::create_dir(&dir).unwrap();
+        let secret = root.path().join("secret.txt");
+        std::fs::write(&secret, "hunter2\n").unwrap();
+        let context = SourceContext {
+            wordlist_dir: Some(&dir),
+            ..Default::default()
+        };
+
+        let escape = CompiledSet::compile(&wordlist_set("../secret.txt"), context);
+        assert!(escape.err().unwrap().to_string().contains("outside"));
+        let absolute = CompiledSet::compile(&wordlist_set(secret.to_str().unwrap()), context);
+        assert!(absolute.err().unwrap().to_string().contains("relative"));
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&secret, dir.join("link.txt")).unwrap();
+            let linked = CompiledSet::compile(&wordlist_set("link.txt"), context);
+            assert!(linked.err().unwrap().to_string().contains("outside"));
+        }
+    }
+
+    #[test]
+    fn test_processor_chain() {
+        let list = PayloadSource::List {
+            values: vec!["a b".to_string()],
+        };
+        let processors = vec![
+            PayloadProcessor::Replace {
+                pattern: r"\s".to_string(),
+                replacement: "_".to_string(),
+            },
+            PayloadProcessor::Prefix {
+                value: "x=".to_string(),
+            },
+            PayloadProcessor::Suffix {
+                value: "&".to_string(),
+            },
+            PayloadProcessor::Encode {
+                encoding: EncodingType::Url,
+            },
+        ];
+        assert_eq!(payloads(list.clone(), processors), vec!["x%3Da_b%26"]);
+
+        let hash = vec![PayloadProcessor::Hash {
+            algorithm: HashAlgorithm::Sha1,
+        }];
+        assert_eq!(
+            payloads(list, hash),
+            vec![hex::encode(Sha1::digest(b"a b"))]
+        );
+    }
+
+    #[test]
+    fn test_extract_from_responses() {
+        let capture = RequestCapture::new(10);
+        for body in ["token=abc; token=def", "token=abc", "none"] {
+            capture.push(
+                CapturedRequest::new("GET", "http://app.test/", false),
+                Some(CapturedResponse {
+                    request_id: 0,
+                    status_code: 200,
+                    headers: vec![],
+                    body: body.as_bytes().to_vec(),
+                    duration_ms: 1,
+                }),
+            );
+        }
+        let set = PayloadSet {
+            sources: vec![PayloadSource::Extract {
+                pattern: "token=(\\w+)".to_string(),
+                group: 1,
+                requests: vec![],
+            }],
+            processors: vec![],
+        };
+        let context = SourceContext {
+            capture: Some(&capture),
+            ..Default::default()
+        };
+        let compiled = CompiledSet::compile(&set, context).unwrap();
+        assert_eq!(compiled.iter().collect::<Vec<_>>(), vec!["abc", "def"]);
+        assert!(CompiledSet::compile(&set, SourceContext::default()).is_err());
+    }
+
+    #[test]
+    fn test_set_accepts_plain_list() {
+        let set: PayloadSet = serde_json::from_str(r#"["a", "b"]"#).unwrap();
+        assert_eq!(
+            set,
+            PayloadSet::from(vec!["a".to_string(), "b".to_string()])
+        );
+
+        let set: PayloadSet = serde_json::from_str(
+            r#"{"sources": [{"type": "numbers", "from": 1, "to": 3}],
+                "processors": [{"type": "hash", "algorithm": "sha256"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(set.processors.len(), 1);
+        assert!(matches!(
+            set.sources[0],
+            PayloadSource::Numbers { step: 1, .. }
+        ));
+    }
+}
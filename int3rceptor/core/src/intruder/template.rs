@@ -0,0 +1,334 @@
+//! Raw request templates: payload positions and parsing into hyper requests
+
+use super::IntruderPosition;
+use crate::connection_pool::ProxyBody;
+use anyhow::{anyhow, bail, Result};
+use hyper::header::CONTENT_LENGTH;
+use hyper::http::uri::{Authority, PathAndQuery, Scheme};
+use hyper::{Method, Request, Uri, Version};
+
+/// Headers that only mean something on an HTTP/1 connection
+const CONNECTION_HEADERS: [&str; 5] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+enum Part {
+    Text(String),
+    /// Payload of the position with this index
+    Slot(usize),
+}
+
+/// A template split into literal text and payload slots
+pub(crate) struct Template {
+    parts: Vec<Part>,
+}
+
+impl Template {
+    /// Locate each position by its `§name§` marker, or by its offsets when
+    /// the template has no marker for it
+    pub(crate) fn compile(raw: &str, positions: &[IntruderPosition]) -> Result<Self> {
+        let mut spans = Vec::new();
+        for (index, position) in positions.iter().enumerate() {
+            let marker = format!("§{}§", position.name);
+            if raw.contains(&marker) {
+                spans.extend(
+                    raw.match_indices(&marker)
+                        .map(|(start, m)| (start, start + m.len(), index)),
+                );
+            } else if position.end > position.start {
+                let start = byte_offset(raw, position.start);
+                let end = byte_offset(raw, position.end);
+                let (Some(start), Some(end)) = (start, end) else {
+                    bail!("Position {} is outside the template", position.name);
+                };
+                spans.push((start, end, index));
+            }
+        }
+        spans.sort_unstable();
+
+        let mut parts = Vec::new();
+        let mut pos = 0;
+        for (start, end, index) in spans {
+            if start < pos {
+                bail!("Position {} overlaps another one", positions[index].name);
+            }
+            if start > pos {
+                parts.push(Part::Text(raw[pos..start].to_string()));
+            }
+            parts.push(Part::Slot(index));
+            pos = end;
+        }
+        if pos < raw.len() {
+            parts.push(Part::Text(raw[pos..].to_string()));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Template text with each slot replaced by its position's payload
+    pub(crate) fn fill(&self, payloads: &[String]) -> String {
+        let mut filled = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => filled.push_str(text),
+                Part::Slot(index) => {
+                    filled.push_str(payloads.get(*index).map_or("", String::as_str))
+                }
+            }
+        }
+        filled
+    }
+}
+
+/// Byte index of a UTF-16 offset, as a browser text selection reports it
+fn byte_offset(raw: &str, offset: usize) -> Option<usize> {
+    let mut units = 0;
+    for (index, c) in raw.char_indices() {
+        if units == offset {
+            return Some(index);
+        }
+        units += c.len_utf16();
+    }
+    (units == offset).then_some(raw.len())
+}
+
+/// Scheme and authority of an explicit `scheme://host[:port]` target
+pub(crate) fn parse_target(target: &str) -> Result<(Scheme, Authority)> {
+    let uri: Uri = target
+        .parse()
+        .map_err(|e| anyhow!("Invalid target {target}: {e}"))?;
+    match (uri.scheme(), uri.authority()) {
+        (Some(scheme), Some(authority)) if *scheme == Scheme::HTTP || *scheme == Scheme::HTTPS => {
+            Ok((scheme.clone(), authority.clone()))
+        }
+        _ => bail!("Target must look like https://host[:port], got {target}"),
+    }
+}
+
+/// Build the request a filled-in template describes
+///
+/// The target, when given, decides where the request goes. Otherwise an
+/// absolute URI in the request line is used as-is and a bare path is sent to
+/// the `Host` header over plain HTTP. A request line ending in `HTTP/2` is
+/// only sent over HTTP/2; any other version lets an HTTPS host negotiate it.
+/// `Content-Length` is recomputed for the body as it stands after payloads
+/// were inserted.
+pub(crate) fn parse_request(raw: &str, target: Option<&str>) -> Result<Request<ProxyBody>> {
+    let (head, body) = split_head(raw);
+    let mut lines = head.lines();
+    let request_line = lines
+        .next()
+        .filter(|line| !line.trim().is_empty())
+        .ok_or_else(|| anyhow!("Empty request"))?;
+    let mut parts = request_line.split_whitespace();
+    let method_str = parts.next().ok_or_else(|| anyhow!("Missing method"))?;
+    let uri_str = parts.next().ok_or_else(|| anyhow!("Missing URI"))?;
+    let version = match parts.next() {
+        None | Some("HTTP/1.1") => Version::HTTP_11,
+        Some("HTTP/1.0") => Version::HTTP_10,
+        Some("HTTP/2") | Some("HTTP/2.0") => Version::HTTP_2,
+        Some(other) => bail!("Unsupported HTTP version {other}"),
+    };
+
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    };
+
+    let uri = request_uri(uri_str, target, header("host"))?;
+    if version == Version::HTTP_2 && uri.scheme() != Some(&Scheme::HTTPS) {
+        bail!("HTTP/2 requests need an https target");
+    }
+    let chunked = header("transfer-encoding")
+        .and_then(|te| te.rsplit(',').next())
+        .is_some_and(|last| last.trim().eq_ignore_ascii_case("chunked"));
+    let body = if chunked {
+        dechunk(body)
+    } else {
+        body.to_string()
+    };
+
+    let method = Method::from_bytes(method_str.as_bytes())?;
+    let mut builder = Request::builder().method(method).uri(uri).version(version);
+    for (name, value) in &headers {
+        let skip = name.eq_ignore_ascii_case(CONTENT_LENGTH.as_str())
+            || (version == Version::HTTP_2
+                && CONNECTION_HEADERS
+                    .iter()
+                    .any(|h| name.eq_ignore_ascii_case(h)));
+        if !skip {
+            builder = builder.header(*name, *value);
+        }
+    }
+    // HTTP/1 chunked bodies are re-chunked by hyper instead
+    let sends_chunked = chunked && version != Version::HTTP_2;
+    if !sends_chunked && (header("content-length").is_some() || !body.is_empty()) {
+        builder = builder.header(CONTENT_LENGTH, body.len());
+    }
+
+    Ok(builder.body(ProxyBody::from(bytes::Bytes::from(body)))?)
+}
+
+fn request_uri(uri_str: &str, target: Option<&str>, host: Option<&str>) -> Result<Uri> {
+    let uri: Uri = uri_str.parse()?;
+    let (scheme, authority) = match target {
+        Some(target) => parse_target(target)?,
+        None if uri.scheme().is_some() => return Ok(uri),
+        None => {
+            let host = host.ok_or_else(|| anyhow!("Request has no Host header or target"))?;
+            (Scheme::HTTP, host.parse::<Authority>()?)
+        }
+    };
+    let path = uri
+        .path_and_query()
+        .cloned()
+        .unwrap_or_else(|| PathAndQuery::from_static("/"));
+    Ok(Uri::builder()
+        .scheme(scheme)
+        .authority(authority)
+        .path_and_query(path)
+        .build()?)
+}
+
+/// Head and body, split at the first blank line
+fn split_head(raw: &str) -> (&str, &str) {
+    let mut pos = 0;
+    for line in raw.split_inclusive('\n') {
+        if line.trim_end_matches(['\r', '\n']).is_empty() {
+            return (&raw[..pos], &raw[pos + line.len()..]);
+        }
+        pos += line.len();
+    }
+    (raw, "")
+}
+
+/// Decode a chunked template body
+///
+/// Payloads change chunk lengths without touching the size lines, so a chunk
+/// whose declared size no longer ends at a line break runs to the end of its
+/// line instead. A body that does not parse as chunks is kept as it is.
+fn dechunk(body: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = body;
+    while let Some((line, after)) = split_line(rest) {
+        let size = line.split(';').next().unwrap_or_default().trim();
+        let Ok(size) = usize::from_str_radix(size, 16) else {
+            return body.to_string();
+        };
+        if size == 0 {
+            break;
+        }
+        let exact = after
+            .get(size..)
+            .filter(|tail| tail.is_empty() || tail.starts_with("\r\n") || tail.starts_with('\n'));
+        let (chunk, tail) = match exact {
+            Some(tail) => (&after[..size], tail),
+            None => split_line(after).unwrap_or_default(),
+        };
+        decoded.push_str(chunk);
+        rest = tail
+            .strip_prefix("\r\n")
+            .or_else(|| tail.strip_prefix('\n'))
+            .unwrap_or(tail);
+    }
+    decoded
+}
+
+/// First line without its line break, and what follows it
+fn split_line(text: &str) -> Option<(&str, &str)> {
+    if text.is_empty() {
+        return None;
+    }
+    let (line, rest) = text.split_once('\n').unwrap_or((text, ""));
+    Some((line.strip_suffix('\r').unwrap_or(line), rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use hyper::header::TRANSFER_ENCODING;
+
+    fn position(name: &str, start: usize, end: usize) -> IntruderPosition {
+        IntruderPosition {
+            start,
+            end,
+            name: name.to_string(),
+            payloads: Default::default(),
+        }
+    }
+
+    async fn body(request: Request<ProxyBody>) -> String {
+        let bytes = request.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn test_offsets_and_markers() {
+        let raw = "GET /?q=é&id=42&x=§x§ HTTP/1.1";
+        let positions = [position("id", 13, 15), position("x", 0, 0)];
+        let template = Template::compile(raw, &positions).unwrap();
+        let filled = template.fill(&["7".to_string(), "y".to_string()]);
+        assert_eq!(filled, "GET /?q=é&id=7&x=y HTTP/1.1");
+
+        // A marker wins over offsets
+        let positions = [position("x", 0, 3)];
+        let filled = Template::compile(raw, &positions)
+            .unwrap()
+            .fill(&["z".to_string()]);
+        assert_eq!(filled, "GET /?q=é&id=42&x=z HTTP/1.1");
+
+        let overlapping = [position("a", 4, 10), position("b", 8, 12)];
+        assert!(Template::compile(raw, &overlapping).is_err());
+        assert!(Template::compile(raw, &[position("a", 4, 99)]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_target_and_content_length() {
+        let raw = "POST /login?next=/ HTTP/1.1\r\nHost: example.com\r\nContent-Length: 3\r\n\r\nuser=admin";
+        let request = parse_request(raw, Some("https://example.com:8443")).unwrap();
+        assert_eq!(
+            request.uri().to_string(),
+            "https://example.com:8443/login?next=/"
+        );
+        assert_eq!(request.headers()["host"], "example.com");
+        assert_eq!(request.headers()[CONTENT_LENGTH], "10");
+        assert_eq!(body(request).await, "user=admin");
+
+        let request = parse_request("GET /a HTTP/1.0\nHost: example.com\n\n", None).unwrap();
+        assert_eq!(request.uri().to_string(), "http://example.com/a");
+        assert_eq!(request.version(), Version::HTTP_10);
+        assert!(request.headers().get(CONTENT_LENGTH).is_none());
+
+        assert!(parse_request("GET /a HTTP/1.1\n\n", None).is_err());
+        assert!(parse_request("GET /a HTTP/1.1\n\n", Some("example.com")).is_err());
+        assert!(parse_request("GET /a HTTP/2\nHost: example.com\n\n", None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_chunked_bodies() {
+        let raw = "POST / HTTP/1.1\nHost: x\nTransfer-Encoding: chunked\nContent-Length: 5\n\n3\nabc\n5\nlonger payload\n0\n\n";
+        let request = parse_request(raw, None).unwrap();
+        assert_eq!(request.headers()[TRANSFER_ENCODING], "chunked");
+        assert!(request.headers().get(CONTENT_LENGTH).is_none());
+        assert_eq!(body(request).await, "abclonger payload");
+
+        let h2 = raw.replacen("HTTP/1.1", "HTTP/2", 1);
+        let request = parse_request(&h2, Some("https://x")).unwrap();
+        assert_eq!(request.version(), Version::HTTP_2);
+        assert!(request.headers().get(TRANSFER_ENCODING).is_none());
+        assert_eq!(request.headers()[CONTENT_LENGTH], "17");
+
+        assert_eq!(dechunk("4\r\nab\r\n\r\n0\r\n\r\n"), "ab\r\n");
+        assert_eq!(dechunk("not chunked"), "not chunked");
+    }
+}
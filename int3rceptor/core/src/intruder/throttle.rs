@@ -0,0 +1,211 @@
+//! Request pacing, retry backoff and adaptive slowdown for intruder attacks
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Attempts judged together when adjusting the slowdown
+const WINDOW: u32 = 10;
+/// Share of failed attempts in a window that triggers a slowdown
+const ERROR_PERCENT: u32 = 25;
+/// First gap added between sends once errors rise
+const MIN_PENALTY: Duration = Duration::from_millis(100);
+const MAX_PENALTY: Duration = Duration::from_secs(5);
+
+/// When and how often a request is sent again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts after the first one; 0 disables retries
+    pub max_retries: u32,
+    /// Wait before the first retry, doubled for each one after it
+    pub backoff_ms: u64,
+    /// Upper bound on any single wait, including a `Retry-After`
+    pub max_backoff_ms: u64,
+    /// Response statuses retried like network errors
+    pub statuses: Vec<u16>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            statuses: vec![429, 503],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Wait before retry number `attempt` (from 0), preferring the server's
+    /// `Retry-After`; `None` once retries are used up
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        if attempt >= self.max_retries {
+            return None;
+        }
+        let backoff = Duration::from_millis(
+            self.backoff_ms
+                .saturating_mul(1u64.checked_shl(attempt).unwrap_or(u64::MAX)),
+        );
+        Some(
+            retry_after
+                .unwrap_or(backoff)
+                .min(Duration::from_millis(self.max_backoff_ms)),
+        )
+    }
+}
+
+/// `Retry-After` as delay-seconds or an HTTP date
+pub(crate) fn retry_after(headers: &[(String, String)]) -> Option<Duration> {
+    let value = headers
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("retry-after"))?
+        .1
+        .trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    (date.with_timezone(&chrono::Utc) - chrono::Utc::now())
+        .to_std()
+        .ok()
+}
+
+struct State {
+    next: Instant,
+    penalty: Duration,
+    attempts: u32,
+    failures: u32,
+}
+
+/// Spaces out sends to a requests-per-second cap, adding a growing gap
+/// while too many attempts fail
+pub(crate) struct Throttle {
+    interval: Duration,
+    adaptive: bool,
+    state: Mutex<State>,
+}
+
+impl Throttle {
+    /// `max_rps` of 0 or `usize::MAX` leaves sends unpaced
+    pub(crate) fn new(max_rps: usize, adaptive: bool) -> Self {
+        let interval = match max_rps {
+            0 | usize::MAX => Duration::ZERO,
+            rps => Duration::from_secs_f64(1.0 / rps as f64),
+        };
+        Self {
+            interval,
+            adaptive,
+            state: Mutex::new(State {
+                next: Instant::now(),
+                penalty: Duration::ZERO,
+                attempts: 0,
+                failures: 0,
+            }),
+        }
+    }
+
+    /// Wait for this send's turn
+    pub(crate) async fn wait(&self) {
+        let at = {
+            let mut state = self.state.lock();
+            let at = state.next.max(Instant::now());
+            state.next = at + self.interval.max(state.penalty);
+            at
+        };
+        tokio::time::sleep_until(at.into()).await;
+    }
+
+    /// Count an attempt, failed if it got no response or a retryable status
+    pub(crate) fn record(&self, failed: bool) {
+        if !self.adaptive {
+            return;
+        }
+        let mut state = self.state.lock();
+        state.attempts += 1;
+        state.failures += u32::from(failed);
+        if state.attempts < WINDOW {
+            return;
+        }
+        state.penalty = if state.failures * 100 >= state.attempts * ERROR_PERCENT {
+            (state.penalty * 2).clamp(MIN_PENALTY, MAX_PENALTY)
+        } else if state.penalty > MIN_PENALTY {
+            state.penalty / 2
+        } else {
+            Duration::ZERO
+        };
+        state.attempts = 0;
+        state.failures = 0;
+    }
+
+    /// Gap currently added because of failures
+    pub(crate) fn penalty(&self) -> Duration {
+        self.state.lock().penalty
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_delays() {
+        let policy = RetryPolicy {
+            max_retries: 3,
+            backoff_ms: 100,
+            max_backoff_ms: 300,
+            ..Default::default()
+        };
+        let delays: Vec<_> = (0..4).map(|attempt| policy.delay(attempt, None)).collect();
+        assert_eq!(
+            delays,
+            vec![
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(300)),
+                None,
+            ]
+        );
+        assert_eq!(
+            policy.delay(0, Some(Duration::from_secs(60))),
+            Some(Duration::from_millis(300))
+        );
+
+        let headers = |value: &str| vec![("Retry-After".to_string(), value.to_string())];
+        assert_eq!(retry_after(&headers("2")), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(&headers("Wed, 21 Oct 2015 07:28:00 GMT")), None);
+        let later = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        assert!(retry_after(&headers(&later)).is_some_and(|d| d > Duration::from_secs(20)));
+        assert_eq!(retry_after(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_pacing_and_slowdown() {
+        let throttle = Throttle::new(50, true);
+        let start = Instant::now();
+        for _ in 0..6 {
+            throttle.wait().await;
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        for _ in 0..WINDOW {
+            throttle.record(true);
+        }
+        assert_eq!(throttle.penalty(), MIN_PENALTY);
+        for _ in 0..WINDOW {
+            throttle.record(true);
+        }
+        assert_eq!(throttle.penalty(), MIN_PENALTY * 2);
+        for _ in 0..WINDOW * 2 {
+            throttle.record(false);
+        }
+        assert_eq!(throttle.penalty(), Duration::ZERO);
+
+        let fixed = Throttle::new(usize::MAX, false);
+        for _ in 0..WINDOW {
+            fixed.record(true);
+        }
+        assert_eq!(fixed.penalty(), Duration::ZERO);
+    }
+}
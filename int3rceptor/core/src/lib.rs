@@ -1,47 +1,66 @@
 pub mod capture;
 pub mod cert_manager;
+pub mod codegen;
 pub mod comparer;
 pub mod connection_pool;
 pub mod crypto;
 pub mod database;
 pub mod encoding;
 pub mod error;
+pub mod har;
+pub mod hosts;
+pub mod import;
 pub mod integration;
 pub mod intruder;
 pub mod license;
 pub mod metrics;
+pub mod network;
 pub mod plugin;
 pub mod project;
 pub mod proxy;
+pub mod race;
+pub mod repeater;
+pub mod retention;
 pub mod rules;
 pub mod scanner;
 pub mod scope;
+pub mod search;
 pub mod security;
 pub mod storage;
 pub mod telemetry;
 pub mod tls;
 pub mod websocket;
 
-pub use capture::{ActivityQuery, CaptureQuery, DashboardActivity, RequestCapture};
+pub use capture::{
+    ActivityQuery, Annotation, CaptureFilter, CaptureQuery, CaptureSort, CaptureSource,
+    DashboardActivity, Highlight, RequestCapture, SortOrder,
+};
 pub use cert_manager::CertManager;
 pub use comparer::{CompareMode, CompareRequest, CompareResponse, Comparer};
 pub use connection_pool::ConnectionPool;
 pub use encoding::{
     Encoder, EncodingType, TransformOperation, TransformRequest, TransformResponse,
 };
+pub use hosts::{HostEntry, HostMap};
 pub use integration::{nowaru_bridge::NowaruBridge, voidwalker_bridge::VoidwalkerBridge};
 pub use intruder::Intruder;
 pub use license::{License, LicenseManager, LicenseTier};
 pub use metrics::{metrics, Metrics, MetricsSnapshot};
+pub use network::{NetworkConditions, NetworkProfile};
 pub use project::{ProjectData, ProjectInfo, ProjectManager, ProjectSummary};
+pub use race::{RaceClient, RaceMode, RaceReport, RaceResult};
+pub use repeater::{Repeater, RepeaterClient, RepeaterRequest, RepeaterResponse, RepeaterTab};
+pub use retention::{PruneReport, RetentionPolicy, StorageStats};
 pub use scanner::{
     DetectionRule, Finding, ScanConfig, ScanStats, Scanner, Severity, VulnerabilityCategory,
 };
-pub use scope::ScopeManager;
+pub use scope::{ScopeConfig, ScopeManager, ScopeRule};
+pub use search::SearchExpr;
 pub use security::{
     constant_time_compare, AuditEntry, AuditEntryBuilder, AuditEventType, AuditLogger,
     AuditOutcome, AuditSeverity, CsrfManager, CsrfValidationResult, IpFilter, IpFilterResult,
     RateLimitResult, RateLimiter, TlsConfig,
 };
+pub use storage::keys::{NewKey, RotationReport};
 pub use storage::CaptureStorage;
 pub use websocket::WsCapture;
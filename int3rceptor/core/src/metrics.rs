@@ -53,6 +53,13 @@ pub struct Metrics {
     rules_applied: AtomicU64,
     rules_matched: AtomicU64,
 
+    // Capture storage writer
+    storage_writes: AtomicU64,
+    storage_write_batches: AtomicU64,
+    storage_writes_dropped: AtomicU64,
+    storage_queue_depth: AtomicU64,
+    storage_queue_peak: AtomicU64,
+
     // Start time for uptime calculation
     start_time: Instant,
 
@@ -94,6 +101,12 @@ impl Metrics {
             rules_applied: AtomicU64::new(0),
             rules_matched: AtomicU64::new(0),
 
+            storage_writes: AtomicU64::new(0),
+            storage_write_batches: AtomicU64::new(0),
+            storage_writes_dropped: AtomicU64::new(0),
+            storage_queue_depth: AtomicU64::new(0),
+            storage_queue_peak: AtomicU64::new(0),
+
             start_time: Instant::now(),
 
             latency_buckets: RwLock::new(LatencyHistogram::new()),
@@ -226,7 +239,7 @@ impl Metrics {
 
     // ==================== Rule Engine Tracking ====================
 
-    /// Record rules being applied
+    /// Record matched rules whose action changed the request or response
     pub fn record_rules_applied(&self, count: u64) {
         self.rules_applied.fetch_add(count, Ordering::Relaxed);
     }
@@ -236,6 +249,33 @@ impl Metrics {
         self.rules_matched.fetch_add(count, Ordering::Relaxed);
     }
 
+    // ==================== Storage Writer Tracking ====================
+
+    /// Record a capture queued for the storage writer
+    pub fn record_storage_write_queued(&self) {
+        let depth = self.storage_queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.storage_queue_peak.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    /// Record a capture dropped because the writer queue was full
+    pub fn record_storage_write_dropped(&self) {
+        self.storage_writes_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a committed batch; failed entries count as dropped
+    pub fn record_storage_batch(&self, written: u64, failed: u64) {
+        self.storage_write_batches.fetch_add(1, Ordering::Relaxed);
+        self.storage_writes.fetch_add(written, Ordering::Relaxed);
+        self.storage_writes_dropped
+            .fetch_add(failed, Ordering::Relaxed);
+        let done = written + failed;
+        let _ =
+            self.storage_queue_depth
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |depth| {
+                    Some(depth.saturating_sub(done))
+                });
+    }
+
     // ==================== Snapshot ====================
 
     /// Get a snapshot of all metrics
@@ -277,6 +317,12 @@ impl Metrics {
             rules_applied: self.rules_applied.load(Ordering::Relaxed),
             rules_matched: self.rules_matched.load(Ordering::Relaxed),
 
+            storage_writes: self.storage_writes.load(Ordering::Relaxed),
+            storage_write_batches: self.storage_write_batches.load(Ordering::Relaxed),
+            storage_writes_dropped: self.storage_writes_dropped.load(Ordering::Relaxed),
+            storage_queue_depth: self.storage_queue_depth.load(Ordering::Relaxed),
+            storage_queue_peak: self.storage_queue_peak.load(Ordering::Relaxed),
+
             latency_histogram: self.latency_buckets.read().snapshot(),
             top_hosts: self.top_hosts(10),
         }
@@ -316,6 +362,11 @@ impl Metrics {
         self.rules_applied.store(0, Ordering::Relaxed);
         self.rules_matched.store(0, Ordering::Relaxed);
 
+        self.storage_writes.store(0, Ordering::Relaxed);
+        self.storage_write_batches.store(0, Ordering::Relaxed);
+        self.storage_writes_dropped.store(0, Ordering::Relaxed);
+        self.storage_queue_peak.store(0, Ordering::Relaxed);
+
         self.latency_buckets.write().reset();
         self.host_requests.write().clear();
     }
@@ -436,6 +487,12 @@ pub struct MetricsSnapshot {
     pub rules_applied: u64,
     pub rules_matched: u64,
 
+    pub storage_writes: u64,
+    pub storage_write_batches: u64,
+    pub storage_writes_dropped: u64,
+    pub storage_queue_depth: u64,
+    pub storage_queue_peak: u64,
+
     pub latency_histogram: LatencyHistogramSnapshot,
     pub top_hosts: Vec<(String, u64)>,
 }
@@ -574,6 +631,29 @@ mod tests {
         assert_eq!(snap.bytes_received, 0);
     }
 
+    #[test]
+    fn test_storage_writer_tracking() {
+        let m = Metrics::new();
+
+        for _ in 0..3 {
+            m.record_storage_write_queued();
+        }
+        m.record_storage_write_dropped();
+        m.record_storage_batch(2, 0);
+
+        let snap = m.snapshot();
+        assert_eq!(snap.storage_queue_depth, 1);
+        assert_eq!(snap.storage_queue_peak, 3);
+        assert_eq!(snap.storage_writes, 2);
+        assert_eq!(snap.storage_writes_dropped, 1);
+
+        m.record_storage_batch(0, 1);
+        let snap = m.snapshot();
+        assert_eq!(snap.storage_queue_depth, 0);
+        assert_eq!(snap.storage_write_batches, 2);
+        assert_eq!(snap.storage_writes_dropped, 2);
+    }
+
     #[test]
     fn test_global_metrics() {
         let m = metrics();
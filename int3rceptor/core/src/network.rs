@@ -0,0 +1,373 @@
+//! Network condition simulation
+//!
+//! Per-host profiles that throttle bandwidth, add latency and inject failures
+//! into proxied traffic, for testing clients on bad networks.
+
+use crate::error::{ProxyError, Result};
+use crate::scope::{ScopeRule, Target};
+use parking_lot::RwLock;
+use rand::Rng;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const COPY_CHUNK: usize = 16 * 1024;
+/// Upper bound on a profile's latency and jitter
+const MAX_DELAY_MS: u64 = 60_000;
+/// Body of responses replaced by an injected error
+pub(crate) const SIMULATED_ERROR_BODY: &[u8] = b"Simulated upstream failure";
+
+/// Simulated conditions for traffic whose host matches `target`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkProfile {
+    pub id: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Which hosts the profile applies to, in scope-rule syntax (e.g. `*.example.com`)
+    pub target: ScopeRule,
+    /// Bytes per second in each direction; 0 means unlimited
+    #[serde(default)]
+    pub bandwidth_bps: u64,
+    /// Fixed delay added before each request or tunnel
+    #[serde(default)]
+    pub latency_ms: u64,
+    /// Extra random delay of up to this many milliseconds
+    #[serde(default)]
+    pub jitter_ms: u64,
+    /// Fraction of requests (0.0 - 1.0) whose connection is reset
+    #[serde(default)]
+    pub reset_rate: f64,
+    /// Fraction of requests (0.0 - 1.0) answered with `error_status`
+    #[serde(default)]
+    pub error_rate: f64,
+    #[serde(default = "default_error_status")]
+    pub error_status: u16,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_error_status() -> u16 {
+    503
+}
+
+/// Failure chosen for a single request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    Reset,
+    Error(u16),
+}
+
+impl NetworkProfile {
+    pub fn validate(&self) -> Result<()> {
+        for (field, rate) in [
+            ("reset_rate", self.reset_rate),
+            ("error_rate", self.error_rate),
+        ] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(ProxyError::invalid_config(
+                    field,
+                    "must be between 0.0 and 1.0",
+                ));
+            }
+        }
+        for (field, delay) in [
+            ("latency_ms", self.latency_ms),
+            ("jitter_ms", self.jitter_ms),
+        ] {
+            if delay > MAX_DELAY_MS {
+                return Err(ProxyError::invalid_config(
+                    field,
+                    format!("must be at most {MAX_DELAY_MS} ms"),
+                ));
+            }
+        }
+        if !(500..=599).contains(&self.error_status) {
+            return Err(ProxyError::invalid_config(
+                "error_status",
+                "must be a 5xx status code",
+            ));
+        }
+        self.target.validate()
+    }
+
+    /// Latency plus a random share of the jitter
+    pub fn delay(&self) -> Duration {
+        let jitter = if self.jitter_ms > 0 {
+            rand::thread_rng().gen_range(0..=self.jitter_ms)
+        } else {
+            0
+        };
+        Duration::from_millis(self.latency_ms.saturating_add(jitter))
+    }
+
+    /// Time `bytes` take at the profile's bandwidth
+    pub fn transfer_time(&self, bytes: usize) -> Duration {
+        if self.bandwidth_bps == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(bytes as f64 / self.bandwidth_bps as f64)
+    }
+
+    /// Roll the dice for an injected failure
+    pub fn roll_fault(&self) -> Option<Fault> {
+        let mut rng = rand::thread_rng();
+        if self.reset_rate > 0.0 && rng.gen_bool(self.reset_rate) {
+            return Some(Fault::Reset);
+        }
+        if self.error_rate > 0.0 && rng.gen_bool(self.error_rate) {
+            return Some(Fault::Error(self.error_status));
+        }
+        None
+    }
+}
+
+/// Sleep for the time `bytes` would take at the profile's bandwidth
+pub(crate) async fn throttle(profile: Option<&NetworkProfile>, bytes: usize) {
+    if let Some(profile) = profile {
+        let delay = profile.transfer_time(bytes);
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct NetworkConditions {
+    profiles: Arc<RwLock<Vec<NetworkProfile>>>,
+    regex_cache: Arc<RwLock<HashMap<String, Regex>>>,
+}
+
+impl NetworkConditions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_profiles(&self) -> Vec<NetworkProfile> {
+        self.profiles.read().clone()
+    }
+
+    pub fn set_profiles(&self, profiles: Vec<NetworkProfile>) -> Result<()> {
+        for profile in &profiles {
+            profile.validate()?;
+        }
+        *self.profiles.write() = profiles;
+        self.regex_cache.write().clear();
+        Ok(())
+    }
+
+    /// Add a profile, replacing any existing one with the same id
+    pub fn upsert_profile(&self, profile: NetworkProfile) -> Result<()> {
+        profile.validate()?;
+        let mut profiles = self.profiles.write();
+        match profiles.iter_mut().find(|p| p.id == profile.id) {
+            Some(existing) => *existing = profile,
+            None => profiles.push(profile),
+        }
+        self.regex_cache.write().clear();
+        Ok(())
+    }
+
+    pub fn remove_profile(&self, id: &str) -> bool {
+        let mut profiles = self.profiles.write();
+        let before = profiles.len();
+        profiles.retain(|p| p.id != id);
+        self.regex_cache.write().clear();
+        profiles.len() != before
+    }
+
+    /// First enabled profile matching a request URL
+    pub fn profile_for_url(&self, url: &str) -> Option<NetworkProfile> {
+        self.find(Target::parse(url)?)
+    }
+
+    /// First enabled profile matching a CONNECT authority (`host:port`)
+    pub fn profile_for_authority(&self, authority: &str) -> Option<NetworkProfile> {
+        self.find(Target::from_authority(authority)?)
+    }
+
+    fn find(&self, target: Target) -> Option<NetworkProfile> {
+        let profiles = self.profiles.read();
+        if profiles.is_empty() {
+            return None;
+        }
+        profiles
+            .iter()
+            .find(|p| p.enabled && p.target.matches(&target, &self.regex_cache))
+            .cloned()
+    }
+}
+
+/// Copy both directions of a tunnel, limiting each to `bps` bytes per second
+///
+/// A `bps` of 0 means unlimited.
+pub async fn copy_bidirectional_throttled<A, B>(mut a: A, mut b: B, bps: u64) -> std::io::Result<()>
+where
+    A: AsyncRead + AsyncWrite + Unpin,
+    B: AsyncRead + AsyncWrite + Unpin,
+{
+    if bps == 0 {
+        tokio::io::copy_bidirectional(&mut a, &mut b).await?;
+        return Ok(());
+    }
+    let (mut a_read, mut a_write) = tokio::io::split(a);
+    let (mut b_read, mut b_write) = tokio::io::split(b);
+    tokio::try_join!(
+        copy_throttled(&mut a_read, &mut b_write, bps),
+        copy_throttled(&mut b_read, &mut a_write, bps),
+    )?;
+    Ok(())
+}
+
+async fn copy_throttled<R, W>(reader: &mut R, writer: &mut W, bps: u64) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    // Small chunks keep the pacing smooth at low rates
+    let chunk = (bps as usize / 10).clamp(1, COPY_CHUNK);
+    let mut buf = vec![0u8; chunk];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            writer.shutdown().await?;
+            return Ok(());
+        }
+        writer.write_all(&buf[..n]).await?;
+        tokio::time::sleep(Duration::from_secs_f64(n as f64 / bps as f64)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(id: &str, target: &str) -> NetworkProfile {
+        NetworkProfile {
+            id: id.to_string(),
+            enabled: true,
+            target: target.parse().unwrap(),
+            bandwidth_bps: 0,
+            latency_ms: 0,
+            jitter_ms: 0,
+            reset_rate: 0.0,
+            error_rate: 0.0,
+            error_status: 503,
+        }
+    }
+
+    #[test]
+    fn test_profile_matching() {
+        let conditions = NetworkConditions::new();
+        let mut disabled = profile("off", "*.example.com");
+        disabled.enabled = false;
+        conditions
+            .set_profiles(vec![
+                disabled,
+                profile("api", "api.example.com"),
+                profile("all", "*.example.com"),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            conditions
+                .profile_for_url("https://api.example.com/x")
+                .unwrap()
+                .id,
+            "api"
+        );
+        assert_eq!(
+            conditions
+                .profile_for_authority("cdn.example.com:443")
+                .unwrap()
+                .id,
+            "all"
+        );
+        assert!(conditions.profile_for_url("https://other.com/").is_none());
+
+        // Replacing a regex profile drops the compiled patterns it used
+        conditions
+            .upsert_profile(profile("api", "regex:^api\\."))
+            .unwrap();
+        assert!(conditions.profile_for_url("https://api.test/").is_some());
+        assert_eq!(conditions.regex_cache.read().len(), 1);
+        conditions
+            .upsert_profile(profile("api", "api.example.com"))
+            .unwrap();
+        assert!(conditions.regex_cache.read().is_empty());
+    }
+
+    #[test]
+    fn test_validation() {
+        let conditions = NetworkConditions::new();
+        let mut bad_rate = profile("a", "example.com");
+        bad_rate.reset_rate = 1.5;
+        assert!(conditions.upsert_profile(bad_rate).is_err());
+
+        let mut bad_status = profile("b", "example.com");
+        bad_status.error_status = 200;
+        assert!(conditions.upsert_profile(bad_status).is_err());
+
+        let mut bad_latency = profile("d", "example.com");
+        bad_latency.latency_ms = u64::MAX;
+        assert!(conditions.upsert_profile(bad_latency).is_err());
+        let mut bad_jitter = profile("e", "example.com");
+        bad_jitter.jitter_ms = MAX_DELAY_MS + 1;
+        assert!(conditions.upsert_profile(bad_jitter).is_err());
+
+        assert!(conditions
+            .upsert_profile(profile("c", "example.com"))
+            .is_ok());
+        assert!(conditions.remove_profile("c"));
+        assert!(conditions.get_profiles().is_empty());
+    }
+
+    #[test]
+    fn test_faults_and_timing() {
+        let mut p = profile("p", "example.com");
+        assert_eq!(p.roll_fault(), None);
+
+        p.error_rate = 1.0;
+        p.error_status = 502;
+        assert_eq!(p.roll_fault(), Some(Fault::Error(502)));
+
+        p.reset_rate = 1.0;
+        assert_eq!(p.roll_fault(), Some(Fault::Reset));
+
+        p.latency_ms = 100;
+        p.jitter_ms = 50;
+        let delay = p.delay();
+        assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(150));
+        // Profiles built without validation must not overflow
+        p.latency_ms = u64::MAX;
+        p.jitter_ms = u64::MAX;
+        assert_eq!(p.delay(), Duration::from_millis(u64::MAX));
+
+        assert_eq!(p.transfer_time(1024), Duration::ZERO);
+        p.bandwidth_bps = 1024;
+        assert_eq!(p.transfer_time(2048), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_throttled_copy_delivers_all_bytes() {
+        // 0 is unlimited and must not divide by zero
+        for bps in [1_000_000, 0] {
+            let (client, mut client_peer) = tokio::io::duplex(64);
+            let (server, mut server_peer) = tokio::io::duplex(64);
+            let tunnel = tokio::spawn(copy_bidirectional_throttled(client, server, bps));
+
+            client_peer.write_all(b"hello upstream").await.unwrap();
+            client_peer.shutdown().await.unwrap();
+            let mut received = Vec::new();
+            server_peer.read_to_end(&mut received).await.unwrap();
+            assert_eq!(received, b"hello upstream");
+
+            server_peer.shutdown().await.unwrap();
+            tunnel.await.unwrap().unwrap();
+        }
+    }
+}
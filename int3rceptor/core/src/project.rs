@@ -1,7 +1,11 @@
 use crate::capture::{CaptureEntry, CaptureQuery};
-use crate::error::Result;
+use crate::error::{ProxyError, Result};
+use crate::hosts::HostEntry;
+use crate::intruder::AttackRecord;
+use crate::repeater::RepeaterTab;
+use crate::scope::{HostPattern, ScopeConfig, ScopeRule};
 use crate::storage::CaptureStorage;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::path::Path;
 use std::sync::Arc;
 use time::OffsetDateTime;
@@ -34,8 +38,19 @@ impl Default for ProjectInfo {
 pub struct ProjectData {
     pub info: ProjectInfo,
     pub traffic: Vec<CaptureEntry>,
-    pub scope: Vec<String>,
+    /// Scope rules; older projects stored include targets as plain strings
+    #[serde(default, deserialize_with = "deserialize_scope")]
+    pub scope: ScopeConfig,
     pub settings: serde_json::Value,
+    /// Hosts overrides for upstream connections
+    #[serde(default)]
+    pub hosts: Vec<HostEntry>,
+    /// Repeater tabs with their send history
+    #[serde(default)]
+    pub repeater: Vec<RepeaterTab>,
+    /// Finished intruder attacks with their results
+    #[serde(default)]
+    pub intruder: Vec<AttackRecord>,
 }
 
 impl ProjectData {
@@ -47,8 +62,11 @@ impl ProjectData {
         Self {
             info,
             traffic: Vec::new(),
-            scope: Vec::new(),
+            scope: ScopeConfig::default(),
             settings: serde_json::json!({}),
+            hosts: Vec::new(),
+            repeater: Vec::new(),
+            intruder: Vec::new(),
         }
     }
 
@@ -91,13 +109,75 @@ impl ProjectData {
             name: self.info.name.clone(),
             description: self.info.description.clone(),
             traffic_count: self.traffic.len(),
-            scope_count: self.scope.len(),
+            scope_count: self.scope.includes.len() + self.scope.excludes.len(),
             created_at: self.info.created_at,
             modified_at: self.info.modified_at,
         }
     }
 }
 
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredScope {
+    Config(ScopeConfig),
+    Targets(Vec<String>),
+}
+
+fn deserialize_scope<'de, D>(deserializer: D) -> std::result::Result<ScopeConfig, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let targets = match StoredScope::deserialize(deserializer)? {
+        StoredScope::Config(config) => return Ok(config),
+        StoredScope::Targets(targets) => targets,
+    };
+
+    // A dropped include would leave the list empty and open the scope to every
+    // host, so any target that cannot be migrated fails the load instead
+    let mut includes = Vec::with_capacity(targets.len());
+    let mut rejected = Vec::new();
+    for target in &targets {
+        match migrate_legacy_target(target) {
+            Ok(rule) => includes.push(rule),
+            Err(reason) => rejected.push(format!("'{}' ({})", target, reason)),
+        }
+    }
+    if !rejected.is_empty() {
+        return Err(serde::de::Error::custom(format!(
+            "legacy scope targets cannot be migrated, re-add them as scope rules: {}",
+            rejected.join(", ")
+        )));
+    }
+    Ok(ScopeConfig {
+        includes,
+        ..Default::default()
+    })
+}
+
+/// Convert a legacy include, which matched as a substring of the URL, into a rule
+///
+/// A bare domain keeps matching its subdomains. Entries whose substring meaning
+/// has no structured equivalent, such as a bare word without a dot, are
+/// rejected; with a scheme, as in `http://localhost:3000`, they are exact hosts.
+fn migrate_legacy_target(target: &str) -> std::result::Result<ScopeRule, String> {
+    let mut rule: ScopeRule = target.parse().map_err(|e: ProxyError| match e {
+        ProxyError::ScopePatternInvalid { reason, .. } => reason,
+        other => other.to_string(),
+    })?;
+    let trimmed = target.trim();
+    let explicit =
+        trimmed.contains("://") || trimmed.starts_with("*.") || trimmed.starts_with("regex:");
+    // Targets with a scheme already name an exact host, dotted or not
+    if let (HostPattern::Exact(host), false) = (&rule.host, explicit) {
+        if !host.contains('.') {
+            return Err("substring target without a domain".to_string());
+        }
+        tracing::warn!(%target, "Migrating legacy scope target to the domain and its subdomains");
+        rule.host = HostPattern::Wildcard(host.clone());
+    }
+    Ok(rule)
+}
+
 /// Lightweight project summary
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProjectSummary {
@@ -141,7 +221,7 @@ impl ProjectManager {
     }
 
     /// Export current project data
-    pub fn export(&self, scope: Vec<String>, settings: serde_json::Value) -> Result<ProjectData> {
+    pub fn export(&self, scope: ScopeConfig, settings: serde_json::Value) -> Result<ProjectData> {
         let info = self.current_info();
 
         // Get all traffic from storage
@@ -156,6 +236,9 @@ impl ProjectManager {
             traffic,
             scope,
             settings,
+            hosts: Vec::new(),
+            repeater: Vec::new(),
+            intruder: Vec::new(),
         })
     }
 
@@ -210,7 +293,28 @@ mod tests {
 
         let mut project = ProjectData::new("Test Project");
         project.info.description = "A test project".to_string();
-        project.scope = vec!["example.com".to_string(), "*.test.com".to_string()];
+        project.scope = ScopeConfig::import("example.com\n*.test.com\n- /logout").unwrap();
+        let repeater = crate::repeater::Repeater::new();
+        repeater.create_tab(Some("login".to_string()), Default::default());
+        project.repeater = repeater.snapshot();
+        project.intruder = vec![serde_json::from_value(serde_json::json!({
+            "id": 3,
+            "template": "GET /?q=§q§ HTTP/1.1",
+            "config": {
+                "positions": [{"start": 0, "end": 0, "name": "q"}],
+                "payloads": {"sources": [{"type": "null", "count": 1}]},
+                "attack_type": "Sniper"
+            },
+            "created_at_ms": 0,
+            "progress": {
+                "id": 3, "status": "done", "total": 1, "sent": 1, "errored": 0, "next_index": 1
+            },
+            "results": [{
+                "request_id": 0, "payloads": [""], "status_code": 200,
+                "response_length": 2, "duration_ms": 4
+            }]
+        }))
+        .unwrap()];
 
         project.save_to_file(&path).unwrap();
         assert!(path.exists());
@@ -218,7 +322,63 @@ mod tests {
         let loaded = ProjectData::load_from_file(&path).unwrap();
         assert_eq!(loaded.info.name, "Test Project");
         assert_eq!(loaded.info.description, "A test project");
-        assert_eq!(loaded.scope.len(), 2);
+        assert_eq!(loaded.scope.includes.len(), 2);
+        assert_eq!(loaded.scope.excludes[0].to_string(), "/logout");
+        assert_eq!(loaded.repeater[0].name, "login");
+        assert_eq!(loaded.intruder[0].results[0].status_code, 200);
+        assert_eq!(
+            loaded.intruder[0].config.payloads,
+            project.intruder[0].config.payloads
+        );
+    }
+
+    #[test]
+    fn test_project_loads_legacy_scope_strings() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("legacy.i3p");
+        let mut legacy = serde_json::to_value(ProjectData::new("Legacy")).unwrap();
+        legacy["scope"] = serde_json::json!([
+            "example.com",
+            "*.test.com",
+            "/api",
+            "10.0.0.0/8",
+            "http://localhost:3000",
+            "https://[::1]:8443"
+        ]);
+        std::fs::write(&path, legacy.to_string()).unwrap();
+
+        let loaded = ProjectData::load_from_file(&path).unwrap();
+        let includes: Vec<String> = loaded
+            .scope
+            .includes
+            .iter()
+            .map(|r| r.to_string())
+            .collect();
+        assert_eq!(
+            includes,
+            [
+                "*.example.com",
+                "*.test.com",
+                "/api",
+                "10.0.0.0/8",
+                "http://localhost:3000",
+                "https://[::1]:8443"
+            ]
+        );
+        assert!(loaded.scope.excludes.is_empty());
+    }
+
+    #[test]
+    fn test_project_rejects_unmigratable_legacy_scope() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("legacy.i3p");
+        let mut legacy = serde_json::to_value(ProjectData::new("Legacy")).unwrap();
+        legacy["scope"] = serde_json::json!(["regex:(", "logout"]);
+        std::fs::write(&path, legacy.to_string()).unwrap();
+
+        let err = ProjectData::load_from_file(&path).unwrap_err().to_string();
+        assert!(err.contains("'regex:('"), "{}", err);
+        assert!(err.contains("'logout'"), "{}", err);
     }
 
     #[test]
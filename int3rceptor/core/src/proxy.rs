@@ -1,14 +1,20 @@
 use crate::capture::{CapturedRequest, CapturedResponse, RequestCapture};
 use crate::connection_pool::{ConnectionPool, ProxyBody};
 use crate::error::{ProxyError, Result};
-use crate::metrics::metrics;
-use crate::rules::RuleEngine;
+use crate::hosts::HostMap;
+use crate::metrics::{metrics, Metrics};
+use crate::network::{
+    copy_bidirectional_throttled, throttle, Fault, NetworkConditions, NetworkProfile,
+    SIMULATED_ERROR_BODY,
+};
+use crate::rules::{RuleEngine, RuleHits};
 use crate::scanner::Scanner;
-use crate::scope::ScopeManager;
+use crate::scope::{OutOfScopeAction, ScopeManager};
 use crate::tls::TlsInterceptor;
-use http_body_util::BodyExt;
-use hyper::body::{Bytes, Incoming};
+use http_body_util::{BodyExt, Either};
+use hyper::body::{Body, Bytes, Incoming};
 use hyper::header::{HeaderMap, HOST};
+use hyper::http::uri::Authority;
 use hyper::service::service_fn;
 use hyper::{Method, Request, Response, StatusCode, Uri};
 use hyper_util::rt::{TokioExecutor, TokioIo};
@@ -36,6 +42,7 @@ pub struct ProxyServer {
     tls: Option<Arc<TlsInterceptor>>,
     plugins: Option<Arc<crate::plugin::PluginManager>>,
     scanner: Option<Arc<Scanner>>,
+    network: Arc<NetworkConditions>,
 }
 
 impl ProxyServer {
@@ -57,9 +64,22 @@ impl ProxyServer {
             tls,
             plugins,
             scanner,
+            network: Arc::new(NetworkConditions::new()),
         }
     }
 
+    /// Share an existing connection pool (and its hosts overrides) with the proxy
+    pub fn with_pool(mut self, pool: ConnectionPool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Apply simulated network conditions to proxied traffic
+    pub fn with_network(mut self, network: Arc<NetworkConditions>) -> Self {
+        self.network = network;
+        self
+    }
+
     pub async fn run(self) -> Result<()> {
         info!(addr = %self.addr, "Starting proxy server");
         let listener = TcpListener::bind(self.addr).await?;
@@ -70,6 +90,7 @@ impl ProxyServer {
         let tls = self.tls.clone();
         let plugins = self.plugins.clone();
         let scanner = self.scanner.clone();
+        let network = self.network.clone();
 
         loop {
             let (stream, peer) = listener.accept().await?;
@@ -85,6 +106,7 @@ impl ProxyServer {
             let tls = tls.clone();
             let plugins = plugins.clone();
             let scanner = scanner.clone();
+            let network = network.clone();
             let peer_addr = peer;
 
             tokio::spawn(
@@ -97,6 +119,7 @@ impl ProxyServer {
                         let tls = tls.clone();
                         let plugins = plugins.clone();
                         let scanner = scanner.clone();
+                        let network = network.clone();
 
                         async move {
                             let request_id = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
@@ -123,6 +146,8 @@ impl ProxyServer {
                                     tls.clone(),
                                     plugins.clone(),
                                     scanner.clone(),
+                                    network.clone(),
+                                    None,
                                 )
                                 .await
                                 {
@@ -130,7 +155,13 @@ impl ProxyServer {
                                         metrics().record_request_success();
                                         metrics().record_response(res.status().as_u16());
                                         debug!(status = %res.status(), "Request completed");
-                                        Ok::<_, hyper::Error>(res)
+                                        Ok::<_, ProxyError>(res)
+                                    }
+                                    // Simulated reset: fail the service so the connection is dropped
+                                    Err(ProxyError::ConnectionClosed) => {
+                                        metrics().record_request_error();
+                                        debug!("Connection reset by network simulation");
+                                        Err(ProxyError::ConnectionClosed)
                                     }
                                     Err(err) => {
                                         metrics().record_request_error();
@@ -177,19 +208,47 @@ async fn handle_request(
     tls: Option<Arc<TlsInterceptor>>,
     plugins: Option<Arc<crate::plugin::PluginManager>>,
     scanner: Option<Arc<Scanner>>,
-) -> Result<Response<ProxyBody>> {
+    network: Arc<NetworkConditions>,
+    tunnel: Option<Authority>,
+) -> Result<Response<ResponseBody>> {
     if req.method() == Method::CONNECT {
-        return handle_connect(req, capture, pool, rules, scope, tls, plugins, scanner);
+        return handle_connect(
+            req, capture, pool, rules, scope, tls, plugins, scanner, network,
+        );
     }
 
-    forward_request(req, pool, capture, rules, scope, plugins, scanner).await
+    forward_request(
+        req, pool, capture, rules, scope, plugins, scanner, network, tunnel,
+    )
+    .await
 }
 
-fn error_response(err: ProxyError) -> Response<ProxyBody> {
+/// Responses built by the proxy, or streamed from upstream untouched
+type ResponseBody = Either<ProxyBody, Incoming>;
+
+fn full(body: impl Into<Bytes>) -> ResponseBody {
+    Either::Left(ProxyBody::from(body.into()))
+}
+
+fn error_response(err: ProxyError) -> Response<ResponseBody> {
     Response::builder()
         .status(StatusCode::BAD_GATEWAY)
-        .body(ProxyBody::from(Bytes::from(format!("Proxy error: {err}"))))
-        .unwrap_or_else(|_| Response::new(ProxyBody::from(Bytes::new())))
+        .body(full(format!("Proxy error: {err}")))
+        .unwrap_or_else(|_| Response::new(full(Bytes::new())))
+}
+
+fn simulated_error_response(status: u16) -> Response<ResponseBody> {
+    Response::builder()
+        .status(status)
+        .body(full(Bytes::from_static(SIMULATED_ERROR_BODY)))
+        .unwrap_or_else(|_| Response::new(full(Bytes::new())))
+}
+
+fn scope_blocked_response() -> Response<ResponseBody> {
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(full(Bytes::from_static(b"Blocked by proxy scope")))
+        .unwrap_or_else(|_| Response::new(full(Bytes::new())))
 }
 
 fn host_from_headers(headers: &HeaderMap) -> Option<String> {
@@ -199,7 +258,11 @@ fn host_from_headers(headers: &HeaderMap) -> Option<String> {
         .map(|s| s.to_string())
 }
 
-fn normalize_uri(uri: &Uri, headers: &HeaderMap) -> Result<Uri> {
+/// Absolute URI for a request, which may arrive in origin form
+///
+/// `tunnel` is the CONNECT target when the request was read from an
+/// intercepted TLS tunnel; it makes the URI https and fixes its port.
+fn normalize_uri(uri: &Uri, headers: &HeaderMap, tunnel: Option<&Authority>) -> Result<Uri> {
     if uri.scheme().is_some() && uri.authority().is_some() {
         return Ok(uri.clone());
     }
@@ -208,13 +271,25 @@ fn normalize_uri(uri: &Uri, headers: &HeaderMap) -> Result<Uri> {
         .authority()
         .map(|a| a.to_string())
         .or_else(|| host_from_headers(headers))
+        .or_else(|| tunnel.map(|t| t.to_string()))
         .ok_or_else(|| ProxyError::InvalidRequest("missing host header".into()))?;
 
     let path = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
-    let full = format!("http://{authority}{path}");
+    let full = match tunnel {
+        Some(tunnel) => {
+            // The Host header may leave out the port the client connected to
+            let host = authority.parse::<Authority>()?.host().to_string();
+            match tunnel.port_u16() {
+                Some(port) if port != 443 => format!("https://{host}:{port}{path}"),
+                _ => format!("https://{host}{path}"),
+            }
+        }
+        None => format!("http://{authority}{path}"),
+    };
     Ok(full.parse()?)
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn forward_request(
     req: Request<Incoming>,
     pool: ConnectionPool,
@@ -223,8 +298,21 @@ async fn forward_request(
     scope: Arc<ScopeManager>,
     plugins: Option<Arc<crate::plugin::PluginManager>>,
     scanner: Option<Arc<Scanner>>,
-) -> Result<Response<ProxyBody>> {
-    let target_uri = normalize_uri(req.uri(), req.headers())?;
+    network: Arc<NetworkConditions>,
+    tunnel: Option<Authority>,
+) -> Result<Response<ResponseBody>> {
+    let target_uri = normalize_uri(req.uri(), req.headers(), tunnel.as_ref())?;
+
+    // Simulated network conditions
+    let profile = network.profile_for_url(&target_uri.to_string());
+    if let Some(profile) = &profile {
+        tokio::time::sleep(profile.delay()).await;
+        match profile.roll_fault() {
+            Some(Fault::Reset) => return Err(ProxyError::ConnectionClosed),
+            Some(Fault::Error(status)) => return Ok(simulated_error_response(status)),
+            None => {}
+        }
+    }
 
     // Track host metrics
     if let Some(host) = target_uri.host() {
@@ -242,13 +330,34 @@ async fn forward_request(
 
     // Check Scope
     if !scope.is_in_scope(&target_uri.to_string()) {
+        if scope.out_of_scope_action() == OutOfScopeAction::Drop {
+            debug!(uri = %target_uri, "Request out of scope, dropping");
+            return Ok(scope_blocked_response());
+        }
         debug!(uri = %target_uri, "Request out of scope, forwarding without capture");
         // Forward without capturing
         let (parts, body) = req.into_parts();
         let mut parts = parts;
         parts.uri = target_uri;
+
+        // Nothing reads the bodies, so stream them unless bandwidth is simulated
+        if profile.as_ref().is_none_or(|p| p.bandwidth_bps == 0) {
+            if let Some(len) = body.size_hint().exact() {
+                metrics().record_bytes_received(len);
+            }
+            let response = pool
+                .streaming_client()
+                .request(Request::from_parts(parts, body))
+                .await?;
+            if let Some(len) = response.body().size_hint().exact() {
+                metrics().record_bytes_sent(len);
+            }
+            return Ok(response.map(Either::Right));
+        }
+
         let body_bytes = body.collect().await?.to_bytes();
         metrics().record_bytes_received(body_bytes.len() as u64);
+        throttle(profile.as_ref(), body_bytes.len()).await;
 
         let forward_req = Request::from_parts(parts, ProxyBody::from(body_bytes));
         let client = pool.client();
@@ -256,8 +365,9 @@ async fn forward_request(
         let (parts, body) = response.into_parts();
         let body_bytes = body.collect().await?.to_bytes();
         metrics().record_bytes_sent(body_bytes.len() as u64);
+        throttle(profile.as_ref(), body_bytes.len()).await;
 
-        return Ok(Response::from_parts(parts, ProxyBody::from(body_bytes)));
+        return Ok(Response::from_parts(parts, full(body_bytes)));
     }
 
     let tls = target_uri.scheme_str() == Some("https");
@@ -267,13 +377,13 @@ async fn forward_request(
 
     let mut body_bytes = body.collect().await?.to_bytes().to_vec();
     metrics().record_bytes_received(body_bytes.len() as u64);
+    throttle(profile.as_ref(), body_bytes.len()).await;
 
     // Apply Request Rules
-    let rules_count = rules.get_rules().len();
-    rules.apply_request_rules(&mut parts, &mut body_bytes);
-    if rules_count > 0 {
-        metrics().record_rules_applied(rules_count as u64);
-    }
+    record_rule_hits(
+        metrics(),
+        rules.apply_request_rules(&mut parts, &mut body_bytes),
+    );
 
     let mut record = CapturedRequest::new(parts.method.to_string(), target_uri.to_string(), tls);
     record.headers = parts
@@ -295,6 +405,7 @@ async fn forward_request(
     let (mut parts, body) = response.into_parts();
     let mut body_bytes = body.collect().await?.to_bytes().to_vec();
     metrics().record_bytes_sent(body_bytes.len() as u64);
+    throttle(profile.as_ref(), body_bytes.len()).await;
 
     // Execute on_response plugin hook
     if let Some(ref plugin_manager) = plugins {
@@ -304,7 +415,10 @@ async fn forward_request(
     }
 
     // Apply Response Rules
-    rules.apply_response_rules(&mut parts, &mut body_bytes);
+    record_rule_hits(
+        metrics(),
+        rules.apply_response_rules(&mut parts, &mut body_bytes),
+    );
 
     let captured_response = CapturedResponse {
         request_id: 0,
@@ -329,6 +443,8 @@ async fn forward_request(
     let entry = crate::capture::CaptureEntry {
         request: record.clone(),
         response: Some(captured_response.clone()),
+        annotation: Default::default(),
+        omitted_bodies: None,
     };
 
     // Passive Scan
@@ -336,12 +452,11 @@ async fn forward_request(
         scanner.passive_scan(&entry);
     }
 
-    capture.push(record, Some(captured_response));
+    if capture.should_capture(&record, Some(&captured_response)) {
+        capture.push(record, Some(captured_response));
+    }
 
-    Ok(Response::from_parts(
-        parts,
-        ProxyBody::from(Bytes::from(body_bytes)),
-    ))
+    Ok(Response::from_parts(parts, full(body_bytes)))
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -354,31 +469,54 @@ fn handle_connect(
     tls: Option<Arc<TlsInterceptor>>,
     plugins: Option<Arc<crate::plugin::PluginManager>>,
     scanner: Option<Arc<Scanner>>,
-) -> Result<Response<ProxyBody>> {
-    let authority = req
+    network: Arc<NetworkConditions>,
+) -> Result<Response<ResponseBody>> {
+    let target = req
         .uri()
         .authority()
-        .map(|a| a.to_string())
+        .cloned()
         .ok_or_else(|| ProxyError::InvalidRequest("CONNECT missing authority".into()))?;
+    let authority = target.to_string();
+
+    let in_scope = scope.is_host_in_scope(&authority);
+    let action = scope.out_of_scope_action();
+    if in_scope {
+        let record = CapturedRequest::new("CONNECT", format!("https://{authority}"), true);
+        capture.push(record, None);
+    } else if action == OutOfScopeAction::Drop {
+        debug!(%authority, "CONNECT out of scope, dropping");
+        return Ok(scope_blocked_response());
+    }
 
-    let record = CapturedRequest::new("CONNECT", format!("https://{authority}"), true);
-    capture.push(record, None);
-
+    // Out-of-scope passthrough skips TLS interception entirely
+    let tls = tls.filter(|_| in_scope || action != OutOfScopeAction::Passthrough);
     if let Some(tls) = tls {
         let capture = capture.clone();
         let rules = rules.clone();
         let scope = scope.clone();
         let scanner = scanner.clone();
         tokio::spawn(async move {
-            if let Err(err) =
-                handle_tls_connect(req, capture, pool, rules, scope, tls, plugins, scanner).await
+            if let Err(err) = handle_tls_connect(
+                req, capture, pool, rules, scope, tls, plugins, scanner, network, target,
+            )
+            .await
             {
                 warn!(%err, "tls intercept error");
             }
         });
     } else {
+        // Intercepted traffic is simulated per request; opaque tunnels per connection
+        let profile = network.profile_for_authority(&authority);
+        if let Some(profile) = &profile {
+            match profile.roll_fault() {
+                Some(Fault::Reset) => return Err(ProxyError::ConnectionClosed),
+                Some(Fault::Error(status)) => return Ok(simulated_error_response(status)),
+                None => {}
+            }
+        }
+        let hosts = pool.hosts().clone();
         tokio::spawn(async move {
-            if let Err(err) = tunnel(authority, req).await {
+            if let Err(err) = tunnel(authority, req, hosts, profile).await {
                 warn!(%err, "connect tunnel error");
             }
         });
@@ -386,7 +524,7 @@ fn handle_connect(
 
     Ok(Response::builder()
         .status(StatusCode::OK)
-        .body(ProxyBody::from(Bytes::new()))
+        .body(full(Bytes::new()))
         .unwrap())
 }
 
@@ -400,6 +538,8 @@ async fn handle_tls_connect(
     tls: Arc<TlsInterceptor>,
     plugins: Option<Arc<crate::plugin::PluginManager>>,
     scanner: Option<Arc<Scanner>>,
+    network: Arc<NetworkConditions>,
+    target: Authority,
 ) -> Result<()> {
     let upgraded = hyper::upgrade::on(req).await?;
 
@@ -431,6 +571,8 @@ async fn handle_tls_connect(
         let tls = Some(tls.clone());
         let plugins = plugins.clone();
         let scanner = scanner.clone();
+        let network = network.clone();
+        let target = target.clone();
         async move {
             handle_request(
                 req,
@@ -441,6 +583,8 @@ async fn handle_tls_connect(
                 tls,
                 plugins,
                 scanner,
+                network,
+                Some(target),
             )
             .await
         }
@@ -453,12 +597,30 @@ async fn handle_tls_connect(
     Ok(())
 }
 
-async fn tunnel(host: String, req: Request<Incoming>) -> Result<()> {
+async fn tunnel(
+    host: String,
+    req: Request<Incoming>,
+    hosts: HostMap,
+    profile: Option<NetworkProfile>,
+) -> Result<()> {
     let upgraded = hyper::upgrade::on(req).await?;
+    if let Some(profile) = &profile {
+        tokio::time::sleep(profile.delay()).await;
+    }
     let addr = resolve_addr(&host);
-    let mut server = TcpStream::connect(addr).await?;
+    let mut server = match hosts.resolve_authority(&addr) {
+        Some(overridden) => TcpStream::connect(overridden).await?,
+        None => TcpStream::connect(addr).await?,
+    };
     let mut upgraded = TokioIo::new(upgraded);
-    copy_bidirectional(&mut upgraded, &mut server).await?;
+    match profile {
+        Some(profile) => {
+            copy_bidirectional_throttled(upgraded, server, profile.bandwidth_bps).await?
+        }
+        None => {
+            copy_bidirectional(&mut upgraded, &mut server).await?;
+        }
+    }
     Ok(())
 }
 
@@ -469,3 +631,91 @@ fn resolve_addr(host: &str) -> String {
         format!("{host}:443")
     }
 }
+
+/// Count the rules that matched and, of those, the ones that changed the message
+fn record_rule_hits(metrics: &Metrics, hits: RuleHits) {
+    if hits.matched > 0 {
+        metrics.record_rules_matched(hits.matched as u64);
+    }
+    if hits.applied > 0 {
+        metrics.record_rules_applied(hits.applied as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{Action, MatchCondition, Rule, RuleType};
+
+    fn normalize(uri: &str, host: Option<&str>, tunnel: Option<&str>) -> String {
+        let mut headers = HeaderMap::new();
+        if let Some(host) = host {
+            headers.insert(HOST, host.parse().unwrap());
+        }
+        let tunnel = tunnel.map(|t| t.parse::<Authority>().unwrap());
+        normalize_uri(&uri.parse().unwrap(), &headers, tunnel.as_ref())
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_normalize_uri() {
+        assert_eq!(normalize("http://a.test/x", None, None), "http://a.test/x");
+        assert_eq!(
+            normalize("/x?q=1", Some("a.test:8080"), None),
+            "http://a.test:8080/x?q=1"
+        );
+        // Requests from an intercepted tunnel keep its scheme and port
+        assert_eq!(
+            normalize("/x", Some("a.test"), Some("a.test:443")),
+            "https://a.test/x"
+        );
+        assert_eq!(
+            normalize("/x", Some("a.test"), Some("a.test:8443")),
+            "https://a.test:8443/x"
+        );
+        assert_eq!(
+            normalize("/", None, Some("[::1]:8443")),
+            "https://[::1]:8443/"
+        );
+        assert!(normalize_uri(&"/".parse().unwrap(), &HeaderMap::new(), None).is_err());
+    }
+
+    #[test]
+    fn test_rule_metrics_count_matches_only() {
+        let rules = RuleEngine::new();
+        rules.add_rule(Rule {
+            id: "admin".to_string(),
+            active: true,
+            rule_type: RuleType::Request,
+            condition: MatchCondition::UrlContains("/admin".to_string()),
+            action: Action::SetHeader("X-Test".to_string(), "1".to_string()),
+        });
+        let metrics = Metrics::new();
+        let parts = |uri: &str| Request::builder().uri(uri).body(()).unwrap().into_parts().0;
+
+        record_rule_hits(
+            &metrics,
+            rules.apply_request_rules(&mut parts("/home"), &mut vec![]),
+        );
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.rules_applied, snapshot.rules_matched), (0, 0));
+
+        record_rule_hits(
+            &metrics,
+            rules.apply_request_rules(&mut parts("/admin"), &mut vec![]),
+        );
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.rules_applied, snapshot.rules_matched), (1, 1));
+
+        // A match whose action leaves the request as it was is not applied
+        let mut request = parts("/admin");
+        request.headers.insert("x-test", "1".parse().unwrap());
+        record_rule_hits(
+            &metrics,
+            rules.apply_request_rules(&mut request, &mut vec![]),
+        );
+        let snapshot = metrics.snapshot();
+        assert_eq!((snapshot.rules_applied, snapshot.rules_matched), (1, 2));
+    }
+}
@@ -0,0 +1,1125 @@
+//! Race-condition testing: release many requests at the same moment
+//!
+//! In last-byte mode every request gets its own HTTP/1.1 connection and is
+//! written in full except for its final byte; once all connections are
+//! primed, the final bytes are written together. In single-packet mode the
+//! requests share one HTTP/2 connection: headers and all but the last body
+//! byte go out first, then the final DATA frame of every stream is sent in a
+//! single write.
+
+use crate::capture::{CaptureSource, CapturedRequest, CapturedResponse, RequestCapture};
+use crate::connection_pool::ConnectionPool;
+use crate::error::{ProxyError, Result};
+use crate::import::http1;
+use crate::repeater::{RepeaterRequest, RepeaterResponse};
+use crate::scope::{OutOfScopeAction, ScopeManager};
+use bytes::Bytes;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Method, Uri};
+use hyper_rustls::ConfigBuilderExt;
+use parking_lot::Mutex;
+use rustls::pki_types::ServerName;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::{watch, Notify};
+use tokio_rustls::TlsConnector;
+
+pub const MAX_RACE_REQUESTS: usize = 100;
+
+/// Bound on connecting and priming, and separately on collecting responses
+const RACE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Length of the HTTP/2 client connection preface
+const PREFACE_LEN: usize = 24;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RaceMode {
+    /// HTTP/1.1, one connection per request, final bytes written together
+    #[default]
+    LastByte,
+    /// HTTP/2, one connection, final frames in a single write
+    SinglePacket,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaceResult {
+    /// Position of the request in the race
+    pub index: usize,
+    pub response: Option<RepeaterResponse>,
+    pub error: Option<String>,
+    /// Microseconds from the release until this request's last byte was written
+    pub sent_offset_us: u64,
+    /// Microseconds from the release until the response was complete
+    pub elapsed_us: u64,
+    pub capture_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RaceReport {
+    pub mode: RaceMode,
+    /// Microseconds between the first and last request leaving
+    pub spread_us: u64,
+    pub results: Vec<RaceResult>,
+}
+
+/// A raced request with its URL already parsed
+struct Prepared {
+    request: RepeaterRequest,
+    uri: Uri,
+    method: Method,
+    tls: bool,
+    in_scope: bool,
+}
+
+/// Response fields shared by both modes
+struct Received {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+trait Io: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Io for T {}
+
+/// Sends a set of requests so they reach the server together
+///
+/// Connections resolve through the pool's hosts overrides and each exchange
+/// is captured with source `repeater` unless it is out of scope.
+#[derive(Clone)]
+pub struct RaceClient {
+    pool: ConnectionPool,
+    capture: Arc<RequestCapture>,
+    scope: Arc<ScopeManager>,
+}
+
+impl RaceClient {
+    pub fn new(
+        pool: ConnectionPool,
+        capture: Arc<RequestCapture>,
+        scope: Arc<ScopeManager>,
+    ) -> Self {
+        Self {
+            pool,
+            capture,
+            scope,
+        }
+    }
+
+    pub async fn race(&self, mode: RaceMode, requests: Vec<RepeaterRequest>) -> Result<RaceReport> {
+        if requests.len() < 2 || requests.len() > MAX_RACE_REQUESTS {
+            return Err(ProxyError::invalid_config(
+                "requests",
+                format!("a race needs between 2 and {MAX_RACE_REQUESTS} requests"),
+            ));
+        }
+        let mut prepared = requests
+            .into_iter()
+            .map(prepare)
+            .collect::<Result<Vec<_>>>()?;
+        for prepared in &mut prepared {
+            prepared.in_scope = self.scope.is_in_scope(&prepared.request.url);
+            if !prepared.in_scope && self.scope.out_of_scope_action() == OutOfScopeAction::Drop {
+                return Err(ProxyError::InvalidRequest(format!(
+                    "{} is out of scope",
+                    prepared.request.url
+                )));
+            }
+        }
+        let (outcomes, offsets) = match mode {
+            RaceMode::LastByte => self.last_byte(&prepared).await,
+            RaceMode::SinglePacket => self.single_packet(&prepared).await?,
+        };
+
+        let spread_us = match (
+            offsets.iter().flatten().min(),
+            offsets.iter().flatten().max(),
+        ) {
+            (Some(first), Some(last)) => last - first,
+            _ => 0,
+        };
+        let results = prepared
+            .into_iter()
+            .zip(outcomes)
+            .zip(offsets)
+            .enumerate()
+            .map(|(index, ((prepared, (outcome, elapsed)), offset))| {
+                self.finish(
+                    index,
+                    prepared,
+                    outcome,
+                    offset.unwrap_or_default(),
+                    elapsed,
+                )
+            })
+            .collect();
+        Ok(RaceReport {
+            mode,
+            spread_us,
+            results,
+        })
+    }
+
+    /// One connection per request, each primed with everything but its last byte
+    async fn last_byte(&self, prepared: &[Prepared]) -> (Vec<Outcome>, Vec<Option<u64>>) {
+        let (release_tx, release_rx) = watch::channel(None::<Instant>);
+        let mut primed = Vec::with_capacity(prepared.len());
+        let mut tasks = Vec::with_capacity(prepared.len());
+        for item in prepared {
+            let (ready_tx, ready_rx) = tokio::sync::oneshot::channel();
+            primed.push(ready_rx);
+            let client = self.clone();
+            let uri = item.uri.clone();
+            let method = item.request.method.clone();
+            let wire = serialize_http1(&item.request, &item.uri);
+            let mut release_rx = release_rx.clone();
+            tasks.push(tokio::spawn(async move {
+                let prime = async {
+                    let mut io = client.connect(&uri, b"http/1.1").await?;
+                    io.write_all(&wire[..wire.len() - 1]).await?;
+                    io.flush().await?;
+                    Ok::<_, ProxyError>(io)
+                };
+                let io = tokio::time::timeout(RACE_TIMEOUT, prime)
+                    .await
+                    .unwrap_or_else(|_| Err(timed_out(&uri)));
+                let _ = ready_tx.send(());
+                let mut io = match io {
+                    Ok(io) => io,
+                    Err(err) => return (Err(err.to_string()), None, 0),
+                };
+                let release = match release_rx.wait_for(Option::is_some).await {
+                    Ok(release) => release.unwrap_or_else(Instant::now),
+                    Err(_) => return (Err("race was abandoned".to_string()), None, 0),
+                };
+
+                let sent = async {
+                    io.write_all(&wire[wire.len() - 1..]).await?;
+                    io.flush().await
+                };
+                if let Err(err) = sent.await {
+                    return (Err(err.to_string()), None, 0);
+                }
+                let offset = micros(release.elapsed());
+                let received =
+                    tokio::time::timeout(RACE_TIMEOUT, read_http1_response(&mut io, &method))
+                        .await
+                        .unwrap_or_else(|_| Err(timed_out(&uri)))
+                        .map_err(|err| err.to_string());
+                (received, Some(offset), micros(release.elapsed()))
+            }));
+        }
+
+        for ready in primed {
+            let _ = ready.await;
+        }
+        let _ = release_tx.send(Some(Instant::now()));
+
+        let mut outcomes = Vec::with_capacity(tasks.len());
+        let mut offsets = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            let (outcome, offset, elapsed) = task
+                .await
+                .unwrap_or_else(|err| (Err(err.to_string()), None, 0));
+            outcomes.push((outcome, elapsed));
+            offsets.push(offset);
+        }
+        (outcomes, offsets)
+    }
+
+    /// All requests on one HTTP/2 connection, final frames in one write
+    async fn single_packet(
+        &self,
+        prepared: &[Prepared],
+    ) -> Result<(Vec<Outcome>, Vec<Option<u64>>)> {
+        let first = &prepared[0];
+        if prepared
+            .iter()
+            .any(|p| p.uri.authority() != first.uri.authority() || p.tls != first.tls)
+        {
+            return Err(ProxyError::InvalidRequest(
+                "single-packet races need every request to go to the same host".to_string(),
+            ));
+        }
+
+        let gate = Arc::new(Gate::default());
+        let prime = async {
+            let io = self.connect(&first.uri, b"h2").await?;
+            let (client, connection) = h2::client::handshake(GateIo {
+                inner: io,
+                gate: gate.clone(),
+            })
+            .await
+            .map_err(h2_error)?;
+            tokio::spawn(async move {
+                if let Err(err) = connection.await {
+                    tracing::debug!(%err, "race connection closed");
+                }
+            });
+
+            let mut client = client;
+            let mut streams = Vec::with_capacity(prepared.len());
+            for item in prepared {
+                client = client.ready().await.map_err(h2_error)?;
+                let (response, mut stream) = client
+                    .send_request(h2_request(item)?, false)
+                    .map_err(h2_error)?;
+                let body = Bytes::from(item.request.body.clone().into_bytes());
+                let split = body.len().saturating_sub(1);
+                if split > 0 {
+                    stream
+                        .send_data(body.slice(..split), false)
+                        .map_err(h2_error)?;
+                }
+                streams.push((response, stream, body.slice(split..), split));
+            }
+            let primed: Vec<(u32, usize)> = streams
+                .iter()
+                .map(|(_, stream, _, split)| (stream.stream_id().as_u32(), *split))
+                .collect();
+            gate.wait_until(|log| {
+                primed.iter().all(|(id, split)| {
+                    log.streams
+                        .get(id)
+                        .is_some_and(|s| s.headers && s.data >= *split)
+                })
+            })
+            .await;
+
+            gate.hold();
+            let mut responses = Vec::with_capacity(streams.len());
+            for (response, mut stream, last, _) in streams {
+                stream.send_data(last, true).map_err(h2_error)?;
+                responses.push(response);
+            }
+            gate.wait_until(|log| {
+                primed
+                    .iter()
+                    .all(|(id, _)| log.streams.get(id).is_some_and(|s| s.end))
+            })
+            .await;
+            Ok::<_, ProxyError>(responses)
+        };
+        let primed = tokio::time::timeout(RACE_TIMEOUT, prime).await;
+        let responses = match primed {
+            Ok(responses) => responses,
+            Err(_) => {
+                // Never leave the final frames buffered
+                gate.release();
+                return Err(timed_out(&first.uri));
+            }
+        };
+        let responses = match responses {
+            Ok(responses) => responses,
+            Err(err) => {
+                gate.release();
+                return Err(err);
+            }
+        };
+
+        let release = Instant::now();
+        gate.release();
+        let tasks: Vec<_> = responses
+            .into_iter()
+            .map(|response| {
+                let uri = first.uri.clone();
+                tokio::spawn(async move {
+                    let received = tokio::time::timeout(RACE_TIMEOUT, read_h2_response(response))
+                        .await
+                        .unwrap_or_else(|_| Err(timed_out(&uri).to_string()));
+                    (received, micros(release.elapsed()))
+                })
+            })
+            .collect();
+        let mut outcomes = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            outcomes.push(task.await.unwrap_or_else(|err| (Err(err.to_string()), 0)));
+        }
+        let offset = gate
+            .flushed_at()
+            .map(|at| micros(at.saturating_duration_since(release)));
+        Ok((outcomes, vec![offset; prepared.len()]))
+    }
+
+    async fn connect(&self, uri: &Uri, alpn: &[u8]) -> Result<Box<dyn Io>> {
+        let host = uri
+            .host()
+            .unwrap_or_default()
+            .trim_start_matches('[')
+            .trim_end_matches(']');
+        let tls = uri.scheme_str() == Some("https");
+        let port = uri.port_u16().unwrap_or(if tls { 443 } else { 80 });
+        let addr = match self.pool.hosts().lookup(host) {
+            Some(ip) => SocketAddr::new(ip, port),
+            None => tokio::net::lookup_host((host, port))
+                .await?
+                .next()
+                .ok_or_else(|| ProxyError::connection_failed(host, "no addresses found"))?,
+        };
+        let tcp = TcpStream::connect(addr)
+            .await
+            .map_err(|err| ProxyError::connection_failed(host, err.to_string()))?;
+        tcp.set_nodelay(true)?;
+        if !tls {
+            // Plain HTTP/2 uses prior knowledge
+            return Ok(Box::new(tcp));
+        }
+
+        let mut config = rustls::ClientConfig::builder()
+            .with_native_roots()?
+            .with_no_client_auth();
+        config.alpn_protocols = vec![alpn.to_vec()];
+        let name = ServerName::try_from(host.to_string())
+            .map_err(|err| ProxyError::InvalidRequest(format!("invalid host {host}: {err}")))?;
+        let stream = TlsConnector::from(Arc::new(config))
+            .connect(name, tcp)
+            .await
+            .map_err(|err| ProxyError::connection_failed(host, err.to_string()))?;
+        if !alpn_accepted(alpn, stream.get_ref().1.alpn_protocol()) {
+            return Err(ProxyError::connection_failed(
+                host,
+                format!("server did not negotiate {}", String::from_utf8_lossy(alpn)),
+            ));
+        }
+        Ok(Box::new(stream))
+    }
+
+    fn finish(
+        &self,
+        index: usize,
+        prepared: Prepared,
+        outcome: std::result::Result<Received, String>,
+        sent_offset_us: u64,
+        elapsed_us: u64,
+    ) -> RaceResult {
+        let in_scope = prepared.in_scope;
+        let mut record = CapturedRequest::new(
+            prepared.request.method.clone(),
+            prepared.request.url.clone(),
+            prepared.tls,
+        );
+        record.headers = prepared.request.headers.clone();
+        record.body = prepared.request.body.clone().into_bytes();
+        record.source = CaptureSource::Repeater;
+
+        let (response, captured, error) = match outcome {
+            Ok(received) => {
+                let response = RepeaterResponse {
+                    status: received.status,
+                    status_text: hyper::StatusCode::from_u16(received.status)
+                        .ok()
+                        .and_then(|s| s.canonical_reason())
+                        .unwrap_or("")
+                        .to_string(),
+                    headers: received.headers.clone(),
+                    body: String::from_utf8_lossy(&received.body).into_owned(),
+                    time_ms: elapsed_us / 1000,
+                    size_bytes: received.body.len(),
+                };
+                let captured = CapturedResponse {
+                    request_id: 0,
+                    status_code: received.status,
+                    headers: received.headers,
+                    body: received.body,
+                    duration_ms: u128::from(elapsed_us / 1000),
+                };
+                (Some(response), Some(captured), None)
+            }
+            Err(error) => (None, None, Some(error)),
+        };
+        RaceResult {
+            index,
+            response,
+            error,
+            sent_offset_us,
+            elapsed_us,
+            // Out-of-scope traffic is never captured, as in the proxy
+            capture_id: in_scope.then(|| self.capture.push(record, captured)),
+        }
+    }
+}
+
+/// Servers without ALPN still speak HTTP/1.1; only h2 must be agreed on
+fn alpn_accepted(wanted: &[u8], negotiated: Option<&[u8]>) -> bool {
+    match negotiated {
+        Some(protocol) => protocol == wanted,
+        None => wanted != b"h2",
+    }
+}
+
+type Outcome = (std::result::Result<Received, String>, u64);
+
+fn prepare(request: RepeaterRequest) -> Result<Prepared> {
+    let uri: Uri = request.url.parse()?;
+    let tls = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        _ => {
+            return Err(ProxyError::InvalidRequest(format!(
+                "URL must be http or https: {}",
+                request.url
+            )))
+        }
+    };
+    if uri.host().is_none() {
+        return Err(ProxyError::InvalidRequest(format!(
+            "URL has no host: {}",
+            request.url
+        )));
+    }
+    let method = Method::from_bytes(request.method.as_bytes())
+        .map_err(|_| ProxyError::InvalidRequest(format!("invalid method: {}", request.method)))?;
+    Ok(Prepared {
+        request,
+        uri,
+        method,
+        tls,
+        in_scope: true,
+    })
+}
+
+fn micros(duration: Duration) -> u64 {
+    u64::try_from(duration.as_micros()).unwrap_or(u64::MAX)
+}
+
+fn timed_out(uri: &Uri) -> ProxyError {
+    ProxyError::ConnectionTimeout {
+        host: uri.host().unwrap_or_default().to_string(),
+        timeout_ms: RACE_TIMEOUT.as_millis() as u64,
+    }
+}
+
+fn h2_error(err: h2::Error) -> ProxyError {
+    ProxyError::InvalidResponse(format!("HTTP/2: {err}"))
+}
+
+/// Request bytes for HTTP/1.1, with `Host` and `Content-Length` filled in
+fn serialize_http1(request: &RepeaterRequest, uri: &Uri) -> Vec<u8> {
+    let target = uri.path_and_query().map_or("/", |pq| pq.as_str());
+    let mut head = format!("{} {} HTTP/1.1\r\n", request.method, target);
+    let has = |name: &str| {
+        request
+            .headers
+            .iter()
+            .any(|(n, _)| n.eq_ignore_ascii_case(name))
+    };
+    if !has("host") {
+        if let Some(authority) = uri.authority() {
+            head.push_str(&format!("Host: {authority}\r\n"));
+        }
+    }
+    for (name, value) in &request.headers {
+        if name.starts_with(':') || name.eq_ignore_ascii_case("content-length") {
+            continue;
+        }
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !request.body.is_empty() && !has("transfer-encoding") {
+        head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+    }
+    head.push_str("\r\n");
+    let mut wire = head.into_bytes();
+    wire.extend_from_slice(request.body.as_bytes());
+    wire
+}
+
+/// Read until one response is complete, skipping interim 1xx responses
+async fn read_http1_response(io: &mut Box<dyn Io>, method: &str) -> Result<Received> {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        let eof = match io.read(&mut buf).await? {
+            0 => true,
+            n => {
+                data.extend_from_slice(&buf[..n]);
+                false
+            }
+        };
+        match http1_response_len(&data, method, eof)? {
+            Some(Http1Len::Interim(len)) => {
+                data.drain(..len);
+            }
+            Some(Http1Len::Final(len)) => {
+                let (response, _) = http1::parse_response(&data[..len], method)?
+                    .ok_or_else(|| ProxyError::InvalidResponse("truncated response".to_string()))?;
+                return Ok(Received {
+                    status: response.status,
+                    headers: response.headers,
+                    body: response.body,
+                });
+            }
+            None if eof => {
+                return Err(ProxyError::InvalidResponse(
+                    "connection closed before the response was complete".to_string(),
+                ))
+            }
+            None => {}
+        }
+    }
+}
+
+enum Http1Len {
+    Interim(usize),
+    Final(usize),
+}
+
+/// Length of the first complete response in `data`, if there is one yet
+fn http1_response_len(data: &[u8], method: &str, eof: bool) -> Result<Option<Http1Len>> {
+    let mut headers = [httparse::EMPTY_HEADER; 128];
+    let mut resp = httparse::Response::new(&mut headers);
+    let head_len = match resp.parse(data) {
+        Ok(httparse::Status::Complete(len)) => len,
+        Ok(httparse::Status::Partial) => return Ok(None),
+        Err(err) => return Err(ProxyError::InvalidResponse(format!("HTTP response: {err}"))),
+    };
+    let status = resp.code.unwrap_or_default();
+    if (100..200).contains(&status) && status != 101 {
+        return Ok(Some(Http1Len::Interim(head_len)));
+    }
+    if method.eq_ignore_ascii_case("HEAD") || matches!(status, 101 | 204 | 304) {
+        return Ok(Some(Http1Len::Final(head_len)));
+    }
+    let header = |name: &str| {
+        resp.headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case(name))
+            .map(|h| String::from_utf8_lossy(h.value).to_ascii_lowercase())
+    };
+    if header("transfer-encoding").is_some_and(|te| te.contains("chunked")) {
+        return Ok(chunked_len(&data[head_len..]).map(|len| Http1Len::Final(head_len + len)));
+    }
+    if let Some(length) = header("content-length") {
+        let length: usize = length
+            .trim()
+            .parse()
+            .map_err(|_| ProxyError::InvalidResponse(format!("bad Content-Length: {length}")))?;
+        let total = head_len.saturating_add(length);
+        return Ok((data.len() >= total).then_some(Http1Len::Final(total)));
+    }
+    // Delimited by the connection closing
+    Ok(eof.then_some(Http1Len::Final(data.len())))
+}
+
+/// Length of a complete chunked body including trailers
+fn chunked_len(body: &[u8]) -> Option<usize> {
+    let mut pos = 0;
+    loop {
+        let line_end = pos + body.get(pos..)?.windows(2).position(|w| w == b"\r\n")?;
+        let line = std::str::from_utf8(&body[pos..line_end]).ok()?;
+        let size = usize::from_str_radix(line.split(';').next()?.trim(), 16).ok()?;
+        pos = line_end + 2;
+        if size == 0 {
+            // Trailers end with an empty line
+            loop {
+                let end = pos + body.get(pos..)?.windows(2).position(|w| w == b"\r\n")?;
+                if end == pos {
+                    return Some(end + 2);
+                }
+                pos = end + 2;
+            }
+        }
+        pos = pos.checked_add(size)?.checked_add(2)?;
+        if pos > body.len() {
+            return None;
+        }
+    }
+}
+
+fn h2_request(item: &Prepared) -> Result<http::Request<()>> {
+    let mut builder = http::Request::builder()
+        .method(item.method.clone())
+        .uri(item.uri.clone());
+    for (name, value) in &item.request.headers {
+        let lower = name.to_ascii_lowercase();
+        // Connection-specific headers are not allowed in HTTP/2; Host becomes :authority
+        if lower.starts_with(':')
+            || matches!(
+                lower.as_str(),
+                "host"
+                    | "connection"
+                    | "keep-alive"
+                    | "proxy-connection"
+                    | "transfer-encoding"
+                    | "upgrade"
+                    | "content-length"
+            )
+            || (lower == "te" && !value.eq_ignore_ascii_case("trailers"))
+        {
+            continue;
+        }
+        let invalid = |e: &dyn std::fmt::Display| ProxyError::InvalidHeader {
+            name: name.clone(),
+            reason: e.to_string(),
+        };
+        let header = HeaderName::from_bytes(lower.as_bytes()).map_err(|e| invalid(&e))?;
+        let value = HeaderValue::from_str(value).map_err(|e| invalid(&e))?;
+        builder = builder.header(header, value);
+    }
+    if !item.request.body.is_empty() {
+        builder = builder.header(http::header::CONTENT_LENGTH, item.request.body.len());
+    }
+    builder
+        .body(())
+        .map_err(|e| ProxyError::InvalidRequest(e.to_string()))
+}
+
+async fn read_h2_response(
+    response: h2::client::ResponseFuture,
+) -> std::result::Result<Received, String> {
+    let response = response.await.map_err(|e| e.to_string())?;
+    let (parts, mut body) = response.into_parts();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| e.to_string())?;
+        let _ = body.flow_control().release_capacity(chunk.len());
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Received {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
+            .collect(),
+        body: data,
+    })
+}
+
+/// What has been written per HTTP/2 stream, parsed from the outgoing bytes
+#[derive(Debug, Default)]
+struct FrameLog {
+    preface_left: usize,
+    pending: Vec<u8>,
+    streams: HashMap<u32, StreamLog>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct StreamLog {
+    headers: bool,
+    /// DATA payload bytes, without padding
+    data: usize,
+    end: bool,
+}
+
+impl FrameLog {
+    fn new() -> Self {
+        Self {
+            preface_left: PREFACE_LEN,
+            ..Default::default()
+        }
+    }
+
+    fn observe(&mut self, mut bytes: &[u8]) {
+        let skip = self.preface_left.min(bytes.len());
+        self.preface_left -= skip;
+        bytes = &bytes[skip..];
+        self.pending.extend_from_slice(bytes);
+
+        while self.pending.len() >= 9 {
+            let len =
+                u32::from_be_bytes([0, self.pending[0], self.pending[1], self.pending[2]]) as usize;
+            if self.pending.len() < 9 + len {
+                break;
+            }
+            let (kind, flags) = (self.pending[3], self.pending[4]);
+            let id = u32::from_be_bytes([
+                self.pending[5],
+                self.pending[6],
+                self.pending[7],
+                self.pending[8],
+            ]) & 0x7fff_ffff;
+            let end_stream = flags & 0x1 != 0;
+            match kind {
+                // DATA
+                0 => {
+                    let padding = if flags & 0x8 != 0 && len > 0 {
+                        usize::from(self.pending[9]) + 1
+                    } else {
+                        0
+                    };
+                    let stream = self.streams.entry(id).or_default();
+                    stream.data += len.saturating_sub(padding);
+                    stream.end |= end_stream;
+                }
+                // HEADERS
+                1 => {
+                    let stream = self.streams.entry(id).or_default();
+                    stream.headers = true;
+                    stream.end |= end_stream;
+                }
+                _ => {}
+            }
+            self.pending.drain(..9 + len);
+        }
+    }
+}
+
+/// Write gate shared between a race and its connection
+///
+/// While held, writes are buffered instead of reaching the socket and flushes
+/// wait; releasing sends the whole buffer in one write.
+struct Gate {
+    state: Mutex<GateState>,
+    changed: Notify,
+}
+
+struct GateState {
+    held: bool,
+    buffer: Vec<u8>,
+    flush_waker: Option<Waker>,
+    frames: FrameLog,
+    flushed_at: Option<Instant>,
+}
+
+impl Default for Gate {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(GateState {
+                held: false,
+                buffer: Vec::new(),
+                flush_waker: None,
+                frames: FrameLog::new(),
+                flushed_at: None,
+            }),
+            changed: Notify::new(),
+        }
+    }
+}
+
+impl Gate {
+    fn hold(&self) {
+        self.state.lock().held = true;
+    }
+
+    fn release(&self) {
+        let waker = {
+            let mut state = self.state.lock();
+            state.held = false;
+            state.flush_waker.take()
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    fn flushed_at(&self) -> Option<Instant> {
+        self.state.lock().flushed_at
+    }
+
+    fn observe(&self, bytes: &[u8]) {
+        self.state.lock().frames.observe(bytes);
+        self.changed.notify_waiters();
+    }
+
+    async fn wait_until(&self, done: impl Fn(&FrameLog) -> bool) {
+        loop {
+            let notified = self.changed.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+            if done(&self.state.lock().frames) {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+struct GateIo<T> {
+    inner: T,
+    gate: Arc<Gate>,
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for GateIo<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for GateIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        {
+            let mut state = this.gate.state.lock();
+            // Bytes written after a hold queue up behind it so ordering is kept
+            if state.held || !state.buffer.is_empty() {
+                state.buffer.extend_from_slice(buf);
+                drop(state);
+                this.gate.observe(buf);
+                return Poll::Ready(Ok(buf.len()));
+            }
+        }
+        let written = match Pin::new(&mut this.inner).poll_write(cx, buf) {
+            Poll::Ready(Ok(n)) => n,
+            other => return other,
+        };
+        this.gate.observe(&buf[..written]);
+        Poll::Ready(Ok(written))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let buffer = {
+                let mut state = this.gate.state.lock();
+                if state.held {
+                    state.flush_waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                if state.buffer.is_empty() {
+                    break;
+                }
+                std::mem::take(&mut state.buffer)
+            };
+            let result = Pin::new(&mut this.inner).poll_write(cx, &buffer);
+            let mut state = this.gate.state.lock();
+            let written = match result {
+                Poll::Ready(Ok(n)) => n,
+                Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
+                Poll::Pending => 0,
+            };
+            let mut rest = buffer[written..].to_vec();
+            rest.append(&mut state.buffer);
+            state.buffer = rest;
+            if state.buffer.is_empty() {
+                state.flushed_at.get_or_insert_with(Instant::now);
+            }
+            if written == 0 {
+                return Poll::Pending;
+            }
+        }
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scope::ScopeConfig;
+    use tokio::net::TcpListener;
+
+    fn request(url: String, body: &str) -> RepeaterRequest {
+        RepeaterRequest {
+            method: "POST".to_string(),
+            url,
+            headers: vec![("Content-Length".to_string(), "1".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    fn client() -> (RaceClient, Arc<RequestCapture>) {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let capture = Arc::new(RequestCapture::new(10));
+        (
+            RaceClient::new(
+                ConnectionPool::new(),
+                capture.clone(),
+                Arc::new(ScopeManager::new()),
+            ),
+            capture,
+        )
+    }
+
+    #[test]
+    fn test_http1_framing() {
+        let request = request("http://shop.test/redeem?c=1".to_string(), "code=X");
+        let wire = serialize_http1(&request, &request.url.parse().unwrap());
+        assert_eq!(
+            wire,
+            b"POST /redeem?c=1 HTTP/1.1\r\nHost: shop.test\r\nContent-Length: 6\r\n\r\ncode=X"
+        );
+
+        let interim = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n";
+        assert!(matches!(
+            http1_response_len(interim, "POST", false).unwrap(),
+            Some(Http1Len::Interim(25))
+        ));
+        let chunked = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nok\r\n0\r\n\r\n";
+        assert!(matches!(
+            http1_response_len(chunked, "GET", false).unwrap(),
+            Some(Http1Len::Final(n)) if n == chunked.len()
+        ));
+        assert!(
+            http1_response_len(&chunked[..chunked.len() - 2], "GET", false)
+                .unwrap()
+                .is_none()
+        );
+        let close_delimited = b"HTTP/1.0 200 OK\r\n\r\npartial";
+        assert!(http1_response_len(close_delimited, "GET", false)
+            .unwrap()
+            .is_none());
+        assert!(http1_response_len(close_delimited, "GET", true)
+            .unwrap()
+            .is_some());
+    }
+
+    #[test]
+    fn test_alpn_accepted() {
+        assert!(alpn_accepted(b"http/1.1", None));
+        assert!(alpn_accepted(b"http/1.1", Some(b"http/1.1")));
+        assert!(alpn_accepted(b"h2", Some(b"h2")));
+        assert!(!alpn_accepted(b"h2", None));
+        assert!(!alpn_accepted(b"h2", Some(b"http/1.1")));
+    }
+
+    #[test]
+    fn test_frame_log() {
+        let mut log = FrameLog::new();
+        log.observe(b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n");
+        // HEADERS on stream 1, then a padded DATA frame split across writes
+        log.observe(&[0, 0, 1, 1, 0x4, 0, 0, 0, 1, 0x82]);
+        log.observe(&[0, 0, 5, 0, 0x9, 0, 0, 0]);
+        assert!(log.streams[&1].data == 0);
+        log.observe(&[1, 1, b'a', b'b', b'c', 0]);
+        let stream = log.streams[&1];
+        assert!(stream.headers && stream.end);
+        assert_eq!(stream.data, 3);
+    }
+
+    #[tokio::test]
+    async fn test_last_byte_race() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for n in 0..3 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut received = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !received.ends_with(b"code=X") {
+                        let read = stream.read(&mut buf).await.unwrap();
+                        received.extend_from_slice(&buf[..read]);
+                    }
+                    let body = format!("redeemed {n}");
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                });
+            }
+        });
+
+        let (client, capture) = client();
+        let url = format!("http://{addr}/redeem");
+        let requests = vec![request(url, "code=X"); 3];
+        let report = client.race(RaceMode::LastByte, requests).await.unwrap();
+        assert_eq!(report.results.len(), 3);
+        for result in &report.results {
+            let response = result.response.as_ref().unwrap();
+            assert_eq!(response.status, 200);
+            assert!(response.body.starts_with("redeemed"));
+            let entry = capture.get(result.capture_id.unwrap()).unwrap().unwrap();
+            assert_eq!(entry.request.source, CaptureSource::Repeater);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_out_of_scope_race() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            for _ in 0..2 {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut received = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    while !received.ends_with(b"x") {
+                        let read = stream.read(&mut buf).await.unwrap();
+                        received.extend_from_slice(&buf[..read]);
+                    }
+                    stream
+                        .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                        .await
+                        .unwrap();
+                });
+            }
+        });
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let capture = Arc::new(RequestCapture::new(10));
+        let scope = Arc::new(ScopeManager::new());
+        let mut config = ScopeConfig::import("example.com").unwrap();
+        scope.set_config(config.clone());
+        let client = RaceClient::new(ConnectionPool::new(), capture.clone(), scope.clone());
+        let requests = vec![request(format!("http://{addr}/"), "x"); 2];
+
+        let report = client
+            .race(RaceMode::LastByte, requests.clone())
+            .await
+            .unwrap();
+        assert!(report.results.iter().all(|r| r.response.is_some()));
+        assert!(report.results.iter().all(|r| r.capture_id.is_none()));
+        assert!(capture.is_empty());
+
+        config.out_of_scope = OutOfScopeAction::Drop;
+        scope.set_config(config);
+        assert!(client.race(RaceMode::LastByte, requests).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_single_packet_race() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = h2::server::handshake(stream).await.unwrap();
+            while let Some(request) = connection.accept().await {
+                let (request, mut respond) = request.unwrap();
+                tokio::spawn(async move {
+                    let (parts, mut body) = request.into_parts();
+                    let mut data = Vec::new();
+                    while let Some(chunk) = body.data().await {
+                        let chunk = chunk.unwrap();
+                        let _ = body.flow_control().release_capacity(chunk.len());
+                        data.extend_from_slice(&chunk);
+                    }
+                    let response = http::Response::builder().status(201).body(()).unwrap();
+                    let mut send = respond.send_response(response, false).unwrap();
+                    let echo = format!("{} {}", parts.uri.path(), String::from_utf8_lossy(&data));
+                    send.send_data(Bytes::from(echo), true).unwrap();
+                });
+            }
+        });
+
+        let (client, _) = client();
+        let requests = vec![
+            request(format!("http://{addr}/a"), "one"),
+            request(format!("http://{addr}/b"), ""),
+        ];
+        let report = client.race(RaceMode::SinglePacket, requests).await.unwrap();
+        let bodies: Vec<_> = report
+            .results
+            .iter()
+            .map(|r| r.response.as_ref().unwrap().body.as_str())
+            .collect();
+        assert_eq!(bodies, vec!["/a one", "/b "]);
+        assert_eq!(report.results[0].response.as_ref().unwrap().status, 201);
+
+        let mixed = vec![
+            request(format!("http://{addr}/a"), ""),
+            request("http://other.test/".to_string(), ""),
+        ];
+        assert!(client.race(RaceMode::SinglePacket, mixed).await.is_err());
+    }
+}
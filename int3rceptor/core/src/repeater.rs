@@ -0,0 +1,714 @@
+//! Repeater sessions: named tabs, each with an ordered history of sends
+//!
+//! Tabs hold the request being edited plus every request/response pair sent
+//! from them. They are saved with the project so work can be resumed later.
+//! [`RepeaterClient`] sends through the same upstream path as the proxy,
+//! including its simulated network conditions, and records each exchange in
+//! the capture history.
+
+use crate::capture::{CaptureSource, CapturedRequest, CapturedResponse, RequestCapture};
+use crate::comparer::{CompareMode, CompareRequest, CompareResponse, Comparer};
+use crate::connection_pool::{ConnectionPool, ProxyBody};
+use crate::error::{ProxyError, Result};
+use crate::network::{throttle, Fault, NetworkConditions, SIMULATED_ERROR_BODY};
+use crate::plugin::{HookContext, PluginHook, PluginManager};
+use crate::rules::RuleEngine;
+use crate::scope::{OutOfScopeAction, ScopeManager};
+use http_body_util::BodyExt;
+use hyper::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use hyper::{Method, Request, Uri};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use time::OffsetDateTime;
+
+/// Oldest history items are dropped past this many per tab
+pub const MAX_HISTORY: usize = 500;
+
+/// Upstream exchanges taking longer than this are abandoned
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepeaterRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    #[serde(default)]
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepeaterResponse {
+    pub status: u16,
+    pub status_text: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    pub time_ms: u64,
+    pub size_bytes: usize,
+}
+
+/// One send from a tab; `error` is set when no response came back
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryItem {
+    pub id: u64,
+    pub sent_at_ms: i128,
+    pub request: RepeaterRequest,
+    pub response: Option<RepeaterResponse>,
+    pub error: Option<String>,
+    /// Id of the exchange in the capture history, unless it was out of scope
+    /// or failed by network simulation
+    #[serde(default)]
+    pub capture_id: Option<u64>,
+}
+
+/// Result of sending a repeater request upstream
+#[derive(Debug, Clone)]
+pub struct Exchange {
+    /// Failed exchanges carry the error instead of a response
+    pub outcome: std::result::Result<RepeaterResponse, String>,
+    pub capture_id: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RepeaterTab {
+    pub id: u64,
+    pub name: String,
+    pub created_at_ms: i128,
+    /// Request currently in the editor
+    pub request: RepeaterRequest,
+    pub history: Vec<HistoryItem>,
+}
+
+impl RepeaterTab {
+    fn item(&self, id: u64) -> Result<&HistoryItem> {
+        self.history
+            .iter()
+            .find(|item| item.id == id)
+            .ok_or_else(|| ProxyError::not_found(format!("History item {id}")))
+    }
+
+    fn next_item_id(&self) -> u64 {
+        self.history.last().map_or(1, |item| item.id + 1)
+    }
+}
+
+/// Tab listing entry without the history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TabSummary {
+    pub id: u64,
+    pub name: String,
+    pub method: String,
+    pub url: String,
+    pub history_len: usize,
+}
+
+/// Which side of two history items to compare
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffTarget {
+    Request,
+    #[default]
+    Response,
+}
+
+#[derive(Debug, Default)]
+pub struct Repeater {
+    tabs: RwLock<Vec<RepeaterTab>>,
+    next_id: AtomicU64,
+}
+
+impl Repeater {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open a tab; an empty name becomes "Tab N"
+    pub fn create_tab(&self, name: Option<String>, request: RepeaterRequest) -> RepeaterTab {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let name = name
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| format!("Tab {id}"));
+        let tab = RepeaterTab {
+            id,
+            name,
+            created_at_ms: now_ms(),
+            request,
+            history: Vec::new(),
+        };
+        self.tabs.write().push(tab.clone());
+        tab
+    }
+
+    pub fn list(&self) -> Vec<TabSummary> {
+        self.tabs
+            .read()
+            .iter()
+            .map(|tab| TabSummary {
+                id: tab.id,
+                name: tab.name.clone(),
+                method: tab.request.method.clone(),
+                url: tab.request.url.clone(),
+                history_len: tab.history.len(),
+            })
+            .collect()
+    }
+
+    pub fn get(&self, id: u64) -> Option<RepeaterTab> {
+        self.tabs.read().iter().find(|tab| tab.id == id).cloned()
+    }
+
+    /// Rename a tab and/or replace the request in its editor
+    pub fn update_tab(
+        &self,
+        id: u64,
+        name: Option<String>,
+        request: Option<RepeaterRequest>,
+    ) -> Result<RepeaterTab> {
+        self.with_tab(id, |tab| {
+            if let Some(name) = name.filter(|name| !name.trim().is_empty()) {
+                tab.name = name;
+            }
+            if let Some(request) = request {
+                tab.request = request;
+            }
+            Ok(tab.clone())
+        })
+    }
+
+    pub fn close_tab(&self, id: u64) -> bool {
+        let mut tabs = self.tabs.write();
+        let before = tabs.len();
+        tabs.retain(|tab| tab.id != id);
+        tabs.len() != before
+    }
+
+    /// Append a send to the tab's history and make it the editor request
+    pub fn record(
+        &self,
+        id: u64,
+        request: RepeaterRequest,
+        exchange: Exchange,
+    ) -> Result<HistoryItem> {
+        self.with_tab(id, |tab| {
+            let (response, error) = match exchange.outcome {
+                Ok(response) => (Some(response), None),
+                Err(error) => (None, Some(error)),
+            };
+            let item = HistoryItem {
+                id: tab.next_item_id(),
+                sent_at_ms: now_ms(),
+                request: request.clone(),
+                response,
+                error,
+                capture_id: exchange.capture_id,
+            };
+            tab.request = request;
+            tab.history.push(item.clone());
+            if tab.history.len() > MAX_HISTORY {
+                let excess = tab.history.len() - MAX_HISTORY;
+                tab.history.drain(..excess);
+            }
+            Ok(item)
+        })
+    }
+
+    /// Diff two history items of a tab with [`Comparer`]
+    pub fn diff(
+        &self,
+        id: u64,
+        left: u64,
+        right: u64,
+        target: DiffTarget,
+        mode: CompareMode,
+    ) -> Result<CompareResponse> {
+        let tabs = self.tabs.read();
+        let tab = tabs
+            .iter()
+            .find(|tab| tab.id == id)
+            .ok_or_else(|| ProxyError::not_found(format!("Repeater tab {id}")))?;
+        let render = |item: &HistoryItem| match target {
+            DiffTarget::Request => render_request(&item.request),
+            DiffTarget::Response => item
+                .response
+                .as_ref()
+                .map(render_response)
+                .unwrap_or_else(|| item.error.clone().unwrap_or_default()),
+        };
+        Ok(Comparer::compare(CompareRequest {
+            left: render(tab.item(left)?),
+            right: render(tab.item(right)?),
+            mode,
+        }))
+    }
+
+    /// All tabs, for saving with the project
+    pub fn snapshot(&self) -> Vec<RepeaterTab> {
+        self.tabs.read().clone()
+    }
+
+    /// Replace all tabs, e.g. when a project is loaded
+    pub fn restore(&self, tabs: Vec<RepeaterTab>) {
+        let max_id = tabs.iter().map(|tab| tab.id).max().unwrap_or(0);
+        *self.tabs.write() = tabs;
+        self.next_id.store(max_id, Ordering::SeqCst);
+    }
+
+    pub fn clear(&self) {
+        self.restore(Vec::new());
+    }
+
+    fn with_tab<T>(&self, id: u64, f: impl FnOnce(&mut RepeaterTab) -> Result<T>) -> Result<T> {
+        let mut tabs = self.tabs.write();
+        let tab = tabs
+            .iter_mut()
+            .find(|tab| tab.id == id)
+            .ok_or_else(|| ProxyError::not_found(format!("Repeater tab {id}")))?;
+        f(tab)
+    }
+}
+
+/// Sends repeater requests the way the proxy forwards intercepted ones
+#[derive(Clone)]
+pub struct RepeaterClient {
+    pool: ConnectionPool,
+    capture: Arc<RequestCapture>,
+    rules: Arc<RuleEngine>,
+    scope: Arc<ScopeManager>,
+    plugins: Option<Arc<PluginManager>>,
+    network: Option<Arc<NetworkConditions>>,
+}
+
+impl RepeaterClient {
+    pub fn new(
+        pool: ConnectionPool,
+        capture: Arc<RequestCapture>,
+        rules: Arc<RuleEngine>,
+        scope: Arc<ScopeManager>,
+    ) -> Self {
+        Self {
+            pool,
+            capture,
+            rules,
+            scope,
+            plugins: None,
+            network: None,
+        }
+    }
+
+    pub fn with_plugins(mut self, plugins: Arc<PluginManager>) -> Self {
+        self.plugins = Some(plugins);
+        self
+    }
+
+    /// Apply the proxy's simulated network conditions to sends
+    pub fn with_network(mut self, network: Arc<NetworkConditions>) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Send `request` upstream and capture the exchange with source `repeater`
+    ///
+    /// Errors are for requests that cannot be sent at all; upstream failures
+    /// are reported in [`Exchange::outcome`] and captured without a response.
+    pub async fn send(&self, request: &RepeaterRequest, apply_rules: bool) -> Result<Exchange> {
+        let uri: Uri = request.url.parse()?;
+        if uri.scheme().is_none() || uri.authority().is_none() {
+            return Err(ProxyError::InvalidRequest(format!(
+                "URL must be absolute: {}",
+                request.url
+            )));
+        }
+        let method = Method::from_bytes(request.method.as_bytes()).map_err(|_| {
+            ProxyError::InvalidRequest(format!("invalid method: {}", request.method))
+        })?;
+
+        let in_scope = self.scope.is_in_scope(&request.url);
+        if !in_scope && self.scope.out_of_scope_action() == OutOfScopeAction::Drop {
+            return Err(ProxyError::InvalidRequest(format!(
+                "{} is out of scope",
+                request.url
+            )));
+        }
+
+        // Simulated network conditions, as in the proxy; faults are not captured
+        let profile = self
+            .network
+            .as_ref()
+            .and_then(|network| network.profile_for_url(&request.url));
+        if let Some(profile) = &profile {
+            tokio::time::sleep(profile.delay()).await;
+            if let Some(fault) = profile.roll_fault() {
+                return Ok(Exchange {
+                    outcome: simulated_fault(fault),
+                    capture_id: None,
+                });
+            }
+        }
+
+        let mut builder = Request::builder().method(method).uri(uri.clone());
+        for (name, value) in &request.headers {
+            // HTTP/2 pseudo-headers come from the URL; the length from the body
+            if name.starts_with(':') || name.eq_ignore_ascii_case(CONTENT_LENGTH.as_str()) {
+                continue;
+            }
+            let invalid = |e: &dyn std::fmt::Display| ProxyError::InvalidHeader {
+                name: name.clone(),
+                reason: e.to_string(),
+            };
+            let header = HeaderName::from_bytes(name.as_bytes()).map_err(|e| invalid(&e))?;
+            let value = HeaderValue::from_str(value).map_err(|e| invalid(&e))?;
+            builder = builder.header(header, value);
+        }
+        let (mut parts, ()) = builder
+            .body(())
+            .map_err(|e| ProxyError::InvalidRequest(e.to_string()))?
+            .into_parts();
+        let mut body = request.body.clone().into_bytes();
+
+        if let Some(plugins) = &self.plugins {
+            let hook_ctx = HookContext::new()
+                .with_method(parts.method.as_str())
+                .with_url(uri.to_string());
+            let _ = plugins.execute_hook(PluginHook::OnRequest, hook_ctx);
+        }
+        if apply_rules {
+            self.rules.apply_request_rules(&mut parts, &mut body);
+        }
+
+        let mut record = CapturedRequest::new(
+            parts.method.to_string(),
+            parts.uri.to_string(),
+            parts.uri.scheme_str() == Some("https"),
+        );
+        record.headers = header_pairs(&parts.headers);
+        record.body = body.clone();
+        record.source = CaptureSource::Repeater;
+
+        throttle(profile.as_ref(), body.len()).await;
+        let start = Instant::now();
+        let outcome = tokio::time::timeout(SEND_TIMEOUT, async {
+            let response = self
+                .pool
+                .client()
+                .request(Request::from_parts(parts, ProxyBody::from(body)))
+                .await
+                .map_err(|e| format!("Request failed: {e}"))?;
+            let (parts, body) = response.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| format!("Reading response failed: {e}"))?
+                .to_bytes()
+                .to_vec();
+            throttle(profile.as_ref(), body.len()).await;
+            Ok::<_, String>((parts, body))
+        })
+        .await
+        .unwrap_or_else(|_| {
+            Err(format!(
+                "Request timed out after {}s",
+                SEND_TIMEOUT.as_secs()
+            ))
+        });
+        let duration = start.elapsed();
+
+        let (outcome, captured_response) = match outcome {
+            Ok((mut parts, mut body)) => {
+                if let Some(plugins) = &self.plugins {
+                    let hook_ctx = HookContext::new().with_status_code(parts.status.as_u16());
+                    let _ = plugins.execute_hook(PluginHook::OnResponse, hook_ctx);
+                }
+                if apply_rules {
+                    self.rules.apply_response_rules(&mut parts, &mut body);
+                }
+                let headers = header_pairs(&parts.headers);
+                let response = RepeaterResponse {
+                    status: parts.status.as_u16(),
+                    status_text: parts.status.canonical_reason().unwrap_or("").to_string(),
+                    headers: headers.clone(),
+                    body: String::from_utf8_lossy(&body).into_owned(),
+                    time_ms: duration.as_millis() as u64,
+                    size_bytes: body.len(),
+                };
+                let captured = CapturedResponse {
+                    request_id: 0,
+                    status_code: parts.status.as_u16(),
+                    headers,
+                    body,
+                    duration_ms: duration.as_millis(),
+                };
+                (Ok(response), Some(captured))
+            }
+            Err(error) => (Err(error), None),
+        };
+
+        // Out-of-scope traffic is never captured, as in the proxy
+        let capture_id = in_scope.then(|| self.capture.push(record, captured_response));
+        Ok(Exchange {
+            outcome,
+            capture_id,
+        })
+    }
+}
+
+/// Outcome of a send failed by network simulation
+fn simulated_fault(fault: Fault) -> std::result::Result<RepeaterResponse, String> {
+    match fault {
+        Fault::Reset => Err("Connection reset by network simulation".to_string()),
+        Fault::Error(status) => Ok(RepeaterResponse {
+            status,
+            status_text: hyper::StatusCode::from_u16(status)
+                .ok()
+                .and_then(|s| s.canonical_reason())
+                .unwrap_or("")
+                .to_string(),
+            headers: Vec::new(),
+            body: String::from_utf8_lossy(SIMULATED_ERROR_BODY).into_owned(),
+            time_ms: 0,
+            size_bytes: SIMULATED_ERROR_BODY.len(),
+        }),
+    }
+}
+
+fn header_pairs(headers: &hyper::HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or_default().to_string()))
+        .collect()
+}
+
+fn render_request(request: &RepeaterRequest) -> String {
+    let mut out = format!("{} {}\n", request.method, request.url);
+    for (name, value) in &request.headers {
+        out.push_str(&format!("{name}: {value}\n"));
+    }
+    out.push('\n');
+    out.push_str(&request.body);
+    out
+}
+
+fn render_response(response: &RepeaterResponse) -> String {
+    let mut out = format!("{} {}\n", response.status, response.status_text);
+    for (name, value) in &response.headers {
+        out.push_str(&format!("{name}: {value}\n"));
+    }
+    out.push('\n');
+    out.push_str(&response.body);
+    out
+}
+
+fn now_ms() -> i128 {
+    OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(body: &str) -> RepeaterRequest {
+        RepeaterRequest {
+            method: "POST".to_string(),
+            url: "https://app.test/api".to_string(),
+            headers: vec![("Content-Type".to_string(), "text/plain".to_string())],
+            body: body.to_string(),
+        }
+    }
+
+    fn response(body: &str) -> RepeaterResponse {
+        RepeaterResponse {
+            status: 200,
+            status_text: "OK".to_string(),
+            headers: Vec::new(),
+            body: body.to_string(),
+            time_ms: 3,
+            size_bytes: body.len(),
+        }
+    }
+
+    fn sent(outcome: std::result::Result<RepeaterResponse, String>) -> Exchange {
+        Exchange {
+            outcome,
+            capture_id: None,
+        }
+    }
+
+    #[test]
+    fn test_tabs_and_history() {
+        let repeater = Repeater::new();
+        let tab = repeater.create_tab(None, request("a"));
+        assert_eq!(tab.name, "Tab 1");
+        let other = repeater.create_tab(Some("login".to_string()), RepeaterRequest::default());
+        assert_eq!(other.id, 2);
+
+        let first = repeater
+            .record(tab.id, request("a"), sent(Ok(response("one"))))
+            .unwrap();
+        let second = repeater
+            .record(
+                tab.id,
+                request("b"),
+                sent(Err("connection refused".to_string())),
+            )
+            .unwrap();
+        assert_eq!((first.id, second.id), (1, 2));
+
+        let tab = repeater.get(tab.id).unwrap();
+        assert_eq!(tab.request.body, "b");
+        assert_eq!(tab.history.len(), 2);
+        assert_eq!(repeater.list()[0].history_len, 2);
+
+        assert!(repeater
+            .record(99, request("c"), sent(Ok(response(""))))
+            .is_err());
+        assert!(repeater.close_tab(other.id));
+        assert!(!repeater.close_tab(other.id));
+    }
+
+    #[test]
+    fn test_diff_history_items() {
+        let repeater = Repeater::new();
+        let tab = repeater.create_tab(None, request("a"));
+        repeater
+            .record(tab.id, request("a"), sent(Ok(response("role=user"))))
+            .unwrap();
+        repeater
+            .record(tab.id, request("b"), sent(Ok(response("role=admin"))))
+            .unwrap();
+
+        let diff = repeater
+            .diff(tab.id, 1, 2, DiffTarget::Response, CompareMode::Lines)
+            .unwrap();
+        let changed: Vec<_> = diff
+            .changes
+            .iter()
+            .filter(|change| change.tag != "equal")
+            .map(|change| (change.tag.as_str(), change.value.as_str()))
+            .collect();
+        assert_eq!(
+            changed,
+            vec![("delete", "role=user"), ("insert", "role=admin")]
+        );
+
+        let diff = repeater
+            .diff(tab.id, 1, 2, DiffTarget::Request, CompareMode::Lines)
+            .unwrap();
+        assert!(diff
+            .changes
+            .iter()
+            .any(|c| c.tag == "insert" && c.value == "b"));
+        assert!(repeater
+            .diff(tab.id, 1, 7, DiffTarget::Response, CompareMode::Lines)
+            .is_err());
+    }
+
+    #[test]
+    fn test_restore_keeps_ids_unique() {
+        let repeater = Repeater::new();
+        repeater.create_tab(None, request("a"));
+        repeater.create_tab(None, request("b"));
+        let saved = repeater.snapshot();
+
+        let restored = Repeater::new();
+        restored.restore(saved);
+        assert_eq!(restored.list().len(), 2);
+        assert_eq!(restored.create_tab(None, request("c")).id, 3);
+    }
+
+    #[tokio::test]
+    async fn test_client_captures_exchange() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0u8; 1024];
+            while !received.ends_with(b"\r\n\r\nping") {
+                let n = stream.read(&mut buf).await.unwrap();
+                received.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 201 Created\r\nContent-Length: 4\r\n\r\npong")
+                .await
+                .unwrap();
+            String::from_utf8(received).unwrap()
+        });
+
+        // Both rustls providers are compiled in, so tests have to pick one
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let capture = Arc::new(RequestCapture::new(10));
+        let client = RepeaterClient::new(
+            ConnectionPool::new(),
+            capture.clone(),
+            Arc::new(RuleEngine::new()),
+            Arc::new(ScopeManager::new()),
+        );
+        let mut request = request("ping");
+        request.url = format!("http://{addr}/echo");
+        // Stale lengths from edited captures must not reach the wire
+        request
+            .headers
+            .push(("Content-Length".to_string(), "99".to_string()));
+        let exchange = client.send(&request, false).await.unwrap();
+
+        let response = exchange.outcome.unwrap();
+        assert_eq!((response.status, response.body.as_str()), (201, "pong"));
+        let received = server.await.unwrap().to_ascii_lowercase();
+        assert!(received.starts_with("post /echo http/1.1\r\n"));
+        assert!(received.contains("content-length: 4\r\n"));
+
+        let entry = capture.get(exchange.capture_id.unwrap()).unwrap().unwrap();
+        assert_eq!(entry.request.source, CaptureSource::Repeater);
+        assert_eq!(entry.response.unwrap().body, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_client_applies_network_profile() {
+        let network = Arc::new(NetworkConditions::new());
+        let profile: crate::network::NetworkProfile = serde_json::from_value(serde_json::json!({
+            "id": "flaky",
+            "target": "app.test",
+            "latency_ms": 50,
+            "error_rate": 1.0,
+            "error_status": 502
+        }))
+        .unwrap();
+        network.upsert_profile(profile.clone()).unwrap();
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let capture = Arc::new(RequestCapture::new(10));
+        let client = RepeaterClient::new(
+            ConnectionPool::new(),
+            capture.clone(),
+            Arc::new(RuleEngine::new()),
+            Arc::new(ScopeManager::new()),
+        )
+        .with_network(network.clone());
+        let mut request = request("ping");
+        request.url = "http://app.test/".to_string();
+
+        // The upstream is never contacted, so the unresolvable host does not matter
+        let start = Instant::now();
+        let exchange = client.send(&request, false).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        let response = exchange.outcome.unwrap();
+        assert_eq!(response.status, 502);
+        assert_eq!(response.body.as_bytes(), SIMULATED_ERROR_BODY);
+        assert_eq!(exchange.capture_id, None);
+
+        network
+            .upsert_profile(crate::network::NetworkProfile {
+                latency_ms: 0,
+                reset_rate: 1.0,
+                ..profile
+            })
+            .unwrap();
+        let exchange = client.send(&request, false).await.unwrap();
+        assert!(exchange.outcome.unwrap_err().contains("reset"));
+        assert!(capture.is_empty());
+    }
+}
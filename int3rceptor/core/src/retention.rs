@@ -0,0 +1,548 @@
+//! Capture retention and database compaction
+//!
+//! Prunes old captures from [`CaptureStorage`] by age, row count or total body
+//! size, then drops unreferenced bodies and compacts the database file.
+
+use crate::error::{ProxyError, Result};
+use crate::scope::{ScopeRule, Target};
+use crate::storage::{migrations, CaptureStorage};
+use parking_lot::RwLock;
+use rusqlite::{params, params_from_iter, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+
+const POLICY_KEY: &str = "retention_policy";
+const MIN_INTERVAL_SECS: u64 = 10;
+/// Roughly a century; larger ages are rejected rather than silently wrapped
+const MAX_AGE_SECS: u64 = 100 * 365 * 86400;
+const DELETE_BATCH: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Run the background pruning task
+    #[serde(default)]
+    pub enabled: bool,
+    /// Delete captures older than this
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    #[serde(default)]
+    pub max_rows: Option<u64>,
+    /// Cap on the summed request and response body sizes, before deduplication
+    #[serde(default)]
+    pub max_body_bytes: Option<u64>,
+    /// Captures to matching hosts are never pruned
+    #[serde(default)]
+    pub exclude: Vec<ScopeRule>,
+    /// Never prune highlighted, noted or tagged captures
+    #[serde(default = "default_true")]
+    pub keep_annotated: bool,
+    #[serde(default = "default_interval")]
+    pub interval_secs: u64,
+    /// Run VACUUM after rows were deleted
+    #[serde(default = "default_true")]
+    pub vacuum: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_interval() -> u64 {
+    3600
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_age_secs: None,
+            max_rows: None,
+            max_body_bytes: None,
+            exclude: Vec::new(),
+            keep_annotated: true,
+            interval_secs: default_interval(),
+            vacuum: true,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    pub fn validate(&self) -> Result<()> {
+        if self.interval_secs < MIN_INTERVAL_SECS {
+            return Err(ProxyError::invalid_config(
+                "interval_secs",
+                format!("must be at least {MIN_INTERVAL_SECS}"),
+            ));
+        }
+        if self.max_age_secs.is_some_and(|age| age > MAX_AGE_SECS) {
+            return Err(ProxyError::invalid_config(
+                "max_age_secs",
+                format!("must be at most {MAX_AGE_SECS}"),
+            ));
+        }
+        self.exclude.iter().try_for_each(ScopeRule::validate)
+    }
+}
+
+/// Outcome of a pruning pass
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PruneReport {
+    pub deleted_captures: usize,
+    pub deleted_bodies: usize,
+    pub vacuumed: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageStats {
+    pub captures: u64,
+    /// Summed body sizes as captured
+    pub body_bytes: u64,
+    /// Distinct bodies after deduplication
+    pub stored_bodies: u64,
+    pub stored_body_bytes: u64,
+    pub file_bytes: u64,
+    pub schema_version: u32,
+}
+
+struct Candidate {
+    id: i64,
+    url: String,
+    inserted_at_ms: i64,
+    size: u64,
+    annotated: bool,
+}
+
+impl CaptureStorage {
+    /// Persisted retention policy, or the default if none was saved
+    pub fn retention_policy(&self) -> Result<RetentionPolicy> {
+        let conn = self.connect()?;
+        let value: Option<String> = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![POLICY_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        match value {
+            Some(json) => Ok(serde_json::from_str(&json)?),
+            None => Ok(RetentionPolicy::default()),
+        }
+    }
+
+    pub fn set_retention_policy(&self, policy: &RetentionPolicy) -> Result<()> {
+        policy.validate()?;
+        let conn = self.connect()?;
+        conn.execute(
+            "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+            params![POLICY_KEY, serde_json::to_string(policy)?],
+        )?;
+        Ok(())
+    }
+
+    pub fn stats(&self) -> Result<StorageStats> {
+        let conn = self.connect()?;
+        let (captures, body_bytes): (i64, i64) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(size_bytes), 0) FROM captures",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let (stored_bodies, stored_body_bytes): (i64, i64) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(length(data)), 0) FROM bodies",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let file_bytes: i64 = conn.query_row(
+            "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+            [],
+            |row| row.get(0),
+        )?;
+        Ok(StorageStats {
+            captures: captures as u64,
+            body_bytes: body_bytes as u64,
+            stored_bodies: stored_bodies as u64,
+            stored_body_bytes: stored_body_bytes as u64,
+            file_bytes: file_bytes as u64,
+            schema_version: migrations::schema_version(&conn)?,
+        })
+    }
+
+    /// Delete captures outside the policy limits, oldest first
+    ///
+    /// Age is measured from when a capture was stored, so imported traffic
+    /// with historical timestamps is not pruned straight away.
+    pub fn prune(&self, policy: &RetentionPolicy) -> Result<PruneReport> {
+        // Captures queue up instead of failing on the locks VACUUM takes
+        let (_paused, mut conn) = self.pause_writer()?;
+        let (rows, bytes): (i64, i64) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(MAX(COALESCE(size_bytes, 0), 0)), 0) FROM captures",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let doomed = {
+            // Walked one row at a time so large databases are never loaded whole
+            let mut stmt = conn.prepare(
+                "SELECT id, url, COALESCE(inserted_at_ms, timestamp_ms), COALESCE(size_bytes, 0), \
+                 capture_id IS NOT NULL \
+                 FROM captures LEFT JOIN annotations ON annotations.capture_id = captures.id \
+                 ORDER BY COALESCE(inserted_at_ms, timestamp_ms) ASC, id ASC",
+            )?;
+            let candidates = stmt.query_map([], |row| {
+                Ok(Candidate {
+                    id: row.get(0)?,
+                    url: row.get(1)?,
+                    inserted_at_ms: row.get(2)?,
+                    size: row.get::<_, i64>(3)?.max(0) as u64,
+                    annotated: row.get(4)?,
+                })
+            })?;
+            select_doomed(candidates, rows as u64, bytes as u64, policy)?
+        };
+        let mut report = PruneReport {
+            deleted_captures: doomed.len(),
+            ..Default::default()
+        };
+        if doomed.is_empty() {
+            return Ok(report);
+        }
+
+        let tx = conn.transaction()?;
+        for chunk in doomed.chunks(DELETE_BATCH) {
+            let placeholders = vec!["?"; chunk.len()].join(",");
+            for sql in [
+                format!("DELETE FROM captures WHERE id IN ({placeholders})"),
+                format!("DELETE FROM capture_fts WHERE rowid IN ({placeholders})"),
+                format!("DELETE FROM annotations WHERE capture_id IN ({placeholders})"),
+            ] {
+                tx.execute(&sql, params_from_iter(chunk.iter()))?;
+            }
+        }
+        report.deleted_bodies = self.collect_orphan_bodies(&tx)?;
+        tx.commit()?;
+
+        if policy.vacuum {
+            conn.execute_batch("INSERT INTO capture_fts(capture_fts) VALUES('optimize'); VACUUM;")?;
+            report.vacuumed = true;
+        }
+        Ok(report)
+    }
+
+    /// Periodically prune with the persisted policy.
+    ///
+    /// The policy is re-read on every tick, so changes apply from the next run.
+    pub fn spawn_retention(self: Arc<Self>) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                // Reading the policy connects and flushes the writer, so it
+                // stays off the async workers along with the prune
+                let storage = self.clone();
+                let tick = tokio::task::spawn_blocking(move || {
+                    let policy = storage.retention_policy().unwrap_or_else(|err| {
+                        tracing::warn!("retention_policy_error" = %err);
+                        RetentionPolicy::default()
+                    });
+                    let pruned = policy.enabled.then(|| storage.prune(&policy));
+                    (policy.interval_secs, pruned)
+                })
+                .await;
+                let interval_secs = match tick {
+                    Ok((interval_secs, pruned)) => {
+                        match pruned {
+                            Some(Ok(report)) if report.deleted_captures > 0 => tracing::info!(
+                                deleted_captures = report.deleted_captures,
+                                deleted_bodies = report.deleted_bodies,
+                                "Pruned capture storage"
+                            ),
+                            Some(Err(err)) => tracing::warn!("retention_prune_error" = %err),
+                            _ => {}
+                        }
+                        interval_secs
+                    }
+                    Err(err) => {
+                        tracing::warn!("retention_task_error" = %err);
+                        default_interval()
+                    }
+                };
+                let interval = Duration::from_secs(interval_secs.max(MIN_INTERVAL_SECS));
+                tokio::time::sleep(interval).await;
+            }
+        })
+    }
+}
+
+/// Ids to delete, walking captures from oldest to newest
+///
+/// `rows` and `bytes` are the totals over all candidates.
+fn select_doomed(
+    candidates: impl Iterator<Item = rusqlite::Result<Candidate>>,
+    rows: u64,
+    bytes: u64,
+    policy: &RetentionPolicy,
+) -> Result<Vec<i64>> {
+    let regex_cache = RwLock::new(HashMap::new());
+    let cutoff = policy.max_age_secs.map(|age| {
+        let now_ms = (OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000) as i64;
+        now_ms.saturating_sub(i64::try_from(age.saturating_mul(1000)).unwrap_or(i64::MAX))
+    });
+    let mut rows_left = rows;
+    let mut bytes_left = bytes;
+
+    let mut doomed = Vec::new();
+    for candidate in candidates {
+        let candidate = candidate?;
+        let expired = cutoff.is_some_and(|cutoff| candidate.inserted_at_ms < cutoff);
+        let over_rows = policy.max_rows.is_some_and(|max| rows_left > max);
+        let over_bytes = policy.max_body_bytes.is_some_and(|max| bytes_left > max);
+        if !(expired || over_rows || over_bytes) {
+            // Everything after this is newer and the totals only shrink
+            break;
+        }
+        if policy.keep_annotated && candidate.annotated {
+            continue;
+        }
+        let excluded = !policy.exclude.is_empty()
+            && Target::parse(&candidate.url).is_some_and(|target| {
+                policy
+                    .exclude
+                    .iter()
+                    .any(|rule| rule.matches(&target, &regex_cache))
+            });
+        if excluded {
+            continue;
+        }
+        doomed.push(candidate.id);
+        rows_left -= 1;
+        bytes_left -= candidate.size;
+    }
+    Ok(doomed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{Annotation, CaptureEntry, CapturedRequest, CapturedResponse};
+    use tempfile::tempdir;
+
+    fn entry(id: u64, url: &str, age_secs: i128, body: &[u8]) -> CaptureEntry {
+        let mut request = CapturedRequest::new("GET", url, true);
+        request.id = id;
+        request.timestamp_ms -= age_secs * 1000;
+        CaptureEntry {
+            request,
+            response: Some(CapturedResponse {
+                request_id: id,
+                status_code: 200,
+                headers: Vec::new(),
+                body: body.to_vec(),
+                duration_ms: 1,
+            }),
+            annotation: Annotation::default(),
+            omitted_bodies: None,
+        }
+    }
+
+    /// Store `entry` as if it had been captured live at its timestamp
+    fn captured(storage: &CaptureStorage, entry: CaptureEntry) {
+        storage.insert(&entry).unwrap();
+        storage
+            .connect()
+            .unwrap()
+            .execute(
+                "UPDATE captures SET inserted_at_ms = timestamp_ms WHERE id = ?1",
+                params![entry.request.id as i64],
+            )
+            .unwrap();
+    }
+
+    fn ids(storage: &CaptureStorage) -> Vec<u64> {
+        let mut ids: Vec<u64> = storage
+            .query(&Default::default())
+            .unwrap()
+            .iter()
+            .map(|e| e.request.id)
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_prune_by_age_rows_and_bytes() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        captured(
+            &storage,
+            entry(1, "https://old.example.com/", 7200, b"aaaa"),
+        );
+        captured(
+            &storage,
+            entry(2, "https://keep.example.com/", 7200, b"bbbb"),
+        );
+        for id in 3..=6 {
+            captured(&storage, entry(id, "https://app.example.com/", 0, b"cccc"));
+        }
+
+        let policy = RetentionPolicy {
+            max_age_secs: Some(3600),
+            exclude: vec!["keep.example.com".parse().unwrap()],
+            ..Default::default()
+        };
+        let report = storage.prune(&policy).unwrap();
+        assert_eq!(report.deleted_captures, 1);
+        assert_eq!(ids(&storage), vec![2, 3, 4, 5, 6]);
+
+        let policy = RetentionPolicy {
+            max_rows: Some(3),
+            vacuum: false,
+            ..Default::default()
+        };
+        storage.prune(&policy).unwrap();
+        assert_eq!(ids(&storage), vec![4, 5, 6]);
+
+        let policy = RetentionPolicy {
+            max_body_bytes: Some(8),
+            ..Default::default()
+        };
+        storage.prune(&policy).unwrap();
+        assert_eq!(ids(&storage), vec![5, 6]);
+    }
+
+    #[test]
+    fn test_prune_orders_by_insertion_time() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        // Ids do not have to follow the order rows were stored in
+        captured(&storage, entry(1, "https://a.test/", 0, b"new"));
+        captured(&storage, entry(9, "https://a.test/", 7200, b"old"));
+        captured(&storage, entry(5, "https://a.test/", 3600, b"mid"));
+
+        let policy = RetentionPolicy {
+            max_rows: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(storage.prune(&policy).unwrap().deleted_captures, 2);
+        assert_eq!(ids(&storage), vec![1]);
+    }
+
+    #[test]
+    fn test_prune_keeps_fresh_imports() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        captured(&storage, entry(1, "https://live.test/", 600, b"live"));
+        // Imported from an engagement a year ago
+        storage
+            .insert_many(&[entry(2, "https://old.test/", 365 * 86400, b"imported")])
+            .unwrap();
+
+        let policy = RetentionPolicy {
+            max_age_secs: Some(3600),
+            ..Default::default()
+        };
+        assert_eq!(storage.prune(&policy).unwrap().deleted_captures, 0);
+        assert_eq!(ids(&storage), vec![1, 2]);
+
+        let policy = RetentionPolicy {
+            max_rows: Some(1),
+            ..Default::default()
+        };
+        storage.prune(&policy).unwrap();
+        assert_eq!(ids(&storage), vec![2]);
+    }
+
+    #[test]
+    fn test_prune_with_huge_max_age_deletes_nothing() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        captured(&storage, entry(1, "https://a.test/", 7200, b"old"));
+        captured(&storage, entry(2, "https://a.test/", 0, b"new"));
+
+        let policy = RetentionPolicy {
+            max_age_secs: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        assert_eq!(storage.prune(&policy).unwrap().deleted_captures, 0);
+        assert_eq!(ids(&storage), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_prune_commits_queued_captures_first() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        for id in 1..=50 {
+            storage.enqueue(entry(id, "https://a.test/", 0, b"queued"));
+        }
+
+        let policy = RetentionPolicy {
+            max_rows: Some(10),
+            ..Default::default()
+        };
+        assert_eq!(storage.prune(&policy).unwrap().deleted_captures, 40);
+        storage.enqueue(entry(51, "https://a.test/", 0, b"after"));
+        assert_eq!(ids(&storage).len(), 11);
+    }
+
+    #[test]
+    fn test_prune_keeps_annotated_and_collects_bodies() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        storage
+            .insert(&entry(1, "https://a.test/", 0, b"unique"))
+            .unwrap();
+        storage
+            .insert(&entry(2, "https://a.test/", 0, b"shared"))
+            .unwrap();
+        storage
+            .insert(&entry(3, "https://a.test/", 0, b"shared"))
+            .unwrap();
+        storage
+            .set_annotation(
+                1,
+                &Annotation {
+                    notes: "keep".to_string(),
+                    ..Default::default()
+                },
+            )
+            .unwrap();
+        assert_eq!(storage.stats().unwrap().stored_bodies, 2);
+
+        let policy = RetentionPolicy {
+            max_rows: Some(1),
+            ..Default::default()
+        };
+        let report = storage.prune(&policy).unwrap();
+        assert_eq!(report.deleted_captures, 2);
+        assert_eq!(report.deleted_bodies, 1);
+        assert_eq!(ids(&storage), vec![1]);
+
+        let stats = storage.stats().unwrap();
+        assert_eq!(stats.captures, 1);
+        assert_eq!(stats.stored_bodies, 1);
+        assert!(stats.file_bytes > 0);
+    }
+
+    #[test]
+    fn test_policy_persistence_and_validation() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        assert_eq!(
+            storage.retention_policy().unwrap(),
+            RetentionPolicy::default()
+        );
+
+        let policy = RetentionPolicy {
+            enabled: true,
+            max_rows: Some(100),
+            ..Default::default()
+        };
+        storage.set_retention_policy(&policy).unwrap();
+        assert_eq!(storage.retention_policy().unwrap(), policy);
+
+        let bad = RetentionPolicy {
+            interval_secs: 1,
+            ..Default::default()
+        };
+        assert!(storage.set_retention_policy(&bad).is_err());
+    }
+}
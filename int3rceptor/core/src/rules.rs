@@ -1,7 +1,11 @@
+use crate::capture::CaptureEntry;
+use crate::comparer::{CompareMode, CompareRequest, CompareResponse, Comparer};
+use crate::error::{ProxyError, Result};
 use parking_lot::RwLock;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -23,6 +27,19 @@ pub enum MatchCondition {
     BodyRegex(String),
 }
 
+impl fmt::Display for MatchCondition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchCondition::UrlContains(s) => write!(f, "url contains {:?}", s),
+            MatchCondition::HeaderContains(k, v) => write!(f, "header {:?} contains {:?}", k, v),
+            MatchCondition::BodyContains(s) => write!(f, "body contains {:?}", s),
+            MatchCondition::UrlRegex(p) => write!(f, "url matches /{}/", p),
+            MatchCondition::HeaderRegex(k, p) => write!(f, "header {:?} matches /{}/", k, p),
+            MatchCondition::BodyRegex(p) => write!(f, "body matches /{}/", p),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Action {
     // Simple replacements
@@ -44,11 +61,44 @@ pub struct Rule {
     pub action: Action,
 }
 
+/// Rules that fired on one request or response
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuleHits {
+    /// Rules whose condition matched
+    pub matched: usize,
+    /// Matched rules whose action changed the message
+    pub applied: usize,
+}
+
+/// Per-rule outcome of a dry run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RuleTrace {
+    pub rule_id: String,
+    pub rule_type: RuleType,
+    pub active: bool,
+    pub matched: bool,
+    /// Why the rule did not fire (inactive, no response, or the failing condition)
+    pub failed_condition: Option<String>,
+    /// Changes made by this rule alone, when it matched
+    pub diff: Option<CompareResponse>,
+}
+
+/// Result of running the active rules against a captured entry without forwarding it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DryRunReport {
+    pub request_id: u64,
+    pub rules: Vec<RuleTrace>,
+    pub request_diff: CompareResponse,
+    pub response_diff: Option<CompareResponse>,
+}
+
 #[derive(Clone)]
 pub struct RuleEngine {
     rules: Arc<RwLock<Vec<Rule>>>,
     // Regex cache for performance (avoid recompiling)
     regex_cache: Arc<RwLock<HashMap<String, Regex>>>,
+    // Live hit counters keyed by rule id
+    hits: Arc<RwLock<HashMap<String, u64>>>,
 }
 
 impl RuleEngine {
@@ -56,6 +106,7 @@ impl RuleEngine {
         Self {
             rules: Arc::new(RwLock::new(Vec::new())),
             regex_cache: Arc::new(RwLock::new(HashMap::new())),
+            hits: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -67,10 +118,32 @@ impl RuleEngine {
         self.rules.read().clone()
     }
 
+    pub fn len(&self) -> usize {
+        self.rules.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.read().is_empty()
+    }
+
     pub fn clear_rules(&self) {
         self.rules.write().clear();
-        // Also clear regex cache
+        // Also clear regex cache and hit counters
         self.regex_cache.write().clear();
+        self.hits.write().clear();
+    }
+
+    /// Number of times each rule has matched live traffic
+    pub fn rule_hits(&self) -> HashMap<String, u64> {
+        self.hits.read().clone()
+    }
+
+    pub fn reset_hits(&self) {
+        self.hits.write().clear();
+    }
+
+    fn record_hit(&self, rule: &Rule) {
+        *self.hits.write().entry(rule.id.clone()).or_insert(0) += 1;
     }
 
     /// Get or compile a regex pattern (with caching)
@@ -98,109 +171,217 @@ impl RuleEngine {
         }
     }
 
-    pub fn apply_request_rules(&self, parts: &mut http::request::Parts, body: &mut Vec<u8>) {
+    /// Apply matching request rules, counting the matches and the actions that changed something
+    pub fn apply_request_rules(
+        &self,
+        parts: &mut http::request::Parts,
+        body: &mut Vec<u8>,
+    ) -> RuleHits {
         let rules = self.rules.read();
+        let mut hits = RuleHits::default();
         for rule in rules.iter() {
             if !rule.active || rule.rule_type != RuleType::Request {
                 continue;
             }
 
             if self.matches_request(rule, parts, body) {
-                self.execute_action(rule, &mut parts.headers, body);
+                if self.execute_action(rule, &mut parts.headers, body) {
+                    hits.applied += 1;
+                }
+                self.record_hit(rule);
+                hits.matched += 1;
             }
         }
+        hits
     }
 
-    pub fn apply_response_rules(&self, parts: &mut http::response::Parts, body: &mut Vec<u8>) {
+    /// Apply matching response rules, counting the matches and the actions that changed something
+    pub fn apply_response_rules(
+        &self,
+        parts: &mut http::response::Parts,
+        body: &mut Vec<u8>,
+    ) -> RuleHits {
         let rules = self.rules.read();
+        let mut hits = RuleHits::default();
         for rule in rules.iter() {
             if !rule.active || rule.rule_type != RuleType::Response {
                 continue;
             }
 
             if self.matches_response(rule, parts, body) {
-                self.execute_action(rule, &mut parts.headers, body);
+                if self.execute_action(rule, &mut parts.headers, body) {
+                    hits.applied += 1;
+                }
+                self.record_hit(rule);
+                hits.matched += 1;
             }
         }
+        hits
     }
 
-    fn matches_request(&self, rule: &Rule, parts: &http::request::Parts, body: &[u8]) -> bool {
-        match &rule.condition {
-            MatchCondition::UrlContains(s) => parts.uri.to_string().contains(s),
-            MatchCondition::UrlRegex(pattern) => {
-                if let Some(regex) = self.get_regex(pattern) {
-                    regex.is_match(&parts.uri.to_string())
-                } else {
-                    false
-                }
-            }
-            MatchCondition::HeaderContains(k, v) => parts
-                .headers
-                .get(k)
-                .and_then(|val| val.to_str().ok())
-                .map(|val| val.contains(v))
-                .unwrap_or(false),
-            MatchCondition::HeaderRegex(k, pattern) => {
-                if let Some(regex) = self.get_regex(pattern) {
-                    parts
-                        .headers
-                        .get(k)
-                        .and_then(|val| val.to_str().ok())
-                        .map(|val| regex.is_match(val))
-                        .unwrap_or(false)
-                } else {
-                    false
-                }
+    /// Run every rule against a captured entry and report what each one would do.
+    ///
+    /// Nothing is forwarded and hit counters are left untouched.
+    pub fn dry_run(&self, entry: &CaptureEntry) -> Result<DryRunReport> {
+        let (mut req_parts, mut req_body) = request_parts_from(entry)?;
+        let mut resp = entry
+            .response
+            .as_ref()
+            .map(|r| response_parts_from(r.status_code, &r.headers).map(|p| (p, r.body.clone())))
+            .transpose()?;
+
+        let original_request = render_request(&req_parts, &req_body);
+        let original_response = resp.as_ref().map(|(p, b)| render_response(p, b));
+
+        let rules = self.rules.read();
+        let mut traces = Vec::with_capacity(rules.len());
+        for rule in rules.iter() {
+            let mut trace = RuleTrace {
+                rule_id: rule.id.clone(),
+                rule_type: rule.rule_type.clone(),
+                active: rule.active,
+                matched: false,
+                failed_condition: None,
+                diff: None,
+            };
+
+            if !rule.active {
+                trace.failed_condition = Some("rule is inactive".to_string());
+                traces.push(trace);
+                continue;
             }
-            MatchCondition::BodyContains(s) => String::from_utf8_lossy(body).contains(s),
-            MatchCondition::BodyRegex(pattern) => {
-                if let Some(regex) = self.get_regex(pattern) {
-                    regex.is_match(&String::from_utf8_lossy(body))
-                } else {
-                    false
-                }
+
+            match rule.rule_type {
+                RuleType::Request => match self.check_request(rule, &req_parts, &req_body) {
+                    Ok(()) => {
+                        let before = render_request(&req_parts, &req_body);
+                        self.execute_action(rule, &mut req_parts.headers, &mut req_body);
+                        trace.matched = true;
+                        trace.diff = Some(diff(before, render_request(&req_parts, &req_body)));
+                    }
+                    Err(reason) => trace.failed_condition = Some(reason),
+                },
+                RuleType::Response => match resp.as_mut() {
+                    Some((parts, body)) => match self.check_response(rule, parts, body) {
+                        Ok(()) => {
+                            let before = render_response(parts, body);
+                            self.execute_action(rule, &mut parts.headers, body);
+                            trace.matched = true;
+                            trace.diff = Some(diff(before, render_response(parts, body)));
+                        }
+                        Err(reason) => trace.failed_condition = Some(reason),
+                    },
+                    None => {
+                        trace.failed_condition = Some("entry has no response".to_string());
+                    }
+                },
             }
+            traces.push(trace);
         }
+
+        Ok(DryRunReport {
+            request_id: entry.request.id,
+            rules: traces,
+            request_diff: diff(original_request, render_request(&req_parts, &req_body)),
+            response_diff: original_response
+                .zip(resp.as_ref())
+                .map(|(before, (parts, body))| diff(before, render_response(parts, body))),
+        })
+    }
+
+    fn matches_request(&self, rule: &Rule, parts: &http::request::Parts, body: &[u8]) -> bool {
+        self.check_request(rule, parts, body).is_ok()
+    }
+
+    fn matches_response(&self, rule: &Rule, parts: &http::response::Parts, body: &[u8]) -> bool {
+        self.check_response(rule, parts, body).is_ok()
+    }
+
+    /// Evaluate a request rule's condition, describing the failure when it does not match
+    fn check_request(
+        &self,
+        rule: &Rule,
+        parts: &http::request::Parts,
+        body: &[u8],
+    ) -> std::result::Result<(), String> {
+        let condition = &rule.condition;
+        let matched = match condition {
+            MatchCondition::UrlContains(s) => parts.uri.to_string().contains(s),
+            MatchCondition::UrlRegex(pattern) => self
+                .compiled(condition, pattern)?
+                .is_match(&parts.uri.to_string()),
+            MatchCondition::HeaderContains(..)
+            | MatchCondition::HeaderRegex(..)
+            | MatchCondition::BodyContains(_)
+            | MatchCondition::BodyRegex(_) => {
+                return self.check_common(condition, &parts.headers, body)
+            }
+        };
+        condition_result(condition, matched)
     }
 
-    fn matches_response(&self, rule: &Rule, _parts: &http::response::Parts, body: &[u8]) -> bool {
+    /// Evaluate a response rule's condition, describing the failure when it does not match
+    fn check_response(
+        &self,
+        rule: &Rule,
+        parts: &http::response::Parts,
+        body: &[u8],
+    ) -> std::result::Result<(), String> {
         match &rule.condition {
-            MatchCondition::UrlContains(_) => false, // Response doesn't have URI
-            MatchCondition::UrlRegex(_) => false,
-            MatchCondition::HeaderContains(k, v) => _parts
-                .headers
-                .get(k)
-                .and_then(|val| val.to_str().ok())
-                .map(|val| val.contains(v))
-                .unwrap_or(false),
+            // Response doesn't have URI
+            MatchCondition::UrlContains(_) | MatchCondition::UrlRegex(_) => Err(format!(
+                "{} cannot match a response (responses have no URL)",
+                rule.condition
+            )),
+            condition => self.check_common(condition, &parts.headers, body),
+        }
+    }
+
+    /// Header and body conditions shared by requests and responses
+    fn check_common(
+        &self,
+        condition: &MatchCondition,
+        headers: &http::HeaderMap,
+        body: &[u8],
+    ) -> std::result::Result<(), String> {
+        let matched = match condition {
+            MatchCondition::HeaderContains(k, v) => {
+                header_str(headers, k, condition)?.contains(v.as_str())
+            }
             MatchCondition::HeaderRegex(k, pattern) => {
-                if let Some(regex) = self.get_regex(pattern) {
-                    _parts
-                        .headers
-                        .get(k)
-                        .and_then(|val| val.to_str().ok())
-                        .map(|val| regex.is_match(val))
-                        .unwrap_or(false)
-                } else {
-                    false
-                }
+                let regex = self.compiled(condition, pattern)?;
+                regex.is_match(header_str(headers, k, condition)?)
             }
             MatchCondition::BodyContains(s) => String::from_utf8_lossy(body).contains(s),
-            MatchCondition::BodyRegex(pattern) => {
-                if let Some(regex) = self.get_regex(pattern) {
-                    regex.is_match(&String::from_utf8_lossy(body))
-                } else {
-                    false
-                }
-            }
-        }
+            MatchCondition::BodyRegex(pattern) => self
+                .compiled(condition, pattern)?
+                .is_match(&String::from_utf8_lossy(body)),
+            MatchCondition::UrlContains(_) | MatchCondition::UrlRegex(_) => false,
+        };
+        condition_result(condition, matched)
+    }
+
+    fn compiled(
+        &self,
+        condition: &MatchCondition,
+        pattern: &str,
+    ) -> std::result::Result<Regex, String> {
+        self.get_regex(pattern)
+            .ok_or_else(|| format!("{} has an invalid regex", condition))
     }
 
-    fn execute_action(&self, rule: &Rule, headers: &mut http::HeaderMap, body: &mut Vec<u8>) {
+    /// Run a rule's action, returning whether it changed the headers or body
+    fn execute_action(
+        &self,
+        rule: &Rule,
+        headers: &mut http::HeaderMap,
+        body: &mut Vec<u8>,
+    ) -> bool {
         match &rule.action {
             Action::ReplaceBody(target, replacement) => {
                 let s = String::from_utf8_lossy(body).to_string();
                 let new_s = s.replace(target, replacement);
+                let changed = new_s != s;
                 *body = new_s.into_bytes();
 
                 // Update Content-Length if present
@@ -209,28 +390,33 @@ impl RuleEngine {
                         headers.insert(http::header::CONTENT_LENGTH, val);
                     }
                 }
+                changed
             }
             Action::RegexReplaceBody(pattern, replacement) => {
-                if let Some(regex) = self.get_regex(pattern) {
-                    let s = String::from_utf8_lossy(body).to_string();
-                    let new_s = regex.replace_all(&s, replacement.as_str()).to_string();
-                    *body = new_s.into_bytes();
-
-                    // Update Content-Length if present
-                    if headers.contains_key(http::header::CONTENT_LENGTH) {
-                        if let Ok(val) = http::HeaderValue::from_str(&body.len().to_string()) {
-                            headers.insert(http::header::CONTENT_LENGTH, val);
-                        }
+                let Some(regex) = self.get_regex(pattern) else {
+                    return false;
+                };
+                let s = String::from_utf8_lossy(body).to_string();
+                let new_s = regex.replace_all(&s, replacement.as_str()).to_string();
+                let changed = new_s != s;
+                *body = new_s.into_bytes();
+
+                // Update Content-Length if present
+                if headers.contains_key(http::header::CONTENT_LENGTH) {
+                    if let Ok(val) = http::HeaderValue::from_str(&body.len().to_string()) {
+                        headers.insert(http::header::CONTENT_LENGTH, val);
                     }
                 }
+                changed
             }
             Action::SetHeader(k, v) => {
                 if let (Ok(k), Ok(v)) = (
                     http::header::HeaderName::from_bytes(k.as_bytes()),
                     http::header::HeaderValue::from_str(v),
                 ) {
-                    headers.insert(k, v);
+                    return headers.insert(k, v.clone()).as_ref() != Some(&v);
                 }
+                false
             }
             Action::RegexReplaceHeader(header_key, pattern, replacement) => {
                 if let Some(regex) = self.get_regex(pattern) {
@@ -239,21 +425,25 @@ impl RuleEngine {
                             if let Ok(val_str) = val.to_str() {
                                 let new_val =
                                     regex.replace_all(val_str, replacement.as_str()).to_string();
+                                if new_val == val_str {
+                                    return false;
+                                }
                                 if let Ok(new_header_val) =
                                     http::header::HeaderValue::from_str(&new_val)
                                 {
                                     headers.insert(k, new_header_val);
+                                    return true;
                                 }
                             }
                         }
                     }
                 }
+                false
             }
-            Action::RemoveHeader(k) => {
-                if let Ok(k) = http::header::HeaderName::from_bytes(k.as_bytes()) {
-                    headers.remove(k);
-                }
-            }
+            Action::RemoveHeader(k) => match http::header::HeaderName::from_bytes(k.as_bytes()) {
+                Ok(k) => headers.remove(k).is_some(),
+                Err(_) => false,
+            },
         }
     }
 }
@@ -264,6 +454,90 @@ impl Default for RuleEngine {
     }
 }
 
+fn condition_result(condition: &MatchCondition, matched: bool) -> std::result::Result<(), String> {
+    if matched {
+        Ok(())
+    } else {
+        Err(format!("{} did not match", condition))
+    }
+}
+
+fn header_str<'a>(
+    headers: &'a http::HeaderMap,
+    key: &str,
+    condition: &MatchCondition,
+) -> std::result::Result<&'a str, String> {
+    match headers.get(key) {
+        Some(val) => val
+            .to_str()
+            .map_err(|_| format!("{} failed: header value is not valid text", condition)),
+        None => Err(format!(
+            "{} failed: header {:?} is not present",
+            condition, key
+        )),
+    }
+}
+
+fn request_parts_from(entry: &CaptureEntry) -> Result<(http::request::Parts, Vec<u8>)> {
+    let request = &entry.request;
+    let mut builder = http::Request::builder()
+        .method(request.method.as_str())
+        .uri(request.url.as_str());
+    for (k, v) in &request.headers {
+        builder = builder.header(k.as_str(), v.as_str());
+    }
+    let (parts, _) = builder
+        .body(())
+        .map_err(|e| ProxyError::InvalidRequest(e.to_string()))?
+        .into_parts();
+    Ok((parts, request.body.clone()))
+}
+
+fn response_parts_from(status: u16, headers: &[(String, String)]) -> Result<http::response::Parts> {
+    let mut builder = http::Response::builder().status(status);
+    for (k, v) in headers {
+        builder = builder.header(k.as_str(), v.as_str());
+    }
+    let (parts, _) = builder
+        .body(())
+        .map_err(|e| ProxyError::InvalidResponse(e.to_string()))?
+        .into_parts();
+    Ok(parts)
+}
+
+fn render_headers(out: &mut String, headers: &http::HeaderMap) {
+    for (k, v) in headers.iter() {
+        out.push_str(&format!(
+            "{}: {}\n",
+            k,
+            String::from_utf8_lossy(v.as_bytes())
+        ));
+    }
+    out.push('\n');
+}
+
+fn render_request(parts: &http::request::Parts, body: &[u8]) -> String {
+    let mut out = format!("{} {}\n", parts.method, parts.uri);
+    render_headers(&mut out, &parts.headers);
+    out.push_str(&String::from_utf8_lossy(body));
+    out
+}
+
+fn render_response(parts: &http::response::Parts, body: &[u8]) -> String {
+    let mut out = format!("{}\n", parts.status);
+    render_headers(&mut out, &parts.headers);
+    out.push_str(&String::from_utf8_lossy(body));
+    out
+}
+
+fn diff(left: String, right: String) -> CompareResponse {
+    Comparer::compare(CompareRequest {
+        left,
+        right,
+        mode: CompareMode::Lines,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -517,4 +791,144 @@ mod tests {
         handle.join().unwrap();
         assert_eq!(engine.get_rules().len(), 1);
     }
+
+    fn create_test_entry() -> CaptureEntry {
+        let mut request = crate::capture::CapturedRequest::new(
+            "POST".to_string(),
+            "http://example.com/api/login".to_string(),
+            false,
+        );
+        request.id = 7;
+        request.headers = vec![("content-type".to_string(), "text/plain".to_string())];
+        request.body = b"user=admin".to_vec();
+        CaptureEntry {
+            request,
+            response: None,
+            annotation: Default::default(),
+            omitted_bodies: None,
+        }
+    }
+
+    #[test]
+    fn test_apply_counts_matches_and_hits() {
+        let engine = RuleEngine::new();
+        engine.add_rule(create_test_rule(
+            "hit",
+            MatchCondition::UrlContains("/api".to_string()),
+            Action::SetHeader("X-Test".to_string(), "1".to_string()),
+        ));
+        engine.add_rule(create_test_rule(
+            "miss",
+            MatchCondition::UrlContains("/other".to_string()),
+            Action::SetHeader("X-Other".to_string(), "1".to_string()),
+        ));
+
+        let mut body = vec![];
+        let (mut parts, _) = http::Request::builder()
+            .uri("/api/users")
+            .body(())
+            .unwrap()
+            .into_parts();
+
+        let first = engine.apply_request_rules(&mut parts, &mut body);
+        assert_eq!((first.matched, first.applied), (1, 1));
+        // The header is already set, so the second pass changes nothing
+        let second = engine.apply_request_rules(&mut parts, &mut body);
+        assert_eq!((second.matched, second.applied), (1, 0));
+
+        let hits = engine.rule_hits();
+        assert_eq!(hits.get("hit"), Some(&2));
+        assert!(!hits.contains_key("miss"));
+
+        engine.reset_hits();
+        assert!(engine.rule_hits().is_empty());
+    }
+
+    #[test]
+    fn test_dry_run_traces_rules() {
+        let engine = RuleEngine::new();
+        engine.add_rule(create_test_rule(
+            "replace",
+            MatchCondition::BodyContains("admin".to_string()),
+            Action::ReplaceBody("admin".to_string(), "guest".to_string()),
+        ));
+        engine.add_rule(create_test_rule(
+            "header",
+            MatchCondition::HeaderContains("x-missing".to_string(), "1".to_string()),
+            Action::RemoveHeader("content-type".to_string()),
+        ));
+        let mut inactive = create_test_rule(
+            "inactive",
+            MatchCondition::UrlContains("/api".to_string()),
+            Action::ReplaceBody("user".to_string(), "never".to_string()),
+        );
+        inactive.active = false;
+        engine.add_rule(inactive);
+        let mut response_rule = create_test_rule(
+            "response",
+            MatchCondition::BodyContains("ok".to_string()),
+            Action::ReplaceBody("ok".to_string(), "changed".to_string()),
+        );
+        response_rule.rule_type = RuleType::Response;
+        engine.add_rule(response_rule);
+
+        let report = engine.dry_run(&create_test_entry()).unwrap();
+        assert_eq!(report.request_id, 7);
+        assert_eq!(report.rules.len(), 4);
+
+        assert!(report.rules[0].matched);
+        assert!(report.rules[0].diff.is_some());
+
+        assert!(!report.rules[1].matched);
+        let reason = report.rules[1].failed_condition.as_deref().unwrap();
+        assert!(reason.contains("x-missing"));
+
+        assert_eq!(
+            report.rules[2].failed_condition.as_deref(),
+            Some("rule is inactive")
+        );
+        assert_eq!(
+            report.rules[3].failed_condition.as_deref(),
+            Some("entry has no response")
+        );
+
+        assert!(report
+            .request_diff
+            .changes
+            .iter()
+            .any(|c| c.tag == "insert" && c.value.contains("user=guest")));
+        assert!(report.response_diff.is_none());
+
+        // Dry runs never count as hits
+        assert!(engine.rule_hits().is_empty());
+    }
+
+    #[test]
+    fn test_dry_run_response_rules() {
+        let engine = RuleEngine::new();
+        let mut rule = create_test_rule(
+            "resp",
+            MatchCondition::HeaderRegex("server".to_string(), "^nginx".to_string()),
+            Action::SetHeader("server".to_string(), "hidden".to_string()),
+        );
+        rule.rule_type = RuleType::Response;
+        engine.add_rule(rule);
+
+        let mut entry = create_test_entry();
+        entry.response = Some(crate::capture::CapturedResponse {
+            request_id: 7,
+            status_code: 200,
+            headers: vec![("server".to_string(), "nginx/1.25".to_string())],
+            body: b"ok".to_vec(),
+            duration_ms: 5,
+        });
+
+        let report = engine.dry_run(&entry).unwrap();
+        assert!(report.rules[0].matched);
+        let diff = report.response_diff.unwrap();
+        assert!(diff
+            .changes
+            .iter()
+            .any(|c| c.tag == "insert" && c.value.contains("server: hidden")));
+    }
 }
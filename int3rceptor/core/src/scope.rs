@@ -1,22 +1,444 @@
+use crate::error::{ProxyError, Result};
+use crate::security::ip_in_cidr;
 use parking_lot::RwLock;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
 use std::sync::Arc;
 
+/// How a scope rule matches the request host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(tag = "type", content = "value", rename_all = "snake_case")]
+pub enum HostPattern {
+    /// Any host
+    #[default]
+    Any,
+    /// Exact hostname, case-insensitive
+    Exact(String),
+    /// `*.example.com`: the domain itself and every subdomain (stores `example.com`)
+    Wildcard(String),
+    /// Regex matched against the lowercased hostname
+    Regex(String),
+    /// IP network; a single address is a /32 or /128
+    Cidr { network: IpAddr, prefix_len: u8 },
+}
+
+/// Inclusive port range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+impl PortRange {
+    pub fn single(port: u16) -> Self {
+        Self {
+            start: port,
+            end: port,
+        }
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+/// A structured scope entry. Every component that is set must match.
+///
+/// Deserializes from either a target string (`https://*.example.com:8000-8100/api`)
+/// or an object with the fields below.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(try_from = "ScopeRuleRepr")]
+pub struct ScopeRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme: Option<String>,
+    #[serde(default)]
+    pub host: HostPattern,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ports: Vec<PortRange>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path_prefix: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ScopeRuleRepr {
+    Target(String),
+    Fields {
+        #[serde(default)]
+        scheme: Option<String>,
+        #[serde(default)]
+        host: HostPattern,
+        #[serde(default)]
+        ports: Vec<PortRange>,
+        #[serde(default)]
+        path_prefix: Option<String>,
+    },
+}
+
+impl TryFrom<ScopeRuleRepr> for ScopeRule {
+    type Error = ProxyError;
+
+    fn try_from(repr: ScopeRuleRepr) -> Result<Self> {
+        match repr {
+            ScopeRuleRepr::Target(target) => target.parse(),
+            ScopeRuleRepr::Fields {
+                scheme,
+                host,
+                ports,
+                path_prefix,
+            } => {
+                let rule = ScopeRule {
+                    scheme: scheme.map(|s| s.to_ascii_lowercase()),
+                    host,
+                    ports,
+                    path_prefix,
+                };
+                rule.validate()?;
+                Ok(rule)
+            }
+        }
+    }
+}
+
+impl ScopeRule {
+    /// Check that the rule can be evaluated (valid regex, prefix length, ports)
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |reason: &str| ProxyError::ScopePatternInvalid {
+            pattern: self.to_string(),
+            reason: reason.to_string(),
+        };
+
+        if let Some(scheme) = &self.scheme {
+            if scheme.is_empty() || !scheme.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(invalid("scheme must be alphanumeric"));
+            }
+        }
+        match &self.host {
+            HostPattern::Regex(pattern) => {
+                Regex::new(pattern).map_err(|e| invalid(&e.to_string()))?;
+            }
+            HostPattern::Cidr {
+                network,
+                prefix_len,
+            } => {
+                let max = if network.is_ipv4() { 32 } else { 128 };
+                if *prefix_len > max {
+                    return Err(invalid("CIDR prefix length out of range"));
+                }
+            }
+            HostPattern::Exact(host) | HostPattern::Wildcard(host) => {
+                if !is_valid_hostname(host) {
+                    return Err(invalid("invalid hostname"));
+                }
+            }
+            HostPattern::Any => {}
+        }
+        if self.ports.iter().any(|r| r.start > r.end) {
+            return Err(invalid("port range start is greater than its end"));
+        }
+        if let Some(prefix) = &self.path_prefix {
+            if !prefix.starts_with('/') {
+                return Err(invalid("path prefix must start with '/'"));
+            }
+        }
+        Ok(())
+    }
+
+    /// A target without a path (CONNECT) satisfies any path prefix
+    pub(crate) fn matches(
+        &self,
+        target: &Target,
+        regex_cache: &RwLock<HashMap<String, Regex>>,
+    ) -> bool {
+        if let Some(scheme) = &self.scheme {
+            if target.scheme.as_deref() != Some(scheme.as_str()) {
+                return false;
+            }
+        }
+
+        let host_matches = match &self.host {
+            HostPattern::Any => true,
+            HostPattern::Exact(host) => target.host.eq_ignore_ascii_case(host),
+            HostPattern::Wildcard(domain) => {
+                let host = target.host.to_ascii_lowercase();
+                let domain = domain.to_ascii_lowercase();
+                host == domain || host.ends_with(&format!(".{}", domain))
+            }
+            HostPattern::Regex(pattern) => match cached_regex(regex_cache, pattern) {
+                Some(regex) => regex.is_match(&target.host.to_ascii_lowercase()),
+                None => false,
+            },
+            HostPattern::Cidr {
+                network,
+                prefix_len,
+            } => match target.host.parse::<IpAddr>() {
+                Ok(ip) => ip_in_cidr(ip, *network, *prefix_len),
+                Err(_) => false,
+            },
+        };
+        if !host_matches {
+            return false;
+        }
+
+        if !self.ports.is_empty() {
+            match target.port {
+                Some(port) if self.ports.iter().any(|r| r.contains(port)) => {}
+                _ => return false,
+            }
+        }
+
+        match (&self.path_prefix, &target.path) {
+            (Some(prefix), Some(path)) => path.starts_with(prefix.as_str()),
+            _ => true,
+        }
+    }
+}
+
+impl FromStr for ScopeRule {
+    type Err = ProxyError;
+
+    /// Parse a target such as `example.com`, `*.example.com`, `https://api.example.com:8443/v1`,
+    /// `10.0.0.0/8`, `regex:^api[0-9]+\.example\.com$` or a bare path like `/logout`
+    fn from_str(s: &str) -> Result<Self> {
+        let target = s.trim();
+        let invalid = |reason: &str| ProxyError::ScopePatternInvalid {
+            pattern: target.to_string(),
+            reason: reason.to_string(),
+        };
+        if target.is_empty() {
+            return Err(invalid("empty scope target"));
+        }
+
+        if let Some(pattern) = target.strip_prefix("regex:") {
+            let rule = ScopeRule {
+                host: HostPattern::Regex(pattern.to_string()),
+                ..Default::default()
+            };
+            rule.validate()?;
+            return Ok(rule);
+        }
+
+        if target.starts_with('/') {
+            return Ok(ScopeRule {
+                path_prefix: Some(target.to_string()),
+                ..Default::default()
+            });
+        }
+
+        let mut rule = ScopeRule::default();
+        let mut rest = target;
+        if let Some((scheme, remainder)) = rest.split_once("://") {
+            if scheme != "*" {
+                rule.scheme = Some(scheme.to_ascii_lowercase());
+            }
+            rest = remainder;
+        }
+
+        let (authority, mut path) = match rest.find('/') {
+            Some(idx) => (&rest[..idx], &rest[idx..]),
+            None => (rest, ""),
+        };
+
+        // A bare IP followed by `/<digits>` is a CIDR block, not a path
+        let mut prefix_len = None;
+        if let Some(ip) = parse_ip_host(authority) {
+            let digits: String = path
+                .chars()
+                .skip(1)
+                .take_while(|c| c.is_ascii_digit())
+                .collect();
+            let after = &path[(1 + digits.len()).min(path.len())..];
+            if !digits.is_empty() && (after.is_empty() || after.starts_with('/')) {
+                prefix_len = Some(
+                    digits
+                        .parse::<u8>()
+                        .map_err(|_| invalid("invalid CIDR prefix"))?,
+                );
+                path = after;
+            }
+            rule.host = HostPattern::Cidr {
+                network: ip,
+                prefix_len: prefix_len.unwrap_or(if ip.is_ipv4() { 32 } else { 128 }),
+            };
+        } else {
+            let (host, ports) = split_port(authority);
+            if let Some(ports) = ports {
+                rule.ports =
+                    parse_ports(ports).ok_or_else(|| invalid("invalid port or port range"))?;
+            }
+            rule.host = match host {
+                "" | "*" => HostPattern::Any,
+                _ => match parse_ip_host(host) {
+                    Some(ip) => HostPattern::Cidr {
+                        network: ip,
+                        prefix_len: if ip.is_ipv4() { 32 } else { 128 },
+                    },
+                    None => match host.strip_prefix("*.") {
+                        Some(domain) => HostPattern::Wildcard(domain.to_ascii_lowercase()),
+                        None => HostPattern::Exact(host.to_ascii_lowercase()),
+                    },
+                },
+            };
+        }
+
+        if !path.is_empty() && path != "/" {
+            rule.path_prefix = Some(path.to_string());
+        }
+
+        rule.validate().map_err(|e| match e {
+            ProxyError::ScopePatternInvalid { reason, .. } => invalid(&reason),
+            other => other,
+        })?;
+        Ok(rule)
+    }
+}
+
+impl fmt::Display for ScopeRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let HostPattern::Regex(pattern) = &self.host {
+            if self.scheme.is_none() && self.ports.is_empty() && self.path_prefix.is_none() {
+                return write!(f, "regex:{}", pattern);
+            }
+        }
+        if let Some(scheme) = &self.scheme {
+            write!(f, "{}://", scheme)?;
+        }
+        match &self.host {
+            // Path-only rules print as the bare path
+            HostPattern::Any
+                if self.scheme.is_none() && self.ports.is_empty() && self.path_prefix.is_some() => {
+            }
+            HostPattern::Any => write!(f, "*")?,
+            HostPattern::Exact(host) => write!(f, "{}", host)?,
+            HostPattern::Wildcard(domain) => write!(f, "*.{}", domain)?,
+            HostPattern::Regex(pattern) => write!(f, "regex:{}", pattern)?,
+            HostPattern::Cidr {
+                network: IpAddr::V4(ip),
+                prefix_len: 32,
+            } => write!(f, "{}", ip)?,
+            HostPattern::Cidr {
+                network: IpAddr::V6(ip),
+                prefix_len: 128,
+            } => write!(f, "[{}]", ip)?,
+            HostPattern::Cidr {
+                network,
+                prefix_len,
+            } => write!(f, "{}/{}", network, prefix_len)?,
+        }
+        if !self.ports.is_empty() {
+            let ports: Vec<String> = self
+                .ports
+                .iter()
+                .map(|r| {
+                    if r.start == r.end {
+                        r.start.to_string()
+                    } else {
+                        format!("{}-{}", r.start, r.end)
+                    }
+                })
+                .collect();
+            write!(f, ":{}", ports.join(","))?;
+        }
+        if let Some(path) = &self.path_prefix {
+            write!(f, "{}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// What the proxy does with traffic that falls outside the scope
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum OutOfScopeAction {
+    /// Intercept as usual (TLS is still decrypted) but never capture
+    #[default]
+    Intercept,
+    /// Tunnel CONNECT without TLS interception; plain HTTP is forwarded uncaptured
+    Passthrough,
+    /// Refuse the request with 403
+    Drop,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct ScopeConfig {
-    pub includes: Vec<String>,
-    pub excludes: Vec<String>,
+    pub includes: Vec<ScopeRule>,
+    pub excludes: Vec<ScopeRule>,
+    #[serde(default)]
+    pub out_of_scope: OutOfScopeAction,
+}
+
+impl ScopeConfig {
+    /// Import a scope from JSON (a `ScopeConfig` object or an array of include rules)
+    /// or from a target list with one entry per line.
+    ///
+    /// In target lists, blank lines and `#` comments are skipped and lines prefixed
+    /// with `-` or `!` are excludes.
+    pub fn import(input: &str) -> Result<Self> {
+        let trimmed = input.trim_start();
+        if trimmed.starts_with('{') {
+            return serde_json::from_str(trimmed)
+                .map_err(|e| ProxyError::ScopeInvalid(e.to_string()));
+        }
+        if trimmed.starts_with('[') {
+            let includes = serde_json::from_str(trimmed)
+                .map_err(|e| ProxyError::ScopeInvalid(e.to_string()))?;
+            return Ok(ScopeConfig {
+                includes,
+                ..Default::default()
+            });
+        }
+
+        let mut config = ScopeConfig::default();
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('-').or_else(|| line.strip_prefix('!')) {
+                Some(target) => config.excludes.push(target.parse()?),
+                None => config.includes.push(line.parse()?),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Whether no rules are set, which puts everything in scope
+    pub fn is_empty(&self) -> bool {
+        self.includes.is_empty() && self.excludes.is_empty()
+    }
+
+    /// Build a config from plain target strings
+    pub fn from_targets<I, S>(includes: I, excludes: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let parse = |targets: I| -> Result<Vec<ScopeRule>> {
+            targets.into_iter().map(|t| t.as_ref().parse()).collect()
+        };
+        Ok(ScopeConfig {
+            includes: parse(includes)?,
+            excludes: parse(excludes)?,
+            ..Default::default()
+        })
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct ScopeManager {
     config: Arc<RwLock<ScopeConfig>>,
+    // Compiled host regexes, shared across rules
+    regex_cache: Arc<RwLock<HashMap<String, Regex>>>,
 }
 
 impl ScopeManager {
     pub fn new() -> Self {
         Self {
             config: Arc::new(RwLock::new(ScopeConfig::default())),
+            regex_cache: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
@@ -26,16 +448,40 @@ impl ScopeManager {
 
     pub fn set_config(&self, config: ScopeConfig) {
         *self.config.write() = config;
+        self.regex_cache.write().clear();
+    }
+
+    pub fn out_of_scope_action(&self) -> OutOfScopeAction {
+        self.config.read().out_of_scope
     }
 
     pub fn is_in_scope(&self, url: &str) -> bool {
+        match Target::parse(url) {
+            Some(target) => self.target_in_scope(&target),
+            // Rules cannot match it, but an empty scope still takes everything
+            None => self.config.read().is_empty(),
+        }
+    }
+
+    /// Whether anything on a CONNECT authority (`host:port`) can be in scope.
+    ///
+    /// Path-specific excludes are ignored here since they only apply per request.
+    pub fn is_host_in_scope(&self, authority: &str) -> bool {
+        match Target::from_authority(authority) {
+            Some(target) => self.target_in_scope(&target),
+            None => self.config.read().is_empty(),
+        }
+    }
+
+    fn target_in_scope(&self, target: &Target) -> bool {
         let config = self.config.read();
 
         // If excluded, it's out of scope immediately
-        for pattern in &config.excludes {
-            if url.contains(pattern) {
-                return false;
-            }
+        if config.excludes.iter().any(|rule| {
+            (target.path.is_some() || rule.path_prefix.is_none())
+                && rule.matches(target, &self.regex_cache)
+        }) {
+            return false;
         }
 
         // If includes is empty, everything is in scope (unless excluded)
@@ -44,34 +490,135 @@ impl ScopeManager {
         }
 
         // Must match at least one include
-        for pattern in &config.includes {
-            if url.contains(pattern) {
-                return true;
+        config
+            .includes
+            .iter()
+            .any(|rule| rule.matches(target, &self.regex_cache))
+    }
+}
+
+/// The parts of a URL that scope rules look at
+pub(crate) struct Target {
+    scheme: Option<String>,
+    host: String,
+    port: Option<u16>,
+    path: Option<String>,
+}
+
+impl Target {
+    pub(crate) fn parse(url: &str) -> Option<Self> {
+        let uri: http::Uri = url.parse().ok()?;
+        let host = uri.host()?;
+        let host = host
+            .strip_prefix('[')
+            .and_then(|h| h.strip_suffix(']'))
+            .unwrap_or(host);
+        let scheme = uri.scheme_str().map(|s| s.to_ascii_lowercase());
+        let port = uri.port_u16().or(match scheme.as_deref() {
+            Some("http") | Some("ws") => Some(80),
+            Some("https") | Some("wss") => Some(443),
+            _ => None,
+        });
+        Some(Target {
+            scheme,
+            host: host.to_string(),
+            port,
+            path: Some(uri.path().to_string()),
+        })
+    }
+
+    /// A CONNECT target (`host:port`) with no path
+    pub(crate) fn from_authority(authority: &str) -> Option<Self> {
+        let mut target = Target::parse(&format!("https://{}", authority))?;
+        target.path = None;
+        Some(target)
+    }
+}
+
+fn cached_regex(cache: &RwLock<HashMap<String, Regex>>, pattern: &str) -> Option<Regex> {
+    if let Some(regex) = cache.read().get(pattern) {
+        return Some(regex.clone());
+    }
+    let regex = Regex::new(pattern).ok()?;
+    cache.write().insert(pattern.to_string(), regex.clone());
+    Some(regex)
+}
+
+fn parse_ip_host(host: &str) -> Option<IpAddr> {
+    let host = host
+        .strip_prefix('[')
+        .and_then(|h| h.strip_suffix(']'))
+        .unwrap_or(host);
+    host.parse().ok()
+}
+
+/// Split `host:ports`, leaving bracketed IPv6 literals intact
+fn split_port(authority: &str) -> (&str, Option<&str>) {
+    if authority.starts_with('[') {
+        return match authority.find(']') {
+            Some(end) => {
+                let ports = authority[end + 1..].strip_prefix(':');
+                (&authority[..=end], ports)
             }
-        }
+            None => (authority, None),
+        };
+    }
+    match authority.rsplit_once(':') {
+        Some((host, ports)) => (host, Some(ports)),
+        None => (authority, None),
+    }
+}
 
-        false
+/// Parse `80`, `8000-8100`, `80,443` or `*` (any port, returned as an empty list)
+fn parse_ports(spec: &str) -> Option<Vec<PortRange>> {
+    if spec == "*" {
+        return Some(Vec::new());
     }
+    spec.split(',')
+        .map(|part| {
+            let part = part.trim();
+            match part.split_once('-') {
+                Some((start, end)) => Some(PortRange {
+                    start: start.trim().parse().ok()?,
+                    end: end.trim().parse().ok()?,
+                }),
+                None => part.parse().ok().map(PortRange::single),
+            }
+        })
+        .collect()
+}
+
+fn is_valid_hostname(host: &str) -> bool {
+    !host.is_empty()
+        && host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn config(includes: &[&str], excludes: &[&str]) -> ScopeConfig {
+        ScopeConfig::from_targets(includes.to_vec(), excludes.to_vec()).unwrap()
+    }
+
     #[test]
     fn test_empty_scope_allows_all() {
         let manager = ScopeManager::new();
         assert!(manager.is_in_scope("https://example.com"));
         assert!(manager.is_in_scope("https://any-domain.org/path"));
+        assert!(manager.is_in_scope("not a url"));
+        assert!(manager.is_host_in_scope("bad host:port"));
+
+        manager.set_config(config(&[], &["/logout"]));
+        assert!(!manager.is_in_scope("not a url"));
     }
 
     #[test]
     fn test_include_patterns() {
         let manager = ScopeManager::new();
-        manager.set_config(ScopeConfig {
-            includes: vec!["example.com".to_string(), "test.org".to_string()],
-            excludes: vec![],
-        });
+        manager.set_config(config(&["*.example.com", "test.org"], &[]));
 
         assert!(manager.is_in_scope("https://example.com/api"));
         assert!(manager.is_in_scope("https://sub.example.com"));
@@ -79,13 +626,22 @@ mod tests {
         assert!(!manager.is_in_scope("https://other.com"));
     }
 
+    #[test]
+    fn test_exact_host_is_not_substring() {
+        let manager = ScopeManager::new();
+        manager.set_config(config(&["example.com"], &[]));
+
+        assert!(manager.is_in_scope("https://example.com/"));
+        assert!(manager.is_in_scope("http://EXAMPLE.com:8080/x"));
+        assert!(!manager.is_in_scope("https://notexample.com.evil.io/"));
+        assert!(!manager.is_in_scope("https://evil.io/?next=example.com"));
+        assert!(!manager.is_in_scope("https://sub.example.com/"));
+    }
+
     #[test]
     fn test_exclude_patterns() {
         let manager = ScopeManager::new();
-        manager.set_config(ScopeConfig {
-            includes: vec![],
-            excludes: vec!["logout".to_string(), "static".to_string()],
-        });
+        manager.set_config(config(&[], &["/logout", "/static"]));
 
         assert!(manager.is_in_scope("https://example.com/api"));
         assert!(!manager.is_in_scope("https://example.com/logout"));
@@ -95,22 +651,144 @@ mod tests {
     #[test]
     fn test_exclude_takes_precedence() {
         let manager = ScopeManager::new();
-        manager.set_config(ScopeConfig {
-            includes: vec!["example.com".to_string()],
-            excludes: vec!["example.com/admin".to_string()],
-        });
+        manager.set_config(config(&["example.com"], &["example.com/admin"]));
 
         assert!(manager.is_in_scope("https://example.com/api"));
         assert!(!manager.is_in_scope("https://example.com/admin/users"));
     }
 
+    #[test]
+    fn test_scheme_and_port_ranges() {
+        let manager = ScopeManager::new();
+        manager.set_config(config(&["https://api.example.com:8000-8100,443/v1"], &[]));
+
+        assert!(manager.is_in_scope("https://api.example.com/v1/users"));
+        assert!(manager.is_in_scope("https://api.example.com:8050/v1"));
+        assert!(!manager.is_in_scope("http://api.example.com/v1"));
+        assert!(!manager.is_in_scope("https://api.example.com:9000/v1"));
+        assert!(!manager.is_in_scope("https://api.example.com/v2"));
+    }
+
+    #[test]
+    fn test_cidr_and_regex_hosts() {
+        let manager = ScopeManager::new();
+        manager.set_config(config(
+            &["10.0.0.0/8", "[::1]", r"regex:^api[0-9]+\.example\.com$"],
+            &[],
+        ));
+
+        assert!(manager.is_in_scope("http://10.1.2.3/"));
+        assert!(manager.is_in_scope("http://[::1]:8080/"));
+        assert!(manager.is_in_scope("https://api42.example.com/"));
+        assert!(!manager.is_in_scope("http://11.0.0.1/"));
+        assert!(!manager.is_in_scope("https://www.example.com/"));
+    }
+
+    #[test]
+    fn test_connect_authority_form() {
+        let manager = ScopeManager::new();
+        manager.set_config(config(&["*.example.com:443"], &[]));
+
+        assert!(manager.is_in_scope("www.example.com:443"));
+        assert!(!manager.is_in_scope("www.example.com:8443"));
+    }
+
+    #[test]
+    fn test_parse_and_display_round_trip() {
+        for target in [
+            "example.com",
+            "*.example.com",
+            "https://api.example.com:8000-8100/v1",
+            "10.0.0.0/8",
+            "192.168.1.5",
+            "[::1]:8080",
+            "/logout",
+            "regex:^a+$",
+        ] {
+            let rule: ScopeRule = target.parse().unwrap();
+            assert_eq!(rule.to_string(), target);
+        }
+    }
+
+    #[test]
+    fn test_invalid_patterns_rejected() {
+        for target in [
+            "",
+            "example.com:99999",
+            "example.com:90-80",
+            "10.0.0.0/33",
+            "regex:(",
+            "bad host.com",
+        ] {
+            assert!(
+                matches!(
+                    target.parse::<ScopeRule>(),
+                    Err(ProxyError::ScopePatternInvalid { .. })
+                ),
+                "{target:?} should be rejected"
+            );
+        }
+    }
+
+    #[test]
+    fn test_deserialize_strings_and_objects() {
+        let config: ScopeConfig = serde_json::from_str(
+            r#"{
+                "includes": ["*.example.com", {"scheme": "https", "host": {"type": "exact", "value": "api.test.com"}, "ports": [{"start": 443, "end": 443}]}],
+                "excludes": ["/logout"]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.includes.len(), 2);
+        assert_eq!(config.includes[1].to_string(), "https://api.test.com:443");
+
+        let err =
+            serde_json::from_str::<ScopeConfig>(r#"{"includes": ["regex:("], "excludes": []}"#);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_import_target_list() {
+        let config = ScopeConfig::import(
+            "# bounty scope\n*.example.com\n\n- /logout\n!static.example.com\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.includes.len(), 1);
+        assert_eq!(config.excludes.len(), 2);
+
+        let json = ScopeConfig::import(r#"["example.com", "10.0.0.0/8"]"#).unwrap();
+        assert_eq!(json.includes.len(), 2);
+        assert!(json.excludes.is_empty());
+
+        assert!(ScopeConfig::import("example.com:abc").is_err());
+    }
+
+    #[test]
+    fn test_host_level_scope() {
+        let manager = ScopeManager::new();
+        manager.set_config(config(
+            &["example.com/api"],
+            &["/logout", "admin.example.com"],
+        ));
+
+        // Host-level checks ignore path constraints on includes and excludes
+        assert!(manager.is_host_in_scope("example.com:443"));
+        assert!(!manager.is_host_in_scope("other.com:443"));
+        assert!(!manager.is_host_in_scope("admin.example.com:443"));
+        assert_eq!(manager.out_of_scope_action(), OutOfScopeAction::Intercept);
+
+        let parsed: ScopeConfig =
+            serde_json::from_str(r#"{"includes": [], "excludes": [], "out_of_scope": "drop"}"#)
+                .unwrap();
+        assert_eq!(parsed.out_of_scope, OutOfScopeAction::Drop);
+    }
+
     #[test]
     fn test_get_set_config() {
         let manager = ScopeManager::new();
-        let config = ScopeConfig {
-            includes: vec!["test.com".to_string()],
-            excludes: vec!["blocked".to_string()],
-        };
+        let config = config(&["test.com"], &["/blocked"]);
         manager.set_config(config.clone());
 
         let retrieved = manager.get_config();
@@ -126,10 +804,7 @@ mod tests {
         let manager_clone = manager.clone();
 
         let handle = thread::spawn(move || {
-            manager_clone.set_config(ScopeConfig {
-                includes: vec!["thread.com".to_string()],
-                excludes: vec![],
-            });
+            manager_clone.set_config(config(&["thread.com"], &[]));
         });
 
         handle.join().unwrap();
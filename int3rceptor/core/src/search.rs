@@ -0,0 +1,874 @@
+//! Search query language for captured traffic
+//!
+//! Queries such as `host:api.* status:>=400 body:"password" header:authorization`
+//! are parsed into a [`SearchExpr`] that is evaluated in memory or pushed down
+//! to SQLite as a prefilter. Terms are ANDed by default; `OR`, `NOT`/`-` and
+//! parentheses are supported.
+//!
+//! Fields:
+//! - `host:`, `url:`, `path:` - case-insensitive substring, or a glob when the value contains `*`
+//! - `method:` - exact, case-insensitive
+//! - `status:` - `404`, `4xx`, `400-499`, `>=400`, `<300`, ...
+//! - `header:name` / `header:name=value` - header present (request or response), optionally with a value phrase
+//! - `body:` - phrase in the request or response body
+//! - `tls:true|false`
+//! - bare text - URL substring, or a phrase in headers or bodies
+//!
+//! Header and body matching works on whole words so it can be served by the
+//! full-text index: `body:pass` does not match `password`.
+//!
+//! Parentheses that are balanced within a value are part of it, so
+//! `url:/api/foo(1)` needs no quotes. A value with an unmatched `)` must be
+//! quoted, since the `)` would otherwise close a group.
+
+use crate::capture::CaptureEntry;
+use crate::error::{ProxyError, Result};
+use rusqlite::types::Value;
+use serde::{Deserialize, Serialize};
+
+/// Bodies are only searched (and indexed) up to this many bytes
+pub const MAX_SEARCH_BODY_BYTES: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", content = "args", rename_all = "snake_case")]
+pub enum SearchExpr {
+    And(Vec<SearchExpr>),
+    Or(Vec<SearchExpr>),
+    Not(Box<SearchExpr>),
+    Term(SearchTerm),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "field", rename_all = "snake_case")]
+pub enum SearchTerm {
+    Host {
+        pattern: TextPattern,
+    },
+    Url {
+        pattern: TextPattern,
+    },
+    Path {
+        pattern: TextPattern,
+    },
+    Method {
+        value: String,
+    },
+    /// Inclusive status range
+    Status {
+        min: u16,
+        max: u16,
+    },
+    Header {
+        name: String,
+        value: Option<String>,
+    },
+    Body {
+        phrase: String,
+    },
+    Tls {
+        value: bool,
+    },
+    Text {
+        text: String,
+    },
+}
+
+/// Lowercased text matched as a substring, or as a glob when it contains `*`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextPattern {
+    pub pattern: String,
+}
+
+impl TextPattern {
+    fn new(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_lowercase(),
+        }
+    }
+
+    fn is_glob(&self) -> bool {
+        self.pattern.contains('*')
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let text = text.to_lowercase();
+        if self.is_glob() {
+            glob_match(&self.pattern, &text)
+        } else {
+            text.contains(&self.pattern)
+        }
+    }
+
+    /// Longest literal run, used for a `LIKE` prefilter
+    fn literal(&self) -> &str {
+        self.pattern
+            .split('*')
+            .max_by_key(|part| part.len())
+            .unwrap_or_default()
+    }
+}
+
+/// Split text into lowercase alphanumeric words, the unit of header/body search
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| t.to_lowercase())
+        .collect()
+}
+
+fn contains_phrase(haystack: &[String], phrase: &[String]) -> bool {
+    phrase.is_empty() || haystack.windows(phrase.len()).any(|w| w == phrase)
+}
+
+fn body_text(body: &[u8]) -> String {
+    let end = body.len().min(MAX_SEARCH_BODY_BYTES);
+    String::from_utf8_lossy(&body[..end]).into_owned()
+}
+
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let parts: Vec<&str> = pattern.split('*').collect();
+    if parts.len() == 1 {
+        return pattern == text;
+    }
+    let (first, last) = (parts[0], parts[parts.len() - 1]);
+    if text.len() < first.len() + last.len() || !text.starts_with(first) || !text.ends_with(last) {
+        return false;
+    }
+    // Both ends were just matched, so these are char boundaries
+    let mut rest = &text[first.len()..text.len() - last.len()];
+    for part in &parts[1..parts.len() - 1] {
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+impl SearchExpr {
+    pub fn parse(query: &str) -> Result<Self> {
+        let tokens = lex(query)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(search_error(
+                "unexpected ')'; quote values that contain unmatched parentheses",
+            ));
+        }
+        Ok(expr)
+    }
+
+    pub fn matches(&self, entry: &CaptureEntry) -> bool {
+        match self {
+            SearchExpr::And(items) => items.iter().all(|e| e.matches(entry)),
+            SearchExpr::Or(items) => items.iter().any(|e| e.matches(entry)),
+            SearchExpr::Not(inner) => !inner.matches(entry),
+            SearchExpr::Term(term) => term.matches(entry),
+        }
+    }
+
+    /// Translate into a SQL condition over the `captures` table.
+    ///
+    /// The condition selects a superset of the matching rows; callers must
+    /// still run [`SearchExpr::matches`] on the results. `fts_token` maps a
+    /// search word to the form stored in the `capture_fts` index.
+    pub fn to_sql(&self, fts_token: &dyn Fn(&str) -> String) -> SqlFilter {
+        match self {
+            SearchExpr::And(items) => combine(items, " AND ", fts_token),
+            SearchExpr::Or(items) => combine(items, " OR ", fts_token),
+            SearchExpr::Not(inner) => {
+                let inner = inner.to_sql(fts_token);
+                // Negating a superset would drop real matches
+                if inner.exact {
+                    SqlFilter {
+                        sql: format!("NOT ({})", inner.sql),
+                        params: inner.params,
+                        exact: true,
+                    }
+                } else {
+                    SqlFilter::all()
+                }
+            }
+            SearchExpr::Term(term) => term.to_sql(fts_token),
+        }
+    }
+}
+
+/// A SQL condition with positional parameters
+#[derive(Debug, Clone)]
+pub struct SqlFilter {
+    pub sql: String,
+    pub params: Vec<Value>,
+    /// Whether the condition matches exactly the rows the expression does
+    pub exact: bool,
+}
+
+impl SqlFilter {
+    fn all() -> Self {
+        Self {
+            sql: "1=1".to_string(),
+            params: Vec::new(),
+            exact: false,
+        }
+    }
+
+    fn exact(sql: impl Into<String>, params: Vec<Value>) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+            exact: true,
+        }
+    }
+
+    fn prefilter(sql: impl Into<String>, params: Vec<Value>) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+            exact: false,
+        }
+    }
+}
+
+fn combine(items: &[SearchExpr], joiner: &str, fts_token: &dyn Fn(&str) -> String) -> SqlFilter {
+    let mut sql = Vec::with_capacity(items.len());
+    let mut params = Vec::new();
+    let mut exact = true;
+    for item in items {
+        let filter = item.to_sql(fts_token);
+        exact &= filter.exact;
+        sql.push(format!("({})", filter.sql));
+        params.extend(filter.params);
+    }
+    SqlFilter {
+        sql: sql.join(joiner),
+        params,
+        exact,
+    }
+}
+
+fn like_param(literal: &str) -> Value {
+    let escaped = literal
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    Value::Text(format!("%{}%", escaped))
+}
+
+/// FTS5 query for a phrase in one column of `capture_fts`
+fn fts_phrase(column: &str, words: &[String], fts_token: &dyn Fn(&str) -> String) -> String {
+    let tokens: Vec<String> = words.iter().map(|w| fts_token(w)).collect();
+    format!("{} : \"{}\"", column, tokens.join(" "))
+}
+
+const FTS_MATCH: &str = "id IN (SELECT rowid FROM capture_fts WHERE capture_fts MATCH ?)";
+
+impl SearchTerm {
+    pub fn matches(&self, entry: &CaptureEntry) -> bool {
+        let request = &entry.request;
+        match self {
+            SearchTerm::Host { pattern } => request_host(&request.url)
+                .map(|host| pattern.matches(host))
+                .unwrap_or(false),
+            SearchTerm::Url { pattern } => pattern.matches(&request.url),
+            SearchTerm::Path { pattern } => pattern.matches(request_path(&request.url)),
+            SearchTerm::Method { value } => request.method.eq_ignore_ascii_case(value),
+            SearchTerm::Status { min, max } => entry
+                .response
+                .as_ref()
+                .map(|r| (*min..=*max).contains(&r.status_code))
+                .unwrap_or(false),
+            SearchTerm::Header { name, value } => {
+                let phrase = value.as_deref().map(tokenize).unwrap_or_default();
+                all_headers(entry).any(|(k, v)| {
+                    k.eq_ignore_ascii_case(name) && contains_phrase(&tokenize(v), &phrase)
+                })
+            }
+            SearchTerm::Body { phrase } => body_contains(entry, &tokenize(phrase)),
+            SearchTerm::Tls { value } => request.tls == *value,
+            SearchTerm::Text { text } => {
+                let phrase = tokenize(text);
+                request.url.to_lowercase().contains(&text.to_lowercase())
+                    || all_headers(entry)
+                        .any(|(k, v)| contains_phrase(&tokenize(&format!("{} {}", k, v)), &phrase))
+                    || body_contains(entry, &phrase)
+            }
+        }
+    }
+
+    fn to_sql(&self, fts_token: &dyn Fn(&str) -> String) -> SqlFilter {
+        match self {
+            SearchTerm::Host { pattern } | SearchTerm::Path { pattern } => SqlFilter::prefilter(
+                "url LIKE ? ESCAPE '\\'",
+                vec![like_param(pattern.literal())],
+            ),
+            SearchTerm::Url { pattern } => {
+                let filter = SqlFilter::prefilter(
+                    "url LIKE ? ESCAPE '\\'",
+                    vec![like_param(pattern.literal())],
+                );
+                // LIKE is only case-insensitive for ASCII
+                SqlFilter {
+                    exact: !pattern.is_glob() && pattern.pattern.is_ascii(),
+                    ..filter
+                }
+            }
+            SearchTerm::Method { value } => SqlFilter::exact(
+                "method = ? COLLATE NOCASE",
+                vec![Value::Text(value.clone())],
+            ),
+            // Spelled out so that NOT keeps rows without a response
+            SearchTerm::Status { min, max } => SqlFilter::exact(
+                "resp_status IS NOT NULL AND resp_status BETWEEN ? AND ?",
+                vec![Value::Integer(*min as i64), Value::Integer(*max as i64)],
+            ),
+            SearchTerm::Tls { value } => {
+                SqlFilter::exact("tls = ?", vec![Value::Integer(*value as i64)])
+            }
+            SearchTerm::Header { name, value } => {
+                let mut words = tokenize(name);
+                words.extend(value.as_deref().map(tokenize).unwrap_or_default());
+                if words.is_empty() {
+                    return SqlFilter::all();
+                }
+                let query = words
+                    .iter()
+                    .map(|w| fts_phrase("headers", std::slice::from_ref(w), fts_token))
+                    .collect::<Vec<_>>()
+                    .join(" AND ");
+                SqlFilter::prefilter(FTS_MATCH, vec![Value::Text(query)])
+            }
+            SearchTerm::Body { phrase } => {
+                let words = tokenize(phrase);
+                if words.is_empty() {
+                    return SqlFilter::all();
+                }
+                let query = fts_phrase("body", &words, fts_token);
+                SqlFilter::prefilter(FTS_MATCH, vec![Value::Text(query)])
+            }
+            SearchTerm::Text { text } => {
+                let words = tokenize(text);
+                if words.is_empty() {
+                    return SqlFilter::prefilter(
+                        "url LIKE ? ESCAPE '\\'",
+                        vec![like_param(&text.to_lowercase())],
+                    );
+                }
+                let query = format!(
+                    "{} OR {}",
+                    fts_phrase("headers", &words, fts_token),
+                    fts_phrase("body", &words, fts_token)
+                );
+                SqlFilter::prefilter(
+                    format!("url LIKE ? ESCAPE '\\' OR {}", FTS_MATCH),
+                    vec![like_param(&text.to_lowercase()), Value::Text(query)],
+                )
+            }
+        }
+    }
+}
+
+fn all_headers(entry: &CaptureEntry) -> impl Iterator<Item = &(String, String)> {
+    entry.request.headers.iter().chain(
+        entry
+            .response
+            .as_ref()
+            .map(|r| r.headers.iter())
+            .into_iter()
+            .flatten(),
+    )
+}
+
+fn body_contains(entry: &CaptureEntry, phrase: &[String]) -> bool {
+    contains_phrase(&tokenize(&body_text(&entry.request.body)), phrase)
+        || entry
+            .response
+            .as_ref()
+            .map(|r| contains_phrase(&tokenize(&body_text(&r.body)), phrase))
+            .unwrap_or(false)
+}
+
+/// Text indexed in `capture_fts` for an entry: (headers, bodies)
+pub fn index_text(entry: &CaptureEntry) -> (String, String) {
+    let headers = all_headers(entry)
+        .map(|(k, v)| format!("{} {}", k, v))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let mut body = body_text(&entry.request.body);
+    if let Some(response) = &entry.response {
+        body.push('\n');
+        body.push_str(&body_text(&response.body));
+    }
+    (headers, body)
+}
+
+fn request_host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map(|(_, r)| r)?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let authority = authority
+        .rsplit_once('@')
+        .map(|(_, a)| a)
+        .unwrap_or(authority);
+    if authority.starts_with('[') {
+        return authority.split(']').next().map(|h| &h[1..]);
+    }
+    Some(authority.split(':').next().unwrap_or(authority))
+}
+
+fn request_path(url: &str) -> &str {
+    let rest = url.split_once("://").map(|(_, r)| r).unwrap_or(url);
+    let path = match rest.find('/') {
+        Some(idx) if url.contains("://") => &rest[idx..],
+        _ if url.contains("://") => "/",
+        _ => rest,
+    };
+    path.split(['?', '#']).next().unwrap_or(path)
+}
+
+fn search_error(reason: impl Into<String>) -> ProxyError {
+    ProxyError::invalid_config("q", reason)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    Term {
+        field: Option<String>,
+        value: String,
+    },
+}
+
+fn lex(input: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    let read_quoted = |i: &mut usize| -> Result<String> {
+        // Skip the opening quote
+        *i += 1;
+        let mut value = String::new();
+        while *i < chars.len() {
+            match chars[*i] {
+                '\\' if *i + 1 < chars.len() => {
+                    value.push(chars[*i + 1]);
+                    *i += 2;
+                }
+                '"' => {
+                    *i += 1;
+                    return Ok(value);
+                }
+                c => {
+                    value.push(c);
+                    *i += 1;
+                }
+            }
+        }
+        Err(search_error("unterminated quote"))
+    };
+
+    // A word runs to whitespace or a `)` closing an enclosing group;
+    // parentheses balanced within it are kept
+    let read_word = |i: &mut usize, stop_at_colon: bool| -> String {
+        let start = *i;
+        let mut nested = 0usize;
+        while *i < chars.len() && !chars[*i].is_whitespace() {
+            match chars[*i] {
+                ':' if stop_at_colon => break,
+                '(' => nested += 1,
+                ')' if nested == 0 => break,
+                ')' => nested -= 1,
+                _ => {}
+            }
+            *i += 1;
+        }
+        chars[start..*i].iter().collect()
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        match c {
+            '(' => {
+                tokens.push(Token::LParen);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                i += 1;
+            }
+            '-' if i + 1 < chars.len() && !chars[i + 1].is_whitespace() => {
+                tokens.push(Token::Not);
+                i += 1;
+            }
+            '"' => {
+                let value = read_quoted(&mut i)?;
+                tokens.push(Token::Term { field: None, value });
+            }
+            _ => {
+                let word = read_word(&mut i, true);
+                if i < chars.len() && chars[i] == ':' && !word.is_empty() {
+                    i += 1;
+                    let value = if i < chars.len() && chars[i] == '"' {
+                        read_quoted(&mut i)?
+                    } else {
+                        read_word(&mut i, false)
+                    };
+                    tokens.push(Token::Term {
+                        field: Some(word.to_lowercase()),
+                        value,
+                    });
+                    continue;
+                }
+                if word.is_empty() {
+                    // A lone ':'; treat literally
+                    tokens.push(Token::Term {
+                        field: None,
+                        value: chars[i].to_string(),
+                    });
+                    i += 1;
+                    continue;
+                }
+                tokens.push(match word.as_str() {
+                    "AND" => Token::And,
+                    "OR" => Token::Or,
+                    "NOT" => Token::Not,
+                    _ => Token::Term {
+                        field: None,
+                        value: word,
+                    },
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn parse_or(&mut self) -> Result<SearchExpr> {
+        let mut items = vec![self.parse_and()?];
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            items.push(self.parse_and()?);
+        }
+        Ok(flatten(items, SearchExpr::Or))
+    }
+
+    fn parse_and(&mut self) -> Result<SearchExpr> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Or) | Some(Token::RParen) => break,
+                Some(Token::And) => {
+                    self.pos += 1;
+                }
+                _ => items.push(self.parse_unary()?),
+            }
+        }
+        if items.is_empty() {
+            return Err(search_error("expected a search term"));
+        }
+        Ok(flatten(items, SearchExpr::And))
+    }
+
+    fn parse_unary(&mut self) -> Result<SearchExpr> {
+        match self.tokens.get(self.pos).cloned() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(SearchExpr::Not(Box::new(self.parse_unary()?)))
+            }
+            Some(Token::LParen) => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Token::RParen) {
+                    return Err(search_error("missing ')'"));
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            Some(Token::Term { field, value }) => {
+                self.pos += 1;
+                Ok(SearchExpr::Term(parse_term(field.as_deref(), &value)?))
+            }
+            _ => Err(search_error("expected a search term")),
+        }
+    }
+}
+
+fn flatten(mut items: Vec<SearchExpr>, wrap: fn(Vec<SearchExpr>) -> SearchExpr) -> SearchExpr {
+    if items.len() == 1 {
+        items.remove(0)
+    } else {
+        wrap(items)
+    }
+}
+
+fn parse_term(field: Option<&str>, value: &str) -> Result<SearchTerm> {
+    let Some(field) = field else {
+        return Ok(SearchTerm::Text {
+            text: value.to_string(),
+        });
+    };
+    if value.is_empty() {
+        return Err(search_error(format!("missing value for '{}:'", field)));
+    }
+    Ok(match field {
+        "host" => SearchTerm::Host {
+            pattern: TextPattern::new(value),
+        },
+        "url" => SearchTerm::Url {
+            pattern: TextPattern::new(value),
+        },
+        "path" => SearchTerm::Path {
+            pattern: TextPattern::new(value),
+        },
+        "method" => SearchTerm::Method {
+            value: value.to_uppercase(),
+        },
+        "status" => {
+            let (min, max) = parse_status(value)
+                .ok_or_else(|| search_error(format!("invalid status filter '{}'", value)))?;
+            SearchTerm::Status { min, max }
+        }
+        "header" => match value.split_once('=') {
+            Some((name, v)) => SearchTerm::Header {
+                name: name.to_string(),
+                value: Some(v.to_string()),
+            },
+            None => SearchTerm::Header {
+                name: value.to_string(),
+                value: None,
+            },
+        },
+        "body" => SearchTerm::Body {
+            phrase: value.to_string(),
+        },
+        "tls" => SearchTerm::Tls {
+            value: match value.to_ascii_lowercase().as_str() {
+                "true" | "yes" | "1" => true,
+                "false" | "no" | "0" => false,
+                _ => return Err(search_error(format!("invalid tls filter '{}'", value))),
+            },
+        },
+        other => return Err(search_error(format!("unknown field '{}'", other))),
+    })
+}
+
+/// Parse `404`, `4xx`, `400-499`, `>=400`, `>400`, `<=299`, `<300`
+fn parse_status(value: &str) -> Option<(u16, u16)> {
+    const MAX: u16 = 999;
+    let num = |s: &str| s.parse::<u16>().ok().filter(|n| *n <= MAX);
+
+    if let Some(rest) = value.strip_prefix(">=") {
+        return Some((num(rest)?, MAX));
+    }
+    if let Some(rest) = value.strip_prefix("<=") {
+        return Some((0, num(rest)?));
+    }
+    if let Some(rest) = value.strip_prefix('>') {
+        return Some((num(rest)?.checked_add(1)?, MAX));
+    }
+    if let Some(rest) = value.strip_prefix('<') {
+        return Some((0, num(rest)?.checked_sub(1)?));
+    }
+    if let Some((lo, hi)) = value.split_once('-') {
+        let (lo, hi) = (num(lo)?, num(hi)?);
+        return (lo <= hi).then_some((lo, hi));
+    }
+    let lower = value.to_ascii_lowercase();
+    if lower.len() == 3 && lower.ends_with("xx") {
+        let class = num(&lower[..1])?;
+        return (1..=9)
+            .contains(&class)
+            .then_some((class * 100, class * 100 + 99));
+    }
+    let n = num(value)?;
+    Some((n, n))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{CapturedRequest, CapturedResponse};
+
+    fn entry(url: &str, status: u16, req_body: &str, resp_body: &str) -> CaptureEntry {
+        let mut request = CapturedRequest::new("POST", url, url.starts_with("https"));
+        request.headers = vec![("Authorization".to_string(), "Bearer abc123".to_string())];
+        request.body = req_body.as_bytes().to_vec();
+        CaptureEntry {
+            request,
+            response: Some(CapturedResponse {
+                request_id: 0,
+                status_code: status,
+                headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+                body: resp_body.as_bytes().to_vec(),
+                duration_ms: 1,
+            }),
+            annotation: Default::default(),
+            omitted_bodies: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_fields_and_operators() {
+        let expr =
+            SearchExpr::parse(r#"host:api.* status:>=400 body:"password" header:authorization"#)
+                .unwrap();
+        let SearchExpr::And(items) = expr else {
+            panic!("expected implicit AND");
+        };
+        assert_eq!(items.len(), 4);
+        assert_eq!(
+            items[1],
+            SearchExpr::Term(SearchTerm::Status { min: 400, max: 999 })
+        );
+
+        let expr = SearchExpr::parse("(method:get OR method:POST) -tls:true").unwrap();
+        assert!(matches!(expr, SearchExpr::And(ref items) if items.len() == 2));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(SearchExpr::parse("").is_err());
+        assert!(SearchExpr::parse("status:abc").is_err());
+        assert!(SearchExpr::parse("nope:value").is_err());
+        assert!(SearchExpr::parse("body:\"open").is_err());
+        assert!(SearchExpr::parse("(host:a").is_err());
+        assert!(SearchExpr::parse("host:a)").is_err());
+        assert!(SearchExpr::parse("(url:/a) b)").is_err());
+    }
+
+    #[test]
+    fn test_parentheses_in_values() {
+        assert_eq!(
+            SearchExpr::parse("url:/api/foo(1)").unwrap(),
+            SearchExpr::parse(r#"url:"/api/foo(1)""#).unwrap()
+        );
+        assert_eq!(
+            SearchExpr::parse("(url:/api/foo(1))").unwrap(),
+            SearchExpr::parse("url:/api/foo(1)").unwrap()
+        );
+        // Not split into `foo AND (bar)`
+        assert_eq!(
+            SearchExpr::parse("foo(bar)").unwrap(),
+            SearchExpr::parse(r#""foo(bar)""#).unwrap()
+        );
+        let err = SearchExpr::parse("url:/a)").unwrap_err().to_string();
+        assert!(err.contains("quote"), "{}", err);
+    }
+
+    #[test]
+    fn test_status_forms() {
+        assert_eq!(parse_status("404"), Some((404, 404)));
+        assert_eq!(parse_status("5xx"), Some((500, 599)));
+        assert_eq!(parse_status("400-403"), Some((400, 403)));
+        assert_eq!(parse_status(">400"), Some((401, 999)));
+        assert_eq!(parse_status("<300"), Some((0, 299)));
+        assert_eq!(parse_status("403-400"), None);
+    }
+
+    #[test]
+    fn test_in_memory_matching() {
+        let e = entry(
+            "https://api.example.com/v1/login?next=/home",
+            401,
+            r#"{"user":"admin","password":"hunter2"}"#,
+            "denied",
+        );
+
+        let yes = [
+            "host:api.*",
+            "host:example",
+            "path:/v1/*",
+            "status:4xx",
+            "body:password",
+            "body:\"password hunter2\"",
+            "header:authorization",
+            "header:authorization=bearer",
+            "header:content-type=\"application json\"",
+            "tls:true",
+            "method:post",
+            "admin",
+            "-status:200",
+            "status:200 OR body:denied",
+        ];
+        for q in yes {
+            assert!(
+                SearchExpr::parse(q).unwrap().matches(&e),
+                "{q} should match"
+            );
+        }
+
+        let no = [
+            "host:www.*",
+            "status:>=500",
+            "body:pass",
+            "header:cookie",
+            "header:authorization=basic",
+            "tls:false",
+            "NOT body:password",
+        ];
+        for q in no {
+            assert!(
+                !SearchExpr::parse(q).unwrap().matches(&e),
+                "{q} should not match"
+            );
+        }
+    }
+
+    #[test]
+    fn test_sql_translation() {
+        let expr = SearchExpr::parse("status:>=400 method:GET").unwrap();
+        let filter = expr.to_sql(&|t| t.to_string());
+        assert!(filter.exact);
+        assert_eq!(filter.params.len(), 3);
+
+        let expr = SearchExpr::parse("-body:secret").unwrap();
+        let filter = expr.to_sql(&|t| t.to_string());
+        assert_eq!(filter.sql, "1=1");
+        assert!(!filter.exact);
+
+        let expr = SearchExpr::parse("body:\"Hunter 2\"").unwrap();
+        let filter = expr.to_sql(&|t| format!("h{}", t));
+        assert_eq!(
+            filter.params,
+            vec![Value::Text("body : \"hhunter h2\"".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_glob_and_helpers() {
+        assert!(glob_match("api.*", "api.example.com"));
+        assert!(glob_match("*.example.*", "a.example.org"));
+        assert!(!glob_match("api.*.com", "api.org"));
+        assert!(glob_match("*é", "/café"));
+        assert!(!glob_match("*é", "/price€"));
+        assert!(!glob_match("€*", "é€"));
+
+        // A multibyte pattern against a URL ending in another multibyte char
+        let expr = SearchExpr::parse("url:*é").unwrap();
+        assert!(!expr.matches(&entry("https://shop.example.com/price/10€", 200, "", "")));
+        assert!(expr.matches(&entry("https://shop.example.com/café", 200, "", "")));
+        assert_eq!(
+            request_host("https://u:p@Host.com:8443/x"),
+            Some("Host.com")
+        );
+        assert_eq!(request_host("http://[::1]:80/"), Some("::1"));
+        assert_eq!(request_path("https://a.com/b/c?d=1"), "/b/c");
+        assert_eq!(request_path("https://a.com"), "/");
+    }
+}
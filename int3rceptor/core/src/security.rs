@@ -337,7 +337,7 @@ impl Default for IpFilter {
 }
 
 /// Check if IP is in CIDR range
-fn ip_in_cidr(ip: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
+pub(crate) fn ip_in_cidr(ip: IpAddr, network: IpAddr, prefix_len: u8) -> bool {
     match (ip, network) {
         (IpAddr::V4(ip), IpAddr::V4(net)) => {
             if prefix_len > 32 {
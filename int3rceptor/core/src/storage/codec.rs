@@ -0,0 +1,508 @@
+//! Row encoding for captures under one set of storage keys
+
+use crate::capture::{Annotation, BodySizes, CaptureEntry, CapturedRequest, CapturedResponse};
+use crate::crypto;
+use crate::database::{self, EncryptionKeyProvider};
+use crate::error::{ProxyError, Result};
+use crate::search;
+use rusqlite::{params, Connection, OptionalExtension, Row, Transaction, TransactionBehavior};
+use std::cmp::min;
+use time::OffsetDateTime;
+
+// Bodies live in `bodies` keyed by content hash and are loaded separately; the
+// inline columns only hold rows that could not be migrated (e.g. written under another key)
+pub(super) const SELECT_COLUMNS: &str = "SELECT id, timestamp_ms, method, url, headers, body, tls, resp_status, resp_headers, resp_body, duration_ms, highlight, notes, tags, body_hash, resp_body_hash, COALESCE(req_blob.size, length(body), 0), COALESCE(resp_blob.size, length(resp_body), 0), source FROM captures LEFT JOIN annotations ON annotations.capture_id = captures.id LEFT JOIN bodies AS req_blob ON req_blob.hash = captures.body_hash LEFT JOIN bodies AS resp_blob ON resp_blob.hash = captures.resp_body_hash";
+
+/// Bodies at least this large are stored zstd-compressed
+const COMPRESS_THRESHOLD: usize = 4 * 1024;
+const ZSTD_LEVEL: i32 = 3;
+pub(super) const ENCODING_RAW: i64 = 0;
+pub(super) const ENCODING_ZSTD: i64 = 1;
+
+/// Hex chars kept from each blind index token
+const BLIND_TOKEN_LEN: usize = 24;
+
+/// Rows rewritten per transaction when upgrading rows from older releases
+const BACKFILL_BATCH: usize = 256;
+
+/// Encrypts, hashes and indexes capture rows with one master key
+#[derive(Debug, Clone)]
+pub(super) struct Codec {
+    encryption: EncryptionKeyProvider,
+    /// Keys the full-text index tokens when encryption is enabled
+    index_key: Option<[u8; 32]>,
+    /// Keys body content hashes when encryption is enabled
+    body_key: Option<[u8; 32]>,
+    /// Key being rotated away from; rows not yet re-encrypted still decrypt with it
+    previous: Option<Box<Codec>>,
+}
+
+impl Codec {
+    pub(super) fn new(encryption: EncryptionKeyProvider) -> Self {
+        let index_key = encryption
+            .is_enabled()
+            .then(|| encryption.derive_subkey("fts-index"));
+        let body_key = encryption
+            .is_enabled()
+            .then(|| encryption.derive_subkey("body-hash"));
+        Self {
+            encryption,
+            index_key,
+            body_key,
+            previous: None,
+        }
+    }
+
+    /// Codec for a rotation in progress: writes use `self`, reads also accept `previous`
+    pub(super) fn rotating_from(mut self, previous: Codec) -> Self {
+        self.previous = Some(Box::new(previous.settled()));
+        self
+    }
+
+    /// The same keys without the previous one
+    pub(super) fn settled(self) -> Self {
+        Self {
+            previous: None,
+            ..self
+        }
+    }
+
+    pub(super) fn previous(&self) -> Option<&Codec> {
+        self.previous.as_deref()
+    }
+
+    pub(super) fn encryption(&self) -> &EncryptionKeyProvider {
+        &self.encryption
+    }
+
+    pub(super) fn encrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(database::encrypt_if_enabled(&self.encryption, data)?)
+    }
+
+    pub(super) fn decrypt(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let decrypted = database::decrypt_if_enabled(&self.encryption, data);
+        if let (Err(_), Some(previous)) = (&decrypted, &self.previous) {
+            return previous.decrypt(data);
+        }
+        decrypted.map_err(|e| {
+            ProxyError::StorageKeyMismatch(format!("stored data cannot be decrypted: {e}"))
+        })
+    }
+
+    /// Keyed value recorded in the database so a different key is detected on open
+    pub(super) fn check_value(&self) -> Option<String> {
+        self.encryption
+            .is_enabled()
+            .then(|| hex::encode(self.encryption.derive_subkey("key-check")))
+    }
+
+    /// Content hash identifying a body in the `bodies` table
+    pub(super) fn body_hash(&self, body: &[u8]) -> String {
+        match &self.body_key {
+            // Keyed so equal hashes do not confirm guessed content
+            Some(key) => hex::encode(crypto::hmac_sha256(key, body)),
+            None => hex::encode(crypto::sha256_hash(body)),
+        }
+    }
+
+    /// Store a body once per distinct content and return its hash
+    pub(super) fn store_body(&self, conn: &Connection, body: &[u8]) -> Result<Option<String>> {
+        if body.is_empty() {
+            return Ok(None);
+        }
+        let hash = self.body_hash(body);
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM bodies WHERE hash = ?1",
+            params![hash],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            // Compress before encrypting; ciphertext does not compress
+            let compressed = if body.len() >= COMPRESS_THRESHOLD {
+                Some(zstd::encode_all(body, ZSTD_LEVEL)?).filter(|c| c.len() < body.len())
+            } else {
+                None
+            };
+            let (data, encoding) = match &compressed {
+                Some(compressed) => (compressed.as_slice(), ENCODING_ZSTD),
+                None => (body, ENCODING_RAW),
+            };
+            conn.execute(
+                "INSERT INTO bodies (hash, data, encoding, size) VALUES (?1, ?2, ?3, ?4)",
+                params![hash, self.encrypt(data)?, encoding, body.len() as i64],
+            )?;
+        }
+        Ok(Some(hash))
+    }
+
+    pub(super) fn load_body(&self, conn: &Connection, hash: &str) -> Result<Vec<u8>> {
+        let stored = conn
+            .prepare_cached("SELECT data, encoding FROM bodies WHERE hash = ?1")?
+            .query_row(params![hash], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?))
+            })
+            .optional()?;
+        let Some((data, encoding)) = stored else {
+            return Ok(Vec::new());
+        };
+        let data = self.decrypt(&data)?;
+        match encoding {
+            ENCODING_ZSTD => Ok(zstd::decode_all(data.as_slice())?),
+            _ => Ok(data),
+        }
+    }
+
+    /// Record the original size of bodies stored before sizes were tracked
+    pub(super) fn backfill_body_sizes(&self, conn: &mut Connection) -> Result<()> {
+        let mut after = String::new();
+        loop {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let hashes = tx
+                .prepare(
+                    "SELECT hash FROM bodies WHERE size IS NULL AND hash > ?1 ORDER BY hash LIMIT ?2",
+                )?
+                .query_map(params![after, BACKFILL_BATCH as i64], |row| {
+                    row.get::<_, String>(0)
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?;
+            let Some(last) = hashes.last() else {
+                return Ok(());
+            };
+            after = last.clone();
+            for hash in &hashes {
+                let body = self.load_body(&tx, hash)?;
+                tx.execute(
+                    "UPDATE bodies SET size = ?1 WHERE hash = ?2",
+                    params![body.len() as i64, hash],
+                )?;
+            }
+            tx.commit()?;
+        }
+    }
+
+    /// Move bodies stored inline by older versions into `bodies`
+    pub(super) fn migrate_inline_bodies(&self, conn: &mut Connection) -> Result<()> {
+        let condition = "(body IS NOT NULL AND body_hash IS NULL) OR (resp_body IS NOT NULL AND resp_body_hash IS NULL)";
+        self.rewrite_where(conn, condition, |tx, entry| {
+            let body_hash = self.store_body(tx, &entry.request.body)?;
+            let resp_body_hash = match &entry.response {
+                Some(response) => self.store_body(tx, &response.body)?,
+                None => None,
+            };
+            tx.execute(
+                "UPDATE captures SET body = NULL, body_hash = ?1, resp_body = NULL, resp_body_hash = ?2 WHERE id = ?3",
+                params![body_hash, resp_body_hash, entry.request.id as i64],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Run `rewrite` on every row matching `condition`
+    ///
+    /// Rows are read in id order, `BACKFILL_BATCH` at a time, and each batch
+    /// is committed in its own transaction so large databases are never
+    /// loaded whole. The key check on open rules out a wrong key, so a row
+    /// that still cannot be decoded is damaged; it fails the pass rather than
+    /// being skipped and retried on every open.
+    fn rewrite_where(
+        &self,
+        conn: &mut Connection,
+        condition: &str,
+        mut rewrite: impl FnMut(&Transaction<'_>, &CaptureEntry) -> Result<()>,
+    ) -> Result<()> {
+        let sql = format!(
+            "{} WHERE ({}) AND captures.id > ?1 ORDER BY captures.id LIMIT ?2",
+            SELECT_COLUMNS, condition
+        );
+        let mut after = i64::MIN;
+        loop {
+            let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+            let entries = {
+                let mut stmt = tx.prepare(&sql)?;
+                let mut rows = stmt.query(params![after, BACKFILL_BATCH as i64])?;
+                let mut entries = Vec::with_capacity(BACKFILL_BATCH);
+                while let Some(row) = rows.next()? {
+                    entries.push(self.read_entry(&tx, row, true)?);
+                }
+                entries
+            };
+            let Some(last) = entries.last() else {
+                return Ok(());
+            };
+            after = last.request.id as i64;
+            for entry in &entries {
+                rewrite(&tx, entry)?;
+            }
+            tx.commit()?;
+        }
+    }
+
+    /// Fill in `size_bytes` for captures stored before the column existed
+    pub(super) fn backfill_sizes(&self, conn: &mut Connection) -> Result<()> {
+        self.rewrite_where(conn, "size_bytes IS NULL", |tx, entry| {
+            tx.execute(
+                "UPDATE captures SET size_bytes = ?1 WHERE id = ?2",
+                params![entry.size_bytes() as i64, entry.request.id as i64],
+            )?;
+            Ok(())
+        })
+    }
+
+    /// Map a search word to its form in `capture_fts`.
+    ///
+    /// With encryption enabled the index only holds keyed hashes of words, so
+    /// it reveals nothing about the content without the key.
+    pub(super) fn index_token(&self, word: &str) -> String {
+        match &self.index_key {
+            Some(key) => {
+                let mut token = hex::encode(crypto::hmac_sha256(key, word.as_bytes()));
+                token.truncate(BLIND_TOKEN_LEN);
+                token
+            }
+            None => word.to_string(),
+        }
+    }
+
+    fn index_field(&self, text: &str) -> String {
+        search::tokenize(text)
+            .iter()
+            .map(|word| self.index_token(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    pub(super) fn index_entry(&self, conn: &Connection, entry: &CaptureEntry) -> Result<()> {
+        let (headers, body) = search::index_text(entry);
+        conn.execute(
+            "DELETE FROM capture_fts WHERE rowid = ?1",
+            params![entry.request.id as i64],
+        )?;
+        conn.execute(
+            "INSERT INTO capture_fts (rowid, headers, body) VALUES (?1, ?2, ?3)",
+            params![
+                entry.request.id as i64,
+                self.index_field(&headers),
+                self.index_field(&body)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Index captures stored before the full-text index existed
+    pub(super) fn reindex_missing(&self, conn: &mut Connection) -> Result<()> {
+        self.rewrite_where(
+            conn,
+            "captures.id NOT IN (SELECT rowid FROM capture_fts)",
+            |tx, entry| self.index_entry(tx, entry),
+        )
+    }
+
+    pub(super) fn write_entry(&self, conn: &Connection, entry: &CaptureEntry) -> Result<()> {
+        // Encrypt sensitive request data
+        let headers_json = serde_json::to_string(&entry.request.headers)?;
+        let encrypted_headers = self.encrypt(headers_json.as_bytes())?;
+
+        // Encrypt sensitive response data
+        let resp_headers = entry
+            .response
+            .as_ref()
+            .map(|r| {
+                let json = serde_json::to_string(&r.headers)?;
+                self.encrypt(json.as_bytes())
+            })
+            .transpose()?;
+
+        let body_hash = self.store_body(conn, &entry.request.body)?;
+        let resp_body_hash = match &entry.response {
+            Some(response) => self.store_body(conn, &response.body)?,
+            None => None,
+        };
+        conn.execute(
+            r#"
+            INSERT OR REPLACE INTO captures (
+                id,
+                timestamp_ms,
+                method,
+                url,
+                headers,
+                body_hash,
+                tls,
+                resp_status,
+                resp_headers,
+                resp_body_hash,
+                duration_ms,
+                size_bytes,
+                source,
+                inserted_at_ms
+            ) VALUES (
+                ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13,
+                COALESCE((SELECT inserted_at_ms FROM captures WHERE id = ?1), ?14)
+            )
+            "#,
+            params![
+                entry.request.id as i64,
+                clamp_i128(entry.request.timestamp_ms),
+                entry.request.method,
+                entry.request.url,
+                encrypted_headers,
+                body_hash,
+                if entry.request.tls { 1 } else { 0 },
+                entry.response.as_ref().map(|r| r.status_code as i64),
+                resp_headers,
+                resp_body_hash,
+                entry.response.as_ref().map(|r| clamp_u128(r.duration_ms)),
+                entry.size_bytes() as i64,
+                entry.request.source.as_str(),
+                // Rewrites (e.g. when the response arrives) keep the first insertion time
+                clamp_i128(OffsetDateTime::now_utc().unix_timestamp_nanos() / 1_000_000),
+            ],
+        )?;
+        self.index_entry(conn, entry)?;
+        self.write_annotation(conn, entry.request.id, &entry.annotation)?;
+        Ok(())
+    }
+
+    pub(super) fn write_annotation(
+        &self,
+        conn: &Connection,
+        id: u64,
+        annotation: &Annotation,
+    ) -> Result<()> {
+        if annotation.is_empty() {
+            conn.execute(
+                "DELETE FROM annotations WHERE capture_id = ?1",
+                params![id as i64],
+            )?;
+            return Ok(());
+        }
+        // Notes may quote captured secrets; highlight and tags stay queryable
+        let notes = if annotation.notes.is_empty() {
+            None
+        } else {
+            Some(self.encrypt(annotation.notes.as_bytes())?)
+        };
+        conn.execute(
+            "INSERT OR REPLACE INTO annotations (capture_id, highlight, notes, tags) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                id as i64,
+                annotation.highlight.map(|h| h.as_str()),
+                notes,
+                serde_json::to_string(&annotation.tags)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn read_body(
+        &self,
+        conn: &Connection,
+        row: &Row<'_>,
+        inline_col: usize,
+        hash_col: usize,
+    ) -> Result<Vec<u8>> {
+        match row.get::<_, Option<String>>(hash_col)? {
+            Some(hash) => self.load_body(conn, &hash),
+            None => Ok(row
+                .get::<_, Option<Vec<u8>>>(inline_col)?
+                .map(|b| self.decrypt(&b))
+                .transpose()?
+                .unwrap_or_default()),
+        }
+    }
+
+    pub(super) fn read_entry(
+        &self,
+        conn: &Connection,
+        row: &Row<'_>,
+        with_bodies: bool,
+    ) -> Result<CaptureEntry> {
+        // Decrypt sensitive request data
+        let encrypted_headers: Vec<u8> = row.get(4)?;
+        let decrypted_headers = self.decrypt(&encrypted_headers)?;
+        let headers = serde_json::from_slice::<Vec<(String, String)>>(&decrypted_headers)?;
+
+        let body = if with_bodies {
+            self.read_body(conn, row, 5, 14)?
+        } else {
+            Vec::new()
+        };
+
+        let request = CapturedRequest {
+            id: row.get::<_, i64>(0)? as u64,
+            timestamp_ms: row.get::<_, i64>(1)? as i128,
+            method: row.get(2)?,
+            url: row.get(3)?,
+            headers,
+            body,
+            tls: row.get::<_, i64>(6)? == 1,
+            source: row
+                .get::<_, Option<String>>(18)?
+                .and_then(|s| s.parse().ok())
+                .unwrap_or_default(),
+        };
+
+        let response = match row.get::<_, Option<i64>>(7)? {
+            Some(status) => {
+                let encrypted_resp_headers: Option<Vec<u8>> = row.get(8)?;
+                let headers = match encrypted_resp_headers {
+                    Some(h) => serde_json::from_slice::<Vec<(String, String)>>(&self.decrypt(&h)?)?,
+                    None => Vec::new(),
+                };
+
+                let body = if with_bodies {
+                    self.read_body(conn, row, 9, 15)?
+                } else {
+                    Vec::new()
+                };
+
+                let duration_ms = row.get::<_, Option<i64>>(10)?.unwrap_or(0) as u128;
+                Some(CapturedResponse {
+                    request_id: request.id,
+                    status_code: status as u16,
+                    headers,
+                    body,
+                    duration_ms,
+                })
+            }
+            None => None,
+        };
+
+        let encrypted_notes: Option<Vec<u8>> = row.get(12)?;
+        let annotation = Annotation {
+            highlight: row
+                .get::<_, Option<String>>(11)?
+                .and_then(|h| h.parse().ok()),
+            notes: encrypted_notes
+                .map(|n| self.decrypt(&n))
+                .transpose()?
+                .map(|n| String::from_utf8_lossy(&n).into_owned())
+                .unwrap_or_default(),
+            tags: row
+                .get::<_, Option<String>>(13)?
+                .and_then(|t| serde_json::from_str(&t).ok())
+                .unwrap_or_default(),
+        };
+        let omitted_bodies = if with_bodies {
+            None
+        } else {
+            Some(BodySizes {
+                request: row.get::<_, i64>(16)? as u64,
+                response: row.get::<_, i64>(17)? as u64,
+            })
+        };
+        Ok(CaptureEntry {
+            request,
+            response,
+            annotation,
+            omitted_bodies,
+        })
+    }
+}
+
+pub(super) fn clamp_i128(value: i128) -> i64 {
+    value.clamp(i64::MIN as i128, i64::MAX as i128) as i64
+}
+
+pub(super) fn clamp_u128(value: u128) -> i64 {
+    min(value, i64::MAX as u128) as i64
+}
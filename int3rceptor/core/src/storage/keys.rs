@@ -0,0 +1,681 @@
+//! Passphrase-derived keys, key verification and key rotation for capture storage
+
+use super::codec::{Codec, SELECT_COLUMNS};
+use super::{migrations, CaptureStorage};
+use crate::database::{EncryptionKeyProvider, KeySource};
+use crate::error::{ProxyError, Result};
+use rand::Rng;
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+const KEY_CHECK_META: &str = "key_check";
+const KDF_SALT_META: &str = "kdf_salt";
+/// Key check value of the key being rotated to
+const ROTATION_TARGET_META: &str = "rotation_target";
+/// Highest capture id re-encrypted so far, or `done`
+const ROTATION_PROGRESS_META: &str = "rotation_progress";
+/// Salt of a new passphrase-derived key
+const ROTATION_SALT_META: &str = "rotation_salt";
+/// New master key encrypted under the old one, so a restart can resume
+const ROTATION_KEY_META: &str = "rotation_key";
+const ROTATION_DONE: &str = "done";
+
+/// Captures re-encrypted per transaction, short enough not to hold up the writer
+const ROTATION_BATCH: usize = 100;
+
+/// Replacement key for [`CaptureStorage::rotate_key`]
+#[derive(Debug, Clone)]
+pub enum NewKey {
+    /// Derived with Argon2 under a fresh salt
+    Passphrase(String),
+    Key([u8; 32]),
+}
+
+/// Rows re-encrypted by a key rotation
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RotationReport {
+    pub captures: u64,
+    pub annotations: u64,
+    pub bodies: u64,
+    /// Whether the new key was written back to the key file
+    pub key_saved: bool,
+}
+
+impl CaptureStorage {
+    /// Open storage whose key is derived from `passphrase`
+    ///
+    /// The salt is generated on first use and kept in the database.
+    pub fn with_passphrase(path: impl AsRef<Path>, passphrase: &str) -> Result<Self> {
+        Self::open_passphrase(path.as_ref(), passphrase, KeySource::Explicit)
+    }
+
+    pub(super) fn open_passphrase(
+        path: &Path,
+        passphrase: &str,
+        source: KeySource,
+    ) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let mut conn = Connection::open(path)?;
+        migrations::migrate(&mut conn)?;
+        let salt = match read_meta(&conn, KDF_SALT_META)? {
+            Some(salt) => parse_salt(&salt)?,
+            None => {
+                let salt = new_salt();
+                write_meta(&conn, KDF_SALT_META, &hex::encode(salt))?;
+                salt
+            }
+        };
+        let mut provider = EncryptionKeyProvider::from_passphrase(passphrase, &salt)?;
+        // A rotation to this passphrase may have stopped before recording its salt
+        if let Some(salt) = read_meta(&conn, ROTATION_SALT_META)? {
+            if read_meta(&conn, KEY_CHECK_META)? != Codec::new(provider.clone()).check_value() {
+                provider = EncryptionKeyProvider::from_passphrase(passphrase, &parse_salt(&salt)?)?;
+            }
+        }
+        drop(conn);
+        Self::with_key_provider(path, provider.with_source(source))
+    }
+
+    pub fn is_rotating_key(&self) -> bool {
+        self.rotating.load(Ordering::SeqCst)
+    }
+
+    /// Where the storage key is loaded from, and so where a rotated key goes
+    pub fn key_source(&self) -> KeySource {
+        self.codec.read().encryption().source().clone()
+    }
+
+    /// Re-encrypt every row under `new_key` and switch to it
+    ///
+    /// Rows are re-encrypted in short batches while the storage stays in use;
+    /// until the last one, reads accept either key and writes use the new one.
+    /// Progress is kept in the database, so an interrupted rotation resumes
+    /// the next time it is opened with the old key. A key loaded from a file
+    /// is replaced there; other sources have to be updated before restarting.
+    pub fn rotate_key(&self, new_key: NewKey) -> Result<RotationReport> {
+        if self.rotating.swap(true, Ordering::SeqCst) {
+            return Err(ProxyError::AlreadyExists(
+                "key rotation in progress".to_string(),
+            ));
+        }
+        let result = self.rotate(new_key);
+        self.rotating.store(false, Ordering::SeqCst);
+        result
+    }
+
+    fn rotate(&self, new_key: NewKey) -> Result<RotationReport> {
+        if self.codec.read().previous().is_some() {
+            return Err(ProxyError::AlreadyExists(
+                "an interrupted key rotation resumes when storage is reopened".to_string(),
+            ));
+        }
+        let (provider, salt) = match new_key {
+            NewKey::Passphrase(passphrase) => {
+                let salt = new_salt();
+                (
+                    EncryptionKeyProvider::from_passphrase(&passphrase, &salt)?,
+                    Some(salt),
+                )
+            }
+            NewKey::Key(key) => (EncryptionKeyProvider::from_key(key), None),
+        };
+        self.begin_rotation(provider, salt)?;
+        self.run_rotation()
+    }
+
+    /// Record the rotation and start writing under the new key
+    fn begin_rotation(
+        &self,
+        provider: EncryptionKeyProvider,
+        salt: Option<[u8; 16]>,
+    ) -> Result<()> {
+        let mut conn = self.connect()?;
+        let mut codec = self.codec.write();
+        let new = Codec::new(provider.with_source(codec.encryption().source().clone()));
+        let target = new
+            .check_value()
+            .ok_or_else(|| ProxyError::internal("rotation target key is not enabled"))?;
+
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        write_meta(&tx, ROTATION_TARGET_META, &target)?;
+        write_meta(&tx, ROTATION_PROGRESS_META, "0")?;
+        match salt {
+            Some(salt) => write_meta(&tx, ROTATION_SALT_META, &hex::encode(salt))?,
+            None => delete_meta(&tx, ROTATION_SALT_META)?,
+        }
+        // Without an old key the database can only be resumed with the new one
+        if codec.encryption().is_enabled() {
+            let sealed = codec.encrypt(new.encryption().key())?;
+            write_meta(&tx, ROTATION_KEY_META, &hex::encode(sealed))?;
+        } else {
+            delete_meta(&tx, ROTATION_KEY_META)?;
+        }
+        tx.commit()?;
+
+        *codec = new.rotating_from(codec.clone());
+        Ok(())
+    }
+
+    /// Re-encrypt the remaining rows, save the new key and finish the rotation
+    fn run_rotation(&self) -> Result<RotationReport> {
+        let mut conn = self.connect()?;
+        let mut report = RotationReport::default();
+        while rotate_batch(&mut conn, &self.codec.read(), ROTATION_BATCH, &mut report)? {}
+
+        // Bodies written before the rotation that nothing refers to any more
+        self.collect_orphan_bodies(&conn)?;
+        let new = self.codec.read().clone().settled();
+        report.key_saved = new.encryption().source().store(new.encryption().key())?;
+        if !report.key_saved {
+            tracing::warn!(
+                source = ?new.encryption().source(),
+                "Rotated storage key was not saved; configure it before restarting"
+            );
+        }
+        finish_rotation(&conn, &new)?;
+        *self.codec.write() = new;
+        tracing::info!(
+            captures = report.captures,
+            bodies = report.bodies,
+            "Rotated capture storage key"
+        );
+        Ok(report)
+    }
+
+    /// Continue a rotation found on open in the background
+    pub(super) fn resume_rotation(&self) {
+        self.rotating.store(true, Ordering::SeqCst);
+        let storage = self.detached();
+        std::thread::spawn(move || {
+            if let Err(err) = storage.run_rotation() {
+                tracing::error!("Resumed storage key rotation failed: {}", err);
+            }
+            storage.rotating.store(false, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Pick up a rotation interrupted in an earlier run
+///
+/// Returns whether the rotation still has to run. Opening with the old key
+/// switches `codec` to read both keys; opening with the new key finishes a
+/// rotation that had already re-encrypted everything.
+pub(super) fn open_rotation(conn: &Connection, codec: &mut Codec) -> Result<bool> {
+    let Some(target) = read_meta(conn, ROTATION_TARGET_META)? else {
+        return Ok(false);
+    };
+    let done = read_meta(conn, ROTATION_PROGRESS_META)?.as_deref() == Some(ROTATION_DONE);
+    let current = codec.check_value();
+    if current.as_deref() == Some(target.as_str()) {
+        if done {
+            finish_rotation(conn, codec)?;
+            return Ok(false);
+        }
+        if read_meta(conn, KEY_CHECK_META)?.is_some() {
+            return Err(ProxyError::StorageKeyMismatch(
+                "a key rotation was interrupted; open with the previous key to resume it"
+                    .to_string(),
+            ));
+        }
+        // Rotating away from no encryption: the old rows need no key
+        *codec = codec
+            .clone()
+            .rotating_from(Codec::new(EncryptionKeyProvider::disabled()));
+        return Ok(true);
+    }
+    if current != read_meta(conn, KEY_CHECK_META)? {
+        // Neither key; verify_key reports it
+        return Ok(false);
+    }
+    let sealed = read_meta(conn, ROTATION_KEY_META)?
+        .and_then(|sealed| hex::decode(sealed).ok())
+        .ok_or_else(|| {
+            ProxyError::StorageKeyMismatch(
+                "a key rotation was interrupted; open with the new key to resume it".to_string(),
+            )
+        })?;
+    let key: [u8; 32] = codec
+        .decrypt(&sealed)?
+        .try_into()
+        .map_err(|_| ProxyError::internal("stored rotation key is malformed"))?;
+    let source = codec.encryption().source().clone();
+    let new = Codec::new(EncryptionKeyProvider::from_key(key).with_source(source));
+    *codec = new.rotating_from(codec.clone());
+    Ok(true)
+}
+
+/// Re-encrypt the next batch of captures in its own transaction
+///
+/// Returns false once no captures are left.
+fn rotate_batch(
+    conn: &mut Connection,
+    codec: &Codec,
+    limit: usize,
+    report: &mut RotationReport,
+) -> Result<bool> {
+    let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    let progress = read_meta(&tx, ROTATION_PROGRESS_META)?;
+    let Some(marker) = progress.and_then(|p| p.parse::<i64>().ok()) else {
+        return Ok(false);
+    };
+    let recrypt = |data: &[u8]| -> Result<Vec<u8>> { codec.encrypt(&codec.decrypt(data)?) };
+
+    let captures = tx
+        .prepare(
+            "SELECT id, headers, resp_headers, body, resp_body, body_hash, resp_body_hash \
+             FROM captures WHERE id > ?1 ORDER BY id LIMIT ?2",
+        )?
+        .query_map(params![marker, limit as i64], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, Option<Vec<u8>>>(2)?,
+                row.get::<_, Option<Vec<u8>>>(3)?,
+                row.get::<_, Option<Vec<u8>>>(4)?,
+                row.get::<_, Option<String>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+            ))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    // The last batch also takes notes on ids past every capture
+    let last = match captures.last() {
+        Some(row) => row.0,
+        None => i64::MAX,
+    };
+
+    let mut old_hashes = Vec::new();
+    for (id, headers, resp_headers, body, resp_body, body_hash, resp_body_hash) in &captures {
+        let mut rehash = |hash: &Option<String>| -> Result<Option<String>> {
+            let Some(hash) = hash else {
+                return Ok(None);
+            };
+            old_hashes.push(hash.clone());
+            rehash_body(&tx, codec, hash, report)
+        };
+        let body_hash = rehash(body_hash)?;
+        let resp_body_hash = rehash(resp_body_hash)?;
+        tx.execute(
+            "UPDATE captures SET headers = ?1, resp_headers = ?2, body = ?3, resp_body = ?4, \
+             body_hash = ?5, resp_body_hash = ?6 WHERE id = ?7",
+            params![
+                recrypt(headers)?,
+                resp_headers.as_deref().map(recrypt).transpose()?,
+                body.as_deref().map(recrypt).transpose()?,
+                resp_body.as_deref().map(recrypt).transpose()?,
+                body_hash,
+                resp_body_hash,
+                id
+            ],
+        )?;
+        report.captures += 1;
+    }
+    for hash in old_hashes {
+        tx.execute(
+            "DELETE FROM bodies WHERE hash = ?1 \
+             AND NOT EXISTS (SELECT 1 FROM captures WHERE body_hash = ?1 OR resp_body_hash = ?1)",
+            params![hash],
+        )?;
+    }
+
+    let notes = tx
+        .prepare(
+            "SELECT capture_id, notes FROM annotations \
+             WHERE notes IS NOT NULL AND capture_id > ?1 AND capture_id <= ?2",
+        )?
+        .query_map(params![marker, last], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+    for (id, notes) in notes {
+        tx.execute(
+            "UPDATE annotations SET notes = ?1 WHERE capture_id = ?2",
+            params![recrypt(&notes)?, id],
+        )?;
+        report.annotations += 1;
+    }
+
+    // Index tokens are keyed hashes; rebuild them from the re-encrypted rows
+    {
+        let mut stmt = tx.prepare(&format!(
+            "{} WHERE captures.id > ?1 AND captures.id <= ?2",
+            SELECT_COLUMNS
+        ))?;
+        let mut rows = stmt.query(params![marker, last])?;
+        while let Some(row) = rows.next()? {
+            let entry = codec.read_entry(&tx, row, true)?;
+            codec.index_entry(&tx, &entry)?;
+        }
+    }
+
+    let more = !captures.is_empty();
+    let progress = if more {
+        last.to_string()
+    } else {
+        ROTATION_DONE.to_string()
+    };
+    write_meta(&tx, ROTATION_PROGRESS_META, &progress)?;
+    tx.commit()?;
+    Ok(more)
+}
+
+/// Move a body to its hash under the new key, returning that hash
+fn rehash_body(
+    conn: &Connection,
+    codec: &Codec,
+    hash: &str,
+    report: &mut RotationReport,
+) -> Result<Option<String>> {
+    let content = codec.load_body(conn, hash)?;
+    let new_hash = codec.body_hash(&content);
+    if new_hash == hash {
+        // Written after the rotation started
+        return Ok(Some(new_hash));
+    }
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM bodies WHERE hash = ?1",
+        params![new_hash],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        report.bodies += 1;
+    }
+    codec.store_body(conn, &content)
+}
+
+/// Make the new key the recorded one and drop the rotation state
+fn finish_rotation(conn: &Connection, codec: &Codec) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    match read_meta(&tx, ROTATION_SALT_META)? {
+        Some(salt) => write_meta(&tx, KDF_SALT_META, &salt)?,
+        None => delete_meta(&tx, KDF_SALT_META)?,
+    }
+    match codec.check_value() {
+        Some(check) => write_meta(&tx, KEY_CHECK_META, &check)?,
+        None => delete_meta(&tx, KEY_CHECK_META)?,
+    }
+    for key in [
+        ROTATION_TARGET_META,
+        ROTATION_PROGRESS_META,
+        ROTATION_SALT_META,
+        ROTATION_KEY_META,
+    ] {
+        delete_meta(&tx, key)?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// Fail unless the configured key is the one the database was written with
+pub(super) fn verify_key(conn: &Connection, codec: &Codec) -> Result<()> {
+    if codec.previous().is_some() {
+        // open_rotation already matched it against the rotation state
+        return Ok(());
+    }
+    let stored = read_meta(conn, KEY_CHECK_META)?;
+    let current = codec.check_value();
+    match (&stored, &current) {
+        (Some(stored), Some(current)) if stored == current => return Ok(()),
+        (Some(_), Some(_)) => {
+            return Err(ProxyError::StorageKeyMismatch(
+                "the configured key is not the one this database was encrypted with".to_string(),
+            ))
+        }
+        (Some(_), None) => {
+            return Err(ProxyError::StorageKeyMismatch(
+                "the database is encrypted but no encryption key is configured".to_string(),
+            ))
+        }
+        _ => {}
+    }
+
+    // Databases from before key checks: make sure stored rows decode first
+    let sample: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT headers FROM captures ORDER BY id DESC LIMIT 1",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(headers) = sample {
+        let decoded = codec
+            .decrypt(&headers)
+            .ok()
+            .and_then(|h| serde_json::from_slice::<Vec<(String, String)>>(&h).ok());
+        if decoded.is_none() {
+            return Err(ProxyError::StorageKeyMismatch(
+                "stored captures cannot be decoded with the configured key".to_string(),
+            ));
+        }
+    }
+    if let Some(current) = current {
+        write_meta(conn, KEY_CHECK_META, &current)?;
+    }
+    Ok(())
+}
+
+fn new_salt() -> [u8; 16] {
+    let mut salt = [0u8; 16];
+    rand::thread_rng().fill(&mut salt);
+    salt
+}
+
+fn parse_salt(value: &str) -> Result<[u8; 16]> {
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| ProxyError::internal("stored key derivation salt is malformed"))
+}
+
+fn read_meta(conn: &Connection, key: &str) -> Result<Option<String>> {
+    Ok(conn
+        .query_row(
+            "SELECT value FROM meta WHERE key = ?1",
+            params![key],
+            |row| row.get(0),
+        )
+        .optional()?)
+}
+
+fn write_meta(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+fn delete_meta(conn: &Connection, key: &str) -> Result<()> {
+    conn.execute("DELETE FROM meta WHERE key = ?1", params![key])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::{
+        Annotation, CaptureEntry, CaptureQuery, CapturedRequest, CapturedResponse,
+    };
+    use crate::search::SearchExpr;
+    use tempfile::tempdir;
+
+    fn entry(id: u64, body: &[u8]) -> CaptureEntry {
+        let mut request = CapturedRequest::new("POST", "https://bank.test/transfer", true);
+        request.id = id;
+        request.headers = vec![("authorization".to_string(), "Bearer secret".to_string())];
+        request.body = body.to_vec();
+        CaptureEntry {
+            request,
+            response: Some(CapturedResponse {
+                request_id: id,
+                status_code: 200,
+                headers: vec![],
+                body: b"transfer accepted".to_vec(),
+                duration_ms: 5,
+            }),
+            annotation: Annotation {
+                notes: "check the amount".to_string(),
+                ..Default::default()
+            },
+            omitted_bodies: None,
+        }
+    }
+
+    #[test]
+    fn test_wrong_key_is_rejected() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("keyed.db");
+        let storage =
+            CaptureStorage::with_key_provider(&db_path, EncryptionKeyProvider::from_key([1; 32]))
+                .unwrap();
+        storage.insert(&entry(1, b"amount=10")).unwrap();
+        drop(storage);
+
+        let err =
+            CaptureStorage::with_key_provider(&db_path, EncryptionKeyProvider::from_key([2; 32]))
+                .unwrap_err();
+        assert!(matches!(err, ProxyError::StorageKeyMismatch(_)));
+        let err = CaptureStorage::new_unencrypted(&db_path).unwrap_err();
+        assert!(matches!(err, ProxyError::StorageKeyMismatch(_)));
+    }
+
+    #[test]
+    fn test_passphrase_reopens_database() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("pass.db");
+        let storage = CaptureStorage::with_passphrase(&db_path, "correct horse").unwrap();
+        storage.insert(&entry(1, b"amount=10")).unwrap();
+        drop(storage);
+
+        let storage = CaptureStorage::with_passphrase(&db_path, "correct horse").unwrap();
+        assert_eq!(storage.get(1).unwrap().unwrap().request.body, b"amount=10");
+        drop(storage);
+        assert!(CaptureStorage::with_passphrase(&db_path, "battery staple").is_err());
+    }
+
+    #[test]
+    fn test_rotate_key_reencrypts_rows() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("rotate.db");
+        let storage =
+            CaptureStorage::with_key_provider(&db_path, EncryptionKeyProvider::from_key([1; 32]))
+                .unwrap();
+        storage.insert(&entry(1, b"amount=10")).unwrap();
+        storage.insert(&entry(2, &vec![b'a'; 10_000])).unwrap();
+
+        let report = storage
+            .rotate_key(NewKey::Passphrase("new passphrase".to_string()))
+            .unwrap();
+        assert_eq!(report.captures, 2);
+        assert_eq!(report.annotations, 2);
+        assert_eq!(report.bodies, 3);
+        assert!(!report.key_saved);
+
+        // The live handle keeps working under the new key
+        assert_eq!(storage.get(1).unwrap().unwrap().request.body, b"amount=10");
+        let query = CaptureQuery {
+            expr: Some(SearchExpr::parse("body:accepted").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&query).unwrap().len(), 2);
+        drop(storage);
+
+        assert!(CaptureStorage::with_key_provider(
+            &db_path,
+            EncryptionKeyProvider::from_key([1; 32])
+        )
+        .is_err());
+        let storage = CaptureStorage::with_passphrase(&db_path, "new passphrase").unwrap();
+        let rotated = storage.get(2).unwrap().unwrap();
+        assert_eq!(rotated.request.body.len(), 10_000);
+        assert_eq!(rotated.annotation.notes, "check the amount");
+    }
+
+    #[test]
+    fn test_rotated_key_is_saved_to_key_file() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("rotate.db");
+        let key_path = dir.path().join("interceptor_key");
+        std::fs::write(&key_path, hex::encode([1u8; 32])).unwrap();
+        let open = || {
+            CaptureStorage::with_key_provider(
+                &db_path,
+                EncryptionKeyProvider::from_file(&key_path).unwrap(),
+            )
+        };
+        let storage = open().unwrap();
+        storage.insert(&entry(1, b"amount=10")).unwrap();
+
+        let report = storage.rotate_key(NewKey::Key([2; 32])).unwrap();
+        assert!(report.key_saved);
+        assert_eq!(
+            std::fs::read_to_string(&key_path).unwrap(),
+            hex::encode([2u8; 32])
+        );
+        drop(storage);
+
+        // A restart reads the new key from the same file
+        let storage = open().unwrap();
+        assert_eq!(storage.get(1).unwrap().unwrap().request.body, b"amount=10");
+    }
+
+    #[test]
+    fn test_interrupted_rotation_resumes_on_open() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("resume.db");
+        let open = |key: [u8; 32]| {
+            CaptureStorage::with_key_provider(&db_path, EncryptionKeyProvider::from_key(key))
+        };
+        let storage = open([1; 32]).unwrap();
+        for id in 1..=3 {
+            storage
+                .insert(&entry(id, format!("amount={id}").as_bytes()))
+                .unwrap();
+        }
+
+        // Stop after the first batch, as if the process had been killed
+        storage
+            .begin_rotation(EncryptionKeyProvider::from_key([2; 32]), None)
+            .unwrap();
+        let mut conn = storage.connect().unwrap();
+        let mut report = RotationReport::default();
+        assert!(rotate_batch(&mut conn, &storage.codec.read(), 1, &mut report).unwrap());
+        assert_eq!(report.captures, 1);
+
+        // Mixed rows stay readable and searchable in the meantime
+        storage.insert(&entry(4, b"amount=4")).unwrap();
+        let query = CaptureQuery {
+            expr: Some(SearchExpr::parse("body:accepted").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&query).unwrap().len(), 4);
+        drop(storage);
+        assert!(matches!(
+            open([2; 32]),
+            Err(ProxyError::StorageKeyMismatch(_))
+        ));
+
+        let storage = open([1; 32]).unwrap();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+        while storage.is_rotating_key() {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "rotation did not finish"
+            );
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(storage.get(3).unwrap().unwrap().request.body, b"amount=3");
+        drop(storage);
+
+        assert!(open([1; 32]).is_err());
+        let storage = open([2; 32]).unwrap();
+        for id in 1..=4 {
+            let stored = storage.get(id).unwrap().unwrap();
+            assert_eq!(stored.request.body, format!("amount={id}").as_bytes());
+        }
+        assert_eq!(storage.query(&query).unwrap().len(), 4);
+    }
+}
@@ -0,0 +1,333 @@
+//! Versioned schema migrations for the capture database
+//!
+//! The schema version lives in SQLite's `user_version`. Each step runs in its
+//! own transaction together with the version bump, so an interrupted upgrade
+//! resumes from the last completed step. Steps only change the schema; data
+//! derived from older rows is filled in by `CaptureStorage::init` afterwards.
+
+use crate::error::{ProxyError, Result};
+use rusqlite::{params, Connection, Transaction};
+
+struct Migration {
+    version: u32,
+    description: &'static str,
+    up: fn(&Transaction<'_>) -> rusqlite::Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "initial capture schema",
+        up: initial_schema,
+    },
+    Migration {
+        version: 2,
+        description: "full-text index",
+        up: full_text_index,
+    },
+    Migration {
+        version: 3,
+        description: "sort keys and capture sizes",
+        up: sort_keys,
+    },
+    Migration {
+        version: 4,
+        description: "annotations",
+        up: annotations,
+    },
+    Migration {
+        version: 5,
+        description: "content-addressed bodies",
+        up: content_addressed_bodies,
+    },
+    Migration {
+        version: 6,
+        description: "compressed bodies",
+        up: compressed_bodies,
+    },
+    Migration {
+        version: 7,
+        description: "capture source",
+        up: capture_source,
+    },
+    Migration {
+        version: 8,
+        description: "insertion time",
+        up: insertion_time,
+    },
+];
+
+/// Schema version written by this release
+pub const SCHEMA_VERSION: u32 = MIGRATIONS[MIGRATIONS.len() - 1].version;
+
+pub fn schema_version(conn: &Connection) -> Result<u32> {
+    Ok(conn.pragma_query_value(None, "user_version", |row| row.get(0))?)
+}
+
+/// Upgrade the database to `SCHEMA_VERSION`, returning the version it had
+pub fn migrate(conn: &mut Connection) -> Result<u32> {
+    let from = schema_version(conn)?;
+    if from > SCHEMA_VERSION {
+        return Err(ProxyError::db_migration(
+            from,
+            format!("database was written by a newer release (this one supports up to v{SCHEMA_VERSION})"),
+        ));
+    }
+    for migration in MIGRATIONS.iter().filter(|m| m.version > from) {
+        let tx = conn.transaction()?;
+        (migration.up)(&tx).map_err(|err| {
+            ProxyError::db_migration(
+                migration.version,
+                format!("{}: {err}", migration.description),
+            )
+        })?;
+        tx.pragma_update(None, "user_version", migration.version)?;
+        tx.commit()?;
+        tracing::info!(
+            version = migration.version,
+            "applied capture schema migration: {}",
+            migration.description
+        );
+    }
+    Ok(from)
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get(0),
+    )
+}
+
+/// Unversioned development builds may already have the column
+fn add_column(conn: &Connection, table: &str, column: &str, ty: &str) -> rusqlite::Result<()> {
+    if !has_column(conn, table, column)? {
+        conn.execute(&format!("ALTER TABLE {table} ADD COLUMN {column} {ty}"), [])?;
+    }
+    Ok(())
+}
+
+/// The schema shipped before versioning; `IF NOT EXISTS` adopts those databases
+fn initial_schema(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS captures (
+            id INTEGER PRIMARY KEY,
+            timestamp_ms INTEGER NOT NULL,
+            method TEXT NOT NULL,
+            url TEXT NOT NULL,
+            headers TEXT NOT NULL,
+            body BLOB,
+            tls INTEGER NOT NULL,
+            resp_status INTEGER,
+            resp_headers TEXT,
+            resp_body BLOB,
+            duration_ms INTEGER
+        );
+        CREATE INDEX IF NOT EXISTS idx_captures_method ON captures(method);
+        CREATE INDEX IF NOT EXISTS idx_captures_url ON captures(url);
+        CREATE INDEX IF NOT EXISTS idx_captures_status ON captures(resp_status);
+        "#,
+    )
+}
+
+fn full_text_index(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS capture_fts USING fts5(
+            headers,
+            body,
+            tokenize = 'unicode61 remove_diacritics 0'
+        );
+        "#,
+    )
+}
+
+fn sort_keys(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    add_column(tx, "captures", "size_bytes", "INTEGER")?;
+    tx.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_captures_timestamp ON captures(timestamp_ms);
+        CREATE INDEX IF NOT EXISTS idx_captures_duration ON captures(COALESCE(duration_ms, -1));
+        CREATE INDEX IF NOT EXISTS idx_captures_status_key ON captures(COALESCE(resp_status, -1));
+        CREATE INDEX IF NOT EXISTS idx_captures_size ON captures(COALESCE(size_bytes, 0));
+        "#,
+    )
+}
+
+fn annotations(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS annotations (
+            capture_id INTEGER PRIMARY KEY,
+            highlight TEXT,
+            notes BLOB,
+            tags TEXT NOT NULL DEFAULT '[]'
+        );
+        CREATE INDEX IF NOT EXISTS idx_annotations_highlight ON annotations(highlight);
+        "#,
+    )
+}
+
+fn content_addressed_bodies(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    tx.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS bodies (
+            hash TEXT PRIMARY KEY,
+            data BLOB NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS meta (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        "#,
+    )?;
+    add_column(tx, "captures", "body_hash", "TEXT")?;
+    add_column(tx, "captures", "resp_body_hash", "TEXT")?;
+    tx.execute_batch(
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_captures_body_hash ON captures(body_hash);
+        CREATE INDEX IF NOT EXISTS idx_captures_resp_body_hash ON captures(resp_body_hash);
+        "#,
+    )
+}
+
+fn compressed_bodies(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    add_column(tx, "bodies", "encoding", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column(tx, "bodies", "size", "INTEGER")
+}
+
+/// Rows from before this step were all proxied, which a NULL source reads as
+fn capture_source(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    add_column(tx, "captures", "source", "TEXT")?;
+    tx.execute_batch("CREATE INDEX IF NOT EXISTS idx_captures_source ON captures(source);")
+}
+
+/// When a row was written, which imported captures do not share with
+/// `timestamp_ms`; rows from before this step fall back to their timestamp
+fn insertion_time(tx: &Transaction<'_>) -> rusqlite::Result<()> {
+    add_column(tx, "captures", "inserted_at_ms", "INTEGER")?;
+    tx.execute_batch(
+        "CREATE INDEX IF NOT EXISTS idx_captures_inserted_at \
+         ON captures(COALESCE(inserted_at_ms, timestamp_ms), id);",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::capture::CaptureQuery;
+    use crate::storage::CaptureStorage;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_versions_are_ordered() {
+        for pair in MIGRATIONS.windows(2) {
+            assert_eq!(pair[1].version, pair[0].version + 1);
+        }
+        assert_eq!(MIGRATIONS[0].version, 1);
+    }
+
+    #[test]
+    fn test_upgrades_unversioned_database() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("old.db");
+        {
+            // Schema and row as written by releases before versioning
+            let mut conn = Connection::open(&db_path).unwrap();
+            let tx = conn.transaction().unwrap();
+            initial_schema(&tx).unwrap();
+            tx.commit().unwrap();
+            conn.execute(
+                "INSERT INTO captures VALUES (7, 1700000000000, 'GET', 'https://old.test/', ?1, ?2, 1, 200, ?1, ?3, 12)",
+                params![b"[]".to_vec(), b"ping".to_vec(), b"pong".to_vec()],
+            )
+            .unwrap();
+            assert_eq!(schema_version(&conn).unwrap(), 0);
+        }
+
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+        assert_eq!(storage.schema_version().unwrap(), SCHEMA_VERSION);
+        let entry = storage.get(7).unwrap().unwrap();
+        assert_eq!(entry.request.body, b"ping");
+        assert_eq!(entry.response.unwrap().body, b"pong");
+        let query = CaptureQuery {
+            expr: Some(crate::SearchExpr::parse("body:pong").unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&query).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_moves_inline_bodies_in_batches() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("old.db");
+        {
+            let mut conn = Connection::open(&db_path).unwrap();
+            let tx = conn.transaction().unwrap();
+            initial_schema(&tx).unwrap();
+            for id in 1..=600i64 {
+                tx.execute(
+                    "INSERT INTO captures VALUES (?1, 1700000000000, 'GET', 'https://old.test/', ?2, ?3, 1, 200, ?2, ?4, 12)",
+                    params![id, b"[]".to_vec(), format!("req{id}").into_bytes(), b"pong".to_vec()],
+                )
+                .unwrap();
+            }
+            tx.commit().unwrap();
+        }
+
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+        let inline: i64 = storage
+            .connect()
+            .unwrap()
+            .query_row(
+                "SELECT COUNT(*) FROM captures WHERE body IS NOT NULL OR resp_body IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(inline, 0);
+        assert_eq!(storage.get(600).unwrap().unwrap().request.body, b"req600");
+        assert_eq!(storage.stats().unwrap().stored_bodies, 601);
+    }
+
+    #[test]
+    fn test_damaged_rows_fail_the_upgrade() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("old.db");
+        {
+            let mut conn = Connection::open(&db_path).unwrap();
+            let tx = conn.transaction().unwrap();
+            initial_schema(&tx).unwrap();
+            tx.commit().unwrap();
+            conn.execute(
+                "INSERT INTO captures VALUES (1, 1700000000000, 'GET', 'https://old.test/', ?1, NULL, 1, NULL, NULL, NULL, NULL)",
+                params![b"not json".to_vec()],
+            )
+            .unwrap();
+        }
+
+        // Skipping the row would leave it to be retried on every open
+        assert!(CaptureStorage::new_unencrypted(&db_path).is_err());
+    }
+
+    #[test]
+    fn test_migrate_is_idempotent() {
+        let dir = tempdir().unwrap();
+        let mut conn = Connection::open(dir.path().join("new.db")).unwrap();
+        assert_eq!(migrate(&mut conn).unwrap(), 0);
+        assert_eq!(migrate(&mut conn).unwrap(), SCHEMA_VERSION);
+        assert!(has_column(&conn, "bodies", "encoding").unwrap());
+    }
+
+    #[test]
+    fn test_rejects_newer_schema() {
+        let dir = tempdir().unwrap();
+        let mut conn = Connection::open(dir.path().join("future.db")).unwrap();
+        conn.pragma_update(None, "user_version", SCHEMA_VERSION + 1)
+            .unwrap();
+        let err = migrate(&mut conn).unwrap_err();
+        assert!(matches!(err, ProxyError::DatabaseMigration { .. }));
+    }
+}
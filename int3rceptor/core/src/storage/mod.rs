@@ -0,0 +1,1292 @@
+pub mod keys;
+pub mod migrations;
+
+mod codec;
+
+use crate::capture::{Annotation, CaptureEntry, CaptureQuery, CaptureSort, SortOrder};
+use crate::database::{EncryptionKeyProvider, KeySource, PASSPHRASE_ENV};
+use crate::error::{ProxyError, Result};
+use crate::metrics::metrics;
+use codec::{Codec, SELECT_COLUMNS};
+use once_cell::sync::OnceCell;
+use parking_lot::{Mutex, MutexGuard, RwLock};
+use rusqlite::types::Value;
+use rusqlite::{params, params_from_iter, Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Captures waiting for the writer before new ones are dropped
+const WRITE_QUEUE_CAPACITY: usize = 4_096;
+/// Most captures committed in one transaction
+const WRITE_BATCH_MAX: usize = 256;
+/// Rows read per page when search results are re-checked after SQL
+const POST_FILTER_BATCH: usize = 200;
+/// Longest wait between attempts to commit a batch while the database is locked
+const BUSY_BACKOFF_MAX: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct CaptureStorage {
+    path: PathBuf,
+    /// Replaced as a whole when the key is rotated; take it after `connect`
+    codec: Arc<RwLock<Codec>>,
+    /// Started on the first `enqueue`
+    writer: OnceCell<StorageWriter>,
+    rotating: Arc<AtomicBool>,
+    /// Held by the writer while it commits, and by maintenance it must not interleave with
+    maintenance: Arc<Mutex<()>>,
+}
+
+impl CaptureStorage {
+    /// Open storage, taking the key or passphrase from the environment
+    pub fn new(path: impl AsRef<Path>) -> Result<Self> {
+        if let Some(passphrase) = EncryptionKeyProvider::passphrase_from_env() {
+            return Self::open_passphrase(
+                path.as_ref(),
+                &passphrase,
+                KeySource::Env(PASSPHRASE_ENV),
+            );
+        }
+        Self::with_key_provider(path, EncryptionKeyProvider::new()?)
+    }
+
+    /// Create storage with optional encryption disabled (testing only)
+    pub fn new_unencrypted(path: impl AsRef<Path>) -> Result<Self> {
+        Self::with_key_provider(path, EncryptionKeyProvider::default())
+    }
+
+    pub fn with_key_provider(
+        path: impl AsRef<Path>,
+        encryption: EncryptionKeyProvider,
+    ) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let storage = Self {
+            path,
+            codec: Arc::new(RwLock::new(Codec::new(encryption))),
+            writer: OnceCell::new(),
+            rotating: Arc::new(AtomicBool::new(false)),
+            maintenance: Arc::new(Mutex::new(())),
+        };
+        storage.init()?;
+        Ok(storage)
+    }
+
+    /// Same database and keys, without a writer of its own
+    fn detached(&self) -> Self {
+        Self {
+            path: self.path.clone(),
+            codec: self.codec.clone(),
+            writer: OnceCell::new(),
+            rotating: self.rotating.clone(),
+            maintenance: self.maintenance.clone(),
+        }
+    }
+
+    pub(crate) fn connect(&self) -> Result<Connection> {
+        // Readers and other writers see everything queued before them
+        if let Some(writer) = self.writer.get() {
+            writer.flush();
+        }
+        self.open()
+    }
+
+    /// Commit everything queued, then hold the writer off until the guard is dropped
+    ///
+    /// Use the returned connection while paused: `connect` would wait on the
+    /// paused writer for captures queued in the meantime.
+    pub(crate) fn pause_writer(&self) -> Result<(MutexGuard<'_, ()>, Connection)> {
+        if let Some(writer) = self.writer.get() {
+            writer.flush();
+        }
+        let paused = self.maintenance.lock();
+        Ok((paused, self.open()?))
+    }
+
+    fn open(&self) -> Result<Connection> {
+        let conn = Connection::open_with_flags(
+            &self.path,
+            OpenFlags::SQLITE_OPEN_CREATE | OpenFlags::SQLITE_OPEN_READ_WRITE,
+        )?;
+        conn.busy_timeout(Duration::from_secs(1))?;
+        Ok(conn)
+    }
+
+    fn init(&self) -> Result<()> {
+        let mut conn = self.connect()?;
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+        migrations::migrate(&mut conn)?;
+        let resume = keys::open_rotation(&conn, &mut self.codec.write())?;
+        let codec = self.codec.read();
+        keys::verify_key(&conn, &codec)?;
+        // Fill in derived data for rows written by older releases
+        codec.backfill_body_sizes(&mut conn)?;
+        codec.backfill_sizes(&mut conn)?;
+        codec.migrate_inline_bodies(&mut conn)?;
+        codec.reindex_missing(&mut conn)?;
+        drop(codec);
+        if resume {
+            self.resume_rotation();
+        }
+        Ok(())
+    }
+
+    /// Schema version of the open database
+    pub fn schema_version(&self) -> Result<u32> {
+        migrations::schema_version(&self.connect()?)
+    }
+
+    /// Delete bodies no longer referenced by any capture
+    pub(crate) fn collect_orphan_bodies(&self, conn: &Connection) -> Result<usize> {
+        Ok(conn.execute(
+            r#"
+            DELETE FROM bodies
+            WHERE hash NOT IN (SELECT body_hash FROM captures WHERE body_hash IS NOT NULL)
+              AND hash NOT IN (SELECT resp_body_hash FROM captures WHERE resp_body_hash IS NOT NULL)
+            "#,
+            [],
+        )?)
+    }
+
+    pub fn insert(&self, entry: &CaptureEntry) -> Result<()> {
+        let mut conn = self.connect()?;
+        let codec = self.codec.read();
+        let tx = conn.transaction()?;
+        codec.write_entry(&tx, entry)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Write entries in one transaction, for bulk imports that must not drop rows
+    pub fn insert_many(&self, entries: &[CaptureEntry]) -> Result<()> {
+        let mut conn = self.connect()?;
+        self.write_batch(&mut conn, entries)
+    }
+
+    /// Queue an entry for the background writer without blocking
+    ///
+    /// If the queue is full the entry is only kept in memory and counted
+    /// as a dropped write.
+    pub fn enqueue(&self, entry: CaptureEntry) {
+        match self
+            .writer
+            .get_or_try_init(|| StorageWriter::spawn(self.detached()))
+        {
+            Ok(writer) => writer.send(entry),
+            Err(err) => {
+                tracing::warn!("capture_writer_error" = %err);
+                if let Err(err) = self.insert(&entry) {
+                    tracing::warn!("persist_capture_error" = %err);
+                }
+            }
+        }
+    }
+
+    /// Write several entries in one transaction
+    fn write_batch(&self, conn: &mut Connection, entries: &[CaptureEntry]) -> Result<()> {
+        let codec = self.codec.read();
+        let tx = conn.transaction()?;
+        for entry in entries {
+            codec.write_entry(&tx, entry)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Replace the annotation on a stored capture; returns false if the id is unknown
+    pub fn set_annotation(&self, id: u64, annotation: &Annotation) -> Result<bool> {
+        let conn = self.connect()?;
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM captures WHERE id = ?1",
+            params![id as i64],
+            |row| row.get(0),
+        )?;
+        if exists {
+            self.codec.read().write_annotation(&conn, id, annotation)?;
+        }
+        Ok(exists)
+    }
+
+    /// Highest capture id in use, or 0 when empty
+    pub fn max_id(&self) -> Result<u64> {
+        let conn = self.connect()?;
+        let max: i64 = conn.query_row("SELECT COALESCE(MAX(id), 0) FROM captures", [], |row| {
+            row.get(0)
+        })?;
+        Ok(max.max(0) as u64)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        let conn = self.connect()?;
+        conn.execute_batch(
+            "DELETE FROM captures; DELETE FROM capture_fts; DELETE FROM annotations; DELETE FROM bodies;",
+        )?;
+        Ok(())
+    }
+
+    pub fn query(&self, filter: &CaptureQuery) -> Result<Vec<CaptureEntry>> {
+        let conn = self.connect()?;
+        let codec = self.codec.read();
+        let mut sql = format!("{} WHERE 1=1", SELECT_COLUMNS);
+        let mut values: Vec<Value> = Vec::new();
+
+        if let Some(method) = &filter.method {
+            sql.push_str(" AND method = ?");
+            values.push(Value::Text(method.clone()));
+        }
+        if let Some(host) = &filter.host {
+            sql.push_str(" AND url LIKE ?");
+            values.push(Value::Text(format!("%{}%", host)));
+        }
+        if let Some(status) = filter.status {
+            sql.push_str(" AND resp_status = ?");
+            values.push(Value::Integer(status as i64));
+        }
+        if let Some(tls) = filter.tls {
+            sql.push_str(" AND tls = ?");
+            values.push(Value::Integer(tls as i64));
+        }
+        if let Some(search) = &filter.search {
+            sql.push_str(" AND (url LIKE ?)");
+            values.push(Value::Text(format!("%{}%", search)));
+        }
+        if let Some(since) = filter.since {
+            sql.push_str(" AND timestamp_ms >= ?");
+            values.push(Value::Integer(since));
+        }
+        if let Some(until) = filter.until {
+            sql.push_str(" AND timestamp_ms < ?");
+            values.push(Value::Integer(until));
+        }
+        if let Some(highlight) = filter.highlight {
+            sql.push_str(" AND highlight = ?");
+            values.push(Value::Text(highlight.as_str().to_string()));
+        }
+        if let Some(tag) = &filter.tag {
+            sql.push_str(" AND EXISTS (SELECT 1 FROM json_each(tags) WHERE value = ?)");
+            values.push(Value::Text(tag.trim().to_lowercase()));
+        }
+        if let Some(source) = filter.source {
+            // Rows written before sources were recorded are all proxied
+            sql.push_str(" AND COALESCE(source, 'proxy') = ?");
+            values.push(Value::Text(source.as_str().to_string()));
+        }
+        match filter.annotated {
+            Some(true) => sql.push_str(" AND capture_id IS NOT NULL"),
+            Some(false) => sql.push_str(" AND capture_id IS NULL"),
+            None => {}
+        }
+        let key = sort_column(filter.sort);
+        let (cmp, direction) = match filter.order {
+            SortOrder::Asc => (">", "ASC"),
+            SortOrder::Desc => ("<", "DESC"),
+        };
+        // Inexact pushdowns only narrow the scan; rows are re-checked below
+        let mut post_filter = None;
+        if let Some(expr) = &filter.expr {
+            let mut pushdown = expr.to_sql(&|word| codec.index_token(word));
+            if let Some(previous) = codec.previous() {
+                // Rows not yet re-encrypted are still indexed under the old key
+                let old = expr.to_sql(&|word| previous.index_token(word));
+                pushdown.sql = format!("({}) OR ({})", pushdown.sql, old.sql);
+                pushdown.params.extend(old.params);
+                pushdown.exact = false;
+            }
+            sql.push_str(&format!(" AND ({})", pushdown.sql));
+            values.extend(pushdown.params);
+            if !pushdown.exact {
+                post_filter = Some(expr);
+            }
+        }
+        let limit = filter.limit.unwrap_or(500);
+        // Re-checked rows are read in pages until enough of them match
+        let batch = match post_filter {
+            Some(_) => POST_FILTER_BATCH,
+            None => limit,
+        };
+
+        // Bodies are only read when returned or needed to re-check the expression
+        let with_bodies = !filter.metadata_only || post_filter.is_some();
+        let mut cursor = filter.cursor;
+        let mut entries = Vec::new();
+        while entries.len() < limit {
+            let mut sql = sql.clone();
+            let mut values = values.clone();
+            if let Some(cursor) = cursor {
+                // Keyset pagination; an unknown cursor yields NULL and so no rows
+                sql.push_str(&format!(
+                    " AND ({key}, id) {cmp} (SELECT {key}, id FROM captures WHERE id = ?)"
+                ));
+                values.push(Value::Integer(cursor as i64));
+            }
+            sql.push_str(&format!(
+                " ORDER BY {key} {direction}, id {direction} LIMIT {batch}"
+            ));
+
+            let mut stmt = conn.prepare(&sql)?;
+            let mut rows = stmt.query(params_from_iter(values))?;
+            let mut fetched = 0;
+            while entries.len() < limit {
+                let Some(row) = rows.next()? else {
+                    break;
+                };
+                fetched += 1;
+                let entry = codec.read_entry(&conn, row, with_bodies)?;
+                cursor = Some(entry.request.id);
+                if post_filter.is_none_or(|expr| expr.matches(&entry)) {
+                    entries.push(if filter.metadata_only {
+                        entry.without_bodies()
+                    } else {
+                        entry
+                    });
+                }
+            }
+            if fetched < batch {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+
+    /// A single capture with its bodies
+    pub fn get(&self, id: u64) -> Result<Option<CaptureEntry>> {
+        let conn = self.connect()?;
+        let codec = self.codec.read();
+        let mut stmt = conn.prepare(&format!("{} WHERE id = ?1", SELECT_COLUMNS))?;
+        let mut rows = stmt.query(params![id as i64])?;
+        match rows.next()? {
+            Some(row) => Ok(Some(codec.read_entry(&conn, row, true)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+enum WriteOp {
+    Insert(Box<CaptureEntry>),
+    Flush(mpsc::Sender<()>),
+}
+
+/// Thread owning the single connection that persists queued captures
+#[derive(Debug)]
+struct StorageWriter {
+    tx: Option<SyncSender<WriteOp>>,
+    /// Inserts queued but not yet committed
+    pending: Arc<AtomicUsize>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StorageWriter {
+    fn spawn(storage: CaptureStorage) -> Result<Self> {
+        let conn = storage.connect()?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        let (tx, rx) = mpsc::sync_channel(WRITE_QUEUE_CAPACITY);
+        let pending = Arc::new(AtomicUsize::new(0));
+        let handle = std::thread::Builder::new()
+            .name("capture-writer".to_string())
+            .spawn({
+                let pending = pending.clone();
+                move || run_writer(storage, conn, rx, pending)
+            })?;
+        Ok(Self {
+            tx: Some(tx),
+            pending,
+            handle: Some(handle),
+        })
+    }
+
+    fn send(&self, entry: CaptureEntry) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        self.pending.fetch_add(1, Ordering::SeqCst);
+        if tx.try_send(WriteOp::Insert(Box::new(entry))).is_ok() {
+            metrics().record_storage_write_queued();
+        } else {
+            // Never block the proxy; the entry stays in the in-memory history
+            self.pending.fetch_sub(1, Ordering::SeqCst);
+            metrics().record_storage_write_dropped();
+        }
+    }
+
+    /// Wait until everything queued so far is committed
+    fn flush(&self) {
+        if self.pending.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let (done_tx, done_rx) = mpsc::channel();
+        if tx.send(WriteOp::Flush(done_tx)).is_ok() {
+            let _ = done_rx.recv();
+        }
+    }
+}
+
+impl Drop for StorageWriter {
+    fn drop(&mut self) {
+        // Closing the channel lets the thread drain the queue and exit
+        self.tx.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn run_writer(
+    storage: CaptureStorage,
+    mut conn: Connection,
+    rx: Receiver<WriteOp>,
+    pending: Arc<AtomicUsize>,
+) {
+    while let Ok(op) = rx.recv() {
+        let mut batch = Vec::new();
+        let mut waiters = Vec::new();
+        let mut next = Some(op);
+        while let Some(op) = next {
+            match op {
+                WriteOp::Insert(entry) => batch.push(*entry),
+                WriteOp::Flush(waiter) => waiters.push(waiter),
+            }
+            next = if batch.len() < WRITE_BATCH_MAX {
+                rx.try_recv().ok()
+            } else {
+                None
+            };
+        }
+        if !batch.is_empty() {
+            let written = {
+                let _writing = storage.maintenance.lock();
+                write_queued(&storage, &mut conn, &batch)
+            };
+            metrics().record_storage_batch(written as u64, (batch.len() - written) as u64);
+            pending.fetch_sub(batch.len(), Ordering::SeqCst);
+        }
+        for waiter in waiters {
+            let _ = waiter.send(());
+        }
+    }
+}
+
+/// Commit a batch, returning how many entries were written
+///
+/// Only errors from the entries themselves fall back to writing one at a
+/// time, so a single bad entry does not lose the batch.
+fn write_queued(storage: &CaptureStorage, conn: &mut Connection, batch: &[CaptureEntry]) -> usize {
+    match write_when_unlocked(storage, conn, batch) {
+        Ok(()) => batch.len(),
+        Err(err) => {
+            tracing::warn!("persist_capture_error" = %err);
+            batch
+                .chunks(1)
+                .filter(|entry| write_when_unlocked(storage, conn, entry).is_ok())
+                .count()
+        }
+    }
+}
+
+/// Write entries, backing off and retrying while another connection holds the lock
+fn write_when_unlocked(
+    storage: &CaptureStorage,
+    conn: &mut Connection,
+    entries: &[CaptureEntry],
+) -> Result<()> {
+    let mut backoff = Duration::from_millis(50);
+    loop {
+        match storage.write_batch(conn, entries) {
+            Err(err) if is_busy(&err) => {
+                tracing::debug!("capture_writer_busy" = %err, ?backoff);
+                std::thread::sleep(backoff);
+                backoff = (backoff * 2).min(BUSY_BACKOFF_MAX);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn is_busy(err: &ProxyError) -> bool {
+    matches!(
+        err,
+        ProxyError::Database(rusqlite::Error::SqliteFailure(failure, _))
+            if matches!(
+                failure.code,
+                rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked
+            )
+    )
+}
+
+fn sort_column(sort: CaptureSort) -> &'static str {
+    match sort {
+        CaptureSort::Time => "timestamp_ms",
+        CaptureSort::Duration => "COALESCE(duration_ms, -1)",
+        CaptureSort::Size => "COALESCE(size_bytes, 0)",
+        CaptureSort::Status => "COALESCE(resp_status, -1)",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::codec::{clamp_i128, clamp_u128, ENCODING_ZSTD};
+    use super::*;
+    use crate::capture::{BodySizes, CaptureSource, CapturedRequest, CapturedResponse, Highlight};
+    use crate::search::SearchExpr;
+    use tempfile::tempdir;
+
+    fn create_test_entry(id: u64, method: &str, url: &str, status: Option<u16>) -> CaptureEntry {
+        let request = CapturedRequest {
+            id,
+            timestamp_ms: 1700000000000,
+            method: method.to_string(),
+            url: url.to_string(),
+            headers: vec![("host".to_string(), "test.com".to_string())],
+            body: b"request body".to_vec(),
+            tls: url.starts_with("https"),
+            source: Default::default(),
+        };
+
+        let response = status.map(|s| CapturedResponse {
+            request_id: id,
+            status_code: s,
+            headers: vec![("content-type".to_string(), "text/plain".to_string())],
+            body: b"response body".to_vec(),
+            duration_ms: 100,
+        });
+
+        CaptureEntry {
+            request,
+            response,
+            annotation: Annotation::default(),
+            omitted_bodies: None,
+        }
+    }
+
+    #[test]
+    fn test_storage_create_and_init() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+
+        let storage = CaptureStorage::new_unencrypted(&db_path);
+        assert!(storage.is_ok());
+        assert!(db_path.exists());
+    }
+
+    #[test]
+    fn test_storage_insert_and_query() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        let entry = create_test_entry(1, "GET", "https://api.test.com/users", Some(200));
+        storage.insert(&entry).unwrap();
+
+        let results = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].request.method, "GET");
+        assert_eq!(results[0].request.url, "https://api.test.com/users");
+        assert_eq!(results[0].response.as_ref().unwrap().status_code, 200);
+    }
+
+    #[test]
+    fn test_storage_query_by_method() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        storage
+            .insert(&create_test_entry(1, "GET", "/api/users", Some(200)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(2, "POST", "/api/users", Some(201)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(3, "GET", "/api/posts", Some(200)))
+            .unwrap();
+
+        let query = CaptureQuery {
+            method: Some("GET".to_string()),
+            ..Default::default()
+        };
+
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|e| e.request.method == "GET"));
+    }
+
+    #[test]
+    fn test_storage_query_by_host() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        storage
+            .insert(&create_test_entry(
+                1,
+                "GET",
+                "https://api.example.com/users",
+                Some(200),
+            ))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(
+                2,
+                "GET",
+                "https://other.com/data",
+                Some(200),
+            ))
+            .unwrap();
+
+        let query = CaptureQuery {
+            host: Some("example.com".to_string()),
+            ..Default::default()
+        };
+
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].request.url.contains("example.com"));
+    }
+
+    #[test]
+    fn test_storage_query_by_status() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        storage
+            .insert(&create_test_entry(1, "GET", "/success", Some(200)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(2, "GET", "/error", Some(500)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(3, "GET", "/not-found", Some(404)))
+            .unwrap();
+
+        let query = CaptureQuery {
+            status: Some(200),
+            ..Default::default()
+        };
+
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].response.as_ref().unwrap().status_code, 200);
+    }
+
+    #[test]
+    fn test_storage_query_by_tls() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        storage
+            .insert(&create_test_entry(
+                1,
+                "GET",
+                "https://secure.com",
+                Some(200),
+            ))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(
+                2,
+                "GET",
+                "http://insecure.com",
+                Some(200),
+            ))
+            .unwrap();
+
+        let query = CaptureQuery {
+            tls: Some(true),
+            ..Default::default()
+        };
+
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].request.tls);
+    }
+
+    #[test]
+    fn test_storage_query_with_search() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        storage
+            .insert(&create_test_entry(1, "GET", "/api/v1/users", Some(200)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(2, "GET", "/web/home", Some(200)))
+            .unwrap();
+
+        let query = CaptureQuery {
+            search: Some("/api/".to_string()),
+            ..Default::default()
+        };
+
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].request.url.contains("/api/"));
+    }
+
+    #[test]
+    fn test_storage_query_with_limit() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        for i in 0..10 {
+            storage
+                .insert(&create_test_entry(
+                    i,
+                    "GET",
+                    &format!("/page/{}", i),
+                    Some(200),
+                ))
+                .unwrap();
+        }
+
+        let query = CaptureQuery {
+            limit: Some(3),
+            ..Default::default()
+        };
+
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 3);
+    }
+
+    #[test]
+    fn test_storage_clear() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        storage
+            .insert(&create_test_entry(1, "GET", "/test1", Some(200)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(2, "GET", "/test2", Some(200)))
+            .unwrap();
+
+        let before = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(before.len(), 2);
+
+        storage.clear().unwrap();
+
+        let after = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(after.len(), 0);
+    }
+
+    #[test]
+    fn test_storage_entry_without_response() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        let entry = create_test_entry(1, "GET", "/pending", None);
+        storage.insert(&entry).unwrap();
+
+        let results = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].response.is_none());
+    }
+
+    #[test]
+    fn test_storage_preserves_body_data() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        let entry = create_test_entry(1, "POST", "/data", Some(200));
+        storage.insert(&entry).unwrap();
+
+        let results = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(results[0].request.body, b"request body");
+        assert_eq!(results[0].response.as_ref().unwrap().body, b"response body");
+    }
+
+    #[test]
+    fn test_storage_upsert_same_id() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        // Insert with same ID twice (should replace)
+        storage
+            .insert(&create_test_entry(1, "GET", "/original", Some(200)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(1, "GET", "/updated", Some(201)))
+            .unwrap();
+
+        let results = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].request.url, "/updated");
+        assert_eq!(results[0].response.as_ref().unwrap().status_code, 201);
+    }
+
+    fn search(storage: &CaptureStorage, q: &str) -> Vec<u64> {
+        let query = CaptureQuery {
+            expr: Some(SearchExpr::parse(q).unwrap()),
+            ..Default::default()
+        };
+        storage
+            .query(&query)
+            .unwrap()
+            .iter()
+            .map(|e| e.request.id)
+            .collect()
+    }
+
+    fn seed_search_entries(storage: &CaptureStorage) {
+        let mut login = create_test_entry(1, "POST", "https://api.example.com/login", Some(401));
+        login
+            .request
+            .headers
+            .push(("Authorization".to_string(), "Bearer secret".to_string()));
+        login.request.body = br#"{"password":"hunter2"}"#.to_vec();
+        storage.insert(&login).unwrap();
+        storage
+            .insert(&create_test_entry(
+                2,
+                "GET",
+                "https://www.example.com/",
+                Some(200),
+            ))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(
+                3,
+                "GET",
+                "https://api.example.com/users",
+                None,
+            ))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_storage_search_expression() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        seed_search_entries(&storage);
+
+        assert_eq!(search(&storage, "host:api.* status:>=400"), vec![1]);
+        assert_eq!(
+            search(&storage, "body:password header:authorization"),
+            vec![1]
+        );
+        assert_eq!(search(&storage, "body:\"response body\""), vec![2, 1]);
+        assert_eq!(search(&storage, "-status:200"), vec![3, 1]);
+        assert_eq!(search(&storage, "hunter2 OR method:get"), vec![3, 2, 1]);
+        assert!(search(&storage, "body:pass").is_empty());
+
+        let limited = CaptureQuery {
+            expr: Some(SearchExpr::parse("host:example").unwrap()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&limited).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_storage_rechecked_search_pages_through_rows() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        let entries: Vec<_> = (0..500)
+            .map(|id| {
+                let path = if id % 100 == 0 { "café" } else { "page" };
+                let url = format!("https://a.test/{path}/{id}");
+                create_test_entry(id, "GET", &url, Some(200))
+            })
+            .collect();
+        storage.insert_many(&entries).unwrap();
+
+        // Globs are only prefiltered in SQL, so matches sit several pages apart
+        let mut query = CaptureQuery {
+            expr: Some(SearchExpr::parse("url:*/café/*").unwrap()),
+            limit: Some(3),
+            ..Default::default()
+        };
+        let ids = |entries: Vec<CaptureEntry>| -> Vec<u64> {
+            entries.iter().map(|e| e.request.id).collect()
+        };
+        assert_eq!(ids(storage.query(&query).unwrap()), vec![400, 300, 200]);
+        query.cursor = Some(200);
+        assert_eq!(ids(storage.query(&query).unwrap()), vec![100, 0]);
+    }
+
+    #[test]
+    fn test_storage_search_encrypted_index() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let key = EncryptionKeyProvider::generate_new();
+        let storage =
+            CaptureStorage::with_key_provider(&db_path, EncryptionKeyProvider::from_key(key))
+                .unwrap();
+        seed_search_entries(&storage);
+
+        assert_eq!(search(&storage, "body:hunter2"), vec![1]);
+        assert_eq!(
+            search(&storage, "header:authorization=\"bearer secret\""),
+            vec![1]
+        );
+
+        // The index holds keyed tokens, never the plaintext words
+        let conn = storage.connect().unwrap();
+        let leaked: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM capture_fts WHERE body LIKE '%hunter2%' OR headers LIKE '%secret%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(leaked, 0);
+
+        // Rows missing from the index are picked up on open
+        conn.execute("DELETE FROM capture_fts", []).unwrap();
+        drop(storage);
+        let reopened =
+            CaptureStorage::with_key_provider(&db_path, EncryptionKeyProvider::from_key(key))
+                .unwrap();
+        assert_eq!(search(&reopened, "body:hunter2"), vec![1]);
+
+        reopened.clear().unwrap();
+        let indexed: i64 = conn
+            .query_row("SELECT COUNT(*) FROM capture_fts", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(indexed, 0);
+    }
+
+    #[test]
+    fn test_storage_reports_undecryptable_rows() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::with_key_provider(
+            dir.path().join("test.db"),
+            EncryptionKeyProvider::from_key(EncryptionKeyProvider::generate_new()),
+        )
+        .unwrap();
+        storage
+            .insert(&create_test_entry(1, "GET", "https://a.test/", Some(200)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(2, "GET", "https://b.test/", Some(200)))
+            .unwrap();
+
+        // Damaged rows surface as errors instead of empty headers or bodies
+        let conn = storage.connect().unwrap();
+        conn.execute("UPDATE bodies SET data = zeroblob(40)", [])
+            .unwrap();
+        assert!(matches!(
+            storage.get(1),
+            Err(crate::error::ProxyError::StorageKeyMismatch(_))
+        ));
+        conn.execute(
+            "UPDATE captures SET resp_headers = zeroblob(40) WHERE id = 2",
+            [],
+        )
+        .unwrap();
+        let headers_only = CaptureQuery {
+            metadata_only: true,
+            ..Default::default()
+        };
+        assert!(storage.query(&headers_only).is_err());
+    }
+
+    #[test]
+    fn test_storage_pagination_and_sorting() {
+        let dir = tempdir().unwrap();
+        let storage = CaptureStorage::new_unencrypted(dir.path().join("test.db")).unwrap();
+        for id in 1..=5u64 {
+            let mut entry = create_test_entry(id, "GET", "/page", Some(200));
+            entry.request.timestamp_ms = 1_700_000_000_000 + id as i128 * 1000;
+            entry.response.as_mut().unwrap().duration_ms = (id % 3) as u128 * 10;
+            storage.insert(&entry).unwrap();
+        }
+        storage
+            .insert(&create_test_entry(6, "GET", "/pending", None))
+            .unwrap();
+
+        let page = |query: &CaptureQuery| -> Vec<u64> {
+            storage
+                .query(query)
+                .unwrap()
+                .iter()
+                .map(|e| e.request.id)
+                .collect()
+        };
+
+        let mut query = CaptureQuery {
+            sort: CaptureSort::Duration,
+            limit: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(page(&query), vec![5, 2, 4]);
+        query.cursor = Some(4);
+        assert_eq!(page(&query), vec![1, 3, 6]);
+        query.cursor = Some(6);
+        assert!(page(&query).is_empty());
+
+        let query = CaptureQuery {
+            since: Some(1_700_000_002_000),
+            until: Some(1_700_000_004_000),
+            order: SortOrder::Asc,
+            ..Default::default()
+        };
+        assert_eq!(page(&query), vec![2, 3]);
+
+        let query = CaptureQuery {
+            sort: CaptureSort::Size,
+            order: SortOrder::Asc,
+            limit: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(page(&query), vec![6]);
+    }
+
+    #[test]
+    fn test_storage_annotations() {
+        let dir = tempdir().unwrap();
+        let key = EncryptionKeyProvider::generate_new();
+        let storage = CaptureStorage::with_key_provider(
+            dir.path().join("test.db"),
+            EncryptionKeyProvider::from_key(key),
+        )
+        .unwrap();
+        storage
+            .insert(&create_test_entry(1, "GET", "/a", Some(200)))
+            .unwrap();
+        storage
+            .insert(&create_test_entry(2, "GET", "/b", Some(200)))
+            .unwrap();
+
+        let annotation = Annotation {
+            highlight: Some(Highlight::Red),
+            notes: "session token leaks in body".to_string(),
+            tags: vec!["idor".to_string(), "auth".to_string()],
+        };
+        assert!(storage.set_annotation(2, &annotation).unwrap());
+        assert!(!storage.set_annotation(99, &annotation).unwrap());
+
+        let query = CaptureQuery {
+            tag: Some("IDOR".to_string()),
+            ..Default::default()
+        };
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].annotation, annotation);
+
+        let query = CaptureQuery {
+            highlight: Some(Highlight::Red),
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&query).unwrap().len(), 1);
+
+        let query = CaptureQuery {
+            annotated: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&query).unwrap()[0].request.id, 1);
+
+        // Notes are encrypted at rest
+        let conn = storage.connect().unwrap();
+        let plaintext: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM annotations WHERE CAST(notes AS TEXT) LIKE '%token%'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(plaintext, 0);
+
+        storage.set_annotation(2, &Annotation::default()).unwrap();
+        let query = CaptureQuery {
+            annotated: Some(true),
+            ..Default::default()
+        };
+        assert!(storage.query(&query).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_storage_deduplicates_bodies() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+        for id in 1..=3 {
+            storage
+                .insert(&create_test_entry(id, "GET", "/logo.png", Some(200)))
+                .unwrap();
+        }
+
+        let conn = storage.connect().unwrap();
+        let count_bodies = || -> i64 {
+            conn.query_row("SELECT COUNT(*) FROM bodies", [], |row| row.get(0))
+                .unwrap()
+        };
+        // One request body and one response body shared by all rows
+        assert_eq!(count_bodies(), 2);
+        let results = storage.query(&CaptureQuery::default()).unwrap();
+        assert!(results.iter().all(|e| e.request.body == b"request body"));
+
+        // Rows written by older versions keep bodies inline until reopened
+        conn.execute_batch(
+            "UPDATE captures SET body = CAST('legacy' AS BLOB), body_hash = NULL WHERE id = 3;",
+        )
+        .unwrap();
+        drop(storage);
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+        assert_eq!(count_bodies(), 3);
+        let inline: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM captures WHERE body IS NOT NULL",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(inline, 0);
+        let results = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(results[0].request.body, b"legacy");
+    }
+
+    #[test]
+    fn test_storage_writer_batches_queued_entries() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+        let before = metrics().snapshot().storage_writes;
+        for id in 1..=500 {
+            storage.enqueue(create_test_entry(id, "GET", "/queued", Some(200)));
+        }
+
+        // Reads wait for everything queued before them
+        let results = storage.query(&CaptureQuery::default()).unwrap();
+        assert_eq!(results.len(), 500);
+        assert!(metrics().snapshot().storage_writes >= before + 500);
+
+        let mode: String = storage
+            .connect()
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+
+        // Dropping the storage drains the queue
+        storage.enqueue(create_test_entry(501, "GET", "/last", Some(200)));
+        drop(storage);
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+        assert!(storage.get(501).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_storage_writer_waits_out_locks() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+
+        // Held past the busy timeout of both the batch and a one-row retry,
+        // as a VACUUM or long prune would be
+        let blocker = Connection::open(&db_path).unwrap();
+        blocker.execute_batch("BEGIN EXCLUSIVE").unwrap();
+        storage.enqueue(create_test_entry(1, "GET", "/during-lock", Some(200)));
+        std::thread::sleep(Duration::from_millis(2500));
+        blocker.execute_batch("COMMIT").unwrap();
+
+        assert!(storage.get(1).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_storage_compresses_and_loads_bodies_lazily() {
+        let dir = tempdir().unwrap();
+        let db_path = dir.path().join("test.db");
+        let storage = CaptureStorage::new_unencrypted(&db_path).unwrap();
+        let large = "{\"items\": [1, 2, 3]}".repeat(1_000).into_bytes();
+        let mut entry = create_test_entry(1, "POST", "/api/items", Some(200));
+        entry.request.body = large.clone();
+        storage.insert(&entry).unwrap();
+
+        let conn = storage.connect().unwrap();
+        let (stored, encoding, size): (i64, i64, i64) = conn
+            .query_row(
+                "SELECT length(data), encoding, size FROM bodies WHERE size = ?1",
+                params![large.len() as i64],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(encoding, ENCODING_ZSTD);
+        assert_eq!(size, large.len() as i64);
+        assert!((stored as usize) < large.len() / 10);
+
+        let query = CaptureQuery {
+            metadata_only: true,
+            ..Default::default()
+        };
+        let results = storage.query(&query).unwrap();
+        assert!(results[0].request.body.is_empty());
+        assert_eq!(
+            results[0].omitted_bodies,
+            Some(BodySizes {
+                request: large.len() as u64,
+                response: b"response body".len() as u64,
+            })
+        );
+        assert_eq!(results[0].size_bytes(), large.len() + 13);
+
+        // Filters on body content still see the bodies
+        let query = CaptureQuery {
+            expr: Some(SearchExpr::parse("body:\"items\"").unwrap()),
+            metadata_only: true,
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&query).unwrap().len(), 1);
+
+        let full = storage.get(1).unwrap().unwrap();
+        assert_eq!(full.request.body, large);
+        assert!(full.omitted_bodies.is_none());
+        assert!(storage.get(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_storage_filters_by_source() {
+        let dir = tempdir().unwrap();
+        let key = EncryptionKeyProvider::generate_new();
+        let storage = CaptureStorage::with_key_provider(
+            dir.path().join("test.db"),
+            EncryptionKeyProvider::from_key(key),
+        )
+        .unwrap();
+        storage
+            .insert(&create_test_entry(1, "GET", "/a", Some(200)))
+            .unwrap();
+        let mut repeated = create_test_entry(2, "GET", "/a", Some(200));
+        repeated.request.source = CaptureSource::Repeater;
+        storage.insert(&repeated).unwrap();
+
+        let query = CaptureQuery {
+            source: Some(CaptureSource::Repeater),
+            ..Default::default()
+        };
+        let results = storage.query(&query).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].request.source, CaptureSource::Repeater);
+
+        let query = CaptureQuery {
+            source: Some(CaptureSource::Proxy),
+            ..Default::default()
+        };
+        assert_eq!(storage.query(&query).unwrap()[0].request.id, 1);
+    }
+
+    #[test]
+    fn test_clamp_functions() {
+        // Test clamp_i128
+        assert_eq!(clamp_i128(100), 100);
+        assert_eq!(clamp_i128(i64::MAX as i128 + 1), i64::MAX);
+        assert_eq!(clamp_i128(i64::MIN as i128 - 1), i64::MIN);
+
+        // Test clamp_u128
+        assert_eq!(clamp_u128(100), 100);
+        assert_eq!(clamp_u128(i64::MAX as u128 + 1), i64::MAX);
+    }
+}
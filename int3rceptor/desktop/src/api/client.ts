@@ -306,8 +306,8 @@ class INT3RCEPTORClient {
     }
 
     // Project API
-    async projectSave(path: string, scope: string[]) {
-        const response = await this.client.post("/project/save", { path, scope });
+    async projectSave(path: string) {
+        const response = await this.client.post("/project/save", { path });
         return response.data;
     }
 
@@ -383,14 +383,26 @@ export interface IntruderPosition {
     start: number;
     end: number;
     name: string;
+    payloads?: string[];
+}
+
+export interface RetryPolicy {
+    max_retries: number;
+    backoff_ms: number;
+    max_backoff_ms: number;
+    statuses: number[];
 }
 
 export interface IntruderOptions {
     concurrency: number;
     delay_ms: number;
+    max_rps?: number | null;
+    retry?: RetryPolicy;
+    adaptive_throttle?: boolean;
 }
 
 export interface IntruderConfig {
+    target?: string;
     positions: IntruderPosition[];
     payloads: string[];
     attack_type: AttackType;
@@ -399,10 +411,16 @@ export interface IntruderConfig {
 
 export interface IntruderResult {
     request_id: number;
-    payload: string;
+    payloads: string[];
     status_code: number;
     response_length: number;
     duration_ms: number;
+    retries: number;
+    error?: string | null;
+    grep_matches: boolean[];
+    extracted: (string | null)[];
+    anomalies: ("status" | "length" | "time")[];
+    response?: { headers: [string, string][]; body: string };
 }
 
 // Scanner Types